@@ -0,0 +1,105 @@
+//! Tour through the features of datafusion_iceberg on the example table in the tests directory
+//!
+//! Run it with `cargo run --example lakehouse` from the datafusion_iceberg directory.
+
+use std::sync::Arc;
+
+use datafusion::{arrow::util::pretty::print_batches, prelude::SessionContext};
+use datafusion_iceberg::{
+    export::{export, ExportFormat},
+    masking::MaskingPolicy,
+    writer::{write_parquet, WriterConfig},
+    DataFusionTable,
+};
+use iceberg_rs::table::Table;
+use object_store::{local::LocalFileSystem, memory::InMemory, ObjectStore};
+
+#[tokio::main]
+async fn main() -> datafusion::error::Result<()> {
+    let object_store: Arc<dyn ObjectStore> = Arc::new(
+        LocalFileSystem::new_with_prefix(concat!(env!("CARGO_MANIFEST_DIR"), "/tests")).unwrap(),
+    );
+    let output: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+    let load = || async {
+        Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+            .await
+            .map_err(|err| datafusion::error::DataFusionError::Internal(err.to_string()))
+    };
+
+    let ctx = SessionContext::new();
+
+    // Plain table with the partition values as additional columns
+    ctx.register_table(
+        "nyc_taxis",
+        Arc::new(DataFusionTable::from(load().await?).with_partition_columns()),
+    )?;
+
+    // The same table with the fare amounts hidden from sessions without the unmasked privilege
+    ctx.register_table(
+        "nyc_taxis_masked",
+        Arc::new(
+            DataFusionTable::from(load().await?).with_masking("fare_amount", MaskingPolicy::Null),
+        ),
+    )?;
+
+    println!("Trips per vendor partition:");
+    let batches = ctx
+        .sql("SELECT _partition_vendor_id, COUNT(*) FROM nyc_taxis GROUP BY _partition_vendor_id")
+        .await?
+        .collect()
+        .await?;
+    print_batches(&batches)?;
+
+    println!("Scan plan with partition pruning:");
+    let batches = ctx
+        .sql("EXPLAIN SELECT trip_id, trip_distance FROM nyc_taxis WHERE vendor_id = 1")
+        .await?
+        .collect()
+        .await?;
+    print_batches(&batches)?;
+
+    println!("Masked fare amounts:");
+    let batches = ctx
+        .sql("SELECT trip_id, fare_amount FROM nyc_taxis_masked")
+        .await?
+        .collect()
+        .await?;
+    print_batches(&batches)?;
+
+    // Write the long trips as parquet data files into the data directory of a new table location
+    let stream = ctx
+        .sql("SELECT * FROM nyc_taxis WHERE trip_distance > 1.0")
+        .await?
+        .execute_stream()
+        .await?;
+    let files = write_parquet(
+        "warehouse/long_trips",
+        stream,
+        &output,
+        &WriterConfig::default(),
+    )
+    .await?;
+    println!("Written data files:");
+    for file in files {
+        println!("{} ({} rows)", file.path, file.record_count);
+    }
+
+    // Export the trips as arrow files partitioned by vendor for tools that can't read iceberg
+    let paths = export(
+        &ctx,
+        "SELECT vendor_id, trip_id, trip_distance FROM nyc_taxis",
+        "export/trips",
+        &["vendor_id"],
+        &ExportFormat::ArrowIpc,
+        &output,
+        None,
+    )
+    .await?;
+    println!("Exported files:");
+    for path in paths {
+        println!("{}", path);
+    }
+
+    Ok(())
+}
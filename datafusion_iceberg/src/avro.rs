@@ -0,0 +1,251 @@
+/*!
+ * Scans of tables with avro data files
+ *
+ * The data files of a table can have different formats, for example if a streaming writer commits avro files that are
+ * later compacted to parquet. The files of every format are read with the reader of their format and the plans are
+ * combined with a union. Like for parquet, the columns of the avro files are matched by name.
+ *
+ * The avro reader doesn't evaluate filters. The filters that the table reports as exact are applied to the rows of the
+ * avro files with a filter, so that tables with avro files can still push down exact filters.
+*/
+
+use std::{collections::HashSet, sync::Arc};
+
+use datafusion::{
+    common::{DataFusionError, Statistics, ToDFSchema},
+    datasource::{
+        file_format::{avro::AvroFormat, FileFormat},
+        listing::PartitionedFile,
+    },
+    execution::context::ExecutionProps,
+    logical_expr::expr_rewriter::unnormalize_col,
+    optimizer::utils::conjunction,
+    physical_expr::create_physical_expr,
+    physical_plan::{
+        expressions::Column,
+        file_format::{FileScanConfig, ParquetFileReaderFactory},
+        filter::FilterExec,
+        projection::ProjectionExec,
+        union::UnionExec,
+        ExecutionPlan, PhysicalExpr,
+    },
+    prelude::Expr,
+};
+
+use crate::table::parquet_plan;
+
+/// Read the avro files with the avro reader and all other files with the parquet reader
+pub(crate) async fn create_physical_plan(
+    config: FileScanConfig,
+    filters: &[Expr],
+    exact_filters: &[Expr],
+    avro_files: &HashSet<String>,
+    reader_factory: Option<Arc<dyn ParquetFileReaderFactory>>,
+    pushdown_filters: bool,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let (avro_groups, parquet_groups) = split_file_groups(config.file_groups.clone(), avro_files);
+    let mut plans = Vec::new();
+    if !parquet_groups.is_empty() {
+        let parquet_config = FileScanConfig {
+            file_groups: parquet_groups,
+            ..config.clone()
+        };
+        plans.push(parquet_plan(
+            parquet_config,
+            filters,
+            reader_factory,
+            pushdown_filters,
+        ));
+    }
+    if !avro_groups.is_empty() {
+        // The statistics are those of the whole table, the union must not count them twice
+        let avro_config = FileScanConfig {
+            file_groups: avro_groups,
+            statistics: if plans.is_empty() {
+                config.statistics.clone()
+            } else {
+                Statistics::default()
+            },
+            ..config
+        };
+        plans.push(avro_plan(avro_config, filters, exact_filters).await?);
+    }
+    match plans.len() {
+        1 => Ok(plans.remove(0)),
+        _ => Ok(Arc::new(UnionExec::new(plans))),
+    }
+}
+
+/// Scan of the avro files that only returns the rows that match the exact filters
+async fn avro_plan(
+    config: FileScanConfig,
+    filters: &[Expr],
+    exact_filters: &[Expr],
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let predicate = match conjunction(exact_filters.iter().cloned().map(unnormalize_col)) {
+        Some(predicate) => predicate,
+        None => return AvroFormat.create_physical_plan(config, filters).await,
+    };
+    // The columns of the filters may not be projected, so all columns are read and projected after filtering. The
+    // limit only holds for the filtered rows.
+    let projection = config.projection.clone();
+    let plan = AvroFormat
+        .create_physical_plan(
+            FileScanConfig {
+                projection: None,
+                limit: None,
+                ..config
+            },
+            filters,
+        )
+        .await?;
+    let schema = plan.schema();
+    let predicate = create_physical_expr(
+        &predicate,
+        &schema.clone().to_dfschema()?,
+        &schema,
+        &ExecutionProps::new(),
+    )?;
+    let plan = Arc::new(FilterExec::try_new(predicate, plan)?);
+    let columns = projection
+        .unwrap_or_else(|| (0..schema.fields().len()).collect())
+        .into_iter()
+        .map(|index| {
+            let name = schema.field(index).name();
+            (
+                Arc::new(Column::new(name, index)) as Arc<dyn PhysicalExpr>,
+                name.to_owned(),
+            )
+        })
+        .collect();
+    Ok(Arc::new(ProjectionExec::try_new(columns, plan)?))
+}
+
+/// Split the file groups into the groups of avro files and the groups of the other files. Empty groups are removed.
+fn split_file_groups(
+    file_groups: Vec<Vec<PartitionedFile>>,
+    avro_files: &HashSet<String>,
+) -> (Vec<Vec<PartitionedFile>>, Vec<Vec<PartitionedFile>>) {
+    let (avro_groups, other_groups): (Vec<_>, Vec<_>) = file_groups
+        .into_iter()
+        .map(|files| {
+            files.into_iter().partition::<Vec<_>, _>(|file| {
+                avro_files.contains(file.object_meta.location.as_ref())
+            })
+        })
+        .unzip();
+    (
+        avro_groups
+            .into_iter()
+            .filter(|files| !files.is_empty())
+            .collect(),
+        other_groups
+            .into_iter()
+            .filter(|files| !files.is_empty())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use apache_avro::{types::Record, Schema as AvroSchema, Writer as AvroWriter};
+    use datafusion::{
+        arrow::{
+            array::Int64Array,
+            datatypes::{DataType, Field, Schema},
+        },
+        datasource::object_store::ObjectStoreUrl,
+        physical_plan::collect,
+        prelude::{col, lit, SessionContext},
+    };
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+
+    use super::*;
+
+    #[test]
+    pub fn test_split_file_groups() {
+        let file_groups = vec![
+            vec![
+                PartitionedFile::new("data/a.parquet".to_owned(), 10),
+                PartitionedFile::new("data/b.avro".to_owned(), 10),
+            ],
+            vec![PartitionedFile::new("data/c.avro".to_owned(), 10)],
+        ];
+        let avro_files = HashSet::from(["data/b.avro".to_owned(), "data/c.avro".to_owned()]);
+
+        let (avro_groups, parquet_groups) = split_file_groups(file_groups, &avro_files);
+
+        assert_eq!(avro_groups.len(), 2);
+        assert_eq!(parquet_groups.len(), 1);
+        assert_eq!(
+            parquet_groups[0][0].object_meta.location.as_ref(),
+            "data/a.parquet"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_avro_plan_applies_exact_filters() {
+        let schema = AvroSchema::parse_str(
+            r#"{"type": "record", "name": "trip", "fields": [
+                {"name": "trip_id", "type": "long"},
+                {"name": "fare", "type": "double"}
+            ]}"#,
+        )
+        .unwrap();
+        let mut writer = AvroWriter::new(&schema, Vec::new());
+        for (trip_id, fare) in [(1_i64, 5.0_f64), (2, 15.0), (3, 25.0)] {
+            let mut record = Record::new(writer.schema()).unwrap();
+            record.put("trip_id", trip_id);
+            record.put("fare", fare);
+            writer.append(record).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+        let size = bytes.len();
+        let object_store = Arc::new(InMemory::new());
+        object_store
+            .put(&Path::from("data/trips.avro"), bytes.into())
+            .await
+            .unwrap();
+        let ctx = SessionContext::new();
+        ctx.runtime_env()
+            .register_object_store("memory", "", object_store);
+
+        let config = FileScanConfig {
+            object_store_url: ObjectStoreUrl::parse("memory://").unwrap(),
+            file_schema: Arc::new(Schema::new(vec![
+                Field::new("trip_id", DataType::Int64, false),
+                Field::new("fare", DataType::Float64, false),
+            ])),
+            file_groups: vec![vec![PartitionedFile::new(
+                "data/trips.avro".to_owned(),
+                size as u64,
+            )]],
+            statistics: Statistics::default(),
+            // The filter column isn't projected
+            projection: Some(vec![0]),
+            limit: Some(1),
+            table_partition_cols: vec![],
+            config_options: ctx.state().config.config_options(),
+        };
+        let filters = [col("fare").gt(lit(10.0_f64))];
+        let plan = avro_plan(config, &filters, &filters).await.unwrap();
+        assert_eq!(plan.schema().fields().len(), 1);
+
+        let trip_ids: Vec<i64> = collect(plan, ctx.task_ctx())
+            .await
+            .unwrap()
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(trip_ids, vec![2, 3]);
+    }
+}
@@ -0,0 +1,95 @@
+/*!
+ * Column statistics from external statistics stores
+ *
+ * The manifests only contain the bounds and null counts of the data files. Warehouse wide profilers often compute
+ * additional statistics like the number of distinct values. A statistics provider supplies them for a snapshot of a
+ * table, so that the optimizer can use them. Statistics from the manifests take precedence, the provider fills in the
+ * statistics that the manifests don't contain. Datafusion has no histograms, so only the statistics it supports are
+ * used.
+*/
+
+use std::{collections::HashMap, fmt::Debug};
+
+use async_trait::async_trait;
+use datafusion::{error::Result, physical_plan::ColumnStatistics};
+
+/// Source of column statistics for the snapshots of a table
+#[async_trait]
+pub trait StatisticsProvider: Debug + Send + Sync {
+    /// Statistics of the columns of the table at the snapshot, keyed by column name. Columns without statistics can be
+    /// left out.
+    async fn column_statistics(
+        &self,
+        table_location: &str,
+        snapshot_id: i64,
+    ) -> Result<HashMap<String, ColumnStatistics>>;
+}
+
+/// Fill in the statistics that are not known from the manifests
+pub(crate) fn merge(manifest: ColumnStatistics, external: ColumnStatistics) -> ColumnStatistics {
+    ColumnStatistics {
+        null_count: manifest.null_count.or(external.null_count),
+        max_value: manifest.max_value.or(external.max_value),
+        min_value: manifest.min_value.or(external.min_value),
+        distinct_count: manifest.distinct_count.or(external.distinct_count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use datafusion::{datasource::TableProvider, prelude::SessionContext};
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use crate::DataFusionTable;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct FixedStatistics;
+
+    #[async_trait]
+    impl StatisticsProvider for FixedStatistics {
+        async fn column_statistics(
+            &self,
+            _table_location: &str,
+            snapshot_id: i64,
+        ) -> Result<HashMap<String, ColumnStatistics>> {
+            assert_eq!(snapshot_id, 638933773299822130);
+            Ok(HashMap::from([(
+                "vendor_id".to_owned(),
+                ColumnStatistics {
+                    distinct_count: Some(2),
+                    ..Default::default()
+                },
+            )]))
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_external_statistics() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        )
+        .with_statistics_provider(Arc::new(FixedStatistics));
+
+        let plan = table
+            .scan(&SessionContext::new().state(), &None, &[], None)
+            .await
+            .expect("Failed to plan scan.");
+
+        let statistics = plan.statistics();
+        assert!(!statistics.is_exact);
+        let columns = statistics.column_statistics.unwrap();
+        assert_eq!(columns[0].distinct_count, Some(2));
+        assert_eq!(columns[1].distinct_count, None);
+    }
+}
@@ -0,0 +1,1512 @@
+/*!
+ * Commits of new snapshots and metadata to iceberg tables
+ *
+ * A commit writes new table metadata and swaps it with the metadata that the change is based on. Tables in a catalog
+ * are swapped by the catalog, which is given the metadata location the change is based on. File system tables are
+ * swapped by creating the metadata file of the next version, which fails if another writer created it first.
+ *
+ * A commit that loses the race loads the current metadata and applies the change to it again, which checks the change
+ * against the new metadata, for example that the removed files are still part of the table. Data files and the
+ * manifest of the added files are only written once, a retry only writes a new manifest list and metadata file. The
+ * number of retries and the wait between them are read from the table properties.
+*/
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use apache_avro::{Schema as AvroSchema, Writer as AvroWriter};
+use datafusion::{
+    common::DataFusionError, error::Result, physical_plan::SendableRecordBatchStream,
+    scalar::ScalarValue,
+};
+use iceberg_rs::{
+    catalog::relation::Relation,
+    model::{
+        data_types::StructType,
+        manifest::{
+            AvroMap, Content, DataFileV1, DataFileV2, FileFormat, ManifestEntry, ManifestEntryV1,
+            ManifestEntryV2, Status,
+        },
+        manifest_list::{FieldSummary, ManifestFile, ManifestFileV1, ManifestFileV2},
+        partition::{PartitionField, Transform},
+        snapshot::{Operation, Reference, Retention, SnapshotV1, SnapshotV2, Summary},
+        table_metadata::{FormatVersion, MetadataLog, SnapshotLog, TableMetadata},
+        values::{Struct, Value},
+    },
+    table::Table,
+    util,
+};
+use object_store::{path::Path, ObjectStore};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_bytes::ByteBuf;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    metadata::{ManifestEntryExt, ManifestFileExt, TableMetadataExt},
+    partition,
+    properties::{COMMIT_MAX_RETRY_WAIT_MS, COMMIT_MIN_RETRY_WAIT_MS, COMMIT_NUM_RETRIES},
+    wap::{branch_metadata, MAIN_BRANCH},
+    writer::{write_parquet, WriterConfig, WrittenFile},
+    DataFusionTable,
+};
+
+const DEFAULT_COMMIT_NUM_RETRIES: usize = 4;
+const DEFAULT_COMMIT_MIN_RETRY_WAIT_MS: u64 = 100;
+const DEFAULT_COMMIT_MAX_RETRY_WAIT_MS: u64 = 60_000;
+/// Block size recorded for the data files in manifests of format version 1
+const BLOCK_SIZE_IN_BYTES: i64 = 67_108_864;
+/// Length of the string bounds of the column metrics
+const BOUND_LENGTH: usize = 16;
+
+/// Change of the table metadata that can be applied again to newer metadata after a conflict
+#[async_trait::async_trait]
+pub(crate) trait Change: Send {
+    /// Metadata of the table with the change applied
+    async fn apply(&mut self, table: &Table) -> Result<TableMetadata>;
+    /// Delete the files that the last attempt wrote and that a retry doesn't reuse
+    async fn discard_attempt(&mut self, _object_store: &Arc<dyn ObjectStore>) {}
+    /// Delete all files that were written for the change
+    async fn discard(&mut self, object_store: &Arc<dyn ObjectStore>) {
+        self.discard_attempt(object_store).await
+    }
+}
+
+/// Result of swapping the metadata of the table
+enum Swap {
+    /// The new metadata is the current metadata of the table
+    Committed(Relation),
+    /// Another writer changed the table first, the relation has the current metadata
+    Conflict(Relation),
+    /// The new metadata was not committed
+    Failed(DataFusionError),
+}
+
+impl DataFusionTable {
+    /// Options to write data files for the table, read from the table properties. The data files are partitioned by
+    /// the default partition spec and checked against the current schema.
+    pub fn writer_config(&self) -> Result<WriterConfig> {
+        let table = self.table()?;
+        let properties = table.metadata().properties().cloned().unwrap_or_default();
+        let config = WriterConfig::from_properties(&properties)?.with_table_schema(table.schema());
+        let spec = table.metadata().default_spec();
+        // Files of a spec without partition values are written without splitting the input
+        if spec
+            .iter()
+            .all(|field| matches!(field.transform, Transform::Void))
+        {
+            Ok(config)
+        } else {
+            config.with_partition_spec(spec, table.schema())
+        }
+    }
+    /// Write the batches into new data files and commit them as a new snapshot. Returns the written files.
+    pub async fn insert(
+        &mut self,
+        batches: SendableRecordBatchStream,
+        config: &WriterConfig,
+    ) -> Result<Vec<WrittenFile>> {
+        let files = self.write(batches, config).await?;
+        let spec_id = self.table()?.metadata().default_spec_id();
+        let mut change =
+            SnapshotChange::new(Operation::Append, spec_id, files.clone(), HashSet::new())
+                .with_owned_files();
+        self.commit(&mut change).await?;
+        Ok(files)
+    }
+    /// Commit the data files as a new snapshot that appends them to the table. The files have to be written with the
+    /// default partition spec of the table.
+    pub async fn append(&mut self, files: Vec<WrittenFile>) -> Result<()> {
+        let spec_id = self.table()?.metadata().default_spec_id();
+        let mut change = SnapshotChange::new(Operation::Append, spec_id, files, HashSet::new());
+        self.commit(&mut change).await
+    }
+    /// Commit data files that another writer produced, together with their metrics, as a new snapshot that appends
+    /// them to the table. The files have to be in the object store of the table and written with the default partition
+    /// spec, they are checked before the commit. The table doesn't own the files, they are kept if the commit fails.
+    pub async fn add_data_files(&mut self, files: Vec<WrittenFile>) -> Result<()> {
+        let table = self.table()?;
+        let object_store = table.object_store();
+        let schema = table.schema();
+        for file in &files {
+            let size = object_store
+                .head(&Path::from(file.path.as_str()))
+                .await
+                .map_err(|err| match err {
+                    object_store::Error::NotFound { .. } => {
+                        Error::NotFound(format!("Data file {} doesn't exist.", file.path)).into()
+                    }
+                    err => DataFusionError::from(err),
+                })?
+                .size;
+            if size != file.file_size_in_bytes {
+                return Err(DataFusionError::Plan(format!(
+                    "Data file {} has {} bytes, but its metadata states {} bytes.",
+                    file.path, size, file.file_size_in_bytes
+                )));
+            }
+            if let Some(column) = file
+                .columns
+                .iter()
+                .find(|column| !schema.fields.iter().any(|field| field.name == column.name))
+            {
+                return Err(DataFusionError::Plan(format!(
+                    "Data file {} has metrics of column {}, which isn't part of the table schema.",
+                    file.path, column.name
+                )));
+            }
+        }
+        self.append(files).await
+    }
+    /// Commit the change. After a conflict with another writer the change is applied to the current metadata again.
+    pub(crate) async fn commit(&mut self, change: &mut dyn Change) -> Result<()> {
+        let object_store = self.table()?.object_store();
+        let retry = RetryPolicy::from_properties(self.table()?.metadata().properties())?;
+        let mut retries = 0;
+        loop {
+            let table = self.table()?;
+            let applied = match &self.branch {
+                Some(branch) => change
+                    .apply(table)
+                    .await
+                    .and_then(|metadata| branch_metadata(table.metadata(), metadata, branch)),
+                None => change.apply(table).await,
+            };
+            let metadata = match applied {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    change.discard(&object_store).await;
+                    return Err(err);
+                }
+            };
+            // If the outcome of the swap is unknown, the files of the change are kept
+            match swap(table, metadata).await? {
+                Swap::Committed(relation) => {
+                    let relation = self.on_branch(relation).await?;
+                    self.replace_relation(relation);
+                    return Ok(());
+                }
+                Swap::Conflict(relation) => {
+                    change.discard_attempt(&object_store).await;
+                    let relation = match self.on_branch(relation).await {
+                        Ok(relation) => relation,
+                        Err(err) => {
+                            change.discard(&object_store).await;
+                            return Err(err);
+                        }
+                    };
+                    self.replace_relation(relation);
+                    if retries == retry.num_retries {
+                        change.discard(&object_store).await;
+                        return Err(Error::CommitConflict(format!(
+                            "Table {} was changed by another writer during {} attempts.",
+                            self.relation.metadata_location(),
+                            retries + 1
+                        ))
+                        .into());
+                    }
+                    tokio::time::sleep(retry.wait(retries)).await;
+                    retries += 1;
+                }
+                Swap::Failed(err) => {
+                    change.discard(&object_store).await;
+                    return Err(err);
+                }
+            }
+        }
+    }
+    /// Write the batches into new data files in the data directory of the table
+    pub(crate) async fn write(
+        &self,
+        batches: SendableRecordBatchStream,
+        config: &WriterConfig,
+    ) -> Result<Vec<WrittenFile>> {
+        let table = self.table()?;
+        let location = util::strip_prefix(table.metadata().location());
+        write_parquet(&location, batches, &table.object_store(), config).await
+    }
+    /// Copy of the table with the same options that reads the current metadata of the table
+    pub(crate) async fn load_current(&self) -> Result<DataFusionTable> {
+        Ok(self.with_relation(self.on_branch(load(self.table()?).await?).await?))
+    }
+    pub(crate) fn table(&self) -> Result<&Table> {
+        match &self.relation {
+            Relation::Table(table) => Ok(table),
+            Relation::View(_) => Err(DataFusionError::Plan(
+                "Only tables can be changed.".to_string(),
+            )),
+        }
+    }
+    fn replace_relation(&mut self, relation: Relation) {
+        self.relation = relation;
+        *self.statistics_cache.lock().unwrap() = None;
+    }
+}
+
+/// Retries with exponential backoff. The retries of commits are read from the table properties.
+#[derive(Debug, Clone)]
+pub(crate) struct RetryPolicy {
+    pub(crate) num_retries: usize,
+    pub(crate) min_wait: Duration,
+    pub(crate) max_wait: Duration,
+}
+
+impl RetryPolicy {
+    pub(crate) fn from_properties(properties: Option<&HashMap<String, String>>) -> Result<Self> {
+        let property = |key: &str| -> Result<Option<u64>> {
+            properties
+                .and_then(|properties| properties.get(key))
+                .map(|value| {
+                    value.parse().map_err(|_| {
+                        DataFusionError::Plan(format!(
+                            "Table property {} has to be a positive integer, found {}.",
+                            key, value
+                        ))
+                    })
+                })
+                .transpose()
+        };
+        Ok(RetryPolicy {
+            num_retries: property(COMMIT_NUM_RETRIES)?
+                .map(|retries| retries as usize)
+                .unwrap_or(DEFAULT_COMMIT_NUM_RETRIES),
+            min_wait: Duration::from_millis(
+                property(COMMIT_MIN_RETRY_WAIT_MS)?.unwrap_or(DEFAULT_COMMIT_MIN_RETRY_WAIT_MS),
+            ),
+            max_wait: Duration::from_millis(
+                property(COMMIT_MAX_RETRY_WAIT_MS)?.unwrap_or(DEFAULT_COMMIT_MAX_RETRY_WAIT_MS),
+            ),
+        })
+    }
+    /// Exponential backoff with jitter, so that concurrent writers don't retry at the same time
+    pub(crate) fn wait(&self, retries: usize) -> Duration {
+        let wait = self
+            .min_wait
+            .saturating_mul(2u32.saturating_pow(retries as u32))
+            .min(self.max_wait);
+        let jitter = (Uuid::new_v4().as_u128() % 1000) as u32;
+        wait / 2 + wait * jitter / 2000
+    }
+}
+
+/// Write the metadata and make it the current metadata of the table
+async fn swap(table: &Table, mut metadata: TableMetadata) -> Result<Swap> {
+    let now = now_ms();
+    let base = table.metadata_location().to_owned();
+    match &mut metadata {
+        TableMetadata::V1(metadata) => {
+            metadata.last_updated_ms = now;
+            metadata
+                .metadata_log
+                .get_or_insert_with(Vec::new)
+                .push(MetadataLog {
+                    metadata_file: base.clone(),
+                    timestamp_ms: table.metadata().last_updated_ms(),
+                });
+        }
+        TableMetadata::V2(metadata) => {
+            metadata.last_updated_ms = now;
+            metadata
+                .metadata_log
+                .get_or_insert_with(Vec::new)
+                .push(MetadataLog {
+                    metadata_file: base.clone(),
+                    timestamp_ms: table.metadata().last_updated_ms(),
+                });
+        }
+    }
+    let json = serde_json::to_vec(&metadata).map_err(Error::iceberg)?;
+    let object_store = table.object_store();
+    let location = table.metadata().location().trim_end_matches('/').to_owned();
+    let version = metadata_version(&base) + 1;
+    match (table.identifier(), table.catalog()) {
+        (Some(identifier), Some(catalog)) => {
+            let metadata_location = format!(
+                "{}/metadata/{:05}-{}.metadata.json",
+                location,
+                version,
+                Uuid::new_v4()
+            );
+            let path = object_path(&metadata_location);
+            object_store.put(&path, json.into()).await?;
+            let err = match catalog
+                .clone()
+                .update_table(identifier.clone(), &metadata_location, &base)
+                .await
+            {
+                Ok(relation) => return Ok(Swap::Committed(relation)),
+                Err(err) => err,
+            };
+            // The request can fail after the catalog applied the change
+            let current = match catalog.clone().load_table(identifier).await {
+                Ok(current) => current,
+                Err(_) => return Err(Error::catalog(err).into()),
+            };
+            let current_location = util::strip_prefix(current.metadata_location());
+            if current_location == util::strip_prefix(&metadata_location) {
+                return Ok(Swap::Committed(current));
+            }
+            let _ = object_store.delete(&path).await;
+            if current_location == util::strip_prefix(&base) {
+                Ok(Swap::Failed(Error::catalog(err).into()))
+            } else {
+                Ok(Swap::Conflict(current))
+            }
+        }
+        _ => {
+            let directory = util::strip_prefix(&location) + "/metadata/";
+            // Temporary files don't end with .metadata.json, so that loading the table ignores them
+            let temp =
+                Path::from(directory.clone() + &Uuid::new_v4().to_string() + ".metadata.json.tmp");
+            object_store.put(&temp, json.into()).await?;
+            let result = object_store
+                .copy_if_not_exists(
+                    &temp,
+                    &Path::from(format!("{}v{}.metadata.json", directory, version)),
+                )
+                .await;
+            let _ = object_store.delete(&temp).await;
+            match result {
+                Ok(()) => Ok(Swap::Committed(load(table).await?)),
+                Err(object_store::Error::AlreadyExists { .. }) => {
+                    Ok(Swap::Conflict(load(table).await?))
+                }
+                Err(err) => Ok(Swap::Failed(err.into())),
+            }
+        }
+    }
+}
+
+/// Load the current metadata of the table from its catalog or, for file system tables, from the object store
+pub(crate) async fn load(table: &Table) -> Result<Relation> {
+    match (table.identifier(), table.catalog()) {
+        (Some(identifier), Some(catalog)) => catalog
+            .clone()
+            .load_table(identifier)
+            .await
+            .map_err(|err| Error::catalog(err).into()),
+        _ => Table::load_file_system_table(
+            &util::strip_prefix(table.metadata().location()),
+            &table.object_store(),
+        )
+        .await
+        .map(Relation::Table)
+        .map_err(|err| Error::iceberg(err).into()),
+    }
+}
+
+/// Version of the metadata file, for names like `v3.metadata.json` or `00003-<uuid>.metadata.json`
+fn metadata_version(metadata_location: &str) -> i64 {
+    let name = metadata_location
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".metadata.json");
+    match name.strip_prefix('v') {
+        Some(version) => version.parse().unwrap_or_default(),
+        None => name
+            .split('-')
+            .next()
+            .and_then(|version| version.parse().ok())
+            .unwrap_or_default(),
+    }
+}
+
+/// Change of the data files of the table that is committed as a new snapshot
+pub(crate) struct SnapshotChange {
+    operation: Operation,
+    snapshot_id: i64,
+    // Partition spec that the added files were written with
+    spec_id: i32,
+    added: Vec<WrittenFile>,
+    // Paths of the data files that are removed from the table
+    removed: HashSet<String>,
+    // Manifest of the added files, written by the first attempt and reused by the retries
+    added_manifest: Option<NewManifest>,
+    // Manifest list and rewritten manifests of the last attempt
+    attempt_files: Vec<Path>,
+    // Whether the added data files are deleted if the change is not committed
+    owned_files: bool,
+}
+
+/// Manifest that was written for the snapshot
+pub(crate) struct NewManifest {
+    pub(crate) path: String,
+    length: i64,
+    spec_id: i32,
+    // Number of data files and records of the entries by status
+    added: (i32, i64),
+    existing: (i32, i64),
+    deleted: (i32, i64),
+    min_sequence_number: Option<i64>,
+    partitions: Vec<FieldSummary>,
+}
+
+impl SnapshotChange {
+    pub(crate) fn new(
+        operation: Operation,
+        spec_id: i32,
+        added: Vec<WrittenFile>,
+        removed: HashSet<String>,
+    ) -> Self {
+        SnapshotChange {
+            operation,
+            snapshot_id: (Uuid::new_v4().as_u128() as i64) & i64::MAX,
+            spec_id,
+            added,
+            removed,
+            added_manifest: None,
+            attempt_files: Vec::new(),
+            owned_files: false,
+        }
+    }
+    /// Delete the added data files if the change can't be committed, for files that were written for the change
+    pub(crate) fn with_owned_files(mut self) -> Self {
+        self.owned_files = true;
+        self
+    }
+    /// Write the manifest of the added files
+    async fn write_added_manifest(&self, table: &Table) -> Result<Option<NewManifest>> {
+        if self.added.is_empty() {
+            return Ok(None);
+        }
+        let metadata = table.metadata();
+        let spec = spec(metadata, self.spec_id)?;
+        let format_version = metadata.format_version();
+        let entries = self
+            .added
+            .iter()
+            .map(|file| {
+                let data_file = DataFileV2 {
+                    content: Content::Data,
+                    file_path: full_path(metadata.location(), &file.path),
+                    file_format: FileFormat::Parquet,
+                    partition: partition_struct(spec, file)?,
+                    record_count: file.record_count as i64,
+                    file_size_in_bytes: file.file_size_in_bytes as i64,
+                    column_sizes: None,
+                    value_counts: metrics(table.schema(), file, |column| {
+                        Some(column.value_count as i64)
+                    })?,
+                    null_value_counts: metrics(table.schema(), file, |column| {
+                        Some(column.null_count as i64)
+                    })?,
+                    nan_value_counts: None,
+                    distinct_counts: None,
+                    lower_bounds: metrics(table.schema(), file, |column| {
+                        column
+                            .lower_bound
+                            .as_ref()
+                            .and_then(|value| bound_bytes(value, false))
+                            .map(ByteBuf::from)
+                    })?,
+                    upper_bounds: metrics(table.schema(), file, |column| {
+                        column
+                            .upper_bound
+                            .as_ref()
+                            .and_then(|value| bound_bytes(value, true))
+                            .map(ByteBuf::from)
+                    })?,
+                    key_metadata: None,
+                    split_offsets: None,
+                    equality_ids: None,
+                    sort_order_id: None,
+                };
+                Ok(manifest_entry(
+                    &format_version,
+                    Status::Added,
+                    Some(self.snapshot_id),
+                    // The sequence number is inherited from the manifest list, so that retries can reuse the manifest
+                    None,
+                    data_file,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let path = format!(
+            "{}/metadata/{}-m0.avro",
+            metadata.location().trim_end_matches('/'),
+            Uuid::new_v4()
+        );
+        write_manifest(table, &path, self.spec_id, &entries, None)
+            .await
+            .map(Some)
+    }
+    /// Rewrite the manifest without the removed files. None if the manifest contains none of them.
+    async fn rewrite_manifest(
+        &self,
+        table: &Table,
+        index: usize,
+        removed: &mut HashSet<String>,
+    ) -> Result<Option<NewManifest>> {
+        let manifest = &table.manifests()[index];
+        let mut mask = vec![false; table.manifests().len()];
+        mask[index] = true;
+        let entries = table.files(Some(mask)).await.map_err(Error::iceberg)?;
+        if !entries
+            .iter()
+            .any(|entry| !entry.is_deleted() && self.removed.contains(entry.file_path()))
+        {
+            return Ok(None);
+        }
+        let format_version = table.metadata().format_version();
+        let sequence_number = match manifest {
+            ManifestFile::V1(_) => None,
+            ManifestFile::V2(manifest) => Some(manifest.sequence_number),
+        };
+        let entries: Vec<ManifestEntry> = entries
+            .into_iter()
+            .filter(|entry| !entry.is_deleted())
+            .map(|entry| {
+                let (snapshot_id, entry_sequence_number, data_file) = explicit_ids(manifest, entry);
+                if removed.remove(&data_file.file_path) {
+                    manifest_entry(
+                        &format_version,
+                        Status::Deleted,
+                        Some(self.snapshot_id),
+                        entry_sequence_number,
+                        data_file,
+                    )
+                } else {
+                    manifest_entry(
+                        &format_version,
+                        Status::Existing,
+                        Some(snapshot_id),
+                        entry_sequence_number,
+                        data_file,
+                    )
+                }
+            })
+            .collect();
+        let path = format!(
+            "{}/metadata/{}-m0.avro",
+            table.metadata().location().trim_end_matches('/'),
+            Uuid::new_v4()
+        );
+        write_manifest(
+            table,
+            &path,
+            manifest.partition_spec_id(),
+            &entries,
+            sequence_number,
+        )
+        .await
+        .map(Some)
+    }
+}
+
+#[async_trait::async_trait]
+impl Change for SnapshotChange {
+    async fn apply(&mut self, table: &Table) -> Result<TableMetadata> {
+        let metadata = table.metadata();
+        if !self.added.is_empty() && metadata.default_spec_id() != self.spec_id {
+            return Err(Error::CommitConflict(format!(
+                "The files were written with partition spec {}, but the default partition spec of the table is {}.",
+                self.spec_id,
+                metadata.default_spec_id()
+            ))
+            .into());
+        }
+        if self.added_manifest.is_none() {
+            self.added_manifest = self.write_added_manifest(table).await?;
+        }
+        let object_store = table.object_store();
+        let location = metadata.location().trim_end_matches('/').to_owned();
+        let sequence_number = match metadata {
+            TableMetadata::V1(_) => 0,
+            TableMetadata::V2(metadata) => metadata.last_sequence_number + 1,
+        };
+
+        let mut manifests = Vec::new();
+        let mut removed = self.removed.clone();
+        let (mut deleted_files, mut deleted_records) = (0, 0);
+        for (index, manifest) in table.manifests().iter().enumerate() {
+            let rewritten = match removed.is_empty() {
+                true => None,
+                false => self.rewrite_manifest(table, index, &mut removed).await?,
+            };
+            match rewritten {
+                Some(rewritten) => {
+                    self.attempt_files.push(object_path(&rewritten.path));
+                    deleted_files += rewritten.deleted.0 as i64;
+                    deleted_records += rewritten.deleted.1;
+                    manifests.push(manifest_file(
+                        &metadata.format_version(),
+                        &rewritten,
+                        self.snapshot_id,
+                        sequence_number,
+                    ));
+                }
+                None => manifests.push(manifest.clone()),
+            }
+        }
+        if let Some(path) = removed.iter().next() {
+            return Err(Error::CommitConflict(format!(
+                "Data file {} is not part of the current snapshot of the table.",
+                path
+            ))
+            .into());
+        }
+        // New manifests come first, so that readers see the latest changes first
+        if let Some(added) = &self.added_manifest {
+            manifests.insert(
+                0,
+                manifest_file(
+                    &metadata.format_version(),
+                    added,
+                    self.snapshot_id,
+                    sequence_number,
+                ),
+            );
+        }
+
+        let manifest_list = format!(
+            "{}/metadata/snap-{}-{}.avro",
+            location,
+            self.snapshot_id,
+            Uuid::new_v4()
+        );
+        write_manifest_list(
+            &object_store,
+            &manifest_list,
+            &manifests,
+            metadata,
+            self.snapshot_id,
+            sequence_number,
+        )
+        .await?;
+        self.attempt_files.push(object_path(&manifest_list));
+
+        let (total_files, total_records) = manifests.iter().fold((0, 0), |acc, manifest| {
+            let (files, records) = live_counts(manifest);
+            (acc.0 + files, acc.1 + records)
+        });
+        let summary = Summary {
+            operation: operation(&self.operation),
+            other: [
+                ("added-data-files", self.added.len() as i64),
+                (
+                    "added-records",
+                    self.added.iter().map(|file| file.record_count as i64).sum(),
+                ),
+                (
+                    "added-files-size",
+                    self.added
+                        .iter()
+                        .map(|file| file.file_size_in_bytes as i64)
+                        .sum(),
+                ),
+                ("deleted-data-files", deleted_files),
+                ("deleted-records", deleted_records),
+                ("total-data-files", total_files),
+                ("total-records", total_records),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_string()))
+            .collect(),
+        };
+
+        add_snapshot(
+            metadata,
+            self.snapshot_id,
+            sequence_number,
+            manifest_list,
+            summary,
+        )
+    }
+    async fn discard_attempt(&mut self, object_store: &Arc<dyn ObjectStore>) {
+        for path in self.attempt_files.drain(..) {
+            let _ = object_store.delete(&path).await;
+        }
+    }
+    async fn discard(&mut self, object_store: &Arc<dyn ObjectStore>) {
+        self.discard_attempt(object_store).await;
+        if let Some(manifest) = self.added_manifest.take() {
+            let _ = object_store.delete(&object_path(&manifest.path)).await;
+        }
+        if self.owned_files {
+            for file in &self.added {
+                let _ = object_store.delete(&Path::from(file.path.as_str())).await;
+            }
+        }
+    }
+}
+
+/// Reference of a branch with the default retention of the table
+pub(crate) fn branch_reference(snapshot_id: i64) -> Reference {
+    Reference {
+        snapshot_id,
+        retention: Retention::Branch {
+            min_snapshots_to_keep: None,
+            max_snapshot_age_ms: None,
+            max_ref_age_ms: None,
+        },
+    }
+}
+
+/// Metadata with the snapshot as new current snapshot of the main branch
+pub(crate) fn add_snapshot(
+    metadata: &TableMetadata,
+    snapshot_id: i64,
+    sequence_number: i64,
+    manifest_list: String,
+    summary: Summary,
+) -> Result<TableMetadata> {
+    let now = now_ms();
+    let mut metadata = clone_metadata(metadata)?;
+    match &mut metadata {
+        TableMetadata::V1(metadata) => {
+            let snapshot = SnapshotV1 {
+                snapshot_id,
+                parent_snapshot_id: metadata.current_snapshot_id,
+                timestamp_ms: now,
+                manifest_list: Some(manifest_list),
+                manifests: None,
+                summary: Some(summary),
+                schema_id: metadata.current_schema_id.map(|id| id as i64),
+            };
+            metadata
+                .snapshots
+                .get_or_insert_with(Vec::new)
+                .push(snapshot);
+            metadata.current_snapshot_id = Some(snapshot_id);
+            metadata
+                .snapshot_log
+                .get_or_insert_with(Vec::new)
+                .push(SnapshotLog {
+                    snapshot_id,
+                    timestamp_ms: now,
+                });
+        }
+        TableMetadata::V2(metadata) => {
+            let snapshot = SnapshotV2 {
+                snapshot_id,
+                parent_snapshot_id: metadata.current_snapshot_id,
+                sequence_number,
+                timestamp_ms: now,
+                manifest_list,
+                summary,
+                schema_id: Some(metadata.current_schema_id as i64),
+            };
+            metadata
+                .snapshots
+                .get_or_insert_with(Vec::new)
+                .push(snapshot);
+            metadata.current_snapshot_id = Some(snapshot_id);
+            metadata.last_sequence_number = sequence_number;
+            metadata
+                .snapshot_log
+                .get_or_insert_with(Vec::new)
+                .push(SnapshotLog {
+                    snapshot_id,
+                    timestamp_ms: now,
+                });
+            // The main branch is implied if the table has no references
+            if let Some(refs) = &mut metadata.refs {
+                refs.insert(MAIN_BRANCH.to_owned(), branch_reference(snapshot_id));
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+/// Snapshot id, sequence number and data file of the entry. Entries that inherited the ids from the manifest list keep
+/// them explicitly, so that they can be moved into a new manifest.
+pub(crate) fn explicit_ids(
+    manifest: &ManifestFile,
+    entry: ManifestEntry,
+) -> (i64, Option<i64>, DataFileV2) {
+    let (snapshot_id, sequence_number, data_file) = match entry {
+        ManifestEntry::V1(entry) => (Some(entry.snapshot_id), None, entry.data_file.into()),
+        ManifestEntry::V2(entry) => (entry.snapshot_id, entry.sequence_number, entry.data_file),
+    };
+    let manifest_sequence_number = match manifest {
+        ManifestFile::V1(_) => None,
+        ManifestFile::V2(manifest) => Some(manifest.sequence_number),
+    };
+    (
+        snapshot_id.unwrap_or_else(|| manifest.added_snapshot_id()),
+        sequence_number.or(manifest_sequence_number),
+        data_file,
+    )
+}
+
+pub(crate) fn manifest_entry(
+    format_version: &FormatVersion,
+    status: Status,
+    snapshot_id: Option<i64>,
+    sequence_number: Option<i64>,
+    data_file: DataFileV2,
+) -> ManifestEntry {
+    match format_version {
+        FormatVersion::V1 => ManifestEntry::V1(ManifestEntryV1 {
+            status,
+            snapshot_id: snapshot_id.unwrap_or_default(),
+            data_file: DataFileV1 {
+                file_path: data_file.file_path,
+                file_format: data_file.file_format,
+                partition: data_file.partition,
+                record_count: data_file.record_count,
+                file_size_in_bytes: data_file.file_size_in_bytes,
+                block_size_in_bytes: BLOCK_SIZE_IN_BYTES,
+                file_ordinal: None,
+                sort_columns: None,
+                column_sizes: data_file.column_sizes,
+                value_counts: data_file.value_counts,
+                null_value_counts: data_file.null_value_counts,
+                nan_value_counts: data_file.nan_value_counts,
+                distinct_counts: data_file.distinct_counts,
+                lower_bounds: data_file.lower_bounds,
+                upper_bounds: data_file.upper_bounds,
+                key_metadata: data_file.key_metadata,
+                split_offsets: data_file.split_offsets,
+                sort_order_id: data_file.sort_order_id,
+            },
+        }),
+        FormatVersion::V2 => ManifestEntry::V2(ManifestEntryV2 {
+            status,
+            snapshot_id,
+            sequence_number,
+            data_file,
+        }),
+    }
+}
+
+/// Write the entries into a new manifest. Entries without a sequence number inherit the sequence number of the
+/// snapshot, the minimum sequence number only covers the entries with one.
+pub(crate) async fn write_manifest(
+    table: &Table,
+    path: &str,
+    spec_id: i32,
+    entries: &[ManifestEntry],
+    sequence_number: Option<i64>,
+) -> Result<NewManifest> {
+    let metadata = table.metadata();
+    let format_version = metadata.format_version();
+    let spec = spec(metadata, spec_id)?;
+    let schema = avro_schema(&ManifestEntry::schema(
+        &partition::avro_schema(spec, table.schema())?,
+        &format_version,
+    ))?;
+    let mut writer = AvroWriter::new(&schema, Vec::new());
+    let user_metadata = [
+        (
+            "schema",
+            serde_json::to_string(table.schema()).map_err(Error::iceberg)?,
+        ),
+        (
+            "partition-spec",
+            serde_json::to_string(spec).map_err(Error::iceberg)?,
+        ),
+        ("partition-spec-id", spec_id.to_string()),
+        (
+            "format-version",
+            format_version_number(&format_version).to_string(),
+        ),
+    ];
+    for (key, value) in user_metadata {
+        writer
+            .add_user_metadata(key.to_owned(), value)
+            .map_err(Error::iceberg)?;
+    }
+    if let FormatVersion::V2 = format_version {
+        writer
+            .add_user_metadata("content".to_owned(), "data")
+            .map_err(Error::iceberg)?;
+    }
+
+    let mut manifest = NewManifest {
+        path: path.to_owned(),
+        length: 0,
+        spec_id,
+        added: (0, 0),
+        existing: (0, 0),
+        deleted: (0, 0),
+        min_sequence_number: None,
+        partitions: Vec::new(),
+    };
+    for entry in entries {
+        let (status, sequence) = match entry {
+            ManifestEntry::V1(entry) => (&entry.status, None),
+            ManifestEntry::V2(entry) => (&entry.status, entry.sequence_number),
+        };
+        let counts = match status {
+            Status::Added => &mut manifest.added,
+            Status::Existing => &mut manifest.existing,
+            Status::Deleted => &mut manifest.deleted,
+        };
+        counts.0 += 1;
+        counts.1 += entry.record_count();
+        manifest.min_sequence_number = match (manifest.min_sequence_number, sequence) {
+            (Some(min), Some(sequence)) => Some(min.min(sequence)),
+            (min, sequence) => min.or(sequence),
+        };
+        writer.append_ser(entry).map_err(Error::iceberg)?;
+    }
+    manifest.min_sequence_number = manifest.min_sequence_number.or(sequence_number);
+    manifest.partitions = summaries(spec.len(), entries);
+    let bytes = writer.into_inner().map_err(Error::iceberg)?;
+    manifest.length = bytes.len() as i64;
+    table
+        .object_store()
+        .put(&object_path(path), bytes.into())
+        .await?;
+    Ok(manifest)
+}
+
+pub(crate) async fn write_manifest_list(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &str,
+    manifests: &[ManifestFile],
+    metadata: &TableMetadata,
+    snapshot_id: i64,
+    sequence_number: i64,
+) -> Result<()> {
+    let format_version = metadata.format_version();
+    let schema = avro_schema(&ManifestFile::schema(&format_version))?;
+    let mut writer = AvroWriter::new(&schema, Vec::new());
+    let mut user_metadata = vec![
+        ("snapshot-id", snapshot_id.to_string()),
+        (
+            "format-version",
+            format_version_number(&format_version).to_string(),
+        ),
+    ];
+    if let Some(parent) = metadata.current_snapshot_id() {
+        user_metadata.push(("parent-snapshot-id", parent.to_string()));
+    }
+    if let FormatVersion::V2 = format_version {
+        user_metadata.push(("sequence-number", sequence_number.to_string()));
+    }
+    for (key, value) in user_metadata {
+        writer
+            .add_user_metadata(key.to_owned(), value)
+            .map_err(Error::iceberg)?;
+    }
+    for manifest in manifests {
+        writer.append_ser(manifest).map_err(Error::iceberg)?;
+    }
+    let bytes = writer.into_inner().map_err(Error::iceberg)?;
+    object_store.put(&object_path(path), bytes.into()).await?;
+    Ok(())
+}
+
+/// Entry of the manifest list for a manifest that was written for the snapshot
+pub(crate) fn manifest_file(
+    format_version: &FormatVersion,
+    manifest: &NewManifest,
+    snapshot_id: i64,
+    sequence_number: i64,
+) -> ManifestFile {
+    let partitions = Some(manifest.partitions.clone());
+    match format_version {
+        FormatVersion::V1 => ManifestFile::V1(ManifestFileV1 {
+            manifest_path: manifest.path.clone(),
+            manifest_length: manifest.length,
+            partition_spec_id: manifest.spec_id,
+            added_snapshot_id: snapshot_id,
+            added_files_count: Some(manifest.added.0),
+            existing_files_count: Some(manifest.existing.0),
+            deleted_files_count: Some(manifest.deleted.0),
+            added_rows_count: Some(manifest.added.1),
+            existing_rows_count: Some(manifest.existing.1),
+            deleted_rows_count: Some(manifest.deleted.1),
+            partitions,
+            key_metadata: None,
+        }),
+        FormatVersion::V2 => ManifestFile::V2(ManifestFileV2 {
+            manifest_path: manifest.path.clone(),
+            manifest_length: manifest.length,
+            partition_spec_id: manifest.spec_id,
+            content: Content::Data,
+            sequence_number,
+            min_sequence_number: manifest.min_sequence_number.unwrap_or(sequence_number),
+            added_snapshot_id: snapshot_id,
+            added_files_count: manifest.added.0,
+            existing_files_count: manifest.existing.0,
+            deleted_files_count: manifest.deleted.0,
+            added_rows_count: manifest.added.1,
+            existing_rows_count: manifest.existing.1,
+            deleted_rows_count: manifest.deleted.1,
+            partitions,
+            key_metadata: None,
+        }),
+    }
+}
+
+/// Number of data files and records that the manifest adds or keeps
+pub(crate) fn live_counts(manifest: &ManifestFile) -> (i64, i64) {
+    match manifest {
+        ManifestFile::V1(manifest) => (
+            (manifest.added_files_count.unwrap_or_default()
+                + manifest.existing_files_count.unwrap_or_default()) as i64,
+            manifest.added_rows_count.unwrap_or_default()
+                + manifest.existing_rows_count.unwrap_or_default(),
+        ),
+        ManifestFile::V2(manifest) => (
+            (manifest.added_files_count + manifest.existing_files_count) as i64,
+            manifest.added_rows_count + manifest.existing_rows_count,
+        ),
+    }
+}
+
+/// Summaries of the partition values of the entries for every field of the partition spec
+fn summaries(fields: usize, entries: &[ManifestEntry]) -> Vec<FieldSummary> {
+    (0..fields)
+        .map(|position| {
+            let values = entries
+                .iter()
+                .map(|entry| entry.partition_values().get(position).cloned().flatten());
+            let mut summary = FieldSummary {
+                contains_null: false,
+                contains_nan: None,
+                lower_bound: None,
+                upper_bound: None,
+            };
+            let mut bounds: Option<(Value, Value)> = None;
+            for value in values {
+                let value = match value {
+                    Some(value) => value,
+                    None => {
+                        summary.contains_null = true;
+                        continue;
+                    }
+                };
+                bounds = Some(match bounds {
+                    None => (value.clone(), value),
+                    Some((lower, upper)) => {
+                        let lower = match partition::compare(&value, &lower) {
+                            Some(Ordering::Less) => value.clone(),
+                            _ => lower,
+                        };
+                        let upper = match partition::compare(&value, &upper) {
+                            Some(Ordering::Greater) => value,
+                            _ => upper,
+                        };
+                        (lower, upper)
+                    }
+                });
+            }
+            if let Some((lower, upper)) = bounds {
+                summary.lower_bound = partition::value_bytes(&lower).map(ByteBuf::from);
+                summary.upper_bound = partition::value_bytes(&upper).map(ByteBuf::from);
+            }
+            summary
+        })
+        .collect()
+}
+
+/// Partition values of the written file as struct of the partition spec
+fn partition_struct(spec: &[PartitionField], file: &WrittenFile) -> Result<Struct> {
+    if file.partition_values.is_empty() {
+        // Files are written without partition values if every field of the spec is void
+        if let Some(field) = spec
+            .iter()
+            .find(|field| !matches!(field.transform, Transform::Void))
+        {
+            return Err(DataFusionError::Plan(format!(
+                "Data file {} has no value for the partition field {}.",
+                file.path, field.name
+            )));
+        }
+        return Ok(spec
+            .iter()
+            .map(|field| (field.name.clone(), None))
+            .collect());
+    }
+    if file.partition_values.len() != spec.len() {
+        return Err(DataFusionError::Plan(format!(
+            "Data file {} has {} partition values, but the partition spec has {} fields.",
+            file.path,
+            file.partition_values.len(),
+            spec.len()
+        )));
+    }
+    Ok(spec
+        .iter()
+        .zip(&file.partition_values)
+        .map(|(field, value)| (field.name.clone(), value.clone()))
+        .collect())
+}
+
+/// Map from the field ids of the top level columns of the file to the metric
+fn metrics<T: Serialize + DeserializeOwned + Clone>(
+    schema: &StructType,
+    file: &WrittenFile,
+    metric: impl Fn(&crate::writer::ColumnMetrics) -> Option<T>,
+) -> Result<Option<AvroMap<T>>> {
+    let entries = file
+        .columns
+        .iter()
+        .filter_map(|column| {
+            let field = schema
+                .fields
+                .iter()
+                .find(|field| field.name == column.name)?;
+            Some(serde_json::json!({"key": field.id, "value": metric(column)?}))
+        })
+        .collect::<Vec<_>>();
+    // The map type of iceberg-rs can only be created by deserializing it
+    serde_json::from_value(serde_json::Value::Array(entries))
+        .map(Some)
+        .map_err(|err| Error::iceberg(err).into())
+}
+
+/// Binary single value serialization of a column bound. Strings are truncated, the upper bound is incremented so that
+/// it is still larger than all values.
+fn bound_bytes(value: &ScalarValue, upper: bool) -> Option<Vec<u8>> {
+    match value {
+        ScalarValue::Boolean(Some(value)) => Some(vec![*value as u8]),
+        ScalarValue::Int32(Some(value)) | ScalarValue::Date32(Some(value)) => {
+            Some(value.to_le_bytes().to_vec())
+        }
+        ScalarValue::Int64(Some(value)) | ScalarValue::TimestampMicrosecond(Some(value), _) => {
+            Some(value.to_le_bytes().to_vec())
+        }
+        ScalarValue::Float32(Some(value)) => Some(value.to_le_bytes().to_vec()),
+        ScalarValue::Float64(Some(value)) => Some(value.to_le_bytes().to_vec()),
+        ScalarValue::Utf8(Some(value)) if value.chars().count() <= BOUND_LENGTH => {
+            Some(value.as_bytes().to_vec())
+        }
+        ScalarValue::Utf8(Some(value)) => {
+            let mut prefix: Vec<char> = value.chars().take(BOUND_LENGTH).collect();
+            if upper {
+                // Increment the last character that can be incremented and drop the ones after it
+                while let Some(last) = prefix.pop() {
+                    if let Some(next) = char::from_u32(last as u32 + 1) {
+                        prefix.push(next);
+                        break;
+                    }
+                }
+                if prefix.is_empty() {
+                    return None;
+                }
+            }
+            Some(prefix.into_iter().collect::<String>().into_bytes())
+        }
+        _ => None,
+    }
+}
+
+/// Parse the avro schema of iceberg-rs. Some of its schemas miss the commas between the attributes of a field and name
+/// the field id attribute differently than the specification.
+fn avro_schema(schema: &str) -> Result<AvroSchema> {
+    let lines: Vec<&str> = schema.lines().collect();
+    let repaired = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let next = lines[index + 1..]
+                .iter()
+                .map(|line| line.trim())
+                .find(|line| !line.is_empty());
+            let line = line.replace("\"field_id\"", "\"field-id\"");
+            match next {
+                Some(next) if line.trim_end().ends_with('"') && next.starts_with('"') => line + ",",
+                _ => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    AvroSchema::parse_str(&repaired).map_err(|err| Error::iceberg(err).into())
+}
+
+fn spec(metadata: &TableMetadata, spec_id: i32) -> Result<&[PartitionField]> {
+    match metadata.get_spec(spec_id) {
+        Some(spec) => Ok(spec),
+        // Tables of format version 1 can have a single spec that is not part of the list of specs
+        None if metadata.default_spec_id() == spec_id => Ok(metadata.default_spec()),
+        None => Err(DataFusionError::Internal(format!(
+            "Partition spec {} doesn't exist.",
+            spec_id
+        ))),
+    }
+}
+
+fn operation(operation: &Operation) -> Operation {
+    match operation {
+        Operation::Append => Operation::Append,
+        Operation::Replace => Operation::Replace,
+        Operation::Overwrite => Operation::Overwrite,
+        Operation::Delete => Operation::Delete,
+    }
+}
+
+fn format_version_number(format_version: &FormatVersion) -> u8 {
+    match format_version {
+        FormatVersion::V1 => 1,
+        FormatVersion::V2 => 2,
+    }
+}
+
+/// The table metadata of iceberg-rs can't be cloned, so it is copied through its json representation
+pub(crate) fn clone_metadata(metadata: &TableMetadata) -> Result<TableMetadata> {
+    serde_json::to_value(metadata)
+        .and_then(serde_json::from_value)
+        .map_err(|err| Error::iceberg(err).into())
+}
+
+/// Path in the object store for a location of the table. Escaped partition values are kept, so their `%` is not
+/// encoded a second time.
+pub(crate) fn object_path(location: &str) -> Path {
+    let location = util::strip_prefix(location);
+    Path::parse(&location).unwrap_or_else(|_| Path::from(location.as_str()))
+}
+
+/// Location of a file in the object store of the table, including the scheme and bucket of the table location
+fn full_path(table_location: &str, path: &str) -> String {
+    match Url::parse(table_location) {
+        Ok(url) => format!(
+            "{}://{}/{}",
+            url.scheme(),
+            url.host_str().unwrap_or_default(),
+            path
+        ),
+        Err(_) => "/".to_owned() + path,
+    }
+}
+
+pub(crate) fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+
+    use datafusion::{
+        arrow::{
+            array::{Float32Array, Float64Array, Int64Array, StringArray},
+            record_batch::RecordBatch,
+        },
+        datasource::TableProvider,
+        physical_plan::{collect, memory::MemoryExec, ExecutionPlan},
+        prelude::{col, lit, SessionContext},
+    };
+    use futures::TryStreamExt;
+    use object_store::ObjectMeta;
+
+    use crate::{
+        properties::{COMMIT_MIN_RETRY_WAIT_MS, COMMIT_NUM_RETRIES},
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    /// Insert one row per vendor id with the trip ids into the taxis table
+    pub(crate) async fn insert_trips(
+        table: &mut DataFusionTable,
+        trips: [i64; 2],
+    ) -> Vec<WrittenFile> {
+        let stream = trips_stream(table, trips);
+        let config = table.writer_config().unwrap();
+        table.insert(stream, &config).await.unwrap()
+    }
+
+    /// One row per vendor id with the trip ids for the taxis table
+    pub(crate) fn trips_stream(
+        table: &DataFusionTable,
+        trips: [i64; 2],
+    ) -> SendableRecordBatchStream {
+        let schema = table.schema();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(Int64Array::from(trips.to_vec())),
+                Arc::new(Float32Array::from(vec![1.0, 2.0])),
+                Arc::new(Float64Array::from(vec![10.0, 20.0])),
+                Arc::new(StringArray::from(vec!["N", "Y"])),
+            ],
+        )
+        .unwrap();
+        MemoryExec::try_new(&[vec![batch]], schema, None)
+            .unwrap()
+            .execute(0, SessionContext::new().task_ctx())
+            .unwrap()
+    }
+
+    /// Number of rows of the current snapshot
+    pub(crate) async fn row_count(table: &DataFusionTable) -> usize {
+        let ctx = SessionContext::new();
+        let plan = table.scan(&ctx.state(), &None, &[], None).await.unwrap();
+        let batches = collect(plan, ctx.task_ctx()).await.unwrap();
+        batches.iter().map(|x| x.num_rows()).sum()
+    }
+
+    async fn files(object_store: &Arc<dyn ObjectStore>, suffix: &str) -> usize {
+        let files: Vec<ObjectMeta> = object_store
+            .list(Some(&"home/iceberg/warehouse/nyc/taxis/metadata".into()))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        files
+            .iter()
+            .filter(|file| file.location.as_ref().ends_with(suffix))
+            .count()
+    }
+
+    #[tokio::test]
+    pub async fn test_concurrent_appends() {
+        let object_store = taxis_copy(&[]).await;
+        let writers = (0..4i64).map(|writer| {
+            let object_store = object_store.clone();
+            tokio::spawn(async move {
+                let mut table = DataFusionTable::from(
+                    Table::load_file_system_table(TAXIS, &object_store)
+                        .await
+                        .unwrap(),
+                );
+                insert_trips(&mut table, [writer * 10, writer * 10 + 1]).await
+            })
+        });
+        for writer in futures::future::join_all(writers).await {
+            // Every writer wrote one file per vendor id
+            assert_eq!(writer.unwrap().len(), 2);
+        }
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(row_count(&table).await, 12);
+        let ctx = SessionContext::new();
+        // The vendor id of the new rows is read from the partition values of the manifests
+        let plan = table
+            .scan(&ctx.state(), &None, &[col("vendor_id").eq(lit(2i64))], None)
+            .await
+            .unwrap();
+        let batches = collect(plan, ctx.task_ctx()).await.unwrap();
+        let trips = batches
+            .iter()
+            .flat_map(|batch| {
+                let vendors = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap();
+                let trips = batch
+                    .column(1)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap();
+                (0..batch.num_rows())
+                    .filter(|row| vendors.value(*row) == 2)
+                    .map(|row| trips.value(row))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|trip| trip % 10 == 1 && *trip < 40)
+            .count();
+        assert_eq!(trips, 4);
+
+        let table = table.table().unwrap();
+        assert_eq!(table.manifests().len(), 5);
+        match table.metadata() {
+            TableMetadata::V1(metadata) => {
+                assert_eq!(metadata.snapshots.as_ref().unwrap().len(), 5)
+            }
+            TableMetadata::V2(_) => panic!("The taxis table has format version 1."),
+        }
+        // Retries reuse the manifests of the added files and delete the manifest lists of the lost attempts
+        assert_eq!(files(&object_store, "-m0.avro").await, 5);
+        assert_eq!(files(&object_store, ".avro").await, 10);
+        assert_eq!(files(&object_store, ".metadata.json").await, 6);
+        assert_eq!(files(&object_store, ".tmp").await, 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_add_data_files() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        // The files stand in for the output of another writer
+        let config = table.writer_config().unwrap();
+        let mut files = table
+            .write(trips_stream(&table, [0, 1]), &config)
+            .await
+            .unwrap();
+
+        let mut foreign = files.clone();
+        foreign[0].columns[0].name = "pickup_zone".to_owned();
+        let err = table.add_data_files(foreign).await.unwrap_err();
+        assert!(matches!(err, DataFusionError::Plan(_)));
+        let mut missing = files.clone();
+        missing[0].path += ".missing";
+        let err = table.add_data_files(missing).await.unwrap_err();
+        assert!(matches!(Error::downcast(&err), Some(Error::NotFound(_))));
+        assert_eq!(row_count(&table).await, 4);
+
+        files.truncate(1);
+        table.add_data_files(files).await.unwrap();
+        assert_eq!(row_count(&table).await, 5);
+    }
+
+    #[tokio::test]
+    pub async fn test_commit_retries() {
+        let object_store = taxis_copy(&[]).await;
+        let load = || async {
+            DataFusionTable::from(
+                Table::load_file_system_table(TAXIS, &object_store)
+                    .await
+                    .unwrap(),
+            )
+        };
+        let mut table = load().await;
+        table
+            .set_properties(HashMap::from([(
+                COMMIT_NUM_RETRIES.to_owned(),
+                "0".to_owned(),
+            )]))
+            .await
+            .unwrap();
+
+        // Without retries the writer that read the old metadata fails
+        let mut stale = load().await;
+        insert_trips(&mut table, [0, 1]).await;
+        let config = stale.writer_config().unwrap();
+        let err = stale
+            .insert(trips_stream(&stale, [2, 3]), &config)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            Error::downcast(&err),
+            Some(Error::CommitConflict(_))
+        ));
+        assert_eq!(row_count(&load().await).await, 6);
+
+        // With a retry the append is applied to the metadata of the other writer
+        stale
+            .set_properties(HashMap::from([(
+                COMMIT_NUM_RETRIES.to_owned(),
+                "1".to_owned(),
+            )]))
+            .await
+            .unwrap();
+        let mut table = load().await;
+        let mut stale = load().await;
+        insert_trips(&mut table, [4, 5]).await;
+        insert_trips(&mut stale, [6, 7]).await;
+        assert_eq!(row_count(&load().await).await, 10);
+
+        assert!(table
+            .set_properties(HashMap::from([(
+                COMMIT_MIN_RETRY_WAIT_MS.to_owned(),
+                "soon".to_owned(),
+            )]))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,131 @@
+/*!
+ * Object store whose credentials can be rotated at runtime
+ *
+ * Object stores are configured with their credentials when they are built. The rotating object store forwards every
+ * operation to the store that is current when the operation starts. Replacing the store with one that is built with new
+ * credentials lets running operations finish with the old credentials while all new operations use the new ones.
+ *
+ * Catalogs and tables should be created with the rotating store, so that they pick up the new credentials as well.
+*/
+
+use std::{
+    fmt::Display,
+    ops::Range,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{
+    path::Path, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+/// Object store that forwards all operations to an object store that can be replaced
+#[derive(Debug)]
+pub struct RotatingObjectStore {
+    current: RwLock<Arc<dyn ObjectStore>>,
+}
+
+impl RotatingObjectStore {
+    pub fn new(object_store: Arc<dyn ObjectStore>) -> Self {
+        RotatingObjectStore {
+            current: RwLock::new(object_store),
+        }
+    }
+    /// Use the object store for all operations that start from now on
+    pub fn update(&self, object_store: Arc<dyn ObjectStore>) {
+        *self.current.write().unwrap() = object_store;
+    }
+    fn current(&self) -> Arc<dyn ObjectStore> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl Display for RotatingObjectStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rotating({})", self.current())
+    }
+}
+
+#[async_trait]
+impl ObjectStore for RotatingObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.current().put(location, bytes).await
+    }
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.current().put_multipart(location).await
+    }
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.current().abort_multipart(location, multipart_id).await
+    }
+    async fn get(&self, location: &Path) -> Result<GetResult> {
+        self.current().get(location).await
+    }
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.current().get_range(location, range).await
+    }
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.current().get_ranges(location, ranges).await
+    }
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.current().head(location).await
+    }
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.current().delete(location).await
+    }
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        // The stream borrows the store, so the listing has to be completed with the store that started it
+        let store = self.current();
+        let objects: Vec<Result<ObjectMeta>> =
+            futures::StreamExt::collect(store.list(prefix).await?).await;
+        Ok(Box::pin(futures::stream::iter(objects)))
+    }
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.current().list_with_delimiter(prefix).await
+    }
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.current().copy(from, to).await
+    }
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.current().copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_rotate_object_store() {
+        let old: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let new: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let location = Path::from("data/file.parquet");
+        old.put(&location, Bytes::from_static(b"old"))
+            .await
+            .unwrap();
+        new.put(&location, Bytes::from_static(b"new"))
+            .await
+            .unwrap();
+
+        let store = RotatingObjectStore::new(old);
+        assert_eq!(
+            store.get_range(&location, 0..3).await.unwrap(),
+            Bytes::from_static(b"old")
+        );
+
+        store.update(new);
+        assert_eq!(
+            store.get_range(&location, 0..3).await.unwrap(),
+            Bytes::from_static(b"new")
+        );
+    }
+}
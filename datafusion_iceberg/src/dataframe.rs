@@ -0,0 +1,728 @@
+/*!
+ * Writing the rows of DataFusion DataFrames into iceberg tables
+ *
+ * The columns of the DataFrame are matched with the columns of the table by name and cast to the types of the table.
+ * Optional columns of the table that the DataFrame lacks are null. All rows are committed as one snapshot. Tables that
+ * don't exist yet are created unpartitioned with the schema of the DataFrame if the options allow it.
+ * Columns of the DataFrame that the table lacks are rejected, unless the options merge the schema. Then they are added
+ * to the schema as optional columns, which is committed together with the rows.
+ *
+ * Appends add the rows to the table and overwrites replace all rows of the table. Upserts replace the rows of the table
+ * that have the same key as a row of the DataFrame and add the others. The table is rewritten with the rows whose key
+ * isn't part of the DataFrame and the rows of the DataFrame. Overwrites and upserts remove the files of the snapshot that
+ * they started from, files that other writers append in the meantime are kept.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use datafusion::{
+    arrow::datatypes::Schema,
+    common::DataFusionError,
+    dataframe::DataFrame,
+    datasource::MemTable,
+    error::Result,
+    logical_expr::{cast, lit, Expr, JoinType},
+    physical_plan::memory::MemoryStream,
+    prelude::SessionContext,
+    scalar::ScalarValue,
+};
+use futures::TryStreamExt;
+use iceberg_rs::{
+    catalog::{identifier::Identifier, Catalog},
+    model::{
+        data_types::StructType,
+        partition::PartitionSpec,
+        schema::SchemaV2,
+        snapshot::Operation,
+        sort::SortOrder,
+        table_metadata::{TableMetadata, TableMetadataV2},
+    },
+    table::Table,
+    util,
+};
+use object_store::{path::Path, ObjectStore};
+use uuid::Uuid;
+
+use crate::{
+    commit::{load, now_ms, object_path, RetryPolicy, SnapshotChange},
+    error::Error,
+    evolution::{evolved_schema, SchemaChange, SchemaUpdate},
+    metadata::{ManifestEntryExt, TableMetadataExt},
+    properties::DEFAULT_NAME_MAPPING,
+    schema::{
+        arrow_to_new_iceberg_schema, iceberg_to_arrow_schema, last_field_id, name_mapping,
+        without_nested_field_ids,
+    },
+    writer::WrittenFile,
+    DataFusionTable,
+};
+
+/// How the rows are written into the table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Add the rows to the table
+    #[default]
+    Append,
+    /// Replace all rows of the table
+    Overwrite,
+    /// Replace the rows of the table with the same values of the key columns and add the other rows
+    Upsert(Vec<String>),
+}
+
+/// Options for writing a DataFrame into a table
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    mode: WriteMode,
+    create_if_not_exists: bool,
+    merge_schema: bool,
+}
+
+impl WriteOptions {
+    /// Mode of the write, appends by default
+    pub fn with_mode(mut self, mode: WriteMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    /// Create the table with the schema of the DataFrame if it doesn't exist
+    pub fn with_create_if_not_exists(mut self) -> Self {
+        self.create_if_not_exists = true;
+        self
+    }
+    /// Add the columns of the DataFrame that the table lacks as optional columns to the schema of the table. The schema
+    /// update is committed with the rows.
+    pub fn with_merge_schema(mut self) -> Self {
+        self.merge_schema = true;
+        self
+    }
+}
+
+/// Table that a DataFrame is written into
+pub enum WriteTarget {
+    /// Loaded table
+    Table(Box<DataFusionTable>),
+    /// File system table at the location of the object store
+    FileSystem {
+        location: String,
+        object_store: Arc<dyn ObjectStore>,
+    },
+    /// Table of the catalog. New tables are created in the directory of the identifier below the base path.
+    Catalog {
+        catalog: Arc<dyn Catalog>,
+        identifier: Identifier,
+        base_path: String,
+    },
+}
+
+/// Extension of DataFrames for writing their rows into iceberg tables
+#[async_trait]
+pub trait WriteIceberg {
+    /// Write the rows into the table with the mode of the options and commit them as one snapshot. Returns the table
+    /// with the new snapshot.
+    async fn write_iceberg(
+        &self,
+        target: WriteTarget,
+        options: &WriteOptions,
+    ) -> Result<DataFusionTable>;
+}
+
+#[async_trait]
+impl WriteIceberg for DataFrame {
+    async fn write_iceberg(
+        &self,
+        target: WriteTarget,
+        options: &WriteOptions,
+    ) -> Result<DataFusionTable> {
+        let mut table = match target {
+            WriteTarget::Table(table) => *table,
+            WriteTarget::FileSystem {
+                location,
+                object_store,
+            } => {
+                if exists(&location, &object_store).await? {
+                    let table = Table::load_file_system_table(&location, &object_store)
+                        .await
+                        .map_err(Error::iceberg)?;
+                    DataFusionTable::from(table)
+                } else if options.create_if_not_exists {
+                    create_file_system_table(&location, object_store, &self.schema().into()).await?
+                } else {
+                    return Err(
+                        Error::NotFound(format!("Table {} doesn't exist.", location)).into(),
+                    );
+                }
+            }
+            WriteTarget::Catalog {
+                catalog,
+                identifier,
+                base_path,
+            } => {
+                let exists = catalog
+                    .table_exists(&identifier)
+                    .await
+                    .map_err(Error::catalog)?;
+                if exists {
+                    let relation = catalog
+                        .load_table(&identifier)
+                        .await
+                        .map_err(Error::catalog)?;
+                    DataFusionTable::from(relation)
+                } else if options.create_if_not_exists {
+                    create_catalog_table(catalog, identifier, &base_path, &self.schema().into())
+                        .await?
+                } else {
+                    return Err(
+                        Error::NotFound(format!("Table {} doesn't exist.", identifier)).into(),
+                    );
+                }
+            }
+        };
+
+        let update = match options.merge_schema {
+            true => added_columns(self, table.table()?.schema()),
+            false => SchemaUpdate::default(),
+        };
+        let (schema, _) = evolved_schema(table.table()?.metadata(), &update)?;
+        let rows = project(self, &iceberg_to_arrow_schema(&schema))?;
+        let config = table.writer_config()?.with_table_schema(&schema);
+        match &options.mode {
+            WriteMode::Append => {
+                let files = table.write(rows.execute_stream().await?, &config).await?;
+                commit_files(
+                    &mut table,
+                    Operation::Append,
+                    files,
+                    HashSet::new(),
+                    &update,
+                )
+                .await?;
+            }
+            WriteMode::Overwrite => {
+                let removed = live_files(table.table()?).await?;
+                let files = table.write(rows.execute_stream().await?, &config).await?;
+                commit_files(&mut table, Operation::Overwrite, files, removed, &update).await?;
+            }
+            WriteMode::Upsert(keys) => {
+                if keys.is_empty() {
+                    return Err(DataFusionError::Plan(
+                        "An upsert needs at least one key column.".to_string(),
+                    ));
+                }
+                let (current, reloaded) = current_snapshot(&table).await?;
+                table = reloaded;
+                let removed = live_files(table.table()?).await?;
+                // The rows are collected, because they are read twice, for the new files and for the keys of the join
+                let batches = rows.collect().await?;
+                let schema = match batches.first() {
+                    Some(batch) => batch.schema(),
+                    None => Arc::new(Schema::from(rows.schema().clone())),
+                };
+                let ctx = SessionContext::new();
+                let new_rows = ctx.read_table(Arc::new(MemTable::try_new(
+                    schema.clone(),
+                    vec![batches.clone()],
+                )?))?;
+                let existing = ctx.read_table(Arc::new(current))?;
+                let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+                let kept = new_rows.join(existing, JoinType::RightAnti, &keys, &keys, None)?;
+                let stream = MemoryStream::try_new(batches, schema, None)?;
+                let mut files = table.write(Box::pin(stream), &config).await?;
+                files.extend(table.write(kept.execute_stream().await?, &config).await?);
+                commit_files(&mut table, Operation::Overwrite, files, removed, &update).await?;
+            }
+        }
+        Ok(table)
+    }
+}
+
+/// Columns of the DataFrame for the fields of the schema, cast to the types of the fields. Optional fields that the
+/// DataFrame lacks are null.
+pub(crate) fn project(df: &DataFrame, schema: &Schema) -> Result<Arc<DataFrame>> {
+    if let Some(field) = df
+        .schema()
+        .fields()
+        .iter()
+        .find(|field| schema.field_with_name(field.name()).is_err())
+    {
+        return Err(DataFusionError::Plan(format!(
+            "The column {} is not part of the table.",
+            field.name()
+        )));
+    }
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let column = match df.schema().field_with_unqualified_name(field.name()) {
+                Ok(column) => Expr::Column(column.qualified_column()),
+                Err(_) if field.is_nullable() => lit(ScalarValue::try_from(field.data_type())?),
+                Err(_) => {
+                    return Err(DataFusionError::Plan(format!(
+                        "The column {} of the table is missing.",
+                        field.name()
+                    )))
+                }
+            };
+            // Data files don't carry the field ids of nested fields
+            Ok(cast(column, without_nested_field_ids(field.data_type())).alias(field.name()))
+        })
+        .collect::<Result<_>>()?;
+    df.select(columns)
+}
+
+/// Optional columns for the columns of the DataFrame that the schema lacks
+fn added_columns(df: &DataFrame, schema: &StructType) -> SchemaUpdate {
+    df.schema()
+        .fields()
+        .iter()
+        .filter(|field| !schema.fields.iter().any(|x| &x.name == field.name()))
+        .fold(SchemaUpdate::default(), |update, field| {
+            update.with_added_column(field.name(), field.data_type().clone())
+        })
+}
+
+/// Commit the files as a snapshot that removes the files. The files were written with the schema that the update
+/// evolves, the update is committed with the snapshot.
+async fn commit_files(
+    table: &mut DataFusionTable,
+    operation: Operation,
+    files: Vec<WrittenFile>,
+    removed: HashSet<String>,
+    update: &SchemaUpdate,
+) -> Result<()> {
+    let metadata = table.table()?.metadata();
+    let change = SnapshotChange::new(operation, metadata.default_spec_id(), files, removed)
+        .with_owned_files();
+    match update.is_empty() {
+        true => table.commit(&mut { change }).await,
+        false => {
+            let mut change = SchemaChange::new(update.clone())
+                .with_snapshot(metadata.current_schema_id(), change);
+            table.commit(&mut change).await
+        }
+    }
+}
+
+/// Copy of the table without its options and the table with the same options, both with the current metadata. The
+/// new snapshot is committed against the same metadata that the kept rows are read from. The copy is read without the
+/// options, like sampling or masking, which would change the rows that are written back.
+async fn current_snapshot(table: &DataFusionTable) -> Result<(DataFusionTable, DataFusionTable)> {
+    let retry = RetryPolicy::from_properties(table.table()?.metadata().properties())?;
+    let mut retries = 0;
+    loop {
+        let reloaded = table.load_current().await?;
+        let current =
+            DataFusionTable::from(reloaded.on_branch(load(reloaded.table()?).await?).await?);
+        if current.table()?.metadata_location() == reloaded.table()?.metadata_location() {
+            return Ok((current, reloaded));
+        }
+        if retries == retry.num_retries {
+            return Err(Error::CommitConflict(format!(
+                "Table {} was changed by another writer during {} attempts.",
+                reloaded.table()?.metadata_location(),
+                retries + 1
+            ))
+            .into());
+        }
+        tokio::time::sleep(retry.wait(retries)).await;
+        retries += 1;
+    }
+}
+
+/// Paths of the data and delete files of the current snapshot
+async fn live_files(table: &Table) -> Result<HashSet<String>> {
+    Ok(table
+        .files(None)
+        .await
+        .map_err(Error::iceberg)?
+        .into_iter()
+        .filter(|entry| !entry.is_deleted())
+        .map(|entry| entry.file_path().to_owned())
+        .collect())
+}
+
+/// Whether the location contains table metadata
+async fn exists(location: &str, object_store: &Arc<dyn ObjectStore>) -> Result<bool> {
+    let directory = Path::from(
+        util::strip_prefix(location)
+            .trim_end_matches('/')
+            .to_owned()
+            + "/metadata",
+    );
+    Ok(object_store
+        .list(Some(&directory))
+        .await?
+        .try_next()
+        .await?
+        .is_some())
+}
+
+pub(crate) async fn create_file_system_table(
+    location: &str,
+    object_store: Arc<dyn ObjectStore>,
+    schema: &Schema,
+) -> Result<DataFusionTable> {
+    let metadata = new_metadata(location, schema)?;
+    let json = serde_json::to_vec(&metadata).map_err(Error::iceberg)?;
+    let directory = util::strip_prefix(location)
+        .trim_end_matches('/')
+        .to_owned()
+        + "/metadata/";
+    // Like a commit, the metadata is created without overwriting the metadata of a table that was created concurrently
+    let temp = Path::from(directory.clone() + &Uuid::new_v4().to_string() + ".metadata.json.tmp");
+    object_store.put(&temp, json.into()).await?;
+    let result = object_store
+        .copy_if_not_exists(&temp, &Path::from(directory + "v0.metadata.json"))
+        .await;
+    let _ = object_store.delete(&temp).await;
+    match result {
+        Ok(()) | Err(object_store::Error::AlreadyExists { .. }) => (),
+        Err(err) => return Err(err.into()),
+    }
+    let table = Table::load_file_system_table(location, &object_store)
+        .await
+        .map_err(Error::iceberg)?;
+    Ok(DataFusionTable::from(table))
+}
+
+pub(crate) async fn create_catalog_table(
+    catalog: Arc<dyn Catalog>,
+    identifier: Identifier,
+    base_path: &str,
+    schema: &Schema,
+) -> Result<DataFusionTable> {
+    let location = format!(
+        "{}/{}",
+        base_path.trim_end_matches('/'),
+        identifier.to_string().replace('.', "/")
+    );
+    let metadata = new_metadata(&location, schema)?;
+    let json = serde_json::to_vec(&metadata).map_err(Error::iceberg)?;
+    let metadata_location = format!(
+        "{}/metadata/00000-{}.metadata.json",
+        location,
+        Uuid::new_v4()
+    );
+    catalog
+        .object_store()
+        .put(&object_path(&metadata_location), json.into())
+        .await?;
+    let relation = catalog
+        .register_table(identifier, &metadata_location)
+        .await
+        .map_err(Error::catalog)?;
+    Ok(DataFusionTable::from(relation))
+}
+
+/// Metadata of an unpartitioned table without snapshots. The fields of the schema get new ids, which the name mapping
+/// assigns to the columns of the data files.
+fn new_metadata(location: &str, schema: &Schema) -> Result<TableMetadata> {
+    let fields = arrow_to_new_iceberg_schema(schema)?;
+    if fields.fields.is_empty() {
+        return Err(DataFusionError::Plan(
+            "A table needs at least one column.".to_string(),
+        ));
+    }
+    let mapping = name_mapping(&fields);
+    Ok(TableMetadata::V2(TableMetadataV2 {
+        table_uuid: Uuid::new_v4(),
+        location: location.to_owned(),
+        last_sequence_number: 0,
+        last_updated_ms: now_ms(),
+        last_column_id: last_field_id(&fields),
+        schemas: vec![SchemaV2 {
+            schema_id: 0,
+            identifier_field_ids: None,
+            fields,
+        }],
+        current_schema_id: 0,
+        partition_specs: vec![PartitionSpec {
+            spec_id: 0,
+            fields: vec![],
+        }],
+        default_spec_id: 0,
+        // Partition field ids start at 1000
+        last_partition_id: 999,
+        properties: Some(HashMap::from([(DEFAULT_NAME_MAPPING.to_owned(), mapping)])),
+        current_snapshot_id: None,
+        snapshots: None,
+        snapshot_log: None,
+        metadata_log: None,
+        sort_orders: vec![SortOrder {
+            order_id: 0,
+            fields: vec![],
+        }],
+        default_sort_order_id: 0,
+        refs: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::arrow::array::{Float64Array, Int64Array};
+
+    use crate::{
+        commit::tests::row_count,
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    const TRIPS: &str = "/home/iceberg/warehouse/nyc/trips";
+
+    async fn query(ctx: &SessionContext, table: DataFusionTable, sql: &str) -> Vec<(i64, f64)> {
+        ctx.deregister_table("trips").unwrap();
+        ctx.register_table("trips", Arc::new(table)).unwrap();
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let trips = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap();
+                let fares = batch
+                    .column(1)
+                    .as_any()
+                    .downcast_ref::<Float64Array>()
+                    .unwrap();
+                (0..batch.num_rows()).map(|row| (trips.value(row), fares.value(row)))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    pub async fn test_write_iceberg() {
+        let object_store = taxis_copy(&[]).await;
+        let ctx = SessionContext::new();
+        let taxis = Table::load_file_system_table(TAXIS, &object_store)
+            .await
+            .unwrap();
+        ctx.register_table("nyc_taxis", Arc::new(DataFusionTable::from(taxis)))
+            .unwrap();
+        let target = || WriteTarget::FileSystem {
+            location: TRIPS.to_owned(),
+            object_store: object_store.clone(),
+        };
+        let df = ctx.sql("SELECT * FROM nyc_taxis").await.unwrap();
+
+        // The table is only created if the options allow it
+        let err = df
+            .write_iceberg(target(), &WriteOptions::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(Error::downcast(&err), Some(Error::NotFound(_))));
+        let options = WriteOptions::default().with_create_if_not_exists();
+        let table = df.write_iceberg(target(), &options).await.unwrap();
+        assert_eq!(row_count(&table).await, 4);
+        let schema = table.table().unwrap().schema();
+        assert_eq!(schema.fields.len(), 5);
+        assert_eq!(schema.fields[4].id, 5);
+        assert!(table.table().unwrap().metadata().default_spec().is_empty());
+        assert_eq!(
+            table.properties().unwrap()[DEFAULT_NAME_MAPPING],
+            name_mapping(schema)
+        );
+
+        let table = df.write_iceberg(target(), &options).await.unwrap();
+        assert_eq!(row_count(&table).await, 8);
+
+        let options = WriteOptions::default().with_mode(WriteMode::Overwrite);
+        let table = df.write_iceberg(target(), &options).await.unwrap();
+        assert_eq!(row_count(&table).await, 4);
+
+        // The trips of vendor 1 are added with new ids and the fares of the trips of vendor 2 are replaced
+        ctx.register_table("trips", Arc::new(table)).unwrap();
+        let upsert = ctx
+            .sql(
+                "SELECT vendor_id, trip_id + 100 AS trip_id, trip_distance, fare_amount, store_and_fwd_flag \
+                 FROM trips WHERE vendor_id = 1 \
+                 UNION ALL \
+                 SELECT vendor_id, trip_id, trip_distance, 0.0 AS fare_amount, store_and_fwd_flag \
+                 FROM trips WHERE vendor_id = 2",
+            )
+            .await
+            .unwrap();
+        let before = query(
+            &ctx,
+            DataFusionTable::from(
+                Table::load_file_system_table(TRIPS, &object_store)
+                    .await
+                    .unwrap(),
+            ),
+            "SELECT trip_id, fare_amount FROM trips ORDER BY trip_id",
+        )
+        .await;
+        let options =
+            WriteOptions::default().with_mode(WriteMode::Upsert(vec!["trip_id".to_owned()]));
+        let table = upsert.write_iceberg(target(), &options).await.unwrap();
+        let after = query(
+            &ctx,
+            table,
+            "SELECT trip_id, fare_amount FROM trips ORDER BY trip_id",
+        )
+        .await;
+        let vendor_1: usize = ctx
+            .sql("SELECT * FROM nyc_taxis WHERE vendor_id = 1")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap()
+            .iter()
+            .map(|batch| batch.num_rows())
+            .sum();
+        assert_eq!(after.len(), before.len() + vendor_1);
+        assert_eq!(
+            after.iter().filter(|(_, fare)| *fare == 0.0).count(),
+            before.len() - vendor_1
+        );
+        assert!(after
+            .iter()
+            .filter(|(trip, _)| !before.iter().any(|(id, _)| id == trip))
+            .all(|(trip, fare)| before.contains(&(trip - 100, *fare))));
+
+        let err = upsert
+            .write_iceberg(
+                target(),
+                &WriteOptions::default().with_mode(WriteMode::Upsert(vec![])),
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, DataFusionError::Plan(_)));
+    }
+
+    #[tokio::test]
+    pub async fn test_merge_schema() {
+        let object_store = taxis_copy(&[]).await;
+        let ctx = SessionContext::new();
+        let taxis = Table::load_file_system_table(TAXIS, &object_store)
+            .await
+            .unwrap();
+        ctx.register_table("nyc_taxis", Arc::new(DataFusionTable::from(taxis)))
+            .unwrap();
+        let target = || WriteTarget::FileSystem {
+            location: TRIPS.to_owned(),
+            object_store: object_store.clone(),
+        };
+        ctx.sql("SELECT * FROM nyc_taxis")
+            .await
+            .unwrap()
+            .write_iceberg(
+                target(),
+                &WriteOptions::default().with_create_if_not_exists(),
+            )
+            .await
+            .unwrap();
+        let tips = ctx
+            .sql("SELECT trip_id, fare_amount * 0.1 AS tip_amount FROM nyc_taxis")
+            .await
+            .unwrap();
+
+        // Without merging the schema the new column is rejected
+        assert!(tips
+            .write_iceberg(target(), &WriteOptions::default())
+            .await
+            .is_err());
+        let table = tips
+            .write_iceberg(target(), &WriteOptions::default().with_merge_schema())
+            .await
+            .unwrap();
+        let metadata = table.table().unwrap().metadata();
+        let schema = metadata.current_schema();
+        assert_eq!(schema.fields.len(), 6);
+        assert_eq!(schema.fields[5].name, "tip_amount");
+        assert_eq!(schema.fields[5].id, 6);
+        assert!(!schema.fields[5].required);
+        assert_eq!(metadata.current_schema_id(), 1);
+        assert_eq!(
+            table.properties().unwrap()[DEFAULT_NAME_MAPPING],
+            name_mapping(schema)
+        );
+        // The snapshot of the rows reads the new schema
+        match metadata {
+            TableMetadata::V2(metadata) => assert_eq!(
+                metadata
+                    .snapshots
+                    .as_ref()
+                    .unwrap()
+                    .last()
+                    .unwrap()
+                    .schema_id,
+                Some(1)
+            ),
+            TableMetadata::V1(_) => panic!("New tables have format version 2."),
+        }
+
+        // The rows that were written before the column was added have no tips
+        ctx.register_table("trips", Arc::new(table)).unwrap();
+        let batches = ctx
+            .sql("SELECT count(tip_amount), count(*) FROM trips")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let count = |column: usize| {
+            batches[0]
+                .column(column)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap()
+                .value(0)
+        };
+        assert_eq!((count(0), count(1)), (4, 8));
+    }
+
+    #[tokio::test]
+    pub async fn test_upsert_stale_table() {
+        let object_store = taxis_copy(&[]).await;
+        let ctx = SessionContext::new();
+        let load = || async {
+            DataFusionTable::from(
+                Table::load_file_system_table(TAXIS, &object_store)
+                    .await
+                    .unwrap(),
+            )
+        };
+        ctx.register_table("nyc_taxis", Arc::new(load().await))
+            .unwrap();
+        let stale = load().await;
+
+        // Another writer appends the rows again after the table was loaded
+        let df = ctx.sql("SELECT * FROM nyc_taxis").await.unwrap();
+        let options = WriteOptions::default();
+        df.write_iceberg(WriteTarget::Table(Box::new(load().await)), &options)
+            .await
+            .unwrap();
+
+        let upsert = ctx
+            .sql(
+                "SELECT vendor_id, trip_id, trip_distance, 0.0 AS fare_amount, store_and_fwd_flag \
+                 FROM nyc_taxis WHERE vendor_id = 2",
+            )
+            .await
+            .unwrap();
+        let vendor_2: usize = upsert
+            .collect()
+            .await
+            .unwrap()
+            .iter()
+            .map(|batch| batch.num_rows())
+            .sum();
+        let options =
+            WriteOptions::default().with_mode(WriteMode::Upsert(vec!["trip_id".to_owned()]));
+        let table = upsert
+            .write_iceberg(WriteTarget::Table(Box::new(stale)), &options)
+            .await
+            .unwrap();
+        assert_eq!(row_count(&table).await, 8 - vendor_2);
+    }
+}
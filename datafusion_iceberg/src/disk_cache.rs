@@ -0,0 +1,183 @@
+/*!
+ * Object store that keeps the table metadata in a local directory
+ *
+ * Short-lived processes read the same metadata files, manifest lists and manifests on every start. Iceberg never
+ * overwrites these files, every change writes new files with unique names, so a copy of a file can't become stale.
+ * The caching object store therefore keys the copies only by their path and serves them without any request to the
+ * remote object store. Data files and all other operations are forwarded to the remote object store.
+*/
+
+use std::{fs, ops::Range, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use object_store::{
+    local::LocalFileSystem, path::Path, GetResult, ListResult, MultipartId, ObjectMeta,
+    ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+/// Object store that caches the metadata files of iceberg tables on the local disk
+#[derive(Debug)]
+pub struct CachingObjectStore {
+    remote: Arc<dyn ObjectStore>,
+    cache: LocalFileSystem,
+}
+
+impl CachingObjectStore {
+    /// Cache the metadata files in the directory, which is created if it doesn't exist
+    pub fn new(remote: Arc<dyn ObjectStore>, directory: &str) -> Result<Self> {
+        fs::create_dir_all(directory).map_err(|err| object_store::Error::Generic {
+            store: "CachingObjectStore",
+            source: Box::new(err),
+        })?;
+        Ok(CachingObjectStore {
+            remote,
+            cache: LocalFileSystem::new_with_prefix(directory)?,
+        })
+    }
+    /// Read the file from the cache. If it isn't cached yet it is read from the remote object store and stored in the
+    /// cache.
+    async fn cached(&self, location: &Path) -> Result<Bytes> {
+        match self.cache.get(location).await {
+            Ok(result) => result.bytes().await,
+            Err(object_store::Error::NotFound { .. }) => {
+                let bytes = self.remote.get(location).await?.bytes().await?;
+                self.cache.put(location, bytes.clone()).await?;
+                Ok(bytes)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Metadata files, manifest lists and manifests are never modified after they are written
+fn is_immutable(location: &Path) -> bool {
+    let location = location.as_ref();
+    location.contains("metadata/")
+        && (location.ends_with(".metadata.json") || location.ends_with(".avro"))
+}
+
+fn slice(bytes: &Bytes, range: &Range<usize>) -> Bytes {
+    bytes.slice(range.start.min(bytes.len())..range.end.min(bytes.len()))
+}
+
+impl std::fmt::Display for CachingObjectStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Caching({}, {})", self.remote, self.cache)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for CachingObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.remote.put(location, bytes).await
+    }
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.remote.put_multipart(location).await
+    }
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.remote.abort_multipart(location, multipart_id).await
+    }
+    async fn get(&self, location: &Path) -> Result<GetResult> {
+        if is_immutable(location) {
+            let bytes = self.cached(location).await?;
+            Ok(GetResult::Stream(
+                futures::stream::once(async move { Ok(bytes) }).boxed(),
+            ))
+        } else {
+            self.remote.get(location).await
+        }
+    }
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        if is_immutable(location) {
+            let bytes = self.cached(location).await?;
+            Ok(slice(&bytes, &range))
+        } else {
+            self.remote.get_range(location, range).await
+        }
+    }
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        if is_immutable(location) {
+            let bytes = self.cached(location).await?;
+            Ok(ranges.iter().map(|range| slice(&bytes, range)).collect())
+        } else {
+            self.remote.get_ranges(location, ranges).await
+        }
+    }
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        if is_immutable(location) {
+            self.cached(location).await?;
+            self.cache.head(location).await
+        } else {
+            self.remote.head(location).await
+        }
+    }
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.remote.delete(location).await
+    }
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.remote.list(prefix).await
+    }
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.remote.list_with_delimiter(prefix).await
+    }
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.remote.copy(from, to).await
+    }
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.remote.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use object_store::memory::InMemory;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_cache_metadata_files() {
+        let remote: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+
+        let metadata = Path::from("table/metadata/v1.metadata.json");
+        let data = Path::from("table/data/file.parquet");
+        remote
+            .put(&metadata, Bytes::from_static(b"metadata"))
+            .await
+            .unwrap();
+        remote
+            .put(&data, Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        let store = CachingObjectStore::new(remote.clone(), directory.to_str().unwrap()).unwrap();
+        assert_eq!(
+            store.get(&metadata).await.unwrap().bytes().await.unwrap(),
+            Bytes::from_static(b"metadata")
+        );
+        assert_eq!(
+            store.get(&data).await.unwrap().bytes().await.unwrap(),
+            Bytes::from_static(b"data")
+        );
+
+        // A new process reads the metadata from the cache directory
+        remote.delete(&metadata).await.unwrap();
+        remote.delete(&data).await.unwrap();
+        let store = CachingObjectStore::new(remote, directory.to_str().unwrap()).unwrap();
+        assert_eq!(
+            store.get_range(&metadata, 0..4).await.unwrap(),
+            Bytes::from_static(b"meta")
+        );
+        assert!(store.get(&data).await.is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+/*!
+ * Errors of iceberg tables and catalogs
+ *
+ * The errors are returned as external DataFusionErrors, so that they can pass through the datafusion traits. The
+ * source of the error is kept, callers can downcast it to tell a missing table from a failed catalog request or a
+ * failed read of the table metadata.
+*/
+
+use std::fmt::Display;
+
+use datafusion::error::DataFusionError;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The table or namespace doesn't exist
+    NotFound(String),
+    /// The request to the catalog failed
+    Catalog(BoxError),
+    /// The table metadata or the manifests couldn't be read
+    Iceberg(BoxError),
+    /// The data file has a format that can't be read
+    UnsupportedFormat(String),
+    /// The table was changed by other writers while the change was committed
+    CommitConflict(String),
+}
+
+impl Error {
+    pub fn catalog(err: impl Into<BoxError>) -> Self {
+        Error::Catalog(err.into())
+    }
+    pub fn iceberg(err: impl Into<BoxError>) -> Self {
+        Error::Iceberg(err.into())
+    }
+    /// The iceberg error of the datafusion error, if it is one
+    pub fn downcast(err: &DataFusionError) -> Option<&Error> {
+        match err {
+            DataFusionError::External(err) => err.downcast_ref::<Error>(),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound(message) => write!(f, "{}", message),
+            Error::Catalog(err) => write!(f, "Catalog request failed: {}", err),
+            Error::Iceberg(err) => write!(f, "Failed to read table metadata: {}", err),
+            Error::UnsupportedFormat(message) => write!(f, "{}", message),
+            Error::CommitConflict(message) => write!(f, "Commit failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Catalog(err) | Error::Iceberg(err) => Some(err.as_ref()),
+            Error::NotFound(_) | Error::UnsupportedFormat(_) | Error::CommitConflict(_) => None,
+        }
+    }
+}
+
+impl From<Error> for DataFusionError {
+    fn from(err: Error) -> Self {
+        DataFusionError::External(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use crate::DataFusionTable;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_downcast_error() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let err = DataFusionTable::from_metadata_location(
+            "/home/iceberg/warehouse/nyc/missing/metadata/v1.metadata.json",
+            &object_store,
+        )
+        .await
+        .err()
+        .expect("Table doesn't exist");
+
+        assert!(matches!(Error::downcast(&err), Some(Error::Iceberg(_))));
+    }
+}
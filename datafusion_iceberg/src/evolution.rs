@@ -0,0 +1,607 @@
+/*!
+ * Schema evolution of tables
+ *
+ * A schema update is committed as a new schema of the table, which becomes the current schema. New columns are optional
+ * and get field ids after the largest id that the table ever assigned, so they never reuse the id of an earlier column.
+ * Data files that were written before a column was added don't contain it, the column is null for their rows. Dropped
+ * columns keep their data in the files, renamed columns keep their field id and widened columns are cast when the files
+ * are read. The name mapping of the table, if it has one, maps the new fields and keeps the previous names.
+ *
+ * The fields of struct columns are evolved like columns. They are named by the name of their struct and their own name,
+ * separated by a dot, like `pickup.latitude`. Fields can be added to structs, dropped, renamed, widened, made optional
+ * and moved among the fields of their struct, which is how columns are reordered as well.
+ *
+ * Writes can evolve the schema as part of their commit. Their data files are written with the new schema and the schema
+ * is committed together with the snapshot of the files. Such a commit fails if another writer changed the schema in the
+ * meantime, because the files carry the field ids of the schema they were written with.
+*/
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use datafusion::{
+    arrow::datatypes::{DataType, Field},
+    common::DataFusionError,
+    error::Result,
+};
+use iceberg_rs::{
+    model::{
+        data_types::{PrimitiveType, StructField, StructType, Type},
+        schema::{SchemaV1, SchemaV2},
+        table_metadata::TableMetadata,
+    },
+    table::Table,
+};
+use object_store::ObjectStore;
+
+use crate::{
+    commit::{clone_metadata, Change, SnapshotChange},
+    error::Error,
+    metadata::TableMetadataExt,
+    properties::DEFAULT_NAME_MAPPING,
+    schema::{new_iceberg_field, updated_name_mapping},
+    DataFusionTable,
+};
+
+#[derive(Debug, Clone)]
+enum SchemaOperation {
+    /// Add the optional column at the end of the struct column with the name, or at the end of the schema
+    Add(Option<String>, Field),
+    /// Drop the column, its field id is never assigned again
+    Drop(String),
+    /// Rename the column, the field keeps its id
+    Rename(String, String),
+    /// Widen the type of the column
+    Type(String, DataType),
+    /// Make the required column optional
+    Optional(String),
+    /// Move the column to the position among the fields of its struct
+    Move(String, ColumnPosition),
+}
+
+/// Position of a moved column among the columns of the schema or the fields of its struct
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnPosition {
+    /// The first column
+    First,
+    /// Before the column with the name, which has to be in the same struct
+    Before(String),
+    /// After the column with the name, which has to be in the same struct
+    After(String),
+}
+
+/// Change of the schema of a table, which is applied to the current schema when it is committed
+#[derive(Debug, Clone, Default)]
+pub struct SchemaUpdate {
+    operations: Vec<SchemaOperation>,
+}
+
+impl SchemaUpdate {
+    /// Add an optional column of the type at the end of the schema. The fields of struct, list and map types get new
+    /// ids as well.
+    pub fn with_added_column(mut self, name: &str, data_type: DataType) -> Self {
+        self.operations.push(SchemaOperation::Add(
+            None,
+            Field::new(name, data_type, true),
+        ));
+        self
+    }
+    /// Add an optional field of the type at the end of the struct column
+    pub fn with_added_nested_column(
+        mut self,
+        parent: &str,
+        name: &str,
+        data_type: DataType,
+    ) -> Self {
+        self.operations.push(SchemaOperation::Add(
+            Some(parent.to_owned()),
+            Field::new(name, data_type, true),
+        ));
+        self
+    }
+    /// Drop the column. Columns that the table is partitioned by and identifier columns can't be dropped.
+    pub fn with_dropped_column(mut self, name: &str) -> Self {
+        self.operations.push(SchemaOperation::Drop(name.to_owned()));
+        self
+    }
+    /// Rename the column. The new name of a field of a struct is its name inside the struct. Data files that were
+    /// written with the old name are read with the new name.
+    pub fn with_renamed_column(mut self, name: &str, new_name: &str) -> Self {
+        self.operations.push(SchemaOperation::Rename(
+            name.to_owned(),
+            new_name.to_owned(),
+        ));
+        self
+    }
+    /// Widen the type of the column. Int columns can be widened to long, float columns to double and decimal columns
+    /// to a larger precision with the same scale.
+    pub fn with_column_type(mut self, name: &str, data_type: DataType) -> Self {
+        self.operations
+            .push(SchemaOperation::Type(name.to_owned(), data_type));
+        self
+    }
+    /// Make the required column optional. Optional columns can't be made required, because the data files may contain
+    /// nulls.
+    pub fn with_optional_column(mut self, name: &str) -> Self {
+        self.operations
+            .push(SchemaOperation::Optional(name.to_owned()));
+        self
+    }
+    /// Move the column among the columns of the schema or the fields of its struct
+    pub fn with_moved_column(mut self, name: &str, position: ColumnPosition) -> Self {
+        self.operations
+            .push(SchemaOperation::Move(name.to_owned(), position));
+        self
+    }
+    /// Whether the update leaves the schema unchanged
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl DataFusionTable {
+    /// Commit the schema update as the new current schema of the table
+    pub async fn update_schema(&mut self, update: &SchemaUpdate) -> Result<()> {
+        if update.is_empty() {
+            return Ok(());
+        }
+        let mut change = SchemaChange::new(update.clone());
+        self.commit(&mut change).await
+    }
+}
+
+/// Change of the schema, optionally committed together with a snapshot whose files were written with the new schema
+pub(crate) struct SchemaChange {
+    update: SchemaUpdate,
+    // Snapshot of the files and the id of the schema that the update was applied to when the files were written
+    snapshot: Option<(i32, SnapshotChange)>,
+}
+
+impl SchemaChange {
+    pub(crate) fn new(update: SchemaUpdate) -> Self {
+        SchemaChange {
+            update,
+            snapshot: None,
+        }
+    }
+    /// Commit the snapshot together with the schema update. Its files were written with the schema that the update
+    /// evolves from the schema with the base schema id.
+    pub(crate) fn with_snapshot(mut self, base_schema_id: i32, snapshot: SnapshotChange) -> Self {
+        self.snapshot = Some((base_schema_id, snapshot));
+        self
+    }
+}
+
+#[async_trait]
+impl Change for SchemaChange {
+    async fn apply(&mut self, table: &Table) -> Result<TableMetadata> {
+        let metadata = match &mut self.snapshot {
+            Some((base_schema_id, snapshot)) => {
+                let current_schema_id = table.metadata().current_schema_id();
+                if current_schema_id != *base_schema_id {
+                    return Err(Error::CommitConflict(format!(
+                        "The files were written with an update of schema {}, but the current schema of the table is {}.",
+                        base_schema_id, current_schema_id
+                    ))
+                    .into());
+                }
+                snapshot.apply(table).await?
+            }
+            None => clone_metadata(table.metadata())?,
+        };
+        evolve(metadata, &self.update, self.snapshot.is_some())
+    }
+    async fn discard_attempt(&mut self, object_store: &Arc<dyn ObjectStore>) {
+        if let Some((_, snapshot)) = &mut self.snapshot {
+            snapshot.discard_attempt(object_store).await
+        }
+    }
+    async fn discard(&mut self, object_store: &Arc<dyn ObjectStore>) {
+        if let Some((_, snapshot)) = &mut self.snapshot {
+            snapshot.discard(object_store).await
+        }
+    }
+}
+
+/// Schema that the update evolves from the current schema of the table, with the largest field id of the table after
+/// the update
+pub(crate) fn evolved_schema(
+    metadata: &TableMetadata,
+    update: &SchemaUpdate,
+) -> Result<(StructType, i32)> {
+    let mut schema = clone_schema(metadata.current_schema())?;
+    let mut next_id = last_column_id(metadata) + 1;
+    for operation in &update.operations {
+        match operation {
+            SchemaOperation::Add(parent, field) => {
+                let fields = match parent {
+                    Some(parent) => {
+                        let (mut path, position) = column_path(&schema.fields, parent)?;
+                        path.push(position);
+                        struct_fields(&mut schema.fields, &path, parent)?
+                    }
+                    None => &mut schema.fields,
+                };
+                if fields.iter().any(|x| &x.name == field.name()) {
+                    return Err(DataFusionError::Plan(format!(
+                        "Column {} exists already.",
+                        match parent {
+                            Some(parent) => parent.clone() + "." + field.name(),
+                            None => field.name().clone(),
+                        }
+                    )));
+                }
+                fields.push(new_iceberg_field(field, &mut next_id)?);
+            }
+            SchemaOperation::Drop(name) => {
+                let (parent, position) = column_path(&schema.fields, name)?;
+                let fields = struct_fields(&mut schema.fields, &parent, name)?;
+                let mut ids = Vec::new();
+                field_ids(fields[position].id, &fields[position].field_type, &mut ids);
+                if metadata
+                    .default_spec()
+                    .iter()
+                    .any(|field| ids.contains(&field.source_id))
+                {
+                    return Err(DataFusionError::Plan(format!(
+                        "Column {} can't be dropped, the table is partitioned by it.",
+                        name
+                    )));
+                }
+                if metadata
+                    .identifier_field_ids()
+                    .is_some_and(|identifiers| ids.iter().any(|id| identifiers.contains(id)))
+                {
+                    return Err(DataFusionError::Plan(format!(
+                        "Column {} can't be dropped, it is an identifier column.",
+                        name
+                    )));
+                }
+                // Structs without fields can't be written to parquet files
+                if !parent.is_empty() && fields.len() == 1 {
+                    return Err(DataFusionError::Plan(format!(
+                        "Column {} can't be dropped, it is the only field of its struct.",
+                        name
+                    )));
+                }
+                fields.remove(position);
+            }
+            SchemaOperation::Rename(name, new_name) => {
+                let (parent, position) = column_path(&schema.fields, name)?;
+                let fields = struct_fields(&mut schema.fields, &parent, name)?;
+                if fields.iter().any(|x| &x.name == new_name) {
+                    return Err(DataFusionError::Plan(format!(
+                        "Column {} exists already.",
+                        new_name
+                    )));
+                }
+                fields[position].name = new_name.clone();
+            }
+            SchemaOperation::Type(name, data_type) => {
+                let (parent, position) = column_path(&schema.fields, name)?;
+                let field = &mut struct_fields(&mut schema.fields, &parent, name)?[position];
+                let widened =
+                    new_iceberg_field(&Field::new(name, data_type.clone(), true), &mut 0)?
+                        .field_type;
+                if !is_widening(&field.field_type, &widened) {
+                    return Err(DataFusionError::Plan(format!(
+                        "The type of column {} can't be changed from {} to {}.",
+                        name, field.field_type, widened
+                    )));
+                }
+                field.field_type = widened;
+            }
+            SchemaOperation::Optional(name) => {
+                let (parent, position) = column_path(&schema.fields, name)?;
+                struct_fields(&mut schema.fields, &parent, name)?[position].required = false;
+            }
+            SchemaOperation::Move(name, column_position) => {
+                let (parent, position) = column_path(&schema.fields, name)?;
+                let next_to = match column_position {
+                    ColumnPosition::First => None,
+                    ColumnPosition::Before(other) | ColumnPosition::After(other) => {
+                        let (other_parent, other_position) = column_path(&schema.fields, other)?;
+                        if other_parent != parent || other_position == position {
+                            return Err(DataFusionError::Plan(format!(
+                                "Column {} can't be moved next to {}, which isn't another field of its struct.",
+                                name, other
+                            )));
+                        }
+                        Some(other_position)
+                    }
+                };
+                let fields = struct_fields(&mut schema.fields, &parent, name)?;
+                let other_id = next_to.map(|other| fields[other].id);
+                let field = fields.remove(position);
+                let target = match (column_position, other_id) {
+                    (ColumnPosition::After(_), Some(id)) => {
+                        fields.iter().position(|x| x.id == id).map(|x| x + 1)
+                    }
+                    (_, Some(id)) => fields.iter().position(|x| x.id == id),
+                    (_, None) => Some(0),
+                };
+                fields.insert(target.unwrap_or_default(), field);
+            }
+        }
+    }
+    Ok((schema, next_id - 1))
+}
+
+/// Positions of the structs that contain the column and the position of the column in the innermost struct. Fields of
+/// struct columns are named by the name of their struct, a dot and their own name.
+fn column_path(fields: &[StructField], name: &str) -> Result<(Vec<usize>, usize)> {
+    nested_path(fields, name)
+        .ok_or_else(|| DataFusionError::Plan(format!("Column {} doesn't exist.", name)))
+}
+
+/// Whether the schema has the column, which can be a field of a struct column
+pub(crate) fn has_column(schema: &StructType, name: &str) -> bool {
+    nested_path(&schema.fields, name).is_some()
+}
+
+fn nested_path(fields: &[StructField], name: &str) -> Option<(Vec<usize>, usize)> {
+    if let Some(position) = fields.iter().position(|x| x.name == name) {
+        return Some((Vec::new(), position));
+    }
+    fields.iter().enumerate().find_map(|(index, field)| {
+        let rest = name.strip_prefix(&field.name)?.strip_prefix('.')?;
+        match &field.field_type {
+            Type::Struct(nested) => {
+                let (mut path, position) = nested_path(&nested.fields, rest)?;
+                path.insert(0, index);
+                Some((path, position))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Fields of the struct at the path, which are the columns of the schema for an empty path
+fn struct_fields<'a>(
+    fields: &'a mut Vec<StructField>,
+    path: &[usize],
+    name: &str,
+) -> Result<&'a mut Vec<StructField>> {
+    match path.split_first() {
+        Some((index, rest)) => match &mut fields[*index].field_type {
+            Type::Struct(nested) => struct_fields(&mut nested.fields, rest, name),
+            _ => Err(DataFusionError::Plan(format!(
+                "Column {} is not a struct.",
+                name
+            ))),
+        },
+        None => Ok(fields),
+    }
+}
+
+/// Ids of the field and of its nested fields
+fn field_ids(id: i32, field_type: &Type, ids: &mut Vec<i32>) {
+    ids.push(id);
+    match field_type {
+        Type::Primitive(_) => (),
+        Type::Struct(nested) => {
+            for field in &nested.fields {
+                field_ids(field.id, &field.field_type, ids);
+            }
+        }
+        Type::List(list) => field_ids(list.element_id, &list.element, ids),
+        Type::Map(map) => {
+            field_ids(map.key_id, &map.key, ids);
+            field_ids(map.value_id, &map.value, ids);
+        }
+    }
+}
+
+/// Whether the values of the previous type can be read as values of the new type
+fn is_widening(previous: &Type, new: &Type) -> bool {
+    match (previous, new) {
+        (Type::Primitive(previous), Type::Primitive(new)) => match (previous, new) {
+            (PrimitiveType::Int, PrimitiveType::Long)
+            | (PrimitiveType::Float, PrimitiveType::Double) => true,
+            (
+                PrimitiveType::Decimal { precision, scale },
+                PrimitiveType::Decimal {
+                    precision: new_precision,
+                    scale: new_scale,
+                },
+            ) => new_precision >= precision && new_scale == scale,
+            (previous, new) => previous == new,
+        },
+        _ => false,
+    }
+}
+
+/// Metadata with the evolved schema as current schema. A snapshot that was added with the update reads the new schema.
+fn evolve(
+    mut metadata: TableMetadata,
+    update: &SchemaUpdate,
+    with_snapshot: bool,
+) -> Result<TableMetadata> {
+    let (schema, last_column_id) = evolved_schema(&metadata, update)?;
+    let mapping = match metadata
+        .properties()
+        .and_then(|properties| properties.get(DEFAULT_NAME_MAPPING))
+    {
+        Some(previous) => Some(updated_name_mapping(previous, &schema)?),
+        None => None,
+    };
+    let identifier_field_ids = metadata.identifier_field_ids().map(<[i32]>::to_vec);
+    match &mut metadata {
+        TableMetadata::V1(metadata) => {
+            let schema_id = metadata
+                .schemas
+                .iter()
+                .flatten()
+                .filter_map(|schema| schema.schema_id)
+                .chain(metadata.schema.schema_id)
+                .max()
+                .unwrap_or_default()
+                + 1;
+            let mut previous = std::mem::replace(
+                &mut metadata.schema,
+                SchemaV1 {
+                    schema_id: Some(schema_id),
+                    identifier_field_ids,
+                    fields: schema,
+                },
+            );
+            // Metadata of format version 1 may only have the current schema, which is schema 0 without an id
+            previous.schema_id.get_or_insert(0);
+            let schemas = metadata.schemas.get_or_insert_with(Vec::new);
+            if !schemas
+                .iter()
+                .any(|schema| schema.schema_id == previous.schema_id)
+            {
+                schemas.push(previous);
+            }
+            schemas.push(clone_v1(&metadata.schema)?);
+            metadata.current_schema_id = Some(schema_id);
+            metadata.last_column_id = last_column_id;
+            if let Some(mapping) = mapping {
+                metadata
+                    .properties
+                    .get_or_insert_with(Default::default)
+                    .insert(DEFAULT_NAME_MAPPING.to_owned(), mapping);
+            }
+            if with_snapshot {
+                let current = metadata.current_snapshot_id;
+                if let Some(snapshot) = metadata
+                    .snapshots
+                    .iter_mut()
+                    .flatten()
+                    .find(|snapshot| Some(snapshot.snapshot_id) == current)
+                {
+                    snapshot.schema_id = Some(schema_id as i64);
+                }
+            }
+        }
+        TableMetadata::V2(metadata) => {
+            let schema_id = metadata
+                .schemas
+                .iter()
+                .map(|schema| schema.schema_id)
+                .max()
+                .unwrap_or_default()
+                + 1;
+            metadata.schemas.push(SchemaV2 {
+                schema_id,
+                identifier_field_ids,
+                fields: schema,
+            });
+            metadata.current_schema_id = schema_id;
+            metadata.last_column_id = last_column_id;
+            if let Some(mapping) = mapping {
+                metadata
+                    .properties
+                    .get_or_insert_with(Default::default)
+                    .insert(DEFAULT_NAME_MAPPING.to_owned(), mapping);
+            }
+            if with_snapshot {
+                let current = metadata.current_snapshot_id;
+                if let Some(snapshot) = metadata
+                    .snapshots
+                    .iter_mut()
+                    .flatten()
+                    .find(|snapshot| Some(snapshot.snapshot_id) == current)
+                {
+                    snapshot.schema_id = Some(schema_id as i64);
+                }
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+// The largest field id that the table assigned, which can belong to a column that was dropped since
+fn last_column_id(metadata: &TableMetadata) -> i32 {
+    match metadata {
+        TableMetadata::V1(metadata) => metadata.last_column_id,
+        TableMetadata::V2(metadata) => metadata.last_column_id,
+    }
+}
+
+fn clone_schema(schema: &StructType) -> Result<StructType> {
+    serde_json::to_value(schema)
+        .and_then(serde_json::from_value)
+        .map_err(|err| Error::iceberg(err).into())
+}
+
+fn clone_v1(schema: &SchemaV1) -> Result<SchemaV1> {
+    serde_json::to_value(schema)
+        .and_then(serde_json::from_value)
+        .map_err(|err| Error::iceberg(err).into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::testing::{taxis_copy, TAXIS};
+    use datafusion::{
+        arrow::{array::Int64Array, datatypes::Field},
+        prelude::SessionContext,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_update_schema() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        let update = SchemaUpdate::default()
+            .with_added_column("tip_amount", DataType::Float64)
+            .with_added_column(
+                "pickup",
+                DataType::Struct(vec![
+                    Field::new("latitude", DataType::Float64, false),
+                    Field::new("longitude", DataType::Float64, false),
+                ]),
+            );
+        table.update_schema(&update).await.unwrap();
+
+        let metadata = table.table().unwrap().metadata();
+        let schema = metadata.current_schema();
+        assert_eq!(metadata.current_schema_id(), 1);
+        assert_eq!(last_column_id(metadata), 9);
+        assert_eq!(schema.fields.len(), 7);
+        assert_eq!((schema.fields[5].id, schema.fields[6].id), (6, 7));
+        assert!(!schema.fields[6].required);
+        match &schema.fields[6].field_type {
+            Type::Struct(pickup) => {
+                assert_eq!(pickup.fields[0].id, 8);
+                assert!(pickup.fields[1].required);
+            }
+            field_type => panic!("Expected a struct, found {}.", field_type),
+        }
+        // The first schema is kept
+        match metadata {
+            TableMetadata::V1(metadata) => assert_eq!(metadata.schemas.as_ref().unwrap().len(), 2),
+            TableMetadata::V2(_) => panic!("The taxis table has format version 1."),
+        }
+        // Existing columns can't be added again
+        let update = SchemaUpdate::default().with_added_column("trip_id", DataType::Int64);
+        assert!(table.update_schema(&update).await.is_err());
+
+        // The files of the table don't contain the new columns
+        let ctx = SessionContext::new();
+        let reloaded = table.load_current().await.unwrap();
+        ctx.register_table("nyc_taxis", Arc::new(reloaded)).unwrap();
+        let batches = ctx
+            .sql("SELECT count(*) FROM nyc_taxis WHERE tip_amount IS NULL")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let count = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(count, 4);
+    }
+}
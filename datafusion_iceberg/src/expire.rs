@@ -0,0 +1,467 @@
+/*!
+ * Expiration of old snapshots
+ *
+ * Every commit adds a snapshot that keeps its manifest list, manifests and data files alive. Expiring removes the
+ * snapshots that are older than a point in time from the table metadata, except for the current snapshot, the snapshots
+ * referenced by branches and tags and the most recent ancestors of the current snapshot. Once the metadata is
+ * committed, the manifest lists, manifests and data files that only the expired snapshots reach are deleted.
+*/
+
+use std::{collections::HashSet, io::Cursor, sync::Arc, time::Duration};
+
+use apache_avro::Reader as AvroReader;
+use datafusion::{common::DataFusionError, error::Result};
+use iceberg_rs::{
+    model::{
+        manifest::{ManifestEntryV1, ManifestEntryV2, Status},
+        manifest_list::{ManifestFileV1, ManifestFileV2},
+        table_metadata::{FormatVersion, TableMetadata},
+    },
+    table::Table,
+    util,
+};
+use object_store::{path::Path, ObjectStore};
+
+use crate::{
+    commit::{clone_metadata, now_ms, Change},
+    error::Error,
+    metadata::TableMetadataExt,
+    purge::{delete_files, BulkDelete},
+    DataFusionTable,
+};
+
+/// Table property for the age after which snapshots are expired
+pub const HISTORY_EXPIRE_MAX_SNAPSHOT_AGE_MS: &str = "history.expire.max-snapshot-age-ms";
+/// Table property for the number of ancestors of the current snapshot that are never expired
+pub const HISTORY_EXPIRE_MIN_SNAPSHOTS_TO_KEEP: &str = "history.expire.min-snapshots-to-keep";
+
+const DEFAULT_MAX_SNAPSHOT_AGE: Duration = Duration::from_secs(5 * 24 * 60 * 60);
+const DEFAULT_MIN_SNAPSHOTS_TO_KEEP: usize = 1;
+
+/// Options for the expiration of snapshots
+#[derive(Debug, Clone, Default)]
+pub struct ExpireOptions {
+    older_than: Option<i64>,
+    retain_last: Option<usize>,
+    delete: BulkDelete,
+}
+
+impl ExpireOptions {
+    /// Expire the snapshots that were committed before the timestamp in milliseconds. Defaults to the maximum snapshot
+    /// age of the table properties.
+    pub fn with_older_than(mut self, timestamp_ms: i64) -> Self {
+        self.older_than = Some(timestamp_ms);
+        self
+    }
+    /// Number of ancestors of the current snapshot, including the current snapshot, that are kept regardless of their
+    /// age. Defaults to the minimum number of snapshots to keep of the table properties.
+    pub fn with_retain_last(mut self, retain_last: usize) -> Self {
+        self.retain_last = Some(retain_last.max(1));
+        self
+    }
+    /// Options for the deletion of the files of the expired snapshots
+    pub fn with_bulk_delete(mut self, delete: BulkDelete) -> Self {
+        self.delete = delete;
+        self
+    }
+}
+
+/// Outcome of the expiration of snapshots
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpireResult {
+    /// Number of snapshots that were removed from the metadata
+    pub expired_snapshots: usize,
+    /// Number of manifest lists that were deleted
+    pub deleted_manifest_lists: usize,
+    /// Number of manifests that were deleted
+    pub deleted_manifests: usize,
+    /// Number of data files that were deleted
+    pub deleted_data_files: usize,
+}
+
+impl DataFusionTable {
+    /// Remove the old snapshots from the table metadata and delete the files that are only reachable from them
+    pub async fn expire_snapshots(&mut self, options: &ExpireOptions) -> Result<ExpireResult> {
+        let properties = self
+            .table()?
+            .metadata()
+            .properties()
+            .cloned()
+            .unwrap_or_default();
+        let older_than = match (
+            options.older_than,
+            properties.get(HISTORY_EXPIRE_MAX_SNAPSHOT_AGE_MS),
+        ) {
+            (Some(older_than), _) => older_than,
+            (None, Some(age)) => {
+                now_ms() - parse_property(HISTORY_EXPIRE_MAX_SNAPSHOT_AGE_MS, age)?
+            }
+            (None, None) => now_ms() - DEFAULT_MAX_SNAPSHOT_AGE.as_millis() as i64,
+        };
+        let retain_last = match (
+            options.retain_last,
+            properties.get(HISTORY_EXPIRE_MIN_SNAPSHOTS_TO_KEEP),
+        ) {
+            (Some(retain_last), _) => retain_last,
+            (None, Some(keep)) => {
+                parse_property(HISTORY_EXPIRE_MIN_SNAPSHOTS_TO_KEEP, keep)?.max(1) as usize
+            }
+            (None, None) => DEFAULT_MIN_SNAPSHOTS_TO_KEEP,
+        };
+
+        if expired(self.table()?.metadata(), older_than, retain_last).is_empty() {
+            return Ok(ExpireResult::default());
+        }
+        let mut change = ExpireChange {
+            older_than,
+            retain_last,
+            expired: Vec::new(),
+        };
+        self.commit(&mut change).await?;
+
+        // Files are only deleted if no retained snapshot reaches them. Data files are reached by the snapshots in which
+        // they are live, the entries of removed files don't keep them.
+        let table = self.table()?;
+        let object_store = table.object_store();
+        let format_version = table.metadata().format_version();
+        let retained = reachable(
+            &object_store,
+            &snapshots(table.metadata()),
+            &format_version,
+            None,
+        )
+        .await?;
+        let expired = reachable(
+            &object_store,
+            &change.expired,
+            &format_version,
+            Some(&retained.manifests),
+        )
+        .await?;
+        let manifest_lists = difference(&expired.manifest_lists, &retained.manifest_lists);
+        let manifests = difference(&expired.manifests, &retained.manifests);
+        let data_files = difference(&expired.data_files, &retained.data_files);
+        // Data files go first, so that an interrupted deletion can be repeated with the manifests that reference them
+        delete_files(&object_store, &data_files, &options.delete).await?;
+        delete_files(&object_store, &manifests, &options.delete).await?;
+        delete_files(&object_store, &manifest_lists, &options.delete).await?;
+        Ok(ExpireResult {
+            expired_snapshots: change.expired.len(),
+            deleted_manifest_lists: manifest_lists.len(),
+            deleted_manifests: manifests.len(),
+            deleted_data_files: data_files.len(),
+        })
+    }
+}
+
+/// Removal of the expired snapshots from the metadata
+struct ExpireChange {
+    older_than: i64,
+    retain_last: usize,
+    // Snapshots that the last attempt removed
+    expired: Vec<SnapshotFiles>,
+}
+
+#[async_trait::async_trait]
+impl Change for ExpireChange {
+    async fn apply(&mut self, table: &Table) -> Result<TableMetadata> {
+        let mut metadata = clone_metadata(table.metadata())?;
+        self.expired = expired(&metadata, self.older_than, self.retain_last);
+        let ids: HashSet<i64> = self.expired.iter().map(|snapshot| snapshot.id).collect();
+        match &mut metadata {
+            TableMetadata::V1(metadata) => {
+                if let Some(snapshots) = &mut metadata.snapshots {
+                    snapshots.retain(|snapshot| !ids.contains(&snapshot.snapshot_id));
+                }
+                if let Some(log) = &mut metadata.snapshot_log {
+                    log.retain(|entry| !ids.contains(&entry.snapshot_id));
+                }
+            }
+            TableMetadata::V2(metadata) => {
+                if let Some(snapshots) = &mut metadata.snapshots {
+                    snapshots.retain(|snapshot| !ids.contains(&snapshot.snapshot_id));
+                }
+                if let Some(log) = &mut metadata.snapshot_log {
+                    log.retain(|entry| !ids.contains(&entry.snapshot_id));
+                }
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// Manifest list or manifests of a snapshot
+pub(crate) struct SnapshotFiles {
+    id: i64,
+    parent_id: Option<i64>,
+    timestamp_ms: i64,
+    manifest_list: Option<String>,
+    manifests: Vec<String>,
+}
+
+pub(crate) fn snapshots(metadata: &TableMetadata) -> Vec<SnapshotFiles> {
+    match metadata {
+        TableMetadata::V1(metadata) => metadata
+            .snapshots
+            .iter()
+            .flatten()
+            .map(|snapshot| SnapshotFiles {
+                id: snapshot.snapshot_id,
+                parent_id: snapshot.parent_snapshot_id,
+                timestamp_ms: snapshot.timestamp_ms,
+                manifest_list: snapshot.manifest_list.clone(),
+                manifests: snapshot.manifests.clone().unwrap_or_default(),
+            })
+            .collect(),
+        TableMetadata::V2(metadata) => metadata
+            .snapshots
+            .iter()
+            .flatten()
+            .map(|snapshot| SnapshotFiles {
+                id: snapshot.snapshot_id,
+                parent_id: snapshot.parent_snapshot_id,
+                timestamp_ms: snapshot.timestamp_ms,
+                manifest_list: Some(snapshot.manifest_list.clone()),
+                manifests: Vec::new(),
+            })
+            .collect(),
+    }
+}
+
+/// Snapshots that are older than the timestamp and neither current, referenced nor one of the retained ancestors
+fn expired(metadata: &TableMetadata, older_than: i64, retain_last: usize) -> Vec<SnapshotFiles> {
+    let snapshots = snapshots(metadata);
+    let mut retained: HashSet<i64> = HashSet::new();
+    let mut ancestor = metadata.current_snapshot_id();
+    while let Some(id) = ancestor.filter(|_| retained.len() < retain_last) {
+        retained.insert(id);
+        ancestor = snapshots
+            .iter()
+            .find(|snapshot| snapshot.id == id)
+            .and_then(|snapshot| snapshot.parent_id);
+    }
+    if let TableMetadata::V2(metadata) = metadata {
+        retained.extend(
+            metadata
+                .refs
+                .iter()
+                .flatten()
+                .map(|(_, reference)| reference.snapshot_id),
+        );
+    }
+    snapshots
+        .into_iter()
+        .filter(|snapshot| snapshot.timestamp_ms < older_than && !retained.contains(&snapshot.id))
+        .collect()
+}
+
+/// Paths in the object store of the files that the snapshots reach
+#[derive(Default)]
+pub(crate) struct Reachable {
+    pub(crate) manifest_lists: HashSet<String>,
+    pub(crate) manifests: HashSet<String>,
+    pub(crate) data_files: HashSet<String>,
+}
+
+/// Files that the snapshots reach. The data files of the manifests that are known to be retained are not read.
+pub(crate) async fn reachable(
+    object_store: &Arc<dyn ObjectStore>,
+    snapshots: &[SnapshotFiles],
+    format_version: &FormatVersion,
+    retained_manifests: Option<&HashSet<String>>,
+) -> Result<Reachable> {
+    let mut reachable = Reachable::default();
+    for snapshot in snapshots {
+        let mut manifests: Vec<String> = snapshot
+            .manifests
+            .iter()
+            .map(|path| util::strip_prefix(path))
+            .collect();
+        if let Some(manifest_list) = &snapshot.manifest_list {
+            let path = util::strip_prefix(manifest_list);
+            manifests.extend(manifest_paths(object_store, &path, format_version).await?);
+            reachable.manifest_lists.insert(path);
+        }
+        for manifest in manifests {
+            let retained = retained_manifests.is_some_and(|retained| retained.contains(&manifest));
+            if !retained && !reachable.manifests.contains(&manifest) {
+                reachable
+                    .data_files
+                    .extend(data_file_paths(object_store, &manifest, format_version).await?);
+            }
+            reachable.manifests.insert(manifest);
+        }
+    }
+    Ok(reachable)
+}
+
+async fn manifest_paths(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &str,
+    format_version: &FormatVersion,
+) -> Result<Vec<String>> {
+    read_avro(object_store, path, |value| {
+        Ok(match format_version {
+            FormatVersion::V1 => apache_avro::from_value::<ManifestFileV1>(value)?.manifest_path,
+            FormatVersion::V2 => apache_avro::from_value::<ManifestFileV2>(value)?.manifest_path,
+        })
+    })
+    .await
+    .map(|paths| paths.iter().map(|path| util::strip_prefix(path)).collect())
+}
+
+/// Paths of the live data files of the manifest
+async fn data_file_paths(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &str,
+    format_version: &FormatVersion,
+) -> Result<Vec<String>> {
+    read_avro(object_store, path, |value| {
+        let (status, path) = match format_version {
+            FormatVersion::V1 => {
+                let entry = apache_avro::from_value::<ManifestEntryV1>(value)?;
+                (entry.status, entry.data_file.file_path)
+            }
+            FormatVersion::V2 => {
+                let entry = apache_avro::from_value::<ManifestEntryV2>(value)?;
+                (entry.status, entry.data_file.file_path)
+            }
+        };
+        Ok((!matches!(status, Status::Deleted)).then_some(path))
+    })
+    .await
+    .map(|paths| {
+        paths
+            .iter()
+            .flatten()
+            .map(|path| util::strip_prefix(path))
+            .collect()
+    })
+}
+
+/// Read the records of the avro file. Empty files have no records.
+async fn read_avro<T>(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &str,
+    read: impl Fn(&apache_avro::types::Value) -> std::result::Result<T, apache_avro::Error>,
+) -> Result<Vec<T>> {
+    let bytes = object_store.get(&Path::from(path)).await?.bytes().await?;
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let reader = AvroReader::new(Cursor::new(bytes)).map_err(Error::iceberg)?;
+    reader
+        .map(|value| {
+            value
+                .and_then(|value| read(&value))
+                .map_err(|err| Error::iceberg(err).into())
+        })
+        .collect()
+}
+
+/// Sorted paths of the expired files that are not retained
+fn difference(expired: &HashSet<String>, retained: &HashSet<String>) -> Vec<String> {
+    let mut paths: Vec<String> = expired.difference(retained).cloned().collect();
+    paths.sort();
+    paths
+}
+
+fn parse_property(key: &str, value: &str) -> Result<i64> {
+    value.parse().map_err(|_| {
+        DataFusionError::Plan(format!(
+            "Table property {} has to be a positive integer, found {}.",
+            key, value
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::prelude::SessionContext;
+    use futures::TryStreamExt;
+    use object_store::ObjectMeta;
+
+    use crate::{
+        commit::tests::{insert_trips, row_count},
+        rewrite::RewriteOptions,
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    async fn file_count(object_store: &Arc<dyn ObjectStore>) -> usize {
+        let files: Vec<ObjectMeta> = object_store
+            .list(None)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        files.len()
+    }
+
+    #[tokio::test]
+    pub async fn test_expire_snapshots() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        for trip in 0..3 {
+            insert_trips(&mut table, [trip * 10, trip * 10 + 1]).await;
+        }
+        let ctx = SessionContext::new();
+        let options = RewriteOptions::default().with_min_input_files(2);
+        table
+            .rewrite_data_files(&ctx.state(), &options)
+            .await
+            .unwrap();
+        let files = file_count(&object_store).await;
+
+        // The snapshot before the rewrite is retained
+        let older_than = now_ms() + 1000;
+        let options = ExpireOptions::default()
+            .with_older_than(older_than)
+            .with_retain_last(2);
+        let result = table.expire_snapshots(&options).await.unwrap();
+        assert_eq!(
+            result,
+            ExpireResult {
+                expired_snapshots: 3,
+                deleted_manifest_lists: 3,
+                deleted_manifests: 0,
+                deleted_data_files: 0,
+            }
+        );
+        assert_eq!(row_count(&table).await, 10);
+
+        // Without the snapshot before the rewrite the small files and their manifests are unreachable
+        let options = ExpireOptions::default().with_older_than(older_than);
+        let result = table.expire_snapshots(&options).await.unwrap();
+        assert_eq!(
+            result,
+            ExpireResult {
+                expired_snapshots: 1,
+                deleted_manifest_lists: 1,
+                deleted_manifests: 4,
+                deleted_data_files: 10,
+            }
+        );
+        assert_eq!(file_count(&object_store).await, files + 2 - 18);
+        assert_eq!(snapshots(table.table().unwrap().metadata()).len(), 1);
+
+        // The table is still readable after a reload
+        let table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(row_count(&table).await, 10);
+
+        let mut table = table;
+        let result = table.expire_snapshots(&options).await.unwrap();
+        assert_eq!(result, ExpireResult::default());
+    }
+}
@@ -0,0 +1,389 @@
+/*!
+ * Export query results as plain Arrow IPC or parquet files.
+ *
+ * The output is partitioned hive-style into `column=value` directories by the given partition columns. The resulting
+ * files are not part of an iceberg table and can be consumed by tools that can't read iceberg.
+*/
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, UInt32Array},
+        compute::take,
+        datatypes::SchemaRef,
+        ipc::writer::FileWriter,
+        record_batch::RecordBatch,
+        util::display::array_value_to_string,
+    },
+    common::DataFusionError,
+    error::Result,
+    physical_plan::{stream::RecordBatchStreamAdapter, SendableRecordBatchStream},
+    prelude::SessionContext,
+};
+use futures::StreamExt;
+use object_store::{path::Path, MultipartId, ObjectStore};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{
+    commit::object_path,
+    progress::{ProgressCallback, ProgressTracker},
+    writer::{write_hive_partitioned, SharedBuffer, WriterConfig},
+};
+
+/// Directory name for rows where the partition column is null
+pub(crate) const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// File format of the exported files
+#[derive(Debug, Clone)]
+pub enum ExportFormat {
+    /// Arrow IPC file format
+    ArrowIpc,
+    /// Parquet files written with the given options
+    Parquet(WriterConfig),
+}
+
+/// Execute the query and write the results into the location of the object store. Returns the paths of the written files.
+/// The output partitions of the query are streamed into the files, so the results don't have to fit into memory.
+pub async fn export(
+    ctx: &SessionContext,
+    sql: &str,
+    location: &str,
+    partition_columns: &[&str],
+    format: &ExportFormat,
+    object_store: &Arc<dyn ObjectStore>,
+    progress: Option<ProgressCallback>,
+) -> Result<Vec<String>> {
+    let streams = ctx.sql(sql).await?.execute_stream_partitioned().await?;
+    let schema = match streams.first() {
+        Some(stream) => stream.schema(),
+        None => return Ok(vec![]),
+    };
+    let batches: SendableRecordBatchStream = Box::pin(RecordBatchStreamAdapter::new(
+        schema.clone(),
+        futures::stream::select_all(streams),
+    ));
+
+    let partition_ids = partition_columns
+        .iter()
+        .map(|column| schema.index_of(column))
+        .collect::<std::result::Result<Vec<usize>, _>>()?;
+
+    // The partition columns are encoded in the directory names and are not stored in the files
+    let projection: Vec<usize> = (0..schema.fields().len())
+        .filter(|idx| !partition_ids.contains(idx))
+        .collect();
+
+    let location = location.trim_end_matches('/');
+    // The number of partitions is only known once all rows are written
+    let mut progress = ProgressTracker::new(progress, None);
+    match format {
+        ExportFormat::Parquet(config) => Ok(write_hive_partitioned(
+            location,
+            batches,
+            object_store,
+            config,
+            partition_columns,
+            &projection,
+            &mut progress,
+        )
+        .await?
+        .into_iter()
+        .map(|file| file.path)
+        .collect()),
+        ExportFormat::ArrowIpc => {
+            write_ipc(
+                location,
+                batches,
+                object_store,
+                partition_columns,
+                &projection,
+                &mut progress,
+            )
+            .await
+        }
+    }
+}
+
+/// Write the record batches of the stream into one arrow IPC file per combination of values of the partition columns
+async fn write_ipc(
+    location: &str,
+    mut batches: SendableRecordBatchStream,
+    object_store: &Arc<dyn ObjectStore>,
+    partition_columns: &[&str],
+    projection: &[usize],
+    progress: &mut ProgressTracker,
+) -> Result<Vec<String>> {
+    let schema = batches.schema();
+    let partition_ids = partition_columns
+        .iter()
+        .map(|column| schema.index_of(column))
+        .collect::<std::result::Result<Vec<usize>, _>>()?;
+    let file_schema: SchemaRef = Arc::new(schema.project(projection)?);
+    let mut files: HashMap<String, IpcFile> = HashMap::new();
+    let written = async {
+        while let Some(batch) = batches.next().await {
+            for (directory, batch) in split_batch(
+                &batch?,
+                &partition_ids,
+                partition_columns,
+                projection,
+                &file_schema,
+            )? {
+                let file = match files.entry(directory) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let directory = if entry.key().is_empty() {
+                            location.to_owned()
+                        } else {
+                            location.to_owned() + "/" + entry.key()
+                        };
+                        let path = directory + "/" + &Uuid::new_v4().to_string() + ".arrow";
+                        entry.insert(IpcFile::try_new(path, &file_schema, object_store).await?)
+                    }
+                };
+                file.write(&batch).await?;
+            }
+        }
+        Ok::<_, DataFusionError>(())
+    }
+    .await;
+    if let Err(err) = written {
+        for (_, file) in files {
+            file.abort(object_store).await;
+        }
+        return Err(err);
+    }
+    let mut paths = Vec::new();
+    for (_, file) in files {
+        paths.push(file.finish(progress).await?);
+    }
+    Ok(paths)
+}
+
+/// Arrow IPC file whose bytes are uploaded after every batch
+struct IpcFile {
+    path: Path,
+    writer: FileWriter<SharedBuffer>,
+    buffer: SharedBuffer,
+    upload: Box<dyn AsyncWrite + Unpin + Send>,
+    multipart_id: MultipartId,
+    bytes_uploaded: usize,
+    record_count: usize,
+}
+
+impl IpcFile {
+    async fn try_new(
+        path: String,
+        schema: &SchemaRef,
+        object_store: &Arc<dyn ObjectStore>,
+    ) -> Result<Self> {
+        let buffer = SharedBuffer::default();
+        let writer = FileWriter::try_new(buffer.clone(), schema)?;
+        let path = object_path(&path);
+        let (multipart_id, upload) = object_store.put_multipart(&path).await?;
+        Ok(IpcFile {
+            path,
+            writer,
+            buffer,
+            upload,
+            multipart_id,
+            bytes_uploaded: 0,
+            record_count: 0,
+        })
+    }
+    async fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.writer.write(batch)?;
+        self.record_count += batch.num_rows();
+        self.upload().await
+    }
+    async fn upload(&mut self) -> Result<()> {
+        let bytes = self.buffer.take();
+        if !bytes.is_empty() {
+            self.upload.write_all(&bytes).await?;
+            self.bytes_uploaded += bytes.len();
+        }
+        Ok(())
+    }
+    async fn finish(mut self, progress: &mut ProgressTracker) -> Result<String> {
+        self.writer.finish()?;
+        self.upload().await?;
+        self.upload.shutdown().await?;
+        progress.file_done(self.bytes_uploaded, self.record_count)?;
+        Ok(self.path.to_string())
+    }
+    async fn abort(self, object_store: &Arc<dyn ObjectStore>) {
+        let _ = object_store
+            .abort_multipart(&self.path, &self.multipart_id)
+            .await;
+    }
+}
+
+/// Escape the characters of the partition value that can't be part of a hive directory name or of an object store path
+/// as `%XX`. Characters outside of ASCII are escaped byte by byte.
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .chars()
+        .fold(String::with_capacity(value.len()), |mut acc, c| {
+            if !c.is_ascii() {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    acc.push_str(&format!("%{:02X}", byte));
+                }
+            } else if c.is_ascii_control() || "\"#%'*/:=?\\[]^{}<>|~`".contains(c) {
+                acc.push_str(&format!("%{:02X}", c as u32));
+            } else {
+                acc.push(c);
+            }
+            acc
+        })
+}
+
+/// Split the batch into one batch per combination of partition values, keyed by the relative partition directory.
+/// The split batches contain the columns of the projection.
+pub(crate) fn split_batch(
+    batch: &RecordBatch,
+    partition_ids: &[usize],
+    partition_columns: &[&str],
+    projection: &[usize],
+    file_schema: &SchemaRef,
+) -> Result<Vec<(String, RecordBatch)>> {
+    let mut rows: HashMap<String, Vec<u32>> = HashMap::new();
+    for row in 0..batch.num_rows() {
+        let directory = partition_ids
+            .iter()
+            .zip(partition_columns)
+            .map(|(id, name)| -> Result<String> {
+                let column = batch.column(*id);
+                let value = if column.is_null(row) {
+                    NULL_PARTITION.to_owned()
+                } else {
+                    escape(&array_value_to_string(column, row)?)
+                };
+                Ok(escape(name) + "=" + &value)
+            })
+            .collect::<Result<Vec<String>>>()?
+            .join("/");
+        rows.entry(directory).or_default().push(row as u32);
+    }
+    rows.into_iter()
+        .map(|(directory, rows)| -> Result<(String, RecordBatch)> {
+            let indices = UInt32Array::from(rows);
+            let columns = projection
+                .iter()
+                .map(|idx| take(batch.column(*idx).as_ref(), &indices, None))
+                .collect::<std::result::Result<Vec<ArrayRef>, _>>()?;
+            Ok((
+                directory,
+                RecordBatch::try_new(file_schema.clone(), columns)?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashSet;
+
+    use datafusion::{
+        arrow::{array::Int64Array, ipc::reader::FileReader},
+        parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+    };
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, memory::InMemory};
+
+    use crate::DataFusionTable;
+
+    use super::*;
+
+    /// Trip ids of the exported files
+    async fn trip_ids(
+        paths: &[String],
+        format: &ExportFormat,
+        object_store: &Arc<dyn ObjectStore>,
+    ) -> HashSet<i64> {
+        let mut trip_ids = HashSet::new();
+        for path in paths {
+            let bytes = object_store
+                .get(&Path::parse(path).unwrap())
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            let batches: Vec<RecordBatch> = match format {
+                ExportFormat::ArrowIpc => FileReader::try_new(std::io::Cursor::new(bytes), None)
+                    .unwrap()
+                    .collect::<std::result::Result<_, _>>()
+                    .unwrap(),
+                ExportFormat::Parquet(_) => ParquetRecordBatchReaderBuilder::try_new(bytes)
+                    .unwrap()
+                    .build()
+                    .unwrap()
+                    .collect::<std::result::Result<_, _>>()
+                    .unwrap(),
+            };
+            for batch in batches {
+                // The partition columns are only part of the path
+                assert_eq!(batch.num_columns(), 2);
+                let column = batch.schema().index_of("trip_id").unwrap();
+                let ids = batch
+                    .column(column)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap();
+                trip_ids.extend(ids.iter().flatten());
+            }
+        }
+        trip_ids
+    }
+
+    #[tokio::test]
+    pub async fn test_export_partitioned() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        ));
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        for format in [
+            ExportFormat::ArrowIpc,
+            ExportFormat::Parquet(WriterConfig::default()),
+        ] {
+            let output: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+            let paths = export(
+                &ctx,
+                "SELECT vendor_id, 'a/b' AS tag, trip_id, trip_distance FROM nyc_taxis",
+                "export",
+                &["vendor_id", "tag"],
+                &format,
+                &output,
+                None,
+            )
+            .await
+            .expect("Failed to export query results.");
+
+            assert_eq!(paths.len(), 2);
+            // Characters that can't be part of directory names are escaped
+            assert!(paths
+                .iter()
+                .all(|path| path.starts_with("export/vendor_id=") && path.contains("/tag=a%2Fb/")));
+            assert_eq!(
+                trip_ids(&paths, &format, &output).await,
+                HashSet::from([1000371, 1000372, 1000373, 1000374])
+            );
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/*!
+ * Object store that falls back to a replica for reads
+ *
+ * Reads are retried against the primary store first. If the primary keeps failing, the same key is read from the
+ * replica, for example a bucket in another region that is kept in sync by bucket replication. Writes always go to the
+ * primary.
+*/
+
+use std::{fmt::Display, ops::Range, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{stream::BoxStream, Future};
+use object_store::{
+    path::Path, Error, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+/// Object store that reads from a replica when the primary fails repeatedly
+#[derive(Debug)]
+pub struct FailoverObjectStore {
+    primary: Arc<dyn ObjectStore>,
+    replica: Arc<dyn ObjectStore>,
+    attempts: usize,
+}
+
+impl FailoverObjectStore {
+    /// Read from the replica after `attempts` failed reads from the primary
+    pub fn new(
+        primary: Arc<dyn ObjectStore>,
+        replica: Arc<dyn ObjectStore>,
+        attempts: usize,
+    ) -> Self {
+        FailoverObjectStore {
+            primary,
+            replica,
+            attempts: attempts.max(1),
+        }
+    }
+    async fn read<'a, T, F, Fut>(&'a self, operation: F) -> Result<T>
+    where
+        F: Fn(&'a Arc<dyn ObjectStore>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation(&self.primary).await {
+                // A missing object is missing in the replica as well
+                Err(Error::NotFound { path, source }) => {
+                    return Err(Error::NotFound { path, source })
+                }
+                Err(_) if attempt < self.attempts => attempt += 1,
+                Err(_) => return operation(&self.replica).await,
+                Ok(value) => return Ok(value),
+            }
+        }
+    }
+}
+
+impl Display for FailoverObjectStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failover({}, {})", self.primary, self.replica)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FailoverObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.primary.put(location, bytes).await
+    }
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.primary.put_multipart(location).await
+    }
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.primary.abort_multipart(location, multipart_id).await
+    }
+    async fn get(&self, location: &Path) -> Result<GetResult> {
+        self.read(|store| store.get(location)).await
+    }
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.read(|store| store.get_range(location, range.clone()))
+            .await
+    }
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.read(|store| store.get_ranges(location, ranges)).await
+    }
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.read(|store| store.head(location)).await
+    }
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.primary.delete(location).await
+    }
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.read(|store| store.list(prefix)).await
+    }
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.read(|store| store.list_with_delimiter(prefix)).await
+    }
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.primary.copy(from, to).await
+    }
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.primary.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_failover_read() {
+        let primary: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let replica: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let location = Path::from("data/file.parquet");
+        primary
+            .put(&location, Bytes::from_static(b"primary"))
+            .await
+            .unwrap();
+        replica
+            .put(&location, Bytes::from_static(b"replica"))
+            .await
+            .unwrap();
+
+        let store = FailoverObjectStore::new(primary, replica, 3);
+
+        let bytes = store.get_range(&location, 0..7).await.unwrap();
+        assert_eq!(bytes, Bytes::from_static(b"primary"));
+
+        // A missing object is reported from the primary without consulting the replica
+        assert!(matches!(
+            store.head(&Path::from("data/missing.parquet")).await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+}
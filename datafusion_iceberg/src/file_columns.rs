@@ -0,0 +1,609 @@
+/*!
+ * Columns of data files that were written with an earlier schema
+ *
+ * Datafusion finds the columns of a parquet file by name, but iceberg identifies them by field id. A data file has the
+ * names of the schema that was current when its snapshot was committed. If a field was renamed since, the reader
+ * renames the column of the file to the current name before datafusion sees the file. A column of a dropped field whose
+ * name was given to a new field gets a name that no field has, so that the new field is null for the rows of the file.
+ * The names are changed in the footer of the file, so that the pruning with the statistics of the row groups and pages
+ * finds the columns as well.
+ *
+ * Columns whose type was widened since have the previous type in the file, as do struct columns whose fields were
+ * added, dropped, reordered or made optional. The files of every combination of previous types are read with a scan of
+ * their own, which casts the columns to the current types and matches the fields of structs by name. These scans don't
+ * prune with the filters, the exact filters are applied after the cast like for avro files.
+ *
+ * The schema of a data file is the schema of the snapshot that added it. Avro data files and files of expired snapshots
+ * are read by the names and types of the current schema.
+*/
+
+use std::{any::Any, collections::HashMap, fmt::Display, ops::Range, sync::Arc};
+
+use bytes::Bytes;
+use datafusion::{
+    arrow::{
+        array::{make_array, new_null_array, Array, ArrayData, ArrayRef},
+        compute::cast as arrow_cast,
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    },
+    common::{Statistics, ToDFSchema},
+    datasource::listing::PartitionedFile,
+    error::Result,
+    execution::context::ExecutionProps,
+    logical_expr::{expr_rewriter::unnormalize_col, ColumnarValue},
+    optimizer::utils::conjunction,
+    parquet::{
+        arrow::{async_reader::AsyncFileReader, ARROW_SCHEMA_META_KEY},
+        errors::Result as ParquetResult,
+        file::metadata::{FileMetaData, ParquetMetaData, RowGroupMetaData},
+        format::SchemaElement,
+        schema::types::{from_thrift, to_thrift, SchemaDescriptor},
+    },
+    physical_expr::create_physical_expr,
+    physical_plan::{
+        expressions::{cast, Column},
+        file_format::{FileMeta, FileScanConfig, ParquetFileReaderFactory},
+        filter::FilterExec,
+        metrics::ExecutionPlanMetricsSet,
+        projection::ProjectionExec,
+        ExecutionPlan, PhysicalExpr,
+    },
+    prelude::Expr,
+};
+use futures::{future::BoxFuture, FutureExt};
+use iceberg_rs::model::{
+    data_types::{StructField, Type},
+    manifest::ManifestEntry,
+    manifest_list::ManifestFile,
+    table_metadata::TableMetadata,
+};
+
+use crate::{
+    metadata::{ManifestEntryExt, ManifestFileExt},
+    schema::{arrow_type, without_nested_field_ids},
+    table::parquet_plan,
+};
+
+/// Columns of a data file that differ from the current schema
+#[derive(Debug, Default)]
+pub(crate) struct WrittenColumns {
+    // New names of the columns, keyed by the path of the column in the file
+    names: HashMap<Vec<String>, String>,
+    // Previous types of the widened columns, by their current name
+    types: ColumnTypes,
+}
+
+/// Files of every partition of a scan
+type FileGroups = Vec<Vec<PartitionedFile>>;
+
+/// Names and previous types of widened columns
+type ColumnTypes = Vec<(String, DataType)>;
+
+/// Written columns of the data files, computed once for every snapshot that added files
+pub(crate) struct FileColumns<'a> {
+    metadata: &'a TableMetadata,
+    snapshots: HashMap<i64, Option<Arc<WrittenColumns>>>,
+}
+
+impl<'a> FileColumns<'a> {
+    pub(crate) fn new(metadata: &'a TableMetadata) -> Self {
+        FileColumns {
+            metadata,
+            snapshots: HashMap::new(),
+        }
+    }
+    /// Columns of the data file that differ from the current schema. None if the file has the names and types of the
+    /// current schema.
+    pub(crate) fn columns(
+        &mut self,
+        manifest: &ManifestFile,
+        entry: &ManifestEntry,
+    ) -> Option<Arc<WrittenColumns>> {
+        let snapshot_id = entry
+            .snapshot_id()
+            .unwrap_or_else(|| manifest.added_snapshot_id());
+        let metadata = self.metadata;
+        self.snapshots
+            .entry(snapshot_id)
+            .or_insert_with(|| {
+                let written = written_schema(metadata, snapshot_id)?;
+                let current = &metadata.current_schema().fields;
+                let mut columns = WrittenColumns::default();
+                renamed_columns(written, current, &mut Vec::new(), &mut columns.names);
+                columns.types = written
+                    .iter()
+                    .filter_map(|field| {
+                        let current = current.iter().find(|x| x.id == field.id)?;
+                        let data_type = written_type(&field.field_type, &current.field_type);
+                        (data_type != without_nested_field_ids(&arrow_type(&current.field_type)))
+                            .then(|| (current.name.clone(), data_type))
+                    })
+                    .collect();
+                (!columns.names.is_empty() || !columns.types.is_empty())
+                    .then_some(Arc::new(columns))
+            })
+            .clone()
+    }
+}
+
+/// Fields of the schema that the snapshot was committed with
+fn written_schema(metadata: &TableMetadata, snapshot_id: i64) -> Option<&[StructField]> {
+    match metadata {
+        TableMetadata::V1(metadata) => {
+            let schema_id = metadata
+                .snapshots
+                .iter()
+                .flatten()
+                .find(|snapshot| snapshot.snapshot_id == snapshot_id)?
+                .schema_id? as i32;
+            metadata
+                .schemas
+                .iter()
+                .flatten()
+                .chain([&metadata.schema])
+                .find(|schema| schema.schema_id.unwrap_or_default() == schema_id)
+                .map(|schema| schema.fields.fields.as_slice())
+        }
+        TableMetadata::V2(metadata) => {
+            let schema_id = metadata
+                .snapshots
+                .iter()
+                .flatten()
+                .find(|snapshot| snapshot.snapshot_id == snapshot_id)?
+                .schema_id? as i32;
+            metadata
+                .schemas
+                .iter()
+                .find(|schema| schema.schema_id == schema_id)
+                .map(|schema| schema.fields.fields.as_slice())
+        }
+    }
+}
+
+/// Columns of the written fields whose name differs from the current name of their field. Dropped fields only get a new
+/// name if a current field has their name.
+fn renamed_columns(
+    written: &[StructField],
+    current: &[StructField],
+    path: &mut Vec<String>,
+    names: &mut HashMap<Vec<String>, String>,
+) {
+    for field in written {
+        path.push(field.name.clone());
+        match current.iter().find(|x| x.id == field.id) {
+            Some(current_field) => {
+                if current_field.name != field.name {
+                    names.insert(path.clone(), current_field.name.clone());
+                }
+                if let (Type::Struct(written), Type::Struct(current)) =
+                    (&field.field_type, &current_field.field_type)
+                {
+                    renamed_columns(&written.fields, &current.fields, path, names);
+                }
+            }
+            None if current.iter().any(|x| x.name == field.name) => {
+                names.insert(path.clone(), format!("_dropped_field_{}", field.id));
+            }
+            None => (),
+        }
+        path.pop();
+    }
+}
+
+/// Type of the written column with the names of the renamed columns. Structs have the written fields in the written
+/// order, so fields that were added, dropped, reordered or made optional since change the type like a widened field.
+fn written_type(written: &Type, current: &Type) -> DataType {
+    match (written, current) {
+        (Type::Struct(written), Type::Struct(current)) => DataType::Struct(
+            written
+                .fields
+                .iter()
+                .map(|field| {
+                    let (name, data_type) = match current.fields.iter().find(|x| x.id == field.id) {
+                        Some(current) => (
+                            current.name.clone(),
+                            written_type(&field.field_type, &current.field_type),
+                        ),
+                        None if current.fields.iter().any(|x| x.name == field.name) => (
+                            format!("_dropped_field_{}", field.id),
+                            without_nested_field_ids(&arrow_type(&field.field_type)),
+                        ),
+                        None => (
+                            field.name.clone(),
+                            without_nested_field_ids(&arrow_type(&field.field_type)),
+                        ),
+                    };
+                    Field::new(&name, data_type, !field.required)
+                })
+                .collect(),
+        ),
+        _ => without_nested_field_ids(&arrow_type(written)),
+    }
+}
+
+impl WrittenColumns {
+    /// Whether columns of the file have been renamed or dropped
+    pub(crate) fn is_renamed(&self) -> bool {
+        !self.names.is_empty()
+    }
+    /// Whether columns of the file have a previous type
+    pub(crate) fn is_widened(&self) -> bool {
+        !self.types.is_empty()
+    }
+}
+
+/// Files that can be read with the file schema and, for every combination of previous types, the groups of the files
+/// with these types. Empty groups are removed.
+pub(crate) fn split_widened(
+    file_groups: FileGroups,
+) -> (FileGroups, Vec<(ColumnTypes, FileGroups)>) {
+    let mut current = Vec::new();
+    let mut widened: Vec<(ColumnTypes, FileGroups)> = Vec::new();
+    for files in file_groups {
+        let mut groups: HashMap<ColumnTypes, Vec<PartitionedFile>> = HashMap::new();
+        for file in files {
+            let types = file
+                .extensions
+                .clone()
+                .and_then(|extensions| extensions.downcast::<WrittenColumns>().ok())
+                .map(|columns| columns.types.clone())
+                .unwrap_or_default();
+            groups.entry(types).or_default().push(file);
+        }
+        for (types, files) in groups {
+            if types.is_empty() {
+                current.push(files);
+            } else {
+                match widened.iter_mut().find(|(x, _)| x == &types) {
+                    Some((_, groups)) => groups.push(files),
+                    None => widened.push((types, vec![files])),
+                }
+            }
+        }
+    }
+    (current, widened)
+}
+
+/// Scan of the files whose columns have the previous types. All columns are read and cast to the types of the file
+/// schema, before the exact filters and the projection of the config are applied.
+pub(crate) fn widened_plan(
+    config: &FileScanConfig,
+    types: &[(String, DataType)],
+    file_groups: FileGroups,
+    exact_filters: &[Expr],
+    reader_factory: Option<Arc<dyn ParquetFileReaderFactory>>,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let file_schema = Arc::new(Schema::new(
+        config
+            .file_schema
+            .fields()
+            .iter()
+            .map(
+                |field| match types.iter().find(|(name, _)| name == field.name()) {
+                    Some((_, data_type)) => {
+                        Field::new(field.name(), data_type.clone(), field.is_nullable())
+                    }
+                    None => field.clone(),
+                },
+            )
+            .collect(),
+    ));
+    let plan = parquet_plan(
+        FileScanConfig {
+            file_schema,
+            file_groups,
+            statistics: Statistics::default(),
+            projection: None,
+            limit: None,
+            ..config.clone()
+        },
+        &[],
+        reader_factory,
+        false,
+    );
+    let input = plan.schema();
+    let columns = input
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let expr: Arc<dyn PhysicalExpr> = Arc::new(Column::new(field.name(), index));
+            let expr = match config.file_schema.field_with_name(field.name()) {
+                Ok(current) if current.data_type() != field.data_type() => {
+                    conform_column(expr, &input, current.data_type())?
+                }
+                _ => expr,
+            };
+            Ok((expr, field.name().clone()))
+        })
+        .collect::<Result<_>>()?;
+    let plan: Arc<dyn ExecutionPlan> = Arc::new(ProjectionExec::try_new(columns, plan)?);
+    let schema = plan.schema();
+    let plan: Arc<dyn ExecutionPlan> =
+        match conjunction(exact_filters.iter().cloned().map(unnormalize_col)) {
+            Some(predicate) => {
+                let predicate = create_physical_expr(
+                    &predicate,
+                    &schema.clone().to_dfschema()?,
+                    &schema,
+                    &ExecutionProps::new(),
+                )?;
+                Arc::new(FilterExec::try_new(predicate, plan)?)
+            }
+            None => plan,
+        };
+    let columns = config
+        .projection
+        .clone()
+        .unwrap_or_else(|| (0..schema.fields().len()).collect())
+        .into_iter()
+        .map(|index| {
+            let name = schema.field(index).name();
+            (
+                Arc::new(Column::new(name, index)) as Arc<dyn PhysicalExpr>,
+                name.to_owned(),
+            )
+        })
+        .collect();
+    Ok(Arc::new(ProjectionExec::try_new(columns, plan)?))
+}
+
+/// Column of nested values that are converted to the type of the table. Struct fields are matched by name, fields that
+/// the values lack are null. Other values are cast.
+#[derive(Debug)]
+pub(crate) struct ConformExpr {
+    expr: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+}
+
+impl ConformExpr {
+    pub(crate) fn new(expr: Arc<dyn PhysicalExpr>, data_type: DataType) -> Self {
+        ConformExpr { expr, data_type }
+    }
+}
+
+impl Display for ConformExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "conform({} AS {:?})", self.expr, self.data_type)
+    }
+}
+
+impl PartialEq<dyn Any> for ConformExpr {
+    fn eq(&self, other: &dyn Any) -> bool {
+        let other = match other.downcast_ref::<Arc<dyn PhysicalExpr>>() {
+            Some(other) => other.as_any(),
+            None => other,
+        };
+        other
+            .downcast_ref::<ConformExpr>()
+            .map(|other| self.data_type == other.data_type && self.expr.eq(other.expr.as_any()))
+            .unwrap_or(false)
+    }
+}
+
+impl PhysicalExpr for ConformExpr {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(self.data_type.clone())
+    }
+    fn nullable(&self, input_schema: &Schema) -> Result<bool> {
+        self.expr.nullable(input_schema)
+    }
+    fn evaluate(&self, batch: &RecordBatch) -> Result<ColumnarValue> {
+        let array = self.expr.evaluate(batch)?.into_array(batch.num_rows());
+        Ok(ColumnarValue::Array(conform_array(
+            &array,
+            &self.data_type,
+        )?))
+    }
+    fn children(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.expr.clone()]
+    }
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn PhysicalExpr>>,
+    ) -> Result<Arc<dyn PhysicalExpr>> {
+        Ok(Arc::new(ConformExpr::new(
+            children.remove(0),
+            self.data_type.clone(),
+        )))
+    }
+}
+
+/// Expression that converts the column to the type. Nested values are conformed, because arrow can't cast structs.
+pub(crate) fn conform_column(
+    expr: Arc<dyn PhysicalExpr>,
+    input: &Schema,
+    data_type: &DataType,
+) -> Result<Arc<dyn PhysicalExpr>> {
+    match data_type {
+        DataType::Struct(_) | DataType::List(_) | DataType::LargeList(_) | DataType::Map(_, _) => {
+            Ok(Arc::new(ConformExpr::new(expr, data_type.clone())))
+        }
+        _ => cast(expr, input, data_type.clone()),
+    }
+}
+
+/// Values of the array with the type. The fields of structs are matched by name, also inside lists and maps.
+fn conform_array(array: &ArrayRef, data_type: &DataType) -> Result<ArrayRef> {
+    let data = array.data();
+    match (array.data_type(), data_type) {
+        (from, to) if from == to => Ok(array.clone()),
+        (DataType::Struct(from), DataType::Struct(to)) => {
+            // The children of struct arrays aren't sliced with the array
+            let children = to
+                .iter()
+                .map(
+                    |field| match from.iter().position(|x| x.name() == field.name()) {
+                        Some(position) => conform_array(
+                            &make_array(
+                                data.child_data()[position].slice(data.offset(), data.len()),
+                            ),
+                            field.data_type(),
+                        )
+                        .map(|child| child.into_data()),
+                        None => Ok(new_null_array(field.data_type(), data.len()).into_data()),
+                    },
+                )
+                .collect::<Result<Vec<_>>>()?;
+            let nulls = data
+                .null_buffer()
+                .map(|nulls| nulls.bit_slice(data.offset(), data.len()));
+            Ok(make_array(
+                ArrayData::builder(data_type.clone())
+                    .len(data.len())
+                    .null_bit_buffer(nulls)
+                    .child_data(children)
+                    .build()?,
+            ))
+        }
+        (
+            DataType::List(from) | DataType::LargeList(from) | DataType::Map(from, _),
+            DataType::List(to) | DataType::LargeList(to) | DataType::Map(to, _),
+        ) if std::mem::discriminant(array.data_type()) == std::mem::discriminant(data_type)
+            && from.data_type() != to.data_type() =>
+        {
+            // The offsets of the array index the whole child array
+            let values = conform_array(&make_array(data.child_data()[0].clone()), to.data_type())?;
+            Ok(make_array(
+                ArrayData::builder(data_type.clone())
+                    .len(data.len())
+                    .offset(data.offset())
+                    .null_bit_buffer(data.null_buffer().cloned())
+                    .buffers(data.buffers().to_vec())
+                    .child_data(vec![values.into_data()])
+                    .build()?,
+            ))
+        }
+        _ => Ok(arrow_cast(array, data_type)?),
+    }
+}
+
+/// Creates parquet readers that rename the columns of the data files with new column names
+#[derive(Debug)]
+pub(crate) struct RenamingReaderFactory {
+    inner: Arc<dyn ParquetFileReaderFactory>,
+}
+
+impl RenamingReaderFactory {
+    pub(crate) fn new(inner: Arc<dyn ParquetFileReaderFactory>) -> Self {
+        RenamingReaderFactory { inner }
+    }
+}
+
+impl ParquetFileReaderFactory for RenamingReaderFactory {
+    fn create_reader(
+        &self,
+        partition_index: usize,
+        file_meta: FileMeta,
+        metadata_size_hint: Option<usize>,
+        metrics: &ExecutionPlanMetricsSet,
+    ) -> Result<Box<dyn AsyncFileReader + Send>> {
+        let names = file_meta
+            .extensions
+            .clone()
+            .and_then(|extensions| extensions.downcast::<WrittenColumns>().ok())
+            .filter(|columns| !columns.names.is_empty());
+        let inner =
+            self.inner
+                .create_reader(partition_index, file_meta, metadata_size_hint, metrics)?;
+        Ok(match names {
+            Some(names) => Box::new(RenamingReader { inner, names }),
+            None => inner,
+        })
+    }
+}
+
+struct RenamingReader {
+    inner: Box<dyn AsyncFileReader + Send>,
+    names: Arc<WrittenColumns>,
+}
+
+impl AsyncFileReader for RenamingReader {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, ParquetResult<Bytes>> {
+        self.inner.get_bytes(range)
+    }
+    fn get_byte_ranges(
+        &mut self,
+        ranges: Vec<Range<usize>>,
+    ) -> BoxFuture<'_, ParquetResult<Vec<Bytes>>> {
+        self.inner.get_byte_ranges(ranges)
+    }
+    fn get_metadata(&mut self) -> BoxFuture<'_, ParquetResult<Arc<ParquetMetaData>>> {
+        async move {
+            let metadata = self.inner.get_metadata().await?;
+            Ok(Arc::new(renamed_metadata(&metadata, &self.names)?))
+        }
+        .boxed()
+    }
+}
+
+/// Metadata of the parquet file with the new column names. The arrow schema that the writer embedded has the old names,
+/// it is dropped and the arrow types are derived from the parquet types.
+fn renamed_metadata(
+    metadata: &ParquetMetaData,
+    names: &WrittenColumns,
+) -> ParquetResult<ParquetMetaData> {
+    let file_metadata = metadata.file_metadata();
+    let mut elements = to_thrift(file_metadata.schema())?;
+    rename_elements(&mut elements, &mut 1, &mut Vec::new(), &names.names);
+    let schema_descr = Arc::new(SchemaDescriptor::new(from_thrift(&elements)?));
+    let row_groups = metadata
+        .row_groups()
+        .iter()
+        .map(|row_group| {
+            let mut renamed =
+                RowGroupMetaData::from_thrift(schema_descr.clone(), row_group.to_thrift())?;
+            if let Some(page_offset) = row_group.page_offset_index() {
+                renamed.set_page_offset(page_offset.clone());
+            }
+            Ok(renamed)
+        })
+        .collect::<ParquetResult<Vec<_>>>()?;
+    let key_value_metadata = file_metadata.key_value_metadata().map(|metadata| {
+        metadata
+            .iter()
+            .filter(|entry| entry.key != ARROW_SCHEMA_META_KEY)
+            .cloned()
+            .collect()
+    });
+    Ok(ParquetMetaData::new_with_page_index(
+        FileMetaData::new(
+            file_metadata.version(),
+            file_metadata.num_rows(),
+            file_metadata.created_by().map(str::to_owned),
+            key_value_metadata,
+            schema_descr,
+            file_metadata.column_orders().cloned(),
+        ),
+        row_groups,
+        metadata.page_indexes().cloned(),
+        metadata.offset_indexes().cloned(),
+    ))
+}
+
+/// Rename the children of the element before the index. The elements list the schema tree depth first, every group
+/// element is followed by its children.
+fn rename_elements(
+    elements: &mut [SchemaElement],
+    index: &mut usize,
+    path: &mut Vec<String>,
+    names: &HashMap<Vec<String>, String>,
+) {
+    let children = elements[*index - 1].num_children.unwrap_or_default();
+    for _ in 0..children {
+        let position = *index;
+        *index += 1;
+        path.push(elements[position].name.clone());
+        if let Some(name) = names.get(path) {
+            elements[position].name = name.clone();
+        }
+        if elements[position].num_children.unwrap_or_default() > 0 {
+            rename_elements(elements, index, path, names);
+        }
+        path.pop();
+    }
+}
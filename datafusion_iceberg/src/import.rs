@@ -0,0 +1,186 @@
+/*!
+ * Importing DataFusion tables as new iceberg tables
+ *
+ * Listing tables of unpartitioned parquet files whose types iceberg supports keep their files. The new table references
+ * the existing files in one append snapshot, their metrics are read from the parquet footers. The files have to be in
+ * the object store of the catalog, the name mapping of the new table maps their columns to the fields of the table. All
+ * other tables are read with the session and their rows are written into the new table. Imported tables are
+ * unpartitioned and are created in the directory of the identifier below the base path.
+*/
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::datatypes::{DataType, Field, Schema},
+    common::DataFusionError,
+    datasource::{file_format::parquet::ParquetFormat, listing::ListingTable, TableProvider},
+    error::Result,
+    execution::{context::SessionState, runtime_env::RuntimeEnv},
+    physical_plan::file_format::ParquetExec,
+    prelude::SessionContext,
+};
+use iceberg_rs::catalog::{identifier::Identifier, Catalog};
+use object_store::{ObjectMeta, ObjectStore};
+
+use crate::{
+    dataframe::{create_catalog_table, WriteIceberg, WriteOptions, WriteTarget},
+    error::Error,
+    schema::{arrow_to_new_iceberg_schema, iceberg_to_arrow_schema},
+    writer::file_metrics,
+    DataFusionTable,
+};
+
+/// Create the iceberg table of the catalog with the data of the provider, which is read with the session. Fails if the
+/// table exists already.
+pub async fn import_table(
+    state: &SessionState,
+    provider: Arc<dyn TableProvider>,
+    catalog: Arc<dyn Catalog>,
+    identifier: Identifier,
+    base_path: &str,
+) -> Result<DataFusionTable> {
+    if catalog
+        .table_exists(&identifier)
+        .await
+        .map_err(Error::catalog)?
+    {
+        return Err(DataFusionError::Plan(format!(
+            "Table {} exists already.",
+            identifier
+        )));
+    }
+    match provider.as_any().downcast_ref::<ListingTable>() {
+        Some(listing) if keeps_files(listing)? => {
+            let files = listed_files(state, listing, catalog.object_store()).await?;
+            let mut table = create_catalog_table(
+                catalog.clone(),
+                identifier.clone(),
+                base_path,
+                &listing.schema(),
+            )
+            .await?;
+            if let Err(error) = add_files(&mut table, catalog.object_store(), &files).await {
+                // The table must not stay behind without the files
+                catalog.drop_table(&identifier).await.ok();
+                return Err(error);
+            }
+            Ok(table)
+        }
+        _ => {
+            SessionContext::with_state(state.clone())
+                .read_table(provider)?
+                .write_iceberg(
+                    WriteTarget::Catalog {
+                        catalog,
+                        identifier,
+                        base_path: base_path.to_owned(),
+                    },
+                    &WriteOptions::default().with_create_if_not_exists(),
+                )
+                .await
+        }
+    }
+}
+
+/// Whether the iceberg table can reference the files of the listing table instead of copying their rows
+fn keeps_files(listing: &ListingTable) -> Result<bool> {
+    let options = listing.options();
+    if !options.table_partition_cols.is_empty()
+        || options
+            .format
+            .as_any()
+            .downcast_ref::<ParquetFormat>()
+            .is_none()
+    {
+        return Ok(false);
+    }
+    // Types that iceberg doesn't have are cast when the rows are written
+    let schema = listing.schema();
+    let iceberg = iceberg_to_arrow_schema(&arrow_to_new_iceberg_schema(&schema)?);
+    Ok(same_types(&schema, &iceberg))
+}
+
+fn same_types(left: &Schema, right: &Schema) -> bool {
+    left.fields().len() == right.fields().len()
+        && left
+            .fields()
+            .iter()
+            .zip(right.fields())
+            .all(|(left, right)| same_field(left, right))
+}
+
+// Field ids and other metadata are ignored
+fn same_field(left: &Field, right: &Field) -> bool {
+    left.name() == right.name()
+        && left.is_nullable() == right.is_nullable()
+        && match (left.data_type(), right.data_type()) {
+            (DataType::Struct(left), DataType::Struct(right)) => {
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right)
+                        .all(|(left, right)| same_field(left, right))
+            }
+            (DataType::List(left), DataType::List(right))
+            | (DataType::LargeList(left), DataType::LargeList(right))
+            | (DataType::Map(left, _), DataType::Map(right, _)) => same_field(left, right),
+            (
+                DataType::FixedSizeList(left, left_size),
+                DataType::FixedSizeList(right, right_size),
+            ) => left_size == right_size && same_field(left, right),
+            (left, right) => left == right,
+        }
+}
+
+/// Files of the listing table. Paths whose object store isn't registered with the session are listed in the object store
+/// of the catalog, without registering it with the session.
+async fn listed_files(
+    state: &SessionState,
+    listing: &ListingTable,
+    object_store: Arc<dyn ObjectStore>,
+) -> Result<Vec<ObjectMeta>> {
+    let registered = listing
+        .table_paths()
+        .iter()
+        .all(|path| state.runtime_env.object_store(path.object_store()).is_ok());
+    let state = match registered {
+        true => state.clone(),
+        false => {
+            let runtime = RuntimeEnv::default();
+            for path in listing.table_paths() {
+                let url = url::Url::parse(path.as_str())
+                    .map_err(|err| DataFusionError::External(Box::new(err)))?;
+                runtime.register_object_store(
+                    url.scheme(),
+                    url.host_str().unwrap_or_default(),
+                    object_store.clone(),
+                );
+            }
+            SessionState::with_config_rt(state.config.clone(), Arc::new(runtime))
+        }
+    };
+    let plan = listing.scan(&state, &None, &[], None).await?;
+    Ok(match plan.as_any().downcast_ref::<ParquetExec>() {
+        Some(exec) => exec
+            .base_config()
+            .file_groups
+            .iter()
+            .flatten()
+            .map(|file| file.object_meta.clone())
+            .collect(),
+        // Listing tables without files scan nothing
+        None => Vec::new(),
+    })
+}
+
+async fn add_files(
+    table: &mut DataFusionTable,
+    object_store: Arc<dyn ObjectStore>,
+    files: &[ObjectMeta],
+) -> Result<()> {
+    let mut written = Vec::with_capacity(files.len());
+    for file in files {
+        written.push(file_metrics(&object_store, file).await?);
+    }
+    table.append(written).await
+}
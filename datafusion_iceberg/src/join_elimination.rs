@@ -0,0 +1,226 @@
+/*!
+ * Optimizer rule that simplifies joins with iceberg tables that only check for the existence of a key
+ *
+ * Dimensional queries often join a fact table with a dimension table only to restrict the facts to existing keys. If
+ * the join keys of the dimension table are its identifier fields, every fact matches at most one row, so the inner join
+ * can be replaced by a semi join. If the fact table additionally declares the join column as a foreign key of the
+ * dimension table with the table property `foreign-key.<column>` set to the location of the dimension table, every
+ * non null fact matches exactly one row and the join is removed.
+ *
+ * The identifier fields and properties are read from the loaded metadata of the tables. The rule trusts these
+ * declarations, it doesn't check that the data of the snapshots satisfies them.
+*/
+
+use std::collections::HashSet;
+
+use datafusion::{
+    common::Column,
+    datasource::source_as_provider,
+    error::Result,
+    logical_expr::{
+        logical_plan::{Join, JoinType, Projection},
+        utils::expr_to_columns,
+        LogicalPlan, LogicalPlanBuilder,
+    },
+    optimizer::{optimizer::OptimizerRule, utils::optimize_children, OptimizerConfig},
+    prelude::Expr,
+};
+use iceberg_rs::catalog::relation::Relation;
+
+use crate::{metadata::TableMetadataExt, DataFusionTable};
+
+/// Table property prefix that declares a column as foreign key. The value is the location of the referenced table.
+pub const FOREIGN_KEY_PROPERTY_PREFIX: &str = "foreign-key.";
+
+/// Replaces inner joins with iceberg tables that are only used to check for matching keys
+#[derive(Default)]
+pub struct JoinElimination {}
+
+impl JoinElimination {
+    pub fn new() -> Self {
+        JoinElimination {}
+    }
+}
+
+impl OptimizerRule for JoinElimination {
+    fn optimize(
+        &self,
+        plan: &LogicalPlan,
+        optimizer_config: &mut OptimizerConfig,
+    ) -> Result<LogicalPlan> {
+        let plan = optimize_children(self, plan, optimizer_config)?;
+        let simplified = match &plan {
+            LogicalPlan::Projection(projection) => match projection.input.as_ref() {
+                LogicalPlan::Join(join) if join.join_type == JoinType::Inner => {
+                    simplify(projection, join)?
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        Ok(simplified.unwrap_or(plan))
+    }
+    fn name(&self) -> &str {
+        "iceberg_join_elimination"
+    }
+}
+
+/// Metadata of an iceberg table that is read by a plan
+struct TableInfo {
+    location: String,
+    identifier_fields: HashSet<String>,
+    foreign_keys: Vec<(String, String)>,
+    // Whether rows of the table are filtered out before the join
+    filtered: bool,
+}
+
+fn simplify(projection: &Projection, join: &Join) -> Result<Option<LogicalPlan>> {
+    let mut columns = HashSet::new();
+    for expr in &projection.expr {
+        expr_to_columns(expr, &mut columns)?;
+    }
+    // Only the columns of the left side may be used after the join
+    if !columns.iter().all(|column| {
+        join.left.schema().field_from_column(column).is_ok()
+            && join.right.schema().field_from_column(column).is_err()
+    }) {
+        return Ok(None);
+    }
+    let right = match table_info(&join.right) {
+        Some(right) => right,
+        None => return Ok(None),
+    };
+    let right_keys: HashSet<String> = join
+        .on
+        .iter()
+        .map(|(_, right)| right.name.clone())
+        .collect();
+    if right.identifier_fields.is_empty() || right_keys != right.identifier_fields {
+        return Ok(None);
+    }
+
+    let input = match foreign_key(join, &right) {
+        // Every non null key of the left side has exactly one match
+        Some(column) => LogicalPlanBuilder::from(join.left.as_ref().clone())
+            .filter(Expr::Column(column).is_not_null())?
+            .build()?,
+        // Every row of the left side has at most one match
+        None => LogicalPlan::Join(Join {
+            join_type: JoinType::LeftSemi,
+            schema: join.left.schema().clone(),
+            ..join.clone()
+        }),
+    };
+    LogicalPlanBuilder::from(input)
+        .project_with_alias(projection.expr.clone(), projection.alias.clone())?
+        .build()
+        .map(Some)
+}
+
+/// Left column of the join that is declared as foreign key of the right table
+fn foreign_key(join: &Join, right: &TableInfo) -> Option<Column> {
+    if join.on.len() != 1 || join.filter.is_some() || right.filtered {
+        return None;
+    }
+    let (left_key, _) = &join.on[0];
+    let left = table_info(&join.left)?;
+    left.foreign_keys
+        .iter()
+        .any(|(column, location)| column == &left_key.name && location == &right.location)
+        .then(|| left_key.clone())
+}
+
+fn table_info(plan: &LogicalPlan) -> Option<TableInfo> {
+    match plan {
+        LogicalPlan::TableScan(scan) => {
+            let provider = source_as_provider(&scan.source).ok()?;
+            let table = match &provider
+                .as_any()
+                .downcast_ref::<DataFusionTable>()?
+                .relation
+            {
+                Relation::Table(table) => table,
+                Relation::View(_) => return None,
+            };
+            let schema = table.schema();
+            let identifier_fields = table
+                .metadata()
+                .identifier_field_ids()
+                .into_iter()
+                .flatten()
+                .filter_map(|id| schema.fields.iter().find(|field| field.id == *id))
+                .map(|field| field.name.clone())
+                .collect();
+            let foreign_keys = table
+                .metadata()
+                .properties()
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, value)| {
+                    key.strip_prefix(FOREIGN_KEY_PROPERTY_PREFIX)
+                        .map(|column| (column.to_owned(), value.to_owned()))
+                })
+                .collect();
+            Some(TableInfo {
+                location: table.metadata().location().to_owned(),
+                identifier_fields,
+                foreign_keys,
+                filtered: !scan.filters.is_empty() || scan.fetch.is_some(),
+            })
+        }
+        LogicalPlan::Filter(filter) => table_info(filter.input()).map(|info| TableInfo {
+            filtered: true,
+            ..info
+        }),
+        LogicalPlan::SubqueryAlias(alias) => table_info(&alias.input),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use datafusion::{
+        arrow::record_batch::RecordBatch,
+        execution::context::SessionState,
+        prelude::{SessionConfig, SessionContext},
+    };
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_keep_join_without_identifier_fields() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        ));
+
+        let state =
+            SessionState::with_config_rt(SessionConfig::new(), SessionContext::new().runtime_env())
+                .add_optimizer_rule(Arc::new(JoinElimination::new()));
+        let ctx = SessionContext::with_state(state);
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        // vendor_id is not an identifier field, so the join can produce duplicates and has to be kept
+        let df = ctx
+            .sql("SELECT a.trip_id FROM nyc_taxis a JOIN nyc_taxis b ON a.vendor_id = b.vendor_id")
+            .await
+            .unwrap();
+
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        let num_rows: usize = results.iter().map(|batch| batch.num_rows()).sum();
+
+        // Two trips of each of the two vendors
+        assert_eq!(num_rows, 8)
+    }
+}
@@ -1,5 +1,50 @@
+#[cfg(feature = "avro")]
+mod avro;
+pub mod column_statistics;
+mod commit;
+pub mod credentials;
+pub mod dataframe;
+pub mod disk_cache;
+pub mod error;
+pub mod evolution;
+pub mod expire;
+pub mod export;
+pub mod failover;
+mod file_columns;
+pub mod import;
+pub mod join_elimination;
+pub mod masking;
+mod metadata;
+pub mod metadata_cache;
+pub mod options;
+pub mod orphan;
+mod partition;
+pub mod progress;
+pub mod properties;
 mod pruning_statistics;
+pub mod purge;
+mod pushdown;
+pub mod query_log;
+pub mod refresh;
+pub mod report;
+pub mod rewrite;
+pub mod sample;
+pub mod scan;
+pub mod schema;
+mod select;
+pub mod statement;
+mod static_table;
 mod statistics;
 pub mod table;
+pub mod table_cache;
+pub mod temporary;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod transform;
+mod validation;
+pub mod wap;
+mod watermark;
+pub mod writer;
+mod zorder;
 
 pub use crate::table::DataFusionTable;
@@ -0,0 +1,110 @@
+/*!
+ * Masking of sensitive columns
+ *
+ * Columns with a masking policy are replaced by a redacted value in the output of the scan. Sessions whose config
+ * carries the `UnmaskedPrivilege` extension read the original values. The extension can only be added by the
+ * application that creates the session, so a query can't grant it to itself with `SET`.
+ *
+ * Filters on masked columns are evaluated on the masked values, which is why they are not used to prune data files.
+ * Partition columns whose partition field is derived from a masked column are masked with nulls as well.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use datafusion::{
+    arrow::datatypes::DataType,
+    common::DataFusionError,
+    error::Result,
+    execution::context::SessionState,
+    logical_expr::{utils::expr_to_columns, BuiltinScalarFunction},
+    physical_expr::functions::create_physical_expr,
+    physical_plan::{
+        expressions::{Column, Literal},
+        projection::ProjectionExec,
+        ExecutionPlan, PhysicalExpr,
+    },
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+/// Session config extension that lets a session read the unmasked values, added with
+/// `SessionConfig::with_extension(Arc::new(UnmaskedPrivilege))`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnmaskedPrivilege;
+
+/// Replacement for the values of a masked column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaskingPolicy {
+    /// Replace every value with null
+    Null,
+    /// Replace every value with its md5 hash. Only supported for string columns.
+    Hash,
+}
+
+/// Whether the masking policies apply to the session
+pub(crate) fn is_masked(policies: &HashMap<String, MaskingPolicy>, session: &SessionState) -> bool {
+    !policies.is_empty()
+        && session
+            .config
+            .get_extension::<UnmaskedPrivilege>()
+            .is_none()
+}
+
+/// Filters that don't reference a masked column
+pub(crate) fn unmasked_filters(
+    policies: &HashMap<String, MaskingPolicy>,
+    filters: &[Expr],
+) -> Result<Vec<Expr>> {
+    filters.iter().try_fold(Vec::new(), |mut acc, filter| {
+        let mut columns = HashSet::new();
+        expr_to_columns(filter, &mut columns)?;
+        let masked = columns
+            .iter()
+            .any(|column| policies.contains_key(&column.name));
+        if !masked {
+            acc.push(filter.clone());
+        }
+        Ok(acc)
+    })
+}
+
+/// Replace the masked columns in the output of the plan
+pub(crate) fn mask(
+    plan: Arc<dyn ExecutionPlan>,
+    policies: &HashMap<String, MaskingPolicy>,
+    session: &SessionState,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let schema = plan.schema();
+    let exprs = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let column: Arc<dyn PhysicalExpr> = Arc::new(Column::new(field.name(), index));
+            let expr = match (policies.get(field.name()), field.data_type()) {
+                (None, _) => column,
+                (Some(MaskingPolicy::Null), data_type) => {
+                    Arc::new(Literal::new(ScalarValue::try_from(data_type)?))
+                }
+                (Some(MaskingPolicy::Hash), DataType::Utf8) => create_physical_expr(
+                    &BuiltinScalarFunction::MD5,
+                    &[column],
+                    &schema,
+                    &session.execution_props,
+                )?,
+                (Some(MaskingPolicy::Hash), data_type) => {
+                    return Err(DataFusionError::Plan(format!(
+                        "Column {} of type {} can't be masked with a hash.",
+                        field.name(),
+                        data_type
+                    )))
+                }
+            };
+            Ok((expr, field.name().clone()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(ProjectionExec::try_new(exprs, plan)?))
+}
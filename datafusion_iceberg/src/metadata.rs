@@ -0,0 +1,156 @@
+/*!
+ * Accessors for table metadata that iceberg-rs only exposes through the fields of the format versions
+*/
+
+use std::collections::HashMap;
+
+use iceberg_rs::model::{
+    manifest::{Content, FileFormat, ManifestEntry, Status},
+    manifest_list::ManifestFile,
+    sort::SortOrder,
+    table_metadata::TableMetadata,
+};
+
+pub(crate) trait TableMetadataExt {
+    /// Id of the current snapshot. None if the table has no snapshot yet.
+    fn current_snapshot_id(&self) -> Option<i64>;
+    /// Ids of the fields that identify a row in the current schema
+    fn identifier_field_ids(&self) -> Option<&[i32]>;
+    /// Sort order that new data files are written with. None if the default sort order is missing.
+    fn default_sort_order(&self) -> Option<&SortOrder>;
+    /// Properties of the table
+    fn properties(&self) -> Option<&HashMap<String, String>>;
+    /// Id of the partition spec that new data files are written with
+    fn default_spec_id(&self) -> i32;
+    /// Id of the current schema. Tables of format version 1 without schema ids have schema 0.
+    fn current_schema_id(&self) -> i32;
+}
+
+impl TableMetadataExt for TableMetadata {
+    fn current_snapshot_id(&self) -> Option<i64> {
+        match self {
+            TableMetadata::V1(metadata) => metadata.current_snapshot_id,
+            TableMetadata::V2(metadata) => metadata.current_snapshot_id,
+        }
+    }
+    fn identifier_field_ids(&self) -> Option<&[i32]> {
+        match self {
+            TableMetadata::V1(metadata) => metadata
+                .schemas
+                .iter()
+                .flatten()
+                .find(|schema| schema.schema_id == metadata.current_schema_id)
+                .unwrap_or(&metadata.schema)
+                .identifier_field_ids
+                .as_deref(),
+            TableMetadata::V2(metadata) => metadata
+                .schemas
+                .iter()
+                .find(|schema| schema.schema_id == metadata.current_schema_id)
+                .and_then(|schema| schema.identifier_field_ids.as_deref()),
+        }
+    }
+    fn default_sort_order(&self) -> Option<&SortOrder> {
+        let (sort_orders, id) = match self {
+            TableMetadata::V1(metadata) => (&metadata.sort_orders, metadata.default_sort_order_id),
+            TableMetadata::V2(metadata) => (&metadata.sort_orders, metadata.default_sort_order_id),
+        };
+        sort_orders
+            .iter()
+            .find(|sort_order| sort_order.order_id as i64 == id)
+    }
+    fn properties(&self) -> Option<&HashMap<String, String>> {
+        match self {
+            TableMetadata::V1(metadata) => metadata.properties.as_ref(),
+            TableMetadata::V2(metadata) => metadata.properties.as_ref(),
+        }
+    }
+    fn default_spec_id(&self) -> i32 {
+        match self {
+            TableMetadata::V1(metadata) => metadata.default_spec_id.unwrap_or_default(),
+            TableMetadata::V2(metadata) => metadata.default_spec_id,
+        }
+    }
+    fn current_schema_id(&self) -> i32 {
+        match self {
+            TableMetadata::V1(metadata) => metadata.current_schema_id.unwrap_or_default(),
+            TableMetadata::V2(metadata) => metadata.current_schema_id,
+        }
+    }
+}
+
+pub(crate) trait ManifestEntryExt {
+    /// Id of the sort order the data file was written with
+    fn sort_order_id(&self) -> Option<i32>;
+    /// Format of the data file
+    fn file_format(&self) -> &FileFormat;
+    /// Number of records in the data file
+    fn record_count(&self) -> i64;
+    /// Whether the data file was removed by the snapshot of the manifest
+    fn is_deleted(&self) -> bool;
+    /// Id of the snapshot that added the data file. None if it is inherited from the manifest.
+    fn snapshot_id(&self) -> Option<i64>;
+}
+
+impl ManifestEntryExt for ManifestEntry {
+    fn sort_order_id(&self) -> Option<i32> {
+        match self {
+            ManifestEntry::V1(entry) => entry.data_file.sort_order_id,
+            ManifestEntry::V2(entry) => entry.data_file.sort_order_id,
+        }
+    }
+    fn file_format(&self) -> &FileFormat {
+        match self {
+            ManifestEntry::V1(entry) => &entry.data_file.file_format,
+            ManifestEntry::V2(entry) => &entry.data_file.file_format,
+        }
+    }
+    fn record_count(&self) -> i64 {
+        match self {
+            ManifestEntry::V1(entry) => entry.data_file.record_count,
+            ManifestEntry::V2(entry) => entry.data_file.record_count,
+        }
+    }
+    fn is_deleted(&self) -> bool {
+        let status = match self {
+            ManifestEntry::V1(entry) => &entry.status,
+            ManifestEntry::V2(entry) => &entry.status,
+        };
+        matches!(status, Status::Deleted)
+    }
+    fn snapshot_id(&self) -> Option<i64> {
+        match self {
+            ManifestEntry::V1(entry) => Some(entry.snapshot_id),
+            ManifestEntry::V2(entry) => entry.snapshot_id,
+        }
+    }
+}
+
+pub(crate) trait ManifestFileExt {
+    /// Number of rows in the data files that the manifest adds or keeps. None if the manifest doesn't record the counts
+    /// or tracks delete files.
+    fn record_count(&self) -> Option<i64>;
+    /// Id of the snapshot that added the manifest
+    fn added_snapshot_id(&self) -> i64;
+}
+
+impl ManifestFileExt for ManifestFile {
+    fn record_count(&self) -> Option<i64> {
+        match self {
+            ManifestFile::V1(manifest) => manifest
+                .added_rows_count
+                .zip(manifest.existing_rows_count)
+                .map(|(added, existing)| added + existing),
+            ManifestFile::V2(manifest) => match manifest.content {
+                Content::Data => Some(manifest.added_rows_count + manifest.existing_rows_count),
+                _ => None,
+            },
+        }
+    }
+    fn added_snapshot_id(&self) -> i64 {
+        match self {
+            ManifestFile::V1(manifest) => manifest.added_snapshot_id,
+            ManifestFile::V2(manifest) => manifest.added_snapshot_id,
+        }
+    }
+}
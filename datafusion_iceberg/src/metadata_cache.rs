@@ -0,0 +1,259 @@
+/*!
+ * Cache of the metadata of parquet data files
+ *
+ * Every scan reads the footer of every data file it opens. Data files are never modified after they are committed, so
+ * their metadata can be kept between scans. The metadata is keyed by the path and the size of the file. The cache holds
+ * the metadata of a limited number of files and evicts the least recently used ones first.
+ *
+ * A cache can be shared by several tables. The hits and misses are counted to judge whether the cache is large enough.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bytes::Bytes;
+use datafusion::{
+    datasource::file_format::parquet::fetch_parquet_metadata,
+    error::Result,
+    parquet::{
+        arrow::async_reader::AsyncFileReader,
+        errors::{ParquetError, Result as ParquetResult},
+        file::metadata::ParquetMetaData,
+    },
+    physical_plan::{
+        file_format::{FileMeta, ParquetFileReaderFactory},
+        metrics::ExecutionPlanMetricsSet,
+    },
+};
+use futures::{future::BoxFuture, FutureExt};
+use object_store::{ObjectMeta, ObjectStore};
+
+type Key = (String, usize);
+
+#[derive(Default)]
+struct Entries {
+    metadata: HashMap<Key, Arc<ParquetMetaData>>,
+    // Least recently used key first
+    keys: VecDeque<Key>,
+}
+
+/// Metadata of the most recently read parquet files
+pub struct ParquetMetadataCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ParquetMetadataCache {
+    /// Cache that holds the metadata of up to `capacity` files
+    pub fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(ParquetMetadataCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+    /// Number of reads that were served from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+    /// Number of reads that had to fetch the metadata from the object store
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+    /// Number of files in the cache
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().metadata.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn get(&self, key: &Key) -> Option<Arc<ParquetMetaData>> {
+        let mut entries = self.entries.lock().unwrap();
+        let metadata = entries.metadata.get(key).cloned();
+        match &metadata {
+            Some(_) => {
+                entries.keys.retain(|x| x != key);
+                entries.keys.push_back(key.clone());
+                self.hits.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        metadata
+    }
+    fn insert(&self, key: Key, metadata: Arc<ParquetMetaData>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.metadata.insert(key.clone(), metadata).is_none() {
+            entries.keys.push_back(key);
+        }
+        while entries.keys.len() > self.capacity {
+            if let Some(key) = entries.keys.pop_front() {
+                entries.metadata.remove(&key);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ParquetMetadataCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParquetMetadataCache")
+            .field("capacity", &self.capacity)
+            .field("hits", &self.hits())
+            .field("misses", &self.misses())
+            .finish()
+    }
+}
+
+/// Creates parquet readers that look up the metadata in the cache before reading the footer
+#[derive(Debug)]
+pub(crate) struct CachedReaderFactory {
+    object_store: Arc<dyn ObjectStore>,
+    cache: Option<Arc<ParquetMetadataCache>>,
+}
+
+impl CachedReaderFactory {
+    pub(crate) fn new(
+        object_store: Arc<dyn ObjectStore>,
+        cache: Arc<ParquetMetadataCache>,
+    ) -> Self {
+        CachedReaderFactory {
+            object_store,
+            cache: Some(cache),
+        }
+    }
+    /// Readers that always read the footer, like the default readers of datafusion
+    pub(crate) fn without_cache(object_store: Arc<dyn ObjectStore>) -> Self {
+        CachedReaderFactory {
+            object_store,
+            cache: None,
+        }
+    }
+}
+
+impl ParquetFileReaderFactory for CachedReaderFactory {
+    fn create_reader(
+        &self,
+        _partition_index: usize,
+        file_meta: FileMeta,
+        metadata_size_hint: Option<usize>,
+        _metrics: &ExecutionPlanMetricsSet,
+    ) -> Result<Box<dyn AsyncFileReader + Send>> {
+        Ok(Box::new(CachedReader {
+            object_store: self.object_store.clone(),
+            meta: file_meta.object_meta,
+            metadata_size_hint,
+            cache: self.cache.clone(),
+        }))
+    }
+}
+
+/// Reads the data file from the object store and the metadata from the cache if it is present
+struct CachedReader {
+    object_store: Arc<dyn ObjectStore>,
+    meta: ObjectMeta,
+    metadata_size_hint: Option<usize>,
+    cache: Option<Arc<ParquetMetadataCache>>,
+}
+
+impl AsyncFileReader for CachedReader {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, ParquetResult<Bytes>> {
+        async move {
+            self.object_store
+                .get_range(&self.meta.location, range)
+                .await
+                .map_err(|err| ParquetError::General(err.to_string()))
+        }
+        .boxed()
+    }
+    fn get_byte_ranges(
+        &mut self,
+        ranges: Vec<Range<usize>>,
+    ) -> BoxFuture<'_, ParquetResult<Vec<Bytes>>> {
+        async move {
+            self.object_store
+                .get_ranges(&self.meta.location, &ranges)
+                .await
+                .map_err(|err| ParquetError::General(err.to_string()))
+        }
+        .boxed()
+    }
+    fn get_metadata(&mut self) -> BoxFuture<'_, ParquetResult<Arc<ParquetMetaData>>> {
+        async move {
+            let key = (self.meta.location.to_string(), self.meta.size);
+            if let Some(metadata) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+                return Ok(metadata);
+            }
+            let metadata = Arc::new(
+                fetch_parquet_metadata(
+                    self.object_store.as_ref(),
+                    &self.meta,
+                    self.metadata_size_hint,
+                )
+                .await
+                .map_err(|err| ParquetError::General(err.to_string()))?,
+            );
+            if let Some(cache) = &self.cache {
+                cache.insert(key, metadata.clone());
+            }
+            Ok(metadata)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::prelude::SessionContext;
+    use iceberg_rs::table::Table;
+    use object_store::local::LocalFileSystem;
+
+    use crate::DataFusionTable;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_metadata_cache() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let cache = ParquetMetadataCache::new(16);
+
+        let table = Arc::new(
+            DataFusionTable::from(
+                Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                    .await
+                    .unwrap(),
+            )
+            .with_metadata_cache(cache.clone()),
+        );
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        for _ in 0..2 {
+            ctx.sql("SELECT SUM(fare_amount) FROM nyc_taxis")
+                .await
+                .unwrap()
+                .collect()
+                .await
+                .expect("Failed to execute query plan.");
+        }
+
+        // The footers of the 4 data files are only read by the first query
+        assert_eq!(cache.misses(), 4);
+        assert_eq!(cache.hits(), 4);
+        assert_eq!(cache.len(), 4);
+    }
+}
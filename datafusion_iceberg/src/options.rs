@@ -0,0 +1,99 @@
+/*!
+ * Config options to tune iceberg tables per session
+ *
+ * The options are read from the config options of the session that plans the scan or writes the files, so they can be
+ * changed with `SET` in SQL. Datafusion only lets `SET` change options that exist, so the session config has to be
+ * created with `with_iceberg_options`. Numeric options that are 0 use the value configured on the table or writer.
+*/
+
+use datafusion::{execution::context::SessionState, prelude::SessionConfig, scalar::ScalarValue};
+
+/// Number of manifests that are fetched at the same time while a scan is planned
+pub const MANIFEST_CONCURRENCY: &str = "iceberg.manifest_concurrency";
+/// Whether manifests and data files are pruned with the statistics of the manifest list and manifests
+pub const ENABLE_PRUNING: &str = "iceberg.enable_pruning";
+/// Size in bytes at which the writer starts a new data file
+pub const WRITE_TARGET_FILE_SIZE: &str = "iceberg.write.target_file_size";
+
+/// Register the iceberg options with their defaults
+pub trait IcebergConfigExt {
+    fn with_iceberg_options(self) -> Self;
+}
+
+impl IcebergConfigExt for SessionConfig {
+    fn with_iceberg_options(self) -> Self {
+        self.set_u64(MANIFEST_CONCURRENCY, 0)
+            .set_bool(ENABLE_PRUNING, true)
+            .set_u64(WRITE_TARGET_FILE_SIZE, 0)
+    }
+}
+
+/// Value of the numeric option, None if it isn't set or 0
+pub(crate) fn get_usize(config: &SessionConfig, key: &str) -> Option<usize> {
+    match config.config_options().read().get(key) {
+        Some(ScalarValue::UInt64(Some(value))) if value > 0 => Some(value as usize),
+        _ => None,
+    }
+}
+
+/// Whether pruning is enabled for the session. Pruning is enabled if the option isn't set.
+pub(crate) fn pruning_enabled(session: &SessionState) -> bool {
+    !matches!(
+        session.config.config_options().read().get(ENABLE_PRUNING),
+        Some(ScalarValue::Boolean(Some(false)))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use datafusion::{arrow::record_batch::RecordBatch, prelude::SessionContext};
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use crate::DataFusionTable;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_set_iceberg_options() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        ));
+
+        let ctx = SessionContext::with_config(SessionConfig::new().with_iceberg_options());
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        ctx.sql("SET iceberg.enable_pruning = false").await.unwrap();
+        ctx.sql("SET iceberg.manifest_concurrency = 2")
+            .await
+            .unwrap();
+
+        assert!(!pruning_enabled(&ctx.state()));
+        assert_eq!(
+            get_usize(&ctx.state().config, MANIFEST_CONCURRENCY),
+            Some(2)
+        );
+
+        let df = ctx
+            .sql("SELECT trip_id FROM nyc_taxis WHERE vendor_id = 1")
+            .await
+            .unwrap();
+
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        // The files of the other vendor are read but filtered out
+        assert_eq!(
+            results.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            2
+        );
+    }
+}
@@ -0,0 +1,215 @@
+/*!
+ * Removal of orphan files
+ *
+ * Files in the location of a table that no metadata of the table reaches are orphans, for example the files of writers
+ * that failed before their commit. Removing orphan files lists the table location and deletes the files that neither
+ * the snapshots with their manifest lists, manifests and data files nor the current and previous metadata files reach.
+ * Files that are younger than the retention threshold are kept, because they can belong to a commit that is still in
+ * progress. A dry run only returns the orphan files.
+*/
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use datafusion::{
+    arrow::{
+        array::{StringArray, UInt64Array},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    },
+    error::Result,
+};
+use futures::TryStreamExt;
+use iceberg_rs::{model::table_metadata::TableMetadata, table::Table, util};
+use object_store::{path::Path, ObjectMeta};
+
+use crate::{
+    commit::now_ms,
+    expire::{reachable, snapshots},
+    purge::{delete_files, BulkDelete},
+    DataFusionTable,
+};
+
+const DEFAULT_RETENTION: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+
+/// Options for the removal of orphan files
+#[derive(Debug, Clone, Default)]
+pub struct OrphanOptions {
+    older_than: Option<i64>,
+    dry_run: bool,
+    delete: BulkDelete,
+}
+
+impl OrphanOptions {
+    /// Only remove the files that were last modified before the timestamp in milliseconds. Defaults to three days ago.
+    pub fn with_older_than(mut self, timestamp_ms: i64) -> Self {
+        self.older_than = Some(timestamp_ms);
+        self
+    }
+    /// Only return the orphan files without deleting them
+    pub fn with_dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+    /// Options for the deletion of the orphan files
+    pub fn with_bulk_delete(mut self, delete: BulkDelete) -> Self {
+        self.delete = delete;
+        self
+    }
+}
+
+impl DataFusionTable {
+    /// Delete the files in the table location that the table metadata doesn't reach. Returns the paths and sizes of the
+    /// orphan files, which are only deleted if it isn't a dry run.
+    pub async fn remove_orphan_files(&self, options: &OrphanOptions) -> Result<RecordBatch> {
+        let table = self.table()?;
+        let object_store = table.object_store();
+        let metadata = table.metadata();
+        let older_than = options
+            .older_than
+            .unwrap_or_else(|| now_ms() - DEFAULT_RETENTION.as_millis() as i64);
+
+        let reachable = reachable(
+            &object_store,
+            &snapshots(metadata),
+            &metadata.format_version(),
+            None,
+        )
+        .await?;
+        let referenced: HashSet<Path> = reachable
+            .manifest_lists
+            .iter()
+            .chain(&reachable.manifests)
+            .chain(&reachable.data_files)
+            .cloned()
+            .chain(metadata_files(table))
+            .map(|path| Path::from(path.as_str()))
+            .collect();
+        let location = Path::from(util::strip_prefix(metadata.location()).as_str());
+        let mut orphans: Vec<ObjectMeta> = object_store
+            .list(Some(&location))
+            .await?
+            .try_filter(|file| {
+                futures::future::ready(
+                    file.last_modified.timestamp_millis() < older_than
+                        && !referenced.contains(&file.location),
+                )
+            })
+            .try_collect()
+            .await?;
+        orphans.sort_by(|left, right| left.location.cmp(&right.location));
+
+        let paths: Vec<String> = orphans
+            .iter()
+            .map(|file| file.location.to_string())
+            .collect();
+        if !options.dry_run {
+            delete_files(&object_store, &paths, &options.delete).await?;
+        }
+        let schema = Schema::new(vec![
+            Field::new("file_path", DataType::Utf8, false),
+            Field::new("file_size_in_bytes", DataType::UInt64, false),
+        ]);
+        Ok(RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from(paths)),
+                Arc::new(UInt64Array::from_iter_values(
+                    orphans.iter().map(|file| file.size as u64),
+                )),
+            ],
+        )?)
+    }
+}
+
+/// Paths of the current metadata file and of the previous metadata files in the metadata log
+fn metadata_files(table: &Table) -> Vec<String> {
+    let log = match table.metadata() {
+        TableMetadata::V1(metadata) => &metadata.metadata_log,
+        TableMetadata::V2(metadata) => &metadata.metadata_log,
+    };
+    log.iter()
+        .flatten()
+        .map(|entry| util::strip_prefix(&entry.metadata_file))
+        .chain([util::strip_prefix(table.metadata_location())])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::arrow::array::Array;
+
+    use crate::{
+        commit::tests::{insert_trips, row_count, trips_stream},
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_remove_orphan_files() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        insert_trips(&mut table, [0, 1]).await;
+        // Files of a writer that failed before its commit
+        let config = table.writer_config().unwrap();
+        let written = table
+            .write(trips_stream(&table, [2, 3]), &config)
+            .await
+            .unwrap();
+        assert_eq!(written.len(), 2);
+
+        // The files are too new for the default retention
+        let orphans = table
+            .remove_orphan_files(&OrphanOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(orphans.num_rows(), 0);
+
+        let options = OrphanOptions::default().with_older_than(now_ms() + 1000);
+        let orphans = table
+            .remove_orphan_files(&options.clone().with_dry_run())
+            .await
+            .unwrap();
+        let mut paths: Vec<String> = orphans
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .iter()
+            .flatten()
+            .map(|path| path.to_owned())
+            .collect();
+        let mut expected: Vec<String> = written
+            .iter()
+            .map(|file| Path::from(file.path.as_str()).to_string())
+            .collect();
+        // The metadata log of the taxis table doesn't list its first metadata file
+        expected.push(TAXIS.trim_start_matches('/').to_owned() + "/metadata/v0.metadata.json");
+        paths.sort();
+        expected.sort();
+        assert_eq!(paths, expected);
+        for file in &written {
+            object_store
+                .head(&Path::from(file.path.as_str()))
+                .await
+                .unwrap();
+        }
+
+        let orphans = table.remove_orphan_files(&options).await.unwrap();
+        assert_eq!(orphans.num_rows(), 3);
+        for file in &written {
+            assert!(object_store
+                .head(&Path::from(file.path.as_str()))
+                .await
+                .is_err());
+        }
+        assert_eq!(row_count(&table).await, 6);
+        let orphans = table.remove_orphan_files(&options).await.unwrap();
+        assert_eq!(orphans.num_rows(), 0);
+    }
+}
@@ -0,0 +1,369 @@
+/*!
+ * Partition values of the rows that are written to a table
+ *
+ * The rows of a batch are grouped by the values of the partition fields of the partition spec, which are computed by
+ * applying the transform of every partition field to its source column. Every group is written into its own data
+ * files, which are stored in the manifest with the partition values of the group.
+*/
+
+use std::{cmp::Ordering, collections::HashMap};
+
+use chrono::{Datelike, NaiveDate};
+use datafusion::{
+    arrow::{array::UInt32Array, compute::take, record_batch::RecordBatch},
+    common::DataFusionError,
+    error::Result,
+    scalar::ScalarValue,
+};
+use iceberg_rs::model::{
+    data_types::{PrimitiveType, StructType, Type},
+    partition::{PartitionField, Transform},
+    values::Value,
+};
+
+use crate::transform;
+
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+const MILLIS_PER_DAY: i64 = 86_400_000;
+// Days from the first day of the common era to the first of January 1970
+const UNIX_EPOCH_DAYS: i32 = 719_163;
+
+/// Partition field of the spec that new data files are written with
+#[derive(Debug, Clone)]
+pub(crate) struct PartitionColumn {
+    /// Name of the partition field
+    pub(crate) name: String,
+    /// Name of the source column in the table schema
+    pub(crate) source: String,
+    pub(crate) transform: Transform,
+}
+
+/// Partition columns of the partition spec. The source columns have to be top level columns of the schema.
+pub(crate) fn partition_columns(
+    spec: &[PartitionField],
+    schema: &StructType,
+) -> Result<Vec<PartitionColumn>> {
+    spec.iter()
+        .map(|field| {
+            let source = schema
+                .fields
+                .iter()
+                .find(|x| x.id == field.source_id)
+                .ok_or_else(|| {
+                    DataFusionError::Plan(format!(
+                        "Source column {} of partition field {} is not a top level column of the table.",
+                        field.source_id, field.name
+                    ))
+                })?;
+            Ok(PartitionColumn {
+                name: field.name.clone(),
+                source: source.name.clone(),
+                transform: field.transform.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Split the batch into one batch per combination of partition values
+pub(crate) fn split(
+    batch: &RecordBatch,
+    columns: &[PartitionColumn],
+) -> Result<Vec<(Vec<ScalarValue>, RecordBatch)>> {
+    let sources = columns
+        .iter()
+        .map(|column| batch.schema().index_of(&column.source))
+        .collect::<std::result::Result<Vec<usize>, _>>()?;
+    let mut rows: HashMap<Vec<ScalarValue>, Vec<u32>> = HashMap::new();
+    for row in 0..batch.num_rows() {
+        let values = columns
+            .iter()
+            .zip(&sources)
+            .map(|(column, source)| {
+                apply(
+                    &column.transform,
+                    &ScalarValue::try_from_array(batch.column(*source), row)?,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        rows.entry(values).or_default().push(row as u32);
+    }
+    rows.into_iter()
+        .map(|(values, rows)| {
+            let indices = UInt32Array::from(rows);
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|column| take(column.as_ref(), &indices, None))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            Ok((values, RecordBatch::try_new(batch.schema(), columns)?))
+        })
+        .collect()
+}
+
+/// Apply the transform to a value of the source column. Dates and timestamps are returned as days and microseconds
+/// since the epoch, like iceberg stores them.
+pub(crate) fn apply(transform: &Transform, value: &ScalarValue) -> Result<ScalarValue> {
+    let value = normalize(value);
+    if value.is_null() {
+        return Ok(match transform {
+            Transform::Identity | Transform::Truncate(_) | Transform::Void => value,
+            _ => ScalarValue::Int32(None),
+        });
+    }
+    match (transform, &value) {
+        (Transform::Identity, _) => Ok(value),
+        (Transform::Void, _) => ScalarValue::try_from(&value.get_datatype()),
+        (Transform::Bucket(buckets), _) => {
+            let hash = transform::hash(&value).ok_or_else(|| unsupported(transform, &value))?;
+            Ok(ScalarValue::Int32(Some(
+                (hash & i32::MAX) % *buckets as i32,
+            )))
+        }
+        (Transform::Truncate(width), ScalarValue::Int32(Some(v))) => {
+            Ok(ScalarValue::Int32(Some(v - v.rem_euclid(*width as i32))))
+        }
+        (Transform::Truncate(width), ScalarValue::Int64(Some(v))) => {
+            Ok(ScalarValue::Int64(Some(v - v.rem_euclid(*width as i64))))
+        }
+        (Transform::Truncate(width), ScalarValue::Utf8(Some(v))) => Ok(ScalarValue::Utf8(Some(
+            v.chars().take(*width as usize).collect(),
+        ))),
+        (Transform::Truncate(width), ScalarValue::Binary(Some(v))) => Ok(ScalarValue::Binary(
+            Some(v.iter().take(*width as usize).cloned().collect()),
+        )),
+        (Transform::Year | Transform::Month | Transform::Day, ScalarValue::Date32(Some(days))) => {
+            date_transform(transform, *days).ok_or_else(|| unsupported(transform, &value))
+        }
+        (
+            Transform::Year | Transform::Month | Transform::Day,
+            ScalarValue::TimestampMicrosecond(Some(micros), _),
+        ) => date_transform(transform, micros.div_euclid(MICROS_PER_DAY) as i32)
+            .ok_or_else(|| unsupported(transform, &value)),
+        (Transform::Hour, ScalarValue::TimestampMicrosecond(Some(micros), _)) => Ok(
+            ScalarValue::Int32(Some(micros.div_euclid(MICROS_PER_HOUR) as i32)),
+        ),
+        _ => Err(unsupported(transform, &value)),
+    }
+}
+
+/// Dates as days and timestamps as microseconds since the epoch
+fn normalize(value: &ScalarValue) -> ScalarValue {
+    let micros = |value: Option<i64>, factor: i64, tz: &Option<String>| {
+        ScalarValue::TimestampMicrosecond(value.map(|value| value * factor), tz.clone())
+    };
+    match value {
+        ScalarValue::Date64(value) => {
+            ScalarValue::Date32(value.map(|value| value.div_euclid(MILLIS_PER_DAY) as i32))
+        }
+        ScalarValue::TimestampSecond(value, tz) => micros(*value, 1_000_000, tz),
+        ScalarValue::TimestampMillisecond(value, tz) => micros(*value, 1_000, tz),
+        ScalarValue::TimestampNanosecond(value, tz) => ScalarValue::TimestampMicrosecond(
+            value.map(|value| value.div_euclid(1_000)),
+            tz.clone(),
+        ),
+        ScalarValue::LargeUtf8(value) => ScalarValue::Utf8(value.clone()),
+        ScalarValue::LargeBinary(value) => ScalarValue::Binary(value.clone()),
+        value => value.clone(),
+    }
+}
+
+fn date_transform(transform: &Transform, days: i32) -> Option<ScalarValue> {
+    let date = NaiveDate::from_num_days_from_ce_opt(days.checked_add(UNIX_EPOCH_DAYS)?)?;
+    let value = match transform {
+        Transform::Year => date.year() - 1970,
+        Transform::Month => (date.year() - 1970) * 12 + date.month0() as i32,
+        _ => days,
+    };
+    Some(ScalarValue::Int32(Some(value)))
+}
+
+fn unsupported(transform: &Transform, value: &ScalarValue) -> DataFusionError {
+    DataFusionError::NotImplemented(format!(
+        "Partition transform {:?} is not supported for values of type {}.",
+        transform,
+        value.get_datatype()
+    ))
+}
+
+/// Iceberg value of a transformed partition value. Dates and timestamps are stored as numbers, which is how the avro
+/// encoding of the manifests represents them.
+pub(crate) fn to_value(value: &ScalarValue) -> Result<Option<Value>> {
+    Ok(match value {
+        ScalarValue::Boolean(value) => value.map(Value::Boolean),
+        ScalarValue::Int32(value) | ScalarValue::Date32(value) => value.map(Value::Int),
+        ScalarValue::Int64(value) | ScalarValue::TimestampMicrosecond(value, _) => {
+            value.map(Value::LongInt)
+        }
+        ScalarValue::Float32(value) => value.map(Value::Double),
+        ScalarValue::Float64(value) => value.map(Value::LongFloat),
+        ScalarValue::Utf8(value) => value.clone().map(Value::String),
+        value if value.is_null() => None,
+        value => {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Partition values of type {} are not supported.",
+                value.get_datatype()
+            )))
+        }
+    })
+}
+
+/// Binary single value serialization of the partition value, used for the bounds of the partition summaries
+pub(crate) fn value_bytes(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Boolean(value) => Some(vec![*value as u8]),
+        Value::Int(value) => Some(value.to_le_bytes().to_vec()),
+        Value::LongInt(value) => Some(value.to_le_bytes().to_vec()),
+        Value::Double(value) => Some(value.to_le_bytes().to_vec()),
+        Value::LongFloat(value) => Some(value.to_le_bytes().to_vec()),
+        Value::String(value) => Some(value.as_bytes().to_vec()),
+        Value::Date(date) => Some(
+            (date.num_days_from_ce() - UNIX_EPOCH_DAYS)
+                .to_le_bytes()
+                .to_vec(),
+        ),
+        Value::Timestamp(timestamp) | Value::TimestampTZ(timestamp) => Some(
+            (timestamp.timestamp() * 1_000_000 + timestamp.timestamp_subsec_micros() as i64)
+                .to_le_bytes()
+                .to_vec(),
+        ),
+        _ => None,
+    }
+}
+
+/// Order of two partition values of the same partition field
+pub(crate) fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Boolean(left), Value::Boolean(right)) => left.partial_cmp(right),
+        (Value::Int(left), Value::Int(right)) => left.partial_cmp(right),
+        (Value::LongInt(left), Value::LongInt(right)) => left.partial_cmp(right),
+        (Value::Double(left), Value::Double(right)) => left.partial_cmp(right),
+        (Value::LongFloat(left), Value::LongFloat(right)) => left.partial_cmp(right),
+        (Value::String(left), Value::String(right)) => left.partial_cmp(right),
+        (Value::Date(left), Value::Date(right)) => left.partial_cmp(right),
+        (Value::Timestamp(left), Value::Timestamp(right))
+        | (Value::TimestampTZ(left), Value::TimestampTZ(right)) => left.partial_cmp(right),
+        _ => None,
+    }
+}
+
+/// Relative directory of the data files of a partition. The values are escaped like hive paths.
+pub(crate) fn path(columns: &[PartitionColumn], values: &[ScalarValue]) -> String {
+    columns
+        .iter()
+        .zip(values)
+        .map(|(column, value)| {
+            let value = if value.is_null() {
+                crate::export::NULL_PARTITION.to_owned()
+            } else {
+                crate::export::escape(&value.to_string())
+            };
+            column.name.clone() + "=" + &value
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Avro schema of the partition values of the manifest entries
+pub(crate) fn avro_schema(spec: &[PartitionField], schema: &StructType) -> Result<String> {
+    let fields = spec
+        .iter()
+        .map(|field| {
+            let avro_type = match &field.transform {
+                Transform::Identity | Transform::Truncate(_) | Transform::Void => {
+                    let source = schema
+                        .fields
+                        .iter()
+                        .find(|x| x.id == field.source_id)
+                        .ok_or_else(|| {
+                            DataFusionError::Plan(format!(
+                                "Source column {} of partition field {} doesn't exist.",
+                                field.source_id, field.name
+                            ))
+                        })?;
+                    avro_type(&source.field_type).ok_or_else(|| {
+                        DataFusionError::NotImplemented(format!(
+                            "Partition field {} of type {} is not supported.",
+                            field.name, source.field_type
+                        ))
+                    })?
+                }
+                _ => "\"int\"",
+            };
+            Ok(format!(
+                r#"{{"name": "{}", "type": ["null", {}], "default": null, "field-id": {}}}"#,
+                field.name, avro_type, field.field_id
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(format!(
+        r#"{{"type": "record", "name": "r102", "fields": [{}]}}"#,
+        fields.join(", ")
+    ))
+}
+
+fn avro_type(field_type: &Type) -> Option<&'static str> {
+    match field_type {
+        Type::Primitive(primitive) => match primitive {
+            PrimitiveType::Boolean => Some("\"boolean\""),
+            PrimitiveType::Int => Some("\"int\""),
+            PrimitiveType::Long => Some("\"long\""),
+            PrimitiveType::Float => Some("\"float\""),
+            PrimitiveType::Double => Some("\"double\""),
+            PrimitiveType::Date => Some(r#"{"type": "int", "logicalType": "date"}"#),
+            PrimitiveType::Timestamp | PrimitiveType::Timestampz => {
+                Some(r#"{"type": "long", "logicalType": "timestamp-micros"}"#)
+            }
+            PrimitiveType::String => Some("\"string\""),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_transforms() {
+        // 2017-11-16 is 17486 days and 574 months after the epoch
+        let date = ScalarValue::Date32(Some(17486));
+        assert_eq!(
+            apply(&Transform::Year, &date).unwrap(),
+            ScalarValue::Int32(Some(47))
+        );
+        assert_eq!(
+            apply(&Transform::Month, &date).unwrap(),
+            ScalarValue::Int32(Some(574))
+        );
+        assert_eq!(
+            apply(&Transform::Day, &date).unwrap(),
+            ScalarValue::Int32(Some(17486))
+        );
+        // 2017-11-16 22:31:08 UTC
+        let timestamp = ScalarValue::TimestampMillisecond(Some(1510871468000), None);
+        assert_eq!(
+            apply(&Transform::Hour, &timestamp).unwrap(),
+            ScalarValue::Int32(Some(419686))
+        );
+        assert_eq!(
+            apply(&Transform::Day, &timestamp).unwrap(),
+            ScalarValue::Int32(Some(17486))
+        );
+        // Values from the appendix of the iceberg specification
+        assert_eq!(
+            apply(&Transform::Truncate(10), &ScalarValue::Int64(Some(-1))).unwrap(),
+            ScalarValue::Int64(Some(-10))
+        );
+        assert_eq!(
+            apply(&Transform::Bucket(16), &ScalarValue::Int32(Some(34))).unwrap(),
+            ScalarValue::Int32(Some(2017239379 % 16))
+        );
+        assert_eq!(
+            apply(&Transform::Month, &ScalarValue::Date32(None)).unwrap(),
+            ScalarValue::Int32(None)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+/*!
+ * Progress reporting for long running operations
+ *
+ * Operations report their progress to a callback after every file. The callback returns whether the operation should
+ * continue, which lets embedders cancel an operation between two files.
+*/
+
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use datafusion::{common::DataFusionError, error::Result};
+
+/// Progress of an operation
+#[derive(Debug, Clone)]
+pub struct Progress {
+    /// Number of files the operation is going to process, if known
+    pub files_planned: Option<usize>,
+    /// Number of files that have been read or written
+    pub files_done: usize,
+    /// Number of bytes that have been read or written
+    pub bytes_done: usize,
+    /// Number of rows that have been read or written
+    pub rows_done: usize,
+    /// Time since the operation started
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Estimated time until the operation finishes, based on the files done so far
+    pub fn eta(&self) -> Option<Duration> {
+        let files_planned = self.files_planned?;
+        if self.files_done == 0 {
+            return None;
+        }
+        let remaining = files_planned.saturating_sub(self.files_done) as u32;
+        Some(self.elapsed / self.files_done as u32 * remaining)
+    }
+}
+
+/// Callback that receives progress updates. Returning false cancels the operation.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&Progress) -> bool + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(&Progress) -> bool + Send + Sync + 'static) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+}
+
+impl Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Keeps track of the progress of one operation and reports it to the callback
+pub(crate) struct ProgressTracker {
+    callback: Option<ProgressCallback>,
+    started: Instant,
+    progress: Progress,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(callback: Option<ProgressCallback>, files_planned: Option<usize>) -> Self {
+        ProgressTracker {
+            callback,
+            started: Instant::now(),
+            progress: Progress {
+                files_planned,
+                files_done: 0,
+                bytes_done: 0,
+                rows_done: 0,
+                elapsed: Duration::ZERO,
+            },
+        }
+    }
+    /// Record a finished file and report the progress. Fails if the callback cancelled the operation.
+    pub(crate) fn file_done(&mut self, bytes: usize, rows: usize) -> Result<()> {
+        self.progress.files_done += 1;
+        self.progress.bytes_done += bytes;
+        self.progress.rows_done += rows;
+        self.report()
+    }
+    /// Record several finished files at once and report the progress once
+    pub(crate) fn files_done(&mut self, files: usize, bytes: usize, rows: usize) -> Result<()> {
+        self.progress.files_done += files;
+        self.progress.bytes_done += bytes;
+        self.progress.rows_done += rows;
+        self.report()
+    }
+    pub(crate) fn report(&mut self) -> Result<()> {
+        self.progress.elapsed = self.started.elapsed();
+        match &self.callback {
+            Some(callback) if !(callback.0)(&self.progress) => Err(DataFusionError::Execution(
+                "Operation was cancelled.".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
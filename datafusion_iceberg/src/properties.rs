@@ -0,0 +1,154 @@
+/*!
+ * Changes of the table properties
+ *
+ * Properties drive how the table is written, for example the target file size, the compression and the commit
+ * retries. Setting and removing properties commits new table metadata, the snapshots of the table stay the same. The
+ * properties of the writer and the commit retries are checked before they are committed, so that invalid values don't
+ * break later writes.
+*/
+
+use std::collections::HashMap;
+
+use datafusion::{common::DataFusionError, error::Result};
+use iceberg_rs::{model::table_metadata::TableMetadata, table::Table};
+
+use crate::{
+    commit::{clone_metadata, Change, RetryPolicy},
+    metadata::TableMetadataExt,
+    writer::WriterConfig,
+    DataFusionTable,
+};
+
+/// Table property for the number of times a commit is retried after another writer changed the table first
+pub const COMMIT_NUM_RETRIES: &str = "commit.retry.num-retries";
+/// Table property for the wait in milliseconds before the first retry, it doubles with every further retry
+pub const COMMIT_MIN_RETRY_WAIT_MS: &str = "commit.retry.min-wait-ms";
+/// Table property for the longest wait in milliseconds between two retries
+pub const COMMIT_MAX_RETRY_WAIT_MS: &str = "commit.retry.max-wait-ms";
+/// Table property for the mapping from the column names of data files without field ids to the fields of the schema
+pub const DEFAULT_NAME_MAPPING: &str = "schema.name-mapping.default";
+
+impl DataFusionTable {
+    /// Properties of the table
+    pub fn properties(&self) -> Result<HashMap<String, String>> {
+        Ok(self
+            .table()?
+            .metadata()
+            .properties()
+            .cloned()
+            .unwrap_or_default())
+    }
+    /// Set the properties of the table, replacing the values of existing properties
+    pub async fn set_properties(&mut self, properties: HashMap<String, String>) -> Result<()> {
+        let mut change = PropertiesChange {
+            set: properties,
+            unset: Vec::new(),
+            if_exists: true,
+        };
+        self.commit(&mut change).await
+    }
+    /// Remove the properties from the table. Fails if one of the properties isn't set, unless `if_exists` is true.
+    pub async fn unset_properties(&mut self, keys: &[&str], if_exists: bool) -> Result<()> {
+        let mut change = PropertiesChange {
+            set: HashMap::new(),
+            unset: keys.iter().map(|key| key.to_string()).collect(),
+            if_exists,
+        };
+        self.commit(&mut change).await
+    }
+}
+
+struct PropertiesChange {
+    set: HashMap<String, String>,
+    unset: Vec<String>,
+    // Whether properties that aren't set can be removed
+    if_exists: bool,
+}
+
+#[async_trait::async_trait]
+impl Change for PropertiesChange {
+    async fn apply(&mut self, table: &Table) -> Result<TableMetadata> {
+        let mut metadata = clone_metadata(table.metadata())?;
+        let properties = match &mut metadata {
+            TableMetadata::V1(metadata) => metadata.properties.get_or_insert_with(HashMap::new),
+            TableMetadata::V2(metadata) => metadata.properties.get_or_insert_with(HashMap::new),
+        };
+        for key in &self.unset {
+            if properties.remove(key).is_none() && !self.if_exists {
+                return Err(DataFusionError::Plan(format!(
+                    "The table has no property {}.",
+                    key
+                )));
+            }
+        }
+        properties.extend(self.set.clone());
+        WriterConfig::from_properties(properties)?;
+        RetryPolicy::from_properties(Some(properties))?;
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        testing::{taxis_copy, TAXIS},
+        writer::WRITE_PARQUET_COMPRESSION_CODEC,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_properties() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        let properties = table.properties().unwrap();
+        table
+            .set_properties(HashMap::from([
+                (
+                    WRITE_PARQUET_COMPRESSION_CODEC.to_owned(),
+                    "zstd".to_owned(),
+                ),
+                ("comment".to_owned(), "taxis".to_owned()),
+            ]))
+            .await
+            .unwrap();
+
+        // The properties are read from the committed metadata
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(table.properties().unwrap()["comment"], "taxis");
+        assert_eq!(table.properties().unwrap().len(), properties.len() + 2);
+
+        // Invalid values of the writer properties and missing properties are rejected
+        assert!(table
+            .set_properties(HashMap::from([(
+                WRITE_PARQUET_COMPRESSION_CODEC.to_owned(),
+                "none at all".to_owned()
+            )]))
+            .await
+            .is_err());
+        assert!(table
+            .unset_properties(&["comment", "missing"], false)
+            .await
+            .is_err());
+        assert_eq!(table.properties().unwrap()["comment"], "taxis");
+
+        table
+            .unset_properties(&["comment", "missing"], true)
+            .await
+            .unwrap();
+        assert!(!table.properties().unwrap().contains_key("comment"));
+        assert_eq!(
+            table.properties().unwrap()[WRITE_PARQUET_COMPRESSION_CODEC],
+            "zstd"
+        );
+    }
+}
@@ -17,7 +17,7 @@ use std::any::Any;
 use datafusion::{
     arrow::{
         array::ArrayRef,
-        datatypes::{DataType, Schema},
+        datatypes::{DataType, Schema, SchemaRef, TimeUnit},
     },
     common::DataFusionError,
     physical_optimizer::pruning::PruningStatistics,
@@ -25,176 +25,201 @@ use datafusion::{
     scalar::ScalarValue,
 };
 
+use crate::{schema::iceberg_to_arrow_schema, transform};
+
 use iceberg_rs::{
-    arrow::schema::iceberg_to_arrow_schema,
-    model::{bytes::bytes_to_any, manifest::ManifestEntry},
+    model::{
+        bytes::bytes_to_any,
+        data_types::{PrimitiveType, Type},
+        manifest::ManifestEntry,
+        manifest_list::FieldSummary,
+        partition::Transform,
+    },
     table::Table,
 };
 
-pub(crate) struct PruneManifests<'table>(&'table Table);
+/// The bounds are decoded with the iceberg types and returned as arrays of the column types in the table provider schema
+pub(crate) struct PruneManifests<'table> {
+    table: &'table Table,
+    schema: &'table SchemaRef,
+}
+
+impl<'table> PruneManifests<'table> {
+    pub fn new(table: &'table Table, schema: &'table SchemaRef) -> Self {
+        PruneManifests { table, schema }
+    }
+}
 
-impl<'table> From<&'table Table> for PruneManifests<'table> {
-    fn from(value: &'table Table) -> Self {
-        PruneManifests(value)
+impl<'table> PruneManifests<'table> {
+    /// Summaries of the partition fields that are derived from the column with the given id, one entry per manifest.
+    /// Manifests that are not partitioned by the column have no summaries.
+    fn summaries(
+        &self,
+        column_id: i32,
+    ) -> impl Iterator<Item = Vec<(&'table Transform, &'table FieldSummary)>> {
+        let table = self.table;
+        table.manifests().iter().map(move |manifest| {
+            match (
+                manifest.partitions(),
+                table.metadata().get_spec(manifest.partition_spec_id()),
+            ) {
+                (Some(partitions), Some(partition_spec)) => partition_spec
+                    .iter()
+                    .zip(partitions)
+                    .filter(|(field, _)| field.source_id == column_id)
+                    .map(|(field, summary)| (&field.transform, summary))
+                    .collect(),
+                _ => vec![],
+            }
+        })
+    }
+    /// Bounds of the column, one entry per manifest. The bounds of the transformed partition values are projected
+    /// back to the source column.
+    fn bounds(&self, column: &Column, lower: bool) -> Option<ArrayRef> {
+        let schema: Schema = iceberg_to_arrow_schema(self.table.schema());
+        let column_id = field_id(self.table, &column.name)?;
+        let datatype = schema.field_with_name(&column.name).ok()?.data_type();
+        let field_type = field_type(self.table, &column.name)?;
+        let values = self.summaries(column_id).map(|summaries| {
+            summaries.into_iter().find_map(|(transform, summary)| {
+                let bound = if lower {
+                    summary.lower_bound.as_ref()
+                } else {
+                    summary.upper_bound.as_ref()
+                }?;
+                let value = bytes_to_any(bound, &bound_type(transform, field_type)?).ok()?;
+                if lower {
+                    transform::source_lower_bound(transform, value, datatype)
+                } else {
+                    transform::source_upper_bound(transform, value, datatype)
+                }
+            })
+        });
+        let output_type = self.schema.field_with_name(&column.name).ok()?.data_type();
+        any_iter_to_array(values, output_type).ok()
     }
 }
 
 impl<'table> PruningStatistics for PruneManifests<'table> {
     fn min_values(&self, column: &Column) -> Option<ArrayRef> {
-        let schema: Schema = iceberg_to_arrow_schema(self.0.schema()).ok()?;
-        let column_id = schema.index_of(&column.name).ok()?;
-        let datatype = schema.field_with_name(&column.name).ok()?.data_type();
-        let min_values =
-            self.0
-                .manifests()
-                .iter()
-                .filter_map(|manifest| match manifest.partitions() {
-                    Some(partitions) => {
-                        let id = manifest.partition_spec_id();
-                        let partition_spec = self.0.metadata().get_spec(id)?;
-                        partition_spec
-                            .iter()
-                            .zip(partitions)
-                            .map(|(field, summary)| {
-                                if field.source_id == column_id as i32 {
-                                    summary.lower_bound.as_ref().and_then(|min| {
-                                        bytes_to_any(min, &datatype.try_into().ok()?).ok()
-                                    })
-                                } else {
-                                    None
-                                }
-                            })
-                            .next()
-                    }
-                    None => None,
-                });
-        any_iter_to_array(min_values, datatype).ok()
+        self.bounds(column, true)
     }
     fn max_values(&self, column: &Column) -> Option<ArrayRef> {
-        let schema: Schema = iceberg_to_arrow_schema(self.0.schema()).ok()?;
-        let column_id = schema.index_of(&column.name).ok()?;
-        let datatype = schema.field_with_name(&column.name).ok()?.data_type();
-        let max_values =
-            self.0
-                .manifests()
-                .iter()
-                .filter_map(|manifest| match manifest.partitions() {
-                    Some(partitions) => {
-                        let id = manifest.partition_spec_id();
-                        let partition_spec = self.0.metadata().get_spec(id)?;
-                        partition_spec
-                            .iter()
-                            .zip(partitions)
-                            .map(|(field, summary)| {
-                                if field.source_id == column_id as i32 {
-                                    summary.upper_bound.as_ref().and_then(|min| {
-                                        bytes_to_any(min, &datatype.try_into().ok()?).ok()
-                                    })
-                                } else {
-                                    None
-                                }
-                            })
-                            .next()
-                    }
-                    None => None,
-                });
-        any_iter_to_array(max_values, datatype).ok()
+        self.bounds(column, false)
     }
     fn num_containers(&self) -> usize {
-        self.0.manifests().len()
+        self.table.manifests().len()
     }
     fn null_counts(&self, column: &Column) -> Option<ArrayRef> {
-        let schema: Schema = iceberg_to_arrow_schema(self.0.schema()).ok()?;
-        let column_id = schema.index_of(&column.name).ok()?;
-        let contains_null =
-            self.0
-                .manifests()
-                .iter()
-                .filter_map(|manifest| match manifest.partitions() {
-                    Some(partitions) => {
-                        let id = manifest.partition_spec_id();
-                        let partition_spec = self.0.metadata().get_spec(id)?;
-                        partition_spec
-                            .iter()
-                            .zip(partitions)
-                            .map(|(field, summary)| {
-                                if field.source_id == column_id as i32 {
-                                    if !summary.contains_null {
-                                        Some(0)
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            })
-                            .next()
-                    }
-                    None => None,
-                });
+        let column_id = field_id(self.table, &column.name)?;
+        let contains_null = self.summaries(column_id).map(|summaries| {
+            // Transforms map null to null, so every partition field of the column knows whether it contains nulls
+            summaries
+                .first()
+                .and_then(|(_, summary)| (!summary.contains_null).then_some(0))
+        });
         ScalarValue::iter_to_array(contains_null.map(ScalarValue::Int32)).ok()
     }
 }
 
 pub(crate) struct PruneDataFiles<'table, 'manifests> {
     table: &'table Table,
+    schema: &'table SchemaRef,
     files: &'manifests [ManifestEntry],
 }
 
 impl<'table, 'manifests> PruneDataFiles<'table, 'manifests> {
-    pub fn new(table: &'table Table, files: &'manifests [ManifestEntry]) -> Self {
-        PruneDataFiles { table, files }
+    pub fn new(
+        table: &'table Table,
+        schema: &'table SchemaRef,
+        files: &'manifests [ManifestEntry],
+    ) -> Self {
+        PruneDataFiles {
+            table,
+            schema,
+            files,
+        }
     }
 }
 
 impl<'table, 'manifests> PruningStatistics for PruneDataFiles<'table, 'manifests> {
     fn min_values(&self, column: &Column) -> Option<ArrayRef> {
-        let schema: Schema = iceberg_to_arrow_schema(self.table.schema()).ok()?;
-        let column_id = schema.index_of(&column.name).ok()?;
-        let datatype = schema.field_with_name(&column.name).ok()?.data_type();
+        let column_id = field_id(self.table, &column.name)?;
+        let field_type = field_type(self.table, &column.name)?;
         let min_values = self
             .files
             .iter()
             .map(|manifest| match &manifest.lower_bounds() {
                 Some(map) => map
-                    .get(&(column_id as i32))
-                    .and_then(|value| bytes_to_any(value, &datatype.try_into().ok()?).ok()),
+                    .get(&column_id)
+                    .and_then(|value| bytes_to_any(value, field_type).ok()),
                 None => None,
             });
-        any_iter_to_array(min_values, datatype).ok()
+        let output_type = self.schema.field_with_name(&column.name).ok()?.data_type();
+        any_iter_to_array(min_values, output_type).ok()
     }
     fn max_values(&self, column: &Column) -> Option<ArrayRef> {
-        let schema: Schema = iceberg_to_arrow_schema(self.table.schema()).ok()?;
-        let column_id = schema.index_of(&column.name).ok()?;
-        let datatype = schema.field_with_name(&column.name).ok()?.data_type();
+        let column_id = field_id(self.table, &column.name)?;
+        let field_type = field_type(self.table, &column.name)?;
         let max_values = self
             .files
             .iter()
             .map(|manifest| match &manifest.upper_bounds() {
                 Some(map) => map
-                    .get(&(column_id as i32))
-                    .and_then(|value| bytes_to_any(value, &datatype.try_into().ok()?).ok()),
+                    .get(&column_id)
+                    .and_then(|value| bytes_to_any(value, field_type).ok()),
                 None => None,
             });
-        any_iter_to_array(max_values, datatype).ok()
+        let output_type = self.schema.field_with_name(&column.name).ok()?.data_type();
+        any_iter_to_array(max_values, output_type).ok()
     }
     fn num_containers(&self) -> usize {
         self.files.len()
     }
     fn null_counts(&self, column: &Column) -> Option<ArrayRef> {
-        let schema: Schema = iceberg_to_arrow_schema(self.table.schema()).ok()?;
-        let column_id = schema.index_of(&column.name).ok()?;
+        let column_id = field_id(self.table, &column.name)?;
         let null_counts = self
             .files
             .iter()
             .map(|manifest| match &manifest.null_value_counts() {
-                Some(map) => map.get(&(column_id as i32)).copied(),
+                Some(map) => map.get(&column_id).copied(),
                 None => None,
             });
         ScalarValue::iter_to_array(null_counts.map(ScalarValue::Int64)).ok()
     }
 }
 
+/// Iceberg field id of the column. The statistics are keyed by field id, which differs from the column position.
+fn field_id(table: &Table, name: &str) -> Option<i32> {
+    table
+        .schema()
+        .fields
+        .iter()
+        .find(|field| field.name == name)
+        .map(|field| field.id)
+}
+
+fn field_type<'table>(table: &'table Table, name: &str) -> Option<&'table Type> {
+    table
+        .schema()
+        .fields
+        .iter()
+        .find(|field| field.name == name)
+        .map(|field| &field.field_type)
+}
+
+/// Iceberg type of the transformed values that are stored in the partition summaries
+fn bound_type(transform: &Transform, field_type: &Type) -> Option<Type> {
+    match (transform, field_type) {
+        (
+            Transform::Identity | Transform::Truncate(_) | Transform::Void,
+            Type::Primitive(primitive),
+        ) => Some(Type::Primitive(primitive.clone())),
+        (Transform::Identity | Transform::Truncate(_) | Transform::Void, _) => None,
+        _ => Some(Type::Primitive(PrimitiveType::Int)),
+    }
+}
+
 fn any_iter_to_array(
     iter: impl Iterator<Item = Option<Box<dyn Any>>>,
     datatype: &DataType,
@@ -215,23 +240,32 @@ fn any_iter_to_array(
         DataType::Float64 => ScalarValue::iter_to_array(iter.map(|opt| {
             ScalarValue::Float64(opt.and_then(|value| Some(*value.downcast::<f64>().ok()?)))
         })),
+        DataType::Date32 => ScalarValue::iter_to_array(iter.map(|opt| {
+            ScalarValue::Date32(opt.and_then(|value| Some(*value.downcast::<i32>().ok()?)))
+        })),
         DataType::Date64 => ScalarValue::iter_to_array(iter.map(|opt| {
             ScalarValue::Date64(opt.and_then(|value| Some(*value.downcast::<i64>().ok()?)))
         })),
         DataType::Time64(_) => ScalarValue::iter_to_array(iter.map(|opt| {
             ScalarValue::Time64(opt.and_then(|value| Some(*value.downcast::<i64>().ok()?)))
         })),
-        DataType::Timestamp(_, _) => ScalarValue::iter_to_array(iter.map(|opt| {
-            ScalarValue::TimestampMillisecond(
-                opt.and_then(|value| Some(*value.downcast::<i64>().ok()?)),
-                None,
-            )
+        DataType::Timestamp(unit, tz) => ScalarValue::iter_to_array(iter.map(|opt| {
+            let value = opt.and_then(|value| Some(*value.downcast::<i64>().ok()?));
+            match unit {
+                TimeUnit::Second => ScalarValue::TimestampSecond(value, tz.clone()),
+                TimeUnit::Millisecond => ScalarValue::TimestampMillisecond(value, tz.clone()),
+                TimeUnit::Microsecond => ScalarValue::TimestampMicrosecond(value, tz.clone()),
+                TimeUnit::Nanosecond => ScalarValue::TimestampNanosecond(value, tz.clone()),
+            }
         })),
         DataType::Utf8 => ScalarValue::iter_to_array(iter.map(|opt| {
             ScalarValue::Utf8(opt.and_then(|value| Some(*value.downcast::<String>().ok()?)))
         })),
-        DataType::FixedSizeBinary(_) => ScalarValue::iter_to_array(iter.map(|opt| {
-            ScalarValue::Binary(opt.and_then(|value| Some(*value.downcast::<Vec<u8>>().ok()?)))
+        DataType::FixedSizeBinary(size) => ScalarValue::iter_to_array(iter.map(|opt| {
+            ScalarValue::FixedSizeBinary(
+                *size,
+                opt.and_then(|value| Some(*value.downcast::<Vec<u8>>().ok()?)),
+            )
         })),
         DataType::Binary => ScalarValue::iter_to_array(iter.map(|opt| {
             ScalarValue::Binary(opt.and_then(|value| Some(*value.downcast::<Vec<u8>>().ok()?)))
@@ -0,0 +1,301 @@
+/*!
+ * Deletion of large numbers of files, as needed to purge tables or to remove orphan files
+ *
+ * The files are deleted in batches. The files of a batch are deleted concurrently and the next batch only starts once
+ * the previous one finished, optionally delayed to stay below a rate limit of the object store.
+ *
+ * Progress is reported once after every batch, so a cancelled deletion stops between two batches. The files done of the
+ * progress are always the leading files of the input that are deleted, so an interrupted deletion can be resumed with
+ * the remaining files. Files that don't exist anymore are treated as deleted, which makes it safe to repeat a batch that
+ * was aborted halfway. Other errors of a delete request, for example throttling, are retried with exponential backoff.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use datafusion::error::Result;
+use futures::{stream, StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+use tokio::time::sleep;
+
+use crate::{
+    commit::RetryPolicy,
+    progress::{ProgressCallback, ProgressTracker},
+};
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+const DEFAULT_CONCURRENCY: usize = 16;
+const DEFAULT_NUM_RETRIES: usize = 3;
+const DEFAULT_MIN_RETRY_WAIT: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_RETRY_WAIT: Duration = Duration::from_secs(10);
+
+/// Options for the deletion of files
+#[derive(Debug, Clone)]
+pub struct BulkDelete {
+    batch_size: usize,
+    concurrency: usize,
+    max_deletes_per_second: Option<usize>,
+    progress: Option<ProgressCallback>,
+    retry: RetryPolicy,
+}
+
+impl Default for BulkDelete {
+    fn default() -> Self {
+        BulkDelete {
+            batch_size: DEFAULT_BATCH_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            max_deletes_per_second: None,
+            progress: None,
+            retry: RetryPolicy {
+                num_retries: DEFAULT_NUM_RETRIES,
+                min_wait: DEFAULT_MIN_RETRY_WAIT,
+                max_wait: DEFAULT_MAX_RETRY_WAIT,
+            },
+        }
+    }
+}
+
+impl BulkDelete {
+    /// Number of files after which the progress is reported
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    /// Number of delete requests that run at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+    /// Limit the number of delete requests per second
+    pub fn with_rate_limit(mut self, max_deletes_per_second: usize) -> Self {
+        self.max_deletes_per_second = Some(max_deletes_per_second.max(1));
+        self
+    }
+    /// Number of times a failed delete request is retried before the deletion fails
+    pub fn with_retries(mut self, num_retries: usize) -> Self {
+        self.retry.num_retries = num_retries;
+        self
+    }
+    /// Wait before the first retry of a delete request, it doubles with every further retry
+    pub fn with_retry_wait(mut self, min_wait: Duration) -> Self {
+        self.retry.min_wait = min_wait;
+        self.retry.max_wait = self.retry.max_wait.max(min_wait);
+        self
+    }
+    /// Report the progress after every batch. Returning false stops the deletion after the batch.
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+}
+
+/// Delete the files in the given order. Returns the number of deleted files.
+pub async fn delete_files(
+    object_store: &Arc<dyn ObjectStore>,
+    paths: &[String],
+    options: &BulkDelete,
+) -> Result<usize> {
+    let mut progress = ProgressTracker::new(options.progress.clone(), Some(paths.len()));
+    for batch in paths.chunks(options.batch_size) {
+        let started = Instant::now();
+        // The requests are created before they are polled, so that the deletion can be spawned as a task
+        let deletes: Vec<_> = batch
+            .iter()
+            .map(|path| delete_file(object_store, path, &options.retry))
+            .collect();
+        stream::iter(deletes)
+            .buffer_unordered(options.concurrency)
+            .try_collect::<Vec<()>>()
+            .await?;
+        progress.files_done(batch.len(), 0, 0)?;
+        if let Some(max_deletes_per_second) = options.max_deletes_per_second {
+            let duration =
+                Duration::from_secs_f64(batch.len() as f64 / max_deletes_per_second as f64);
+            sleep(duration.saturating_sub(started.elapsed())).await;
+        }
+    }
+    Ok(paths.len())
+}
+
+async fn delete_file(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &str,
+    retry: &RetryPolicy,
+) -> Result<()> {
+    let mut retries = 0;
+    loop {
+        match object_store.delete(&Path::from(path)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => return Ok(()),
+            Err(err) if retries == retry.num_retries => return Err(err.into()),
+            Err(_) => {
+                sleep(retry.wait(retries)).await;
+                retries += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        fmt::Display,
+        ops::Range,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use futures::stream::BoxStream;
+    use object_store::{memory::InMemory, GetResult, ListResult, MultipartId, ObjectMeta};
+    use tokio::io::AsyncWrite;
+
+    use super::*;
+
+    /// Object store whose first delete requests fail
+    #[derive(Debug)]
+    struct FlakyStore {
+        inner: InMemory,
+        failures: AtomicUsize,
+    }
+
+    impl Display for FlakyStore {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Flaky({})", self.inner)
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStore for FlakyStore {
+        async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+            self.inner.put(location, bytes).await
+        }
+        async fn put_multipart(
+            &self,
+            location: &Path,
+        ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+            self.inner.put_multipart(location).await
+        }
+        async fn abort_multipart(
+            &self,
+            location: &Path,
+            multipart_id: &MultipartId,
+        ) -> object_store::Result<()> {
+            self.inner.abort_multipart(location, multipart_id).await
+        }
+        async fn get(&self, location: &Path) -> object_store::Result<GetResult> {
+            self.inner.get(location).await
+        }
+        async fn get_range(
+            &self,
+            location: &Path,
+            range: Range<usize>,
+        ) -> object_store::Result<Bytes> {
+            self.inner.get_range(location, range).await
+        }
+        async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+            self.inner.head(location).await
+        }
+        async fn delete(&self, location: &Path) -> object_store::Result<()> {
+            let failures = self.failures.load(Ordering::SeqCst);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::SeqCst);
+                return Err(object_store::Error::Generic {
+                    store: "flaky",
+                    source: "Slow down".into(),
+                });
+            }
+            self.inner.delete(location).await
+        }
+        async fn list(
+            &self,
+            prefix: Option<&Path>,
+        ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+            self.inner.list(prefix).await
+        }
+        async fn list_with_delimiter(
+            &self,
+            prefix: Option<&Path>,
+        ) -> object_store::Result<ListResult> {
+            self.inner.list_with_delimiter(prefix).await
+        }
+        async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy(from, to).await
+        }
+        async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy_if_not_exists(from, to).await
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_bulk_delete() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let paths: Vec<String> = (0..10).map(|i| format!("data/{}.parquet", i)).collect();
+        // The last file was already deleted by an earlier attempt
+        for path in &paths[..9] {
+            object_store
+                .put(&Path::from(path.as_str()), Bytes::from_static(b"data"))
+                .await
+                .unwrap();
+        }
+
+        let checkpoints = Arc::new(Mutex::new(Vec::new()));
+        let options = {
+            let checkpoints = checkpoints.clone();
+            BulkDelete::default()
+                .with_batch_size(4)
+                .with_progress(ProgressCallback::new(move |progress| {
+                    checkpoints.lock().unwrap().push(progress.files_done);
+                    // Stop after the second batch
+                    progress.files_done < 8
+                }))
+        };
+
+        assert!(delete_files(&object_store, &paths, &options).await.is_err());
+        // The progress is reported once per batch
+        let done = *checkpoints.lock().unwrap().last().unwrap();
+        assert_eq!(*checkpoints.lock().unwrap(), vec![4, 8]);
+
+        delete_files(&object_store, &paths[done..], &BulkDelete::default())
+            .await
+            .expect("Failed to resume deletion.");
+
+        let remaining: Vec<_> = object_store.list(None).await.unwrap().collect().await;
+        assert!(remaining.is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_retry_failed_deletes() {
+        let store = Arc::new(FlakyStore {
+            inner: InMemory::new(),
+            failures: AtomicUsize::new(2),
+        });
+        let object_store: Arc<dyn ObjectStore> = store.clone();
+        let paths = vec!["data/0.parquet".to_owned()];
+        object_store
+            .put(&Path::from(paths[0].as_str()), Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        // Without retries the first failure fails the deletion
+        let options = BulkDelete::default().with_retry_wait(Duration::from_millis(1));
+        assert!(
+            delete_files(&object_store, &paths, &options.clone().with_retries(0))
+                .await
+                .is_err()
+        );
+
+        delete_files(&object_store, &paths, &options)
+            .await
+            .expect("Failed to retry the deletion.");
+        let remaining: Vec<_> = object_store.list(None).await.unwrap().collect().await;
+        assert!(remaining.is_empty());
+        assert_eq!(store.failures.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,93 @@
+/*!
+ * Filters that the parquet reader evaluates exactly
+ *
+ * With filter pushdown the parquet reader evaluates the filters on every row it decodes, so datafusion doesn't have to
+ * filter the output of the scan again. The reader skips filters it can't evaluate, which is why only simple comparisons
+ * of primitive columns with literals are reported as exact. Partition columns are not stored in the data files and
+ * masked columns must not be filtered on their original values, so filters on them are always inexact.
+*/
+
+use std::collections::HashSet;
+
+use datafusion::{
+    arrow::datatypes::{DataType, Schema},
+    logical_expr::{Between, BinaryExpr, Cast, Operator},
+    prelude::Expr,
+};
+
+/// Whether the filter only consists of expressions that the parquet reader evaluates exactly
+pub(crate) fn is_exact(expr: &Expr, schema: &Schema, excluded: &HashSet<String>) -> bool {
+    match expr {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::And | Operator::Or,
+            right,
+        }) => is_exact(left, schema, excluded) && is_exact(right, schema, excluded),
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) if is_comparison(op) => {
+            match (left.as_ref(), right.as_ref()) {
+                (column, Expr::Literal(_)) | (Expr::Literal(_), column) => {
+                    is_supported_column(column, schema, excluded)
+                }
+                _ => false,
+            }
+        }
+        Expr::Between(Between {
+            expr, low, high, ..
+        }) => {
+            matches!(
+                (low.as_ref(), high.as_ref()),
+                (Expr::Literal(_), Expr::Literal(_))
+            ) && is_supported_column(expr, schema, excluded)
+        }
+        Expr::IsNull(expr) | Expr::IsNotNull(expr) | Expr::Not(expr) => {
+            is_supported_column(expr, schema, excluded)
+        }
+        _ => false,
+    }
+}
+
+fn is_comparison(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Eq
+            | Operator::NotEq
+            | Operator::Lt
+            | Operator::LtEq
+            | Operator::Gt
+            | Operator::GtEq
+    )
+}
+
+/// Column of a primitive type, possibly cast to another type by the type coercion
+fn is_supported_column(expr: &Expr, schema: &Schema, excluded: &HashSet<String>) -> bool {
+    match expr {
+        Expr::Column(column) => {
+            !excluded.contains(&column.name)
+                && schema
+                    .field_with_name(&column.name)
+                    .map(|field| {
+                        matches!(
+                            field.data_type(),
+                            DataType::Boolean
+                                | DataType::Int8
+                                | DataType::Int16
+                                | DataType::Int32
+                                | DataType::Int64
+                                | DataType::UInt8
+                                | DataType::UInt16
+                                | DataType::UInt32
+                                | DataType::UInt64
+                                | DataType::Float32
+                                | DataType::Float64
+                                | DataType::Utf8
+                                | DataType::Date32
+                        )
+                    })
+                    .unwrap_or(false)
+        }
+        Expr::Cast(Cast { expr, .. }) | Expr::TryCast { expr, .. } => {
+            is_supported_column(expr, schema, excluded)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,167 @@
+/*!
+ * Log of the table snapshots that were read by queries
+ *
+ * Tables registered through a QueryLog record the snapshot they scan. Running a query through the log stores the sql
+ * together with all snapshots that were read, so that the query can be reproduced later.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use datafusion::{
+    arrow::record_batch::RecordBatch, common::DataFusionError, error::Result,
+    prelude::SessionContext,
+};
+use iceberg_rs::catalog::relation::Relation;
+
+use crate::{metadata::TableMetadataExt, DataFusionTable};
+
+/// Snapshot of a table that was read by a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRead {
+    /// Name under which the table is registered
+    pub name: String,
+    /// Location of the metadata file of the table
+    pub metadata_location: String,
+    /// Id of the snapshot that was read. None if the table has no snapshot yet.
+    pub snapshot_id: Option<i64>,
+}
+
+/// Query together with the snapshots it read
+#[derive(Debug, Clone)]
+pub struct QueryLogEntry {
+    pub sql: String,
+    pub tables: Vec<TableRead>,
+}
+
+/// Session level log of the queries and the table snapshots they read. Queries have to be run one at a time.
+#[derive(Debug, Default)]
+pub struct QueryLog {
+    reads: Mutex<Vec<TableRead>>,
+    entries: Mutex<Vec<QueryLogEntry>>,
+    // The tables reference the log, so the log only keeps weak references to them
+    tables: Mutex<HashMap<String, Weak<DataFusionTable>>>,
+}
+
+impl QueryLog {
+    pub fn new() -> Arc<Self> {
+        Arc::new(QueryLog::default())
+    }
+    /// Register the table with the context so that every scan of the table is recorded in the log
+    pub fn register_table(
+        self: &Arc<Self>,
+        ctx: &SessionContext,
+        name: &str,
+        table: DataFusionTable,
+    ) -> Result<()> {
+        let table = Arc::new(table.with_query_log(self.clone(), name.to_owned()));
+        ctx.register_table(name, table.clone())?;
+        self.tables
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), Arc::downgrade(&table));
+        Ok(())
+    }
+    /// Execute the query and record the snapshots of the tables that were read
+    pub async fn sql(&self, ctx: &SessionContext, sql: &str) -> Result<Vec<RecordBatch>> {
+        self.reads.lock().unwrap().clear();
+        let batches = ctx.sql(sql).await?.collect().await?;
+        let tables = std::mem::take(&mut *self.reads.lock().unwrap());
+        self.entries.lock().unwrap().push(QueryLogEntry {
+            sql: sql.to_owned(),
+            tables,
+        });
+        Ok(batches)
+    }
+    /// Execute the query of the entry again. Fails if one of the tables registered with the log is no longer at the
+    /// recorded snapshot, because the results could differ.
+    pub async fn replay(
+        &self,
+        ctx: &SessionContext,
+        entry: &QueryLogEntry,
+    ) -> Result<Vec<RecordBatch>> {
+        for read in &entry.tables {
+            let snapshot_id = self
+                .tables
+                .lock()
+                .unwrap()
+                .get(&read.name)
+                .and_then(Weak::upgrade)
+                .map(|table| match &table.relation {
+                    Relation::Table(table) => table.metadata().current_snapshot_id(),
+                    Relation::View(_) => None,
+                })
+                .ok_or_else(|| {
+                    DataFusionError::Plan(format!(
+                        "Table {} is not registered with the query log.",
+                        read.name
+                    ))
+                })?;
+            if snapshot_id != read.snapshot_id {
+                return Err(DataFusionError::Plan(format!(
+                    "Table {} is at snapshot {:?} but the query read snapshot {:?}.",
+                    read.name, snapshot_id, read.snapshot_id
+                )));
+            }
+        }
+        self.sql(ctx, &entry.sql).await
+    }
+    /// All queries that were executed through the log
+    pub fn entries(&self) -> Vec<QueryLogEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+    pub(crate) fn record(&self, read: TableRead) {
+        let mut reads = self.reads.lock().unwrap();
+        if !reads.contains(&read) {
+            reads.push(read);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_query_log() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        );
+
+        let ctx = SessionContext::new();
+        let log = QueryLog::new();
+
+        log.register_table(&ctx, "nyc_taxis", table).unwrap();
+
+        log.sql(&ctx, "SELECT COUNT(*) FROM nyc_taxis")
+            .await
+            .expect("Failed to execute query.");
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].tables.len(), 1);
+        assert_eq!(entries[0].tables[0].name, "nyc_taxis");
+        assert_eq!(entries[0].tables[0].snapshot_id, Some(638933773299822130));
+
+        let batches = log
+            .replay(&ctx, &entries[0])
+            .await
+            .expect("Failed to replay query.");
+        assert_eq!(
+            batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            1
+        );
+        assert_eq!(log.entries().len(), 2);
+    }
+}
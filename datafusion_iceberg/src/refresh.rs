@@ -0,0 +1,133 @@
+/*!
+ * Refreshing tables with the current metadata of the catalog
+ *
+ * A loaded table always reads the snapshot of the metadata it was loaded with. Refreshing loads the current metadata
+ * of the table from the catalog again. The refreshing table does this automatically before a scan once the loaded
+ * metadata is older than the maximum age, so long running services pick up new snapshots.
+*/
+
+use std::{
+    any::Any,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use datafusion::{
+    arrow::datatypes::SchemaRef,
+    datasource::TableProvider,
+    error::{DataFusionError, Result},
+    execution::context::SessionState,
+    logical_expr::{TableProviderFilterPushDown, TableType},
+    physical_plan::ExecutionPlan,
+    prelude::Expr,
+};
+use iceberg_rs::catalog::{identifier::Identifier, Catalog};
+
+use crate::{error::Error, DataFusionTable};
+
+impl DataFusionTable {
+    /// Load the current metadata of the table from the catalog. Returns true if the metadata changed.
+    pub async fn refresh(
+        &mut self,
+        catalog: &Arc<dyn Catalog>,
+        identifier: &Identifier,
+    ) -> Result<bool> {
+        let relation = catalog
+            .clone()
+            .load_table(identifier)
+            .await
+            .map_err(Error::catalog)?;
+        let relation = self.on_branch(relation).await?;
+        let changed = relation.metadata_location() != self.relation.metadata_location();
+        self.relation = relation;
+        *self.statistics_cache.lock().unwrap() = None;
+        Ok(changed)
+    }
+}
+
+/// Table that loads the current metadata from the catalog before a scan when the loaded metadata is too old
+pub struct RefreshingTable {
+    // Table that is currently read and the time it was loaded
+    current: RwLock<(Arc<DataFusionTable>, Instant)>,
+    catalog: Arc<dyn Catalog>,
+    identifier: Identifier,
+    max_age: Duration,
+}
+
+impl RefreshingTable {
+    /// The table has to be loaded from the catalog under the identifier. Its options are kept for every refresh.
+    pub fn new(
+        table: DataFusionTable,
+        catalog: Arc<dyn Catalog>,
+        identifier: Identifier,
+        max_age: Duration,
+    ) -> Self {
+        RefreshingTable {
+            current: RwLock::new((Arc::new(table), Instant::now())),
+            catalog,
+            identifier,
+            max_age,
+        }
+    }
+    /// Table with the metadata that was loaded last
+    pub fn current(&self) -> Arc<DataFusionTable> {
+        self.current.read().unwrap().0.clone()
+    }
+    /// Load the current metadata of the table from the catalog
+    pub async fn refresh(&self) -> Result<Arc<DataFusionTable>> {
+        // The lock is not held while loading, concurrent refreshes both load the table and the last one wins
+        let relation = self
+            .catalog
+            .clone()
+            .load_table(&self.identifier)
+            .await
+            .map_err(Error::catalog)?;
+        let current = self.current();
+        let table = Arc::new(current.with_relation(current.on_branch(relation).await?));
+        *self.current.write().unwrap() = (table.clone(), Instant::now());
+        Ok(table)
+    }
+    fn is_stale(&self) -> bool {
+        self.current.read().unwrap().1.elapsed() > self.max_age
+    }
+}
+
+#[async_trait::async_trait]
+impl TableProvider for RefreshingTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn schema(&self) -> SchemaRef {
+        self.current().schema()
+    }
+    fn supports_filter_pushdown(&self, filter: &Expr) -> Result<TableProviderFilterPushDown> {
+        self.current().supports_filter_pushdown(filter)
+    }
+    fn table_type(&self) -> TableType {
+        self.current().table_type()
+    }
+    async fn scan(
+        &self,
+        session: &SessionState,
+        projection: &Option<Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        // The projection refers to the schema the query was planned with, so a refresh can't change the schema
+        let table = match self.is_stale() {
+            true => {
+                let planned = self.schema();
+                let table = self.refresh().await?;
+                if table.schema() != planned {
+                    return Err(DataFusionError::Plan(format!(
+                        "The schema of table {} changed while the query was planned.",
+                        self.identifier
+                    )));
+                }
+                table
+            }
+            false => self.current(),
+        };
+        table.scan(session, projection, filters, limit).await
+    }
+}
@@ -0,0 +1,85 @@
+/*!
+ * Reports about scans for external metrics systems
+ *
+ * Tables call the metrics reporter after a scan was planned, similar to the scan reports of the iceberg java library.
+ * The reporter is called on the planning path, so it should hand the report off instead of blocking.
+*/
+
+use std::{fmt::Debug, time::Duration};
+
+/// Statistics about the planning of a scan
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    /// Location of the metadata file of the scanned table
+    pub metadata_location: String,
+    /// Snapshot that is scanned. None if the table has no snapshot yet.
+    pub snapshot_id: Option<i64>,
+    /// Filter that was used to prune manifests and data files
+    pub filter: Option<String>,
+    /// Columns that are read
+    pub projection: Vec<String>,
+    /// Manifests that were read during planning
+    pub manifests_read: usize,
+    /// Data files that are read by the scan
+    pub files_matched: usize,
+    /// Data files of the read manifests that were pruned
+    pub files_pruned: usize,
+    /// Bytes of the data files that are read
+    pub bytes_scanned: usize,
+    /// Time it took to plan the scan
+    pub planning_duration: Duration,
+}
+
+/// Receiver of the reports of a table
+pub trait MetricsReporter: Debug + Send + Sync {
+    fn report_scan(&self, report: &ScanReport);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use datafusion::{datasource::TableProvider, prelude::SessionContext};
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use crate::DataFusionTable;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct CollectingReporter(Mutex<Vec<ScanReport>>);
+
+    impl MetricsReporter for CollectingReporter {
+        fn report_scan(&self, report: &ScanReport) {
+            self.0.lock().unwrap().push(report.clone());
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_scan_report() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let reporter = Arc::new(CollectingReporter::default());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        )
+        .with_metrics_reporter(reporter.clone());
+
+        table
+            .scan(&SessionContext::new().state(), &None, &[], None)
+            .await
+            .expect("Failed to plan scan.");
+
+        let reports = reporter.0.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].snapshot_id, Some(638933773299822130));
+        assert_eq!(reports[0].files_matched, 4);
+        assert!(reports[0].filter.is_none());
+    }
+}
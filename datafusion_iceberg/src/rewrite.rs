@@ -0,0 +1,767 @@
+/*!
+ * Compaction of the small data files and manifests of a table
+ *
+ * Tables that are written in small batches end up with many small data files, which makes planning and reading slow.
+ * The rewrite groups the small files of every partition into bins of the target file size, reads the bins with
+ * datafusion and writes their rows into new files. The new files replace the small ones in a replace snapshot, so the
+ * rows of the table don't change.
+ *
+ * Only files that were written with the default partition spec are rewritten. By default the rows keep the order in
+ * which the files are read. The sort strategy sorts the rows of the rewrite by columns, the z-order strategy clusters
+ * them by the z-order of the columns, so that the new files cover small ranges of all the columns.
+ *
+ * Every commit also adds a manifest, so planning has to read more manifests the more often a table is written. The
+ * manifest rewrite sorts the entries of the data manifests by partition and writes them into manifests of the target
+ * manifest size, so that planning reads few manifests and can skip the manifests of other partitions. Only the
+ * manifests change, the data files stay the same.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use datafusion::{
+    arrow::compute::SortOptions,
+    common::DataFusionError,
+    error::Result,
+    execution::context::{SessionState, TaskContext},
+    physical_plan::{
+        coalesce_partitions::CoalescePartitionsExec,
+        expressions::{Column, PhysicalSortExpr},
+        sorts::sort::SortExec,
+        ExecutionPlan, PhysicalExpr,
+    },
+};
+use iceberg_rs::{
+    model::{
+        manifest::{Content, FileFormat, ManifestEntry, Status},
+        manifest_list::ManifestFile,
+        snapshot::{Operation, Summary},
+        table_metadata::TableMetadata,
+    },
+    table::Table,
+};
+use object_store::{path::Path, ObjectStore};
+use uuid::Uuid;
+
+use crate::{
+    commit::{
+        add_snapshot, explicit_ids, live_counts, manifest_entry, manifest_file, object_path,
+        write_manifest, write_manifest_list, Change, SnapshotChange,
+    },
+    error::Error,
+    masking,
+    metadata::{ManifestEntryExt, TableMetadataExt},
+    zorder::{self, ZOrderExpr},
+    DataFusionTable,
+};
+
+/// Table property for the size of the manifests that are written by the manifest rewrite
+pub const COMMIT_MANIFEST_TARGET_SIZE_BYTES: &str = "commit.manifest.target-size-bytes";
+
+const DEFAULT_MIN_INPUT_FILES: usize = 5;
+const DEFAULT_MANIFEST_TARGET_SIZE: usize = 8_388_608;
+
+/// Order of the rows in the rewritten files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RewriteStrategy {
+    /// The rows keep the order in which the files are read
+    #[default]
+    BinPack,
+    /// Sort the rows by the columns in ascending order, nulls first
+    Sort(Vec<String>),
+    /// Cluster the rows by the z-order of the columns
+    ZOrder(Vec<String>),
+}
+
+/// Options for the rewrite of the data files
+#[derive(Debug, Clone)]
+pub struct RewriteOptions {
+    target_file_size: Option<usize>,
+    min_input_files: usize,
+    strategy: RewriteStrategy,
+    rewrite_all: bool,
+}
+
+impl Default for RewriteOptions {
+    fn default() -> Self {
+        RewriteOptions {
+            target_file_size: None,
+            min_input_files: DEFAULT_MIN_INPUT_FILES,
+            strategy: RewriteStrategy::default(),
+            rewrite_all: false,
+        }
+    }
+}
+
+impl RewriteOptions {
+    /// Size of the rewritten files. Defaults to the target file size of the table properties. Files smaller than three
+    /// quarters of the target file size are rewritten.
+    pub fn with_target_file_size(mut self, target_file_size: usize) -> Self {
+        self.target_file_size = Some(target_file_size);
+        self
+    }
+    /// Smallest number of files of a partition that are rewritten into one file
+    pub fn with_min_input_files(mut self, min_input_files: usize) -> Self {
+        self.min_input_files = min_input_files.max(2);
+        self
+    }
+    /// Order of the rows in the rewritten files
+    pub fn with_strategy(mut self, strategy: RewriteStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+    /// Rewrite all files of the default partition spec regardless of their size and number, for example to sort a
+    /// table that was written without order
+    pub fn with_rewrite_all(mut self) -> Self {
+        self.rewrite_all = true;
+        self
+    }
+}
+
+/// Outcome of the rewrite of the data files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RewriteResult {
+    /// Number of data files that were replaced
+    pub rewritten_data_files: usize,
+    /// Number of data files that were written
+    pub added_data_files: usize,
+    /// Size in bytes of the data files that were replaced
+    pub rewritten_bytes: usize,
+}
+
+/// Options for the rewrite of the manifests
+#[derive(Debug, Clone, Default)]
+pub struct RewriteManifestsOptions {
+    target_manifest_size: Option<usize>,
+}
+
+impl RewriteManifestsOptions {
+    /// Size of the rewritten manifests. Defaults to the target manifest size of the table properties.
+    pub fn with_target_manifest_size(mut self, target_manifest_size: usize) -> Self {
+        self.target_manifest_size = Some(target_manifest_size);
+        self
+    }
+}
+
+/// Outcome of the rewrite of the manifests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RewriteManifestsResult {
+    /// Number of manifests that were replaced
+    pub rewritten_manifests: usize,
+    /// Number of manifests that were written
+    pub added_manifests: usize,
+}
+
+impl DataFusionTable {
+    /// Rewrite the small data files of the table into files of the target file size and commit them as a replace
+    /// snapshot. Does nothing if no partition has enough small files.
+    pub async fn rewrite_data_files(
+        &mut self,
+        session: &SessionState,
+        options: &RewriteOptions,
+    ) -> Result<RewriteResult> {
+        // The rewritten files would contain the masked values
+        if masking::is_masked(&self.masking, session) {
+            return Err(DataFusionError::Plan(
+                "The data files of a table with masked columns can only be rewritten by sessions that read the \
+                 unmasked values."
+                    .to_string(),
+            ));
+        }
+        let mut config = self.writer_config()?.with_session_options(&session.config);
+        if let Some(target_file_size) = options.target_file_size {
+            config = config.with_target_file_size(target_file_size);
+        }
+        let table = self.table()?;
+        let spec_id = table.metadata().default_spec_id();
+
+        // The small files of the default partition spec by partition
+        let min_file_size = match options.rewrite_all {
+            true => usize::MAX,
+            false => config.target_file_size() / 4 * 3,
+        };
+        let min_input_files = match options.rewrite_all {
+            true => 1,
+            false => options.min_input_files,
+        };
+        let mut partitions: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+        for (index, manifest) in table.manifests().iter().enumerate() {
+            if manifest.partition_spec_id() != spec_id {
+                continue;
+            }
+            let mut mask = vec![false; table.manifests().len()];
+            mask[index] = true;
+            let files = table.files(Some(mask)).await.map_err(Error::iceberg)?;
+            for entry in files {
+                let size = entry.file_size_in_bytes() as usize;
+                if entry.is_deleted() || !is_data(&entry) || size >= min_file_size {
+                    continue;
+                }
+                let partition =
+                    serde_json::to_string(entry.partition_values()).map_err(Error::iceberg)?;
+                partitions
+                    .entry(partition)
+                    .or_default()
+                    .push((entry.file_path().to_owned(), size));
+            }
+        }
+        let bins: Vec<Vec<(String, usize)>> = partitions
+            .into_values()
+            .flat_map(|files| bin_pack(files, config.target_file_size()))
+            .filter(|bin| bin.len() >= min_input_files)
+            .collect();
+        if bins.is_empty() {
+            return Ok(RewriteResult::default());
+        }
+        let rewritten: HashSet<String> = bins
+            .iter()
+            .flatten()
+            .map(|(path, _)| path.clone())
+            .collect();
+        let rewritten_bytes = bins.iter().flatten().map(|(_, size)| size).sum();
+
+        // The rows are read with the columns of the table schema and written with the partition spec of the table
+        let projection = (0..table.schema().fields.len()).collect();
+        let plan = self
+            .scan_files(session, &Some(projection), &[], None, Some(&rewritten))
+            .await?;
+        let plan = cluster(
+            Arc::new(CoalescePartitionsExec::new(plan)),
+            &options.strategy,
+        )?;
+        let batches = plan.execute(0, Arc::new(TaskContext::from(session)))?;
+        let files = self.write(batches, &config).await?;
+
+        let result = RewriteResult {
+            rewritten_data_files: rewritten.len(),
+            added_data_files: files.len(),
+            rewritten_bytes,
+        };
+        let mut change =
+            SnapshotChange::new(Operation::Replace, spec_id, files, rewritten).with_owned_files();
+        self.commit(&mut change).await?;
+        Ok(result)
+    }
+}
+
+impl DataFusionTable {
+    /// Rewrite the data manifests of the table into manifests of the target manifest size, grouped by partition, and
+    /// commit them as a replace snapshot. Does nothing if the rewrite wouldn't reduce the number of manifests.
+    pub async fn rewrite_manifests(
+        &mut self,
+        options: &RewriteManifestsOptions,
+    ) -> Result<RewriteManifestsResult> {
+        let table = self.table()?;
+        let target_size = match options.target_manifest_size {
+            Some(target_size) => target_size,
+            None => target_manifest_size(table.metadata())?,
+        };
+        let plan = plan_manifests(table, target_size).await?;
+        if plan.manifests.len() >= plan.rewritten.len() {
+            return Ok(RewriteManifestsResult::default());
+        }
+        let mut change = ManifestChange {
+            snapshot_id: (Uuid::new_v4().as_u128() as i64) & i64::MAX,
+            target_size,
+            attempt_files: Vec::new(),
+            result: RewriteManifestsResult::default(),
+        };
+        self.commit(&mut change).await?;
+        Ok(change.result)
+    }
+}
+
+/// Plan that orders the rows of the input by the strategy
+fn cluster(
+    plan: Arc<dyn ExecutionPlan>,
+    strategy: &RewriteStrategy,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let schema = plan.schema();
+    let columns = |names: &[String]| -> Result<Vec<Arc<dyn PhysicalExpr>>> {
+        if names.is_empty() {
+            return Err(DataFusionError::Plan(
+                "A rewrite that orders the rows needs at least one column.".to_string(),
+            ));
+        }
+        names
+            .iter()
+            .map(|name| {
+                let index = schema.index_of(name).map_err(|_| {
+                    DataFusionError::Plan(format!("Column {} doesn't exist.", name))
+                })?;
+                Ok(Arc::new(Column::new(name, index)) as Arc<dyn PhysicalExpr>)
+            })
+            .collect()
+    };
+    let ordering = match strategy {
+        RewriteStrategy::BinPack => return Ok(plan),
+        RewriteStrategy::Sort(names) => columns(names)?
+            .into_iter()
+            .map(|expr| PhysicalSortExpr {
+                expr,
+                options: SortOptions::default(),
+            })
+            .collect(),
+        RewriteStrategy::ZOrder(names) => {
+            let columns = columns(names)?;
+            for column in &columns {
+                let data_type = column.data_type(&schema)?;
+                if !zorder::is_supported(&data_type) {
+                    return Err(DataFusionError::Plan(format!(
+                        "Column {} of type {} can't be z-ordered.",
+                        column, data_type
+                    )));
+                }
+            }
+            vec![PhysicalSortExpr {
+                expr: Arc::new(ZOrderExpr::new(columns)),
+                options: SortOptions::default(),
+            }]
+        }
+    };
+    Ok(Arc::new(SortExec::try_new(ordering, plan, None)?))
+}
+
+fn target_manifest_size(metadata: &TableMetadata) -> Result<usize> {
+    match metadata
+        .properties()
+        .and_then(|properties| properties.get(COMMIT_MANIFEST_TARGET_SIZE_BYTES))
+    {
+        Some(value) => value.parse().map_err(|_| {
+            DataFusionError::Plan(format!(
+                "Table property {} has to be a positive integer, found {}.",
+                COMMIT_MANIFEST_TARGET_SIZE_BYTES, value
+            ))
+        }),
+        None => Ok(DEFAULT_MANIFEST_TARGET_SIZE),
+    }
+}
+
+/// Manifests of the table that are rewritten and the entries of the new manifests with their partition spec
+struct ManifestPlan {
+    rewritten: Vec<usize>,
+    manifests: Vec<(i32, Vec<ManifestEntry>)>,
+}
+
+/// Group the entries of the data manifests by partition spec and partition and split them into manifests of the target
+/// size. The size of an entry is estimated from the size of the current manifests.
+async fn plan_manifests(table: &Table, target_size: usize) -> Result<ManifestPlan> {
+    let format_version = table.metadata().format_version();
+    let mut rewritten = Vec::new();
+    let mut partitions: HashMap<(i32, String), Vec<ManifestEntry>> = HashMap::new();
+    let (mut bytes, mut entries) = (0, 0);
+    for (index, manifest) in table.manifests().iter().enumerate() {
+        // Delete manifests are kept as they are
+        if let ManifestFile::V2(manifest) = manifest {
+            if !matches!(manifest.content, Content::Data) {
+                continue;
+            }
+        }
+        rewritten.push(index);
+        let mut mask = vec![false; table.manifests().len()];
+        mask[index] = true;
+        let files = table.files(Some(mask)).await.map_err(Error::iceberg)?;
+        bytes += manifest_length(manifest);
+        entries += files.len();
+        for entry in files {
+            if entry.is_deleted() {
+                continue;
+            }
+            let partition =
+                serde_json::to_string(entry.partition_values()).map_err(Error::iceberg)?;
+            let (snapshot_id, sequence_number, data_file) = explicit_ids(manifest, entry);
+            partitions
+                .entry((manifest.partition_spec_id(), partition))
+                .or_default()
+                .push(manifest_entry(
+                    &format_version,
+                    Status::Existing,
+                    Some(snapshot_id),
+                    sequence_number,
+                    data_file,
+                ));
+        }
+    }
+    let entries_per_manifest = match bytes / entries.max(1) {
+        0 => usize::MAX,
+        entry_size => (target_size / entry_size).max(1),
+    };
+
+    // The partitions of a spec are written in order, so that a manifest covers few partitions
+    let mut partitions: Vec<_> = partitions.into_iter().collect();
+    partitions.sort_by(|left, right| left.0.cmp(&right.0));
+    let mut manifests: Vec<(i32, Vec<ManifestEntry>)> = Vec::new();
+    for ((spec_id, _), entries) in partitions {
+        for entry in entries {
+            match manifests.last_mut() {
+                Some((id, manifest)) if *id == spec_id && manifest.len() < entries_per_manifest => {
+                    manifest.push(entry)
+                }
+                _ => manifests.push((spec_id, vec![entry])),
+            }
+        }
+    }
+    Ok(ManifestPlan {
+        rewritten,
+        manifests,
+    })
+}
+
+fn manifest_length(manifest: &ManifestFile) -> usize {
+    match manifest {
+        ManifestFile::V1(manifest) => manifest.manifest_length as usize,
+        ManifestFile::V2(manifest) => manifest.manifest_length as usize,
+    }
+}
+
+/// Replacement of the data manifests of the table, planned again for every attempt
+struct ManifestChange {
+    snapshot_id: i64,
+    target_size: usize,
+    // Manifests and manifest list of the last attempt
+    attempt_files: Vec<Path>,
+    result: RewriteManifestsResult,
+}
+
+#[async_trait::async_trait]
+impl Change for ManifestChange {
+    async fn apply(&mut self, table: &Table) -> Result<TableMetadata> {
+        let metadata = table.metadata();
+        let format_version = metadata.format_version();
+        let location = metadata.location().trim_end_matches('/');
+        let sequence_number = match metadata {
+            TableMetadata::V1(_) => 0,
+            TableMetadata::V2(metadata) => metadata.last_sequence_number + 1,
+        };
+        let plan = plan_manifests(table, self.target_size).await?;
+
+        let mut manifests = Vec::new();
+        for (spec_id, entries) in &plan.manifests {
+            let path = format!("{}/metadata/{}-m0.avro", location, Uuid::new_v4());
+            let manifest = write_manifest(table, &path, *spec_id, entries, None).await?;
+            self.attempt_files.push(object_path(&manifest.path));
+            manifests.push(manifest_file(
+                &format_version,
+                &manifest,
+                self.snapshot_id,
+                sequence_number,
+            ));
+        }
+        manifests.extend(
+            table
+                .manifests()
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !plan.rewritten.contains(index))
+                .map(|(_, manifest)| manifest.clone()),
+        );
+
+        let manifest_list = format!(
+            "{}/metadata/snap-{}-{}.avro",
+            location,
+            self.snapshot_id,
+            Uuid::new_v4()
+        );
+        write_manifest_list(
+            &table.object_store(),
+            &manifest_list,
+            &manifests,
+            metadata,
+            self.snapshot_id,
+            sequence_number,
+        )
+        .await?;
+        self.attempt_files.push(object_path(&manifest_list));
+
+        let (total_files, total_records) = manifests.iter().fold((0, 0), |acc, manifest| {
+            let (files, records) = live_counts(manifest);
+            (acc.0 + files, acc.1 + records)
+        });
+        let summary = Summary {
+            operation: Operation::Replace,
+            other: [
+                ("manifests-created", plan.manifests.len() as i64),
+                ("manifests-replaced", plan.rewritten.len() as i64),
+                (
+                    "manifests-kept",
+                    (table.manifests().len() - plan.rewritten.len()) as i64,
+                ),
+                (
+                    "entries-processed",
+                    plan.manifests
+                        .iter()
+                        .map(|(_, entries)| entries.len() as i64)
+                        .sum(),
+                ),
+                ("total-data-files", total_files),
+                ("total-records", total_records),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_string()))
+            .collect(),
+        };
+        self.result = RewriteManifestsResult {
+            rewritten_manifests: plan.rewritten.len(),
+            added_manifests: plan.manifests.len(),
+        };
+        add_snapshot(
+            metadata,
+            self.snapshot_id,
+            sequence_number,
+            manifest_list,
+            summary,
+        )
+    }
+    async fn discard_attempt(&mut self, object_store: &Arc<dyn ObjectStore>) {
+        for path in self.attempt_files.drain(..) {
+            let _ = object_store.delete(&path).await;
+        }
+    }
+}
+
+/// Only parquet files with data can be rewritten, delete files would have to be applied to the rows
+fn is_data(entry: &ManifestEntry) -> bool {
+    let content = match entry {
+        ManifestEntry::V1(_) => &Content::Data,
+        ManifestEntry::V2(entry) => &entry.data_file.content,
+    };
+    matches!(content, Content::Data) && matches!(entry.file_format(), FileFormat::Parquet)
+}
+
+/// Group the files into bins of at most the target size. Each file goes into the first bin with enough space, starting
+/// with the largest file.
+fn bin_pack(mut files: Vec<(String, usize)>, target_size: usize) -> Vec<Vec<(String, usize)>> {
+    files.sort_by(|left, right| right.1.cmp(&left.1).then_with(|| left.0.cmp(&right.0)));
+    let mut bins: Vec<(usize, Vec<(String, usize)>)> = Vec::new();
+    for file in files {
+        match bins
+            .iter_mut()
+            .find(|(size, _)| size + file.1 <= target_size)
+        {
+            Some((size, bin)) => {
+                *size += file.1;
+                bin.push(file);
+            }
+            None => bins.push((file.1, vec![file])),
+        }
+    }
+    bins.into_iter().map(|(_, bin)| bin).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::{
+        arrow::array::Int64Array, parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+        prelude::SessionContext,
+    };
+    use iceberg_rs::table::Table;
+
+    use crate::{
+        commit::tests::{insert_trips, row_count},
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_rewrite_data_files() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        for trip in 0..3 {
+            insert_trips(&mut table, [trip * 10, trip * 10 + 1]).await;
+        }
+        assert_eq!(row_count(&table).await, 10);
+
+        let ctx = SessionContext::new();
+        let options = RewriteOptions::default().with_min_input_files(3);
+        let result = table
+            .rewrite_data_files(&ctx.state(), &options)
+            .await
+            .unwrap();
+        // The two original files and the three inserted files of both vendor ids are rewritten into one file each
+        assert_eq!(
+            result,
+            RewriteResult {
+                rewritten_data_files: 10,
+                added_data_files: 2,
+                rewritten_bytes: result.rewritten_bytes,
+            }
+        );
+        assert_eq!(row_count(&table).await, 10);
+
+        // The table is read from the rewritten files after a reload
+        let table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(row_count(&table).await, 10);
+        let files = table
+            .table()
+            .unwrap()
+            .files(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|entry| !entry.is_deleted())
+            .count();
+        assert_eq!(files, 2);
+
+        // Nothing is left to rewrite
+        let mut table = table;
+        let result = table
+            .rewrite_data_files(&ctx.state(), &options)
+            .await
+            .unwrap();
+        assert_eq!(result, RewriteResult::default());
+    }
+
+    /// Trip ids of the live data files in the order of their rows
+    async fn trip_ids(table: &DataFusionTable) -> Vec<Vec<i64>> {
+        let table = table.table().unwrap();
+        let mut files = Vec::new();
+        for entry in table.files(None).await.unwrap() {
+            if entry.is_deleted() {
+                continue;
+            }
+            let bytes = table
+                .object_store()
+                .get(&object_path(entry.file_path()))
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(bytes)
+                .unwrap()
+                .build()
+                .unwrap();
+            let mut trips = Vec::new();
+            for batch in reader {
+                let batch = batch.unwrap();
+                let column = batch.column(batch.schema().index_of("trip_id").unwrap());
+                let column = column.as_any().downcast_ref::<Int64Array>().unwrap();
+                trips.extend(column.iter().flatten());
+            }
+            files.push(trips);
+        }
+        files
+    }
+
+    #[tokio::test]
+    pub async fn test_rewrite_strategies() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        for trip in (0..3).rev() {
+            insert_trips(&mut table, [trip * 10 + 1, trip * 10]).await;
+        }
+        let ctx = SessionContext::new();
+
+        let options = RewriteOptions::default()
+            .with_rewrite_all()
+            .with_strategy(RewriteStrategy::Sort(vec!["pickup_zone".to_owned()]));
+        assert!(table
+            .rewrite_data_files(&ctx.state(), &options)
+            .await
+            .is_err());
+
+        // One sorted file per vendor id
+        let options = RewriteOptions::default()
+            .with_rewrite_all()
+            .with_strategy(RewriteStrategy::Sort(vec!["trip_id".to_owned()]));
+        let result = table
+            .rewrite_data_files(&ctx.state(), &options)
+            .await
+            .unwrap();
+        assert_eq!(result.added_data_files, 2);
+        let files = trip_ids(&table).await;
+        assert_eq!(files.len(), 2);
+        for trips in files {
+            let mut sorted = trips.clone();
+            sorted.sort();
+            assert_eq!(trips, sorted);
+        }
+
+        // The files are rewritten again even though there are only two of them
+        let options =
+            RewriteOptions::default()
+                .with_rewrite_all()
+                .with_strategy(RewriteStrategy::ZOrder(vec![
+                    "trip_distance".to_owned(),
+                    "trip_id".to_owned(),
+                ]));
+        let result = table
+            .rewrite_data_files(&ctx.state(), &options)
+            .await
+            .unwrap();
+        assert_eq!(result.rewritten_data_files, 2);
+        assert_eq!(row_count(&table).await, 10);
+    }
+
+    #[tokio::test]
+    pub async fn test_rewrite_manifests() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        for trip in 0..3 {
+            insert_trips(&mut table, [trip * 10, trip * 10 + 1]).await;
+        }
+        assert_eq!(table.table().unwrap().manifests().len(), 4);
+
+        // Manifests with one entry each wouldn't reduce the number of manifests
+        let options = RewriteManifestsOptions::default().with_target_manifest_size(1);
+        let result = table.rewrite_manifests(&options).await.unwrap();
+        assert_eq!(result, RewriteManifestsResult::default());
+
+        let options = RewriteManifestsOptions::default();
+        let result = table.rewrite_manifests(&options).await.unwrap();
+        assert_eq!(
+            result,
+            RewriteManifestsResult {
+                rewritten_manifests: 4,
+                added_manifests: 1,
+            }
+        );
+        assert_eq!(row_count(&table).await, 10);
+
+        // The entries of both vendor ids are sorted by partition
+        let table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(table.table().unwrap().manifests().len(), 1);
+        assert_eq!(row_count(&table).await, 10);
+        let vendor_ids: Vec<String> = table
+            .table()
+            .unwrap()
+            .files(None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| serde_json::to_string(entry.partition_values()).unwrap())
+            .collect();
+        let mut sorted = vendor_ids.clone();
+        sorted.sort();
+        assert_eq!(vendor_ids.len(), 10);
+        assert_eq!(vendor_ids, sorted);
+
+        let mut table = table;
+        let result = table.rewrite_manifests(&options).await.unwrap();
+        assert_eq!(result, RewriteManifestsResult::default());
+    }
+}
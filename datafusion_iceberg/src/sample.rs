@@ -0,0 +1,168 @@
+/*!
+ * Approximate sampling of iceberg tables
+ *
+ * The data files are split into byte ranges and a deterministic subset of the ranges is scanned. The parquet reader only
+ * reads the row groups that start inside of a range, so whole row groups are selected or skipped.
+ *
+ * File samples select whole data files instead. The files are taken in a random order until they contain the fraction
+ * of the records of the table, so the record counts of the manifests determine the size of the sample and large
+ * files are as likely to be selected as small ones. Only the selected files are opened.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use datafusion::{
+    datasource::listing::{FileRange, PartitionedFile},
+    physical_plan::Statistics,
+};
+
+use crate::transform::murmur3_32;
+
+/// Size of the byte ranges a data file is split into. Row groups that are smaller than this are sampled together.
+const SAMPLE_RANGE_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unit {
+    RowGroups,
+    Files,
+}
+
+/// Random sample of the row groups or data files of a table
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    fraction: f64,
+    seed: u64,
+    unit: Unit,
+}
+
+impl Sample {
+    /// Select every row group with probability `fraction`. The same seed always selects the same row groups.
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        Sample {
+            fraction: fraction.clamp(0.0, 1.0),
+            seed,
+            unit: Unit::RowGroups,
+        }
+    }
+    /// Select whole data files that together contain about `fraction` of the records. The same seed always selects the
+    /// same files.
+    pub fn files(fraction: f64, seed: u64) -> Self {
+        Sample {
+            unit: Unit::Files,
+            ..Sample::new(fraction, seed)
+        }
+    }
+    /// Sample the files of the groups and scale the statistics to the sample. The record counts are keyed by the path of
+    /// the data files.
+    pub(crate) fn sample<K: Eq + Hash>(
+        &self,
+        file_groups: HashMap<K, Vec<PartitionedFile>>,
+        record_counts: &HashMap<String, usize>,
+        statistics: Statistics,
+    ) -> (HashMap<K, Vec<PartitionedFile>>, Statistics) {
+        match self.unit {
+            Unit::RowGroups => (
+                file_groups
+                    .into_iter()
+                    .map(|(key, files)| (key, self.sample_files(files)))
+                    .collect(),
+                self.scale_statistics(statistics),
+            ),
+            Unit::Files => self.sample_whole_files(file_groups, record_counts, statistics),
+        }
+    }
+    fn sample_whole_files<K: Eq + Hash>(
+        &self,
+        file_groups: HashMap<K, Vec<PartitionedFile>>,
+        record_counts: &HashMap<String, usize>,
+        statistics: Statistics,
+    ) -> (HashMap<K, Vec<PartitionedFile>>, Statistics) {
+        let record_count = |file: &PartitionedFile| {
+            record_counts
+                .get(file.object_meta.location.as_ref())
+                .copied()
+                .unwrap_or_default()
+        };
+        let mut files: Vec<&PartitionedFile> = file_groups.values().flatten().collect();
+        files.sort_by_key(|file| self.hash(file.object_meta.location.as_ref(), 0));
+        let target =
+            files.iter().map(|file| record_count(file)).sum::<usize>() as f64 * self.fraction;
+        let mut records = 0;
+        let mut bytes = 0;
+        let mut selected = HashSet::new();
+        for file in files {
+            if records as f64 >= target {
+                break;
+            }
+            records += record_count(file);
+            bytes += file.object_meta.size;
+            selected.insert(file.object_meta.location.to_string());
+        }
+        let file_groups = file_groups
+            .into_iter()
+            .map(|(key, files)| {
+                (
+                    key,
+                    files
+                        .into_iter()
+                        .filter(|file| selected.contains(file.object_meta.location.as_ref()))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .filter(|(_, files)| !files.is_empty())
+            .collect();
+        let statistics = Statistics {
+            num_rows: Some(records),
+            total_byte_size: Some(bytes),
+            column_statistics: statistics.column_statistics,
+            is_exact: false,
+        };
+        (file_groups, statistics)
+    }
+    /// Replace the files with the byte ranges that are part of the sample
+    fn sample_files(&self, files: Vec<PartitionedFile>) -> Vec<PartitionedFile> {
+        files
+            .into_iter()
+            .flat_map(|file| {
+                let size = file.object_meta.size;
+                (0..size.div_ceil(SAMPLE_RANGE_SIZE))
+                    .filter(|index| self.selects(file.object_meta.location.as_ref(), *index))
+                    .map(|index| PartitionedFile {
+                        range: Some(FileRange {
+                            start: (index * SAMPLE_RANGE_SIZE) as i64,
+                            end: ((index + 1) * SAMPLE_RANGE_SIZE).min(size) as i64,
+                        }),
+                        ..file.clone()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+    /// Scale the statistics to the expected size of the sample
+    fn scale_statistics(&self, statistics: Statistics) -> Statistics {
+        Statistics {
+            num_rows: statistics
+                .num_rows
+                .map(|num_rows| (num_rows as f64 * self.fraction).round() as usize),
+            total_byte_size: statistics
+                .total_byte_size
+                .map(|size| (size as f64 * self.fraction).round() as usize),
+            column_statistics: statistics.column_statistics,
+            is_exact: false,
+        }
+    }
+    fn selects(&self, path: &str, index: usize) -> bool {
+        (self.hash(path, index) as f64) < self.fraction * u32::MAX as f64
+    }
+    // The hash of the standard library may change between releases, murmur3 keeps samples stable across builds
+    fn hash(&self, path: &str, index: usize) -> u32 {
+        let mut bytes = Vec::with_capacity(16 + path.len());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(path.as_bytes());
+        bytes.extend_from_slice(&(index as u64).to_le_bytes());
+        murmur3_32(&bytes) as u32
+    }
+}
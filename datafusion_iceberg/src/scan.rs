@@ -0,0 +1,288 @@
+/*!
+ * Execution plan of a scan of an iceberg table
+ *
+ * The data files are read by the parquet execution plan of datafusion. The iceberg scan wraps it to report the
+ * properties of the table that datafusion can't know from the data files. EXPLAIN shows the snapshot that is read,
+ * the predicate used for pruning, the number of selected out of candidate data files and the projected columns.
+ *
+ * The scan reports the following metrics:
+ *
+ * - manifests_read: manifests that were read during planning
+ * - files_matched: data files that are read by the scan
+ * - files_pruned: data files of the read manifests that were pruned by the filters
+ * - delete_files_applied: delete files that were applied to the data files. Delete files are not read yet, so this is
+ *   always zero.
+ * - bytes_scanned: size of the data files, or of the sampled byte ranges, that are read
+ * - output_rows: rows emitted by the scan
+*/
+
+use std::{any::Any, fmt, sync::Arc};
+
+use futures::StreamExt;
+
+use datafusion::{
+    arrow::datatypes::SchemaRef,
+    error::Result,
+    execution::context::TaskContext,
+    physical_plan::{
+        expressions::PhysicalSortExpr,
+        metrics::{ExecutionPlanMetricsSet, MetricBuilder, MetricsSet},
+        stream::RecordBatchStreamAdapter,
+        ColumnStatistics, DisplayFormatType, ExecutionPlan, Partitioning,
+        SendableRecordBatchStream, Statistics,
+    },
+    prelude::Expr,
+};
+
+/// Scan of an iceberg table
+#[derive(Debug)]
+pub struct IcebergScanExec {
+    input: Arc<dyn ExecutionPlan>,
+    // Partitioning derived from the bucket partitioning of the table
+    partitioning: Option<Partitioning>,
+    // Ordering derived from the sort order of the data files
+    ordering: Option<Vec<PhysicalSortExpr>>,
+    planning: PlanningMetrics,
+    details: ScanDetails,
+    // Statistics of the projected columns of the table, the projection of the partition columns doesn't keep them
+    column_statistics: Option<Vec<ColumnStatistics>>,
+    metrics: ExecutionPlanMetricsSet,
+}
+
+/// Information about the scan that is shown by EXPLAIN
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ScanDetails {
+    pub snapshot_id: Option<i64>,
+    // Predicate that was used to prune manifests and data files
+    pub predicate: Option<Expr>,
+}
+
+/// Counts that are collected while planning the scan
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PlanningMetrics {
+    pub manifests_read: usize,
+    pub files_matched: usize,
+    pub files_pruned: usize,
+    pub delete_files_applied: usize,
+    pub bytes_scanned: usize,
+}
+
+impl IcebergScanExec {
+    pub(crate) fn new(
+        input: Arc<dyn ExecutionPlan>,
+        partitioning: Option<Partitioning>,
+        ordering: Option<Vec<PhysicalSortExpr>>,
+        planning: PlanningMetrics,
+        details: ScanDetails,
+    ) -> Self {
+        let metrics = ExecutionPlanMetricsSet::new();
+        for (name, value) in [
+            ("manifests_read", planning.manifests_read),
+            ("files_matched", planning.files_matched),
+            ("files_pruned", planning.files_pruned),
+            ("delete_files_applied", planning.delete_files_applied),
+            ("bytes_scanned", planning.bytes_scanned),
+        ] {
+            MetricBuilder::new(&metrics).global_counter(name).add(value);
+        }
+        IcebergScanExec {
+            input,
+            partitioning,
+            ordering,
+            planning,
+            details,
+            column_statistics: None,
+            metrics,
+        }
+    }
+    pub(crate) fn with_column_statistics(
+        mut self,
+        column_statistics: Vec<ColumnStatistics>,
+    ) -> Self {
+        self.column_statistics = Some(column_statistics);
+        self
+    }
+}
+
+impl ExecutionPlan for IcebergScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+    fn output_partitioning(&self) -> Partitioning {
+        self.partitioning
+            .clone()
+            .unwrap_or_else(|| self.input.output_partitioning())
+    }
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        self.ordering
+            .as_deref()
+            .or_else(|| self.input.output_ordering())
+    }
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(IcebergScanExec {
+            column_statistics: self.column_statistics.clone(),
+            ..IcebergScanExec::new(
+                children[0].clone(),
+                self.partitioning.clone(),
+                self.ordering.clone(),
+                self.planning.clone(),
+                self.details.clone(),
+            )
+        }))
+    }
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        let output_rows = MetricBuilder::new(&self.metrics).output_rows(partition);
+        let stream = self.input.execute(partition, context)?.map(move |batch| {
+            if let Ok(batch) = &batch {
+                output_rows.add(batch.num_rows());
+            }
+            batch
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema(),
+            stream,
+        )))
+    }
+    fn metrics(&self) -> Option<MetricsSet> {
+        Some(self.metrics.clone_inner())
+    }
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                let snapshot = match self.details.snapshot_id {
+                    Some(snapshot_id) => snapshot_id.to_string(),
+                    None => "none".to_string(),
+                };
+                let predicate = match &self.details.predicate {
+                    Some(predicate) => predicate.to_string(),
+                    None => "none".to_string(),
+                };
+                let projection: Vec<String> = self
+                    .schema()
+                    .fields()
+                    .iter()
+                    .map(|field| field.name().clone())
+                    .collect();
+                let mut details = vec![
+                    format!("snapshot={}", snapshot),
+                    format!("predicate={}", predicate),
+                    format!(
+                        "files={}/{}",
+                        self.planning.files_matched,
+                        self.planning.files_matched + self.planning.files_pruned
+                    ),
+                    format!("projection=[{}]", projection.join(", ")),
+                ];
+                if let Some(partitioning) = &self.partitioning {
+                    details.push(format!("partitioning={:?}", partitioning));
+                }
+                if let Some(ordering) = &self.ordering {
+                    let ordering: Vec<String> = ordering.iter().map(|x| x.to_string()).collect();
+                    details.push(format!("ordering=[{}]", ordering.join(", ")));
+                }
+                write!(f, "IcebergScanExec: {}", details.join(", "))
+            }
+        }
+    }
+    fn statistics(&self) -> Statistics {
+        let statistics = self.input.statistics();
+        match &self.column_statistics {
+            Some(column_statistics) => Statistics {
+                column_statistics: Some(column_statistics.clone()),
+                ..statistics
+            },
+            None => statistics,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::{
+        arrow::util::pretty::pretty_format_batches, datasource::TableProvider,
+        physical_plan::collect, prelude::SessionContext,
+    };
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use crate::DataFusionTable;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_scan_metrics() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        );
+
+        let ctx = SessionContext::new();
+
+        let plan = table.scan(&ctx.state(), &None, &[], None).await.unwrap();
+        collect(plan.clone(), ctx.task_ctx())
+            .await
+            .expect("Failed to execute scan.");
+
+        let metrics = plan.metrics().expect("Scan has no metrics.");
+        assert_eq!(metrics.output_rows(), Some(4));
+        assert_eq!(
+            metrics
+                .sum_by_name("files_matched")
+                .map(|value| value.as_usize()),
+            Some(4)
+        );
+        assert_eq!(
+            metrics
+                .sum_by_name("files_pruned")
+                .map(|value| value.as_usize()),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_scan_explain() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        ));
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        let batches = ctx
+            .sql("EXPLAIN SELECT trip_id FROM nyc_taxis WHERE trip_distance > 100.0")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .expect("Failed to explain query.");
+
+        let plan = pretty_format_batches(&batches).unwrap().to_string();
+        assert!(plan.contains("IcebergScanExec: snapshot=638933773299822130"));
+        // The filter is pushed down to the scan and used for pruning
+        assert!(!plan.contains("predicate=none"));
+    }
+}
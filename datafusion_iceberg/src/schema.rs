@@ -0,0 +1,564 @@
+/*!
+ * Conversion between iceberg and arrow schemas
+ *
+ * Iceberg identifies fields by id, so the id of every field, including the elements of lists and the keys and values
+ * of maps, is stored in the metadata of the arrow field under [`PARQUET_FIELD_ID`]. The parquet writer doesn't write
+ * these ids into the data files, so the tables carry a [`name_mapping`] from the column names of the files to the
+ * field ids instead. Dates are days, times and timestamps microseconds, like iceberg stores them. Uuids are fixed size
+ * binaries of 16 bytes that carry the arrow uuid extension type, so that they are converted back to uuids.
+ *
+ * Arrow schemas that weren't converted from iceberg may lack field ids. Their fields are numbered after the largest id
+ * of the schema.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+
+use datafusion::{
+    arrow::datatypes::{DataType, Field, Schema, TimeUnit},
+    common::DataFusionError,
+    error::Result,
+};
+use iceberg_rs::model::data_types::{
+    ListType, MapType, PrimitiveType, StructField, StructType, Type,
+};
+use serde_json::{json, Value};
+
+/// Metadata key of the arrow fields for the iceberg field id. The ids only live in the arrow schemas, the data files
+/// are written without them.
+pub const PARQUET_FIELD_ID: &str = "PARQUET:field_id";
+
+const EXTENSION_NAME: &str = "ARROW:extension:name";
+const UUID_EXTENSION: &str = "arrow.uuid";
+const UTC: &str = "UTC";
+
+/// Arrow schema of the iceberg schema with the field ids in the field metadata
+pub fn iceberg_to_arrow_schema(schema: &StructType) -> Schema {
+    Schema::new(arrow_fields(schema))
+}
+
+/// Iceberg schema of the arrow schema. Field ids are read from the field metadata, fields without id get new ids.
+pub fn arrow_to_iceberg_schema(schema: &Schema) -> Result<StructType> {
+    let mut next_id = schema.fields().iter().map(max_field_id).max().unwrap_or(0) + 1;
+    iceberg_fields(schema.fields(), &mut next_id)
+}
+
+/// Iceberg schema of the arrow schema with new field ids that are numbered from 1, for tables that are created for the
+/// arrow schema
+pub(crate) fn arrow_to_new_iceberg_schema(schema: &Schema) -> Result<StructType> {
+    let fields: Vec<Field> = schema.fields().iter().map(without_field_id).collect();
+    iceberg_fields(&fields, &mut 1)
+}
+
+/// Largest field id of the iceberg schema, including the ids of the nested fields
+pub(crate) fn last_field_id(schema: &StructType) -> i32 {
+    arrow_fields(schema)
+        .iter()
+        .map(max_field_id)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Name mapping of the iceberg schema, the value of the [`crate::properties::DEFAULT_NAME_MAPPING`] property. Readers
+/// use it to find the fields of data files without field ids, the elements of lists are named `element` and the keys
+/// and values of maps `key` and `value`.
+pub fn name_mapping(schema: &StructType) -> String {
+    Value::Array(mapped_fields(&schema.fields)).to_string()
+}
+
+/// Name mapping of the evolved schema. Fields keep the names that the previous mapping has for their ids after their
+/// current name, so that the columns of data files that were written before a rename are still found.
+pub(crate) fn updated_name_mapping(previous: &str, schema: &StructType) -> Result<String> {
+    let previous: Value = serde_json::from_str(previous).map_err(|err| {
+        DataFusionError::Plan(format!(
+            "The name mapping of the table is invalid: {}.",
+            err
+        ))
+    })?;
+    let mut names = HashMap::new();
+    previous_names(&previous, &mut names);
+    let mut mapping = Value::Array(mapped_fields(&schema.fields));
+    add_previous_names(&mut mapping, &names);
+    Ok(mapping.to_string())
+}
+
+fn previous_names(mapping: &Value, names: &mut HashMap<i64, Vec<Value>>) {
+    for field in mapping.as_array().into_iter().flatten() {
+        if let (Some(id), Some(field_names)) =
+            (field["field-id"].as_i64(), field["names"].as_array())
+        {
+            names
+                .entry(id)
+                .or_default()
+                .extend(field_names.iter().cloned());
+        }
+        previous_names(&field["fields"], names);
+    }
+}
+
+fn add_previous_names(mapping: &mut Value, names: &HashMap<i64, Vec<Value>>) {
+    for field in mapping.as_array_mut().into_iter().flatten() {
+        if let Some(previous) = field["field-id"].as_i64().and_then(|id| names.get(&id)) {
+            if let Some(field_names) = field["names"].as_array_mut() {
+                for name in previous {
+                    if !field_names.contains(name) {
+                        field_names.push(name.clone());
+                    }
+                }
+            }
+        }
+        if let Some(nested) = field.get_mut("fields") {
+            add_previous_names(nested, names);
+        }
+    }
+}
+
+fn mapped_fields(fields: &[StructField]) -> Vec<Value> {
+    fields
+        .iter()
+        .map(|field| mapped_field(field.id, &field.name, &field.field_type))
+        .collect()
+}
+
+fn mapped_field(id: i32, name: &str, field_type: &Type) -> Value {
+    let mut field = json!({ "field-id": id, "names": [name] });
+    let nested = match field_type {
+        Type::Primitive(_) => return field,
+        Type::Struct(schema) => mapped_fields(&schema.fields),
+        Type::List(list) => vec![mapped_field(list.element_id, "element", &list.element)],
+        Type::Map(map) => vec![
+            mapped_field(map.key_id, "key", &map.key),
+            mapped_field(map.value_id, "value", &map.value),
+        ],
+    };
+    field["fields"] = Value::Array(nested);
+    field
+}
+
+/// Iceberg field id of the arrow field
+pub fn field_id(field: &Field) -> Option<i32> {
+    field.metadata()?.get(PARQUET_FIELD_ID)?.parse().ok()
+}
+
+fn arrow_fields(schema: &StructType) -> Vec<Field> {
+    schema
+        .fields
+        .iter()
+        .map(|field| arrow_field(&field.name, &field.field_type, !field.required, field.id))
+        .collect()
+}
+
+fn arrow_field(name: &str, field_type: &Type, nullable: bool, id: i32) -> Field {
+    let mut metadata = BTreeMap::from([(PARQUET_FIELD_ID.to_owned(), id.to_string())]);
+    if let Type::Primitive(PrimitiveType::Uuid) = field_type {
+        metadata.insert(EXTENSION_NAME.to_owned(), UUID_EXTENSION.to_owned());
+    }
+    Field::new(name, arrow_type(field_type), nullable).with_metadata(Some(metadata))
+}
+
+pub(crate) fn arrow_type(field_type: &Type) -> DataType {
+    match field_type {
+        Type::Primitive(primitive) => match primitive {
+            PrimitiveType::Boolean => DataType::Boolean,
+            PrimitiveType::Int => DataType::Int32,
+            PrimitiveType::Long => DataType::Int64,
+            PrimitiveType::Float => DataType::Float32,
+            PrimitiveType::Double => DataType::Float64,
+            PrimitiveType::Decimal { precision, scale } => {
+                DataType::Decimal128(*precision as u8, *scale as u8)
+            }
+            PrimitiveType::Date => DataType::Date32,
+            PrimitiveType::Time => DataType::Time64(TimeUnit::Microsecond),
+            PrimitiveType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+            PrimitiveType::Timestampz => {
+                DataType::Timestamp(TimeUnit::Microsecond, Some(UTC.to_owned()))
+            }
+            PrimitiveType::String => DataType::Utf8,
+            PrimitiveType::Uuid => DataType::FixedSizeBinary(16),
+            PrimitiveType::Fixed(size) => DataType::FixedSizeBinary(*size as i32),
+            PrimitiveType::Binary => DataType::Binary,
+        },
+        Type::Struct(schema) => DataType::Struct(arrow_fields(schema)),
+        Type::List(list) => DataType::List(Box::new(arrow_field(
+            "element",
+            &list.element,
+            !list.element_required,
+            list.element_id,
+        ))),
+        Type::Map(map) => DataType::Map(
+            Box::new(Field::new(
+                "entries",
+                DataType::Struct(vec![
+                    arrow_field("key", &map.key, false, map.key_id),
+                    arrow_field("value", &map.value, !map.value_required, map.value_id),
+                ]),
+                false,
+            )),
+            false,
+        ),
+    }
+}
+
+/// Largest field id of the field and its nested fields
+fn max_field_id(field: &Field) -> i32 {
+    let nested = match field.data_type() {
+        DataType::Struct(fields) => fields.iter().map(max_field_id).max(),
+        DataType::List(element)
+        | DataType::LargeList(element)
+        | DataType::FixedSizeList(element, _)
+        | DataType::Map(element, _) => Some(max_field_id(element)),
+        _ => None,
+    };
+    field_id(field).into_iter().chain(nested).max().unwrap_or(0)
+}
+
+fn without_field_id(field: &Field) -> Field {
+    // Fields without other metadata have none, like the fields that are read from data files
+    let metadata = field
+        .metadata()
+        .map(|metadata| {
+            metadata
+                .iter()
+                .filter(|(key, _)| key.as_str() != PARQUET_FIELD_ID)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<BTreeMap<_, _>>()
+        })
+        .filter(|metadata| !metadata.is_empty());
+    Field::new(
+        field.name(),
+        without_nested_field_ids(field.data_type()),
+        field.is_nullable(),
+    )
+    .with_metadata(metadata)
+}
+
+/// Type without the field ids of the nested fields, which is the type of the column in the data files
+pub(crate) fn without_nested_field_ids(data_type: &DataType) -> DataType {
+    match data_type {
+        DataType::Struct(fields) => DataType::Struct(fields.iter().map(without_field_id).collect()),
+        DataType::List(element) => DataType::List(Box::new(without_field_id(element))),
+        DataType::LargeList(element) => DataType::LargeList(Box::new(without_field_id(element))),
+        DataType::FixedSizeList(element, size) => {
+            DataType::FixedSizeList(Box::new(without_field_id(element)), *size)
+        }
+        DataType::Map(entries, sorted) => {
+            DataType::Map(Box::new(without_field_id(entries)), *sorted)
+        }
+        data_type => data_type.clone(),
+    }
+}
+
+/// Iceberg field of the arrow field with new ids for the field and its nested fields, starting at the next id
+pub(crate) fn new_iceberg_field(field: &Field, next_id: &mut i32) -> Result<StructField> {
+    let mut fields = iceberg_fields(&[without_field_id(field)], next_id)?.fields;
+    Ok(fields.remove(0))
+}
+
+fn iceberg_fields(fields: &[Field], next_id: &mut i32) -> Result<StructType> {
+    Ok(StructType {
+        fields: fields
+            .iter()
+            .map(|field| {
+                let (id, field_type) = iceberg_field(field, next_id)?;
+                Ok(StructField {
+                    id,
+                    name: field.name().clone(),
+                    required: !field.is_nullable(),
+                    field_type,
+                    doc: None,
+                })
+            })
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// Id and type of the field. The id is assigned before the ids of the nested fields.
+fn iceberg_field(field: &Field, next_id: &mut i32) -> Result<(i32, Type)> {
+    let id = match field_id(field) {
+        Some(id) => id,
+        None => {
+            *next_id += 1;
+            *next_id - 1
+        }
+    };
+    let uuid = field
+        .metadata()
+        .and_then(|metadata| metadata.get(EXTENSION_NAME))
+        .map(|name| name == UUID_EXTENSION)
+        .unwrap_or(false);
+    let primitive = match field.data_type() {
+        DataType::Boolean => PrimitiveType::Boolean,
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::UInt8 | DataType::UInt16 => {
+            PrimitiveType::Int
+        }
+        DataType::Int64 | DataType::UInt32 => PrimitiveType::Long,
+        DataType::Float16 | DataType::Float32 => PrimitiveType::Float,
+        DataType::Float64 => PrimitiveType::Double,
+        DataType::Decimal128(precision, scale) => PrimitiveType::Decimal {
+            precision: *precision as u32,
+            scale: *scale as u32,
+        },
+        DataType::Date32 | DataType::Date64 => PrimitiveType::Date,
+        DataType::Time32(_) | DataType::Time64(_) => PrimitiveType::Time,
+        DataType::Timestamp(_, None) => PrimitiveType::Timestamp,
+        DataType::Timestamp(_, Some(_)) => PrimitiveType::Timestampz,
+        DataType::Utf8 | DataType::LargeUtf8 => PrimitiveType::String,
+        DataType::FixedSizeBinary(16) if uuid => PrimitiveType::Uuid,
+        DataType::FixedSizeBinary(size) => PrimitiveType::Fixed(*size as u64),
+        DataType::Binary | DataType::LargeBinary => PrimitiveType::Binary,
+        DataType::Struct(fields) => {
+            return Ok((id, Type::Struct(iceberg_fields(fields, next_id)?)))
+        }
+        DataType::List(element)
+        | DataType::LargeList(element)
+        | DataType::FixedSizeList(element, _) => {
+            let (element_id, element_type) = iceberg_field(element, next_id)?;
+            return Ok((
+                id,
+                Type::List(ListType {
+                    element_id,
+                    element_required: !element.is_nullable(),
+                    element: Box::new(element_type),
+                }),
+            ));
+        }
+        DataType::Map(entries, _) => {
+            let (key, value) = match entries.data_type() {
+                DataType::Struct(fields) if fields.len() == 2 => (&fields[0], &fields[1]),
+                data_type => {
+                    return Err(DataFusionError::Plan(format!(
+                        "The entries of map {} have to be a struct of key and value, found {}.",
+                        field.name(),
+                        data_type
+                    )))
+                }
+            };
+            let (key_id, key_type) = iceberg_field(key, next_id)?;
+            let (value_id, value_type) = iceberg_field(value, next_id)?;
+            return Ok((
+                id,
+                Type::Map(MapType {
+                    key_id,
+                    key: Box::new(key_type),
+                    value_id,
+                    value_required: !value.is_nullable(),
+                    value: Box::new(value_type),
+                }),
+            ));
+        }
+        data_type => {
+            return Err(DataFusionError::Plan(format!(
+                "Column {} of type {} has no iceberg type.",
+                field.name(),
+                data_type
+            )))
+        }
+    };
+    Ok((id, Type::Primitive(primitive)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn field(id: i32, name: &str, required: bool, field_type: Type) -> StructField {
+        StructField {
+            id,
+            name: name.to_owned(),
+            required,
+            field_type,
+            doc: None,
+        }
+    }
+
+    fn primitive(primitive: PrimitiveType) -> Type {
+        Type::Primitive(primitive)
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let schema = StructType {
+            fields: vec![
+                field(1, "id", true, primitive(PrimitiveType::Uuid)),
+                field(
+                    2,
+                    "amount",
+                    false,
+                    primitive(PrimitiveType::Decimal {
+                        precision: 12,
+                        scale: 2,
+                    }),
+                ),
+                field(3, "hash", false, primitive(PrimitiveType::Fixed(32))),
+                field(4, "payload", false, primitive(PrimitiveType::Binary)),
+                field(5, "day", false, primitive(PrimitiveType::Date)),
+                field(6, "at", false, primitive(PrimitiveType::Timestampz)),
+                field(
+                    7,
+                    "location",
+                    false,
+                    Type::Struct(StructType {
+                        fields: vec![
+                            field(8, "lat", true, primitive(PrimitiveType::Double)),
+                            field(9, "long", true, primitive(PrimitiveType::Double)),
+                        ],
+                    }),
+                ),
+                field(
+                    10,
+                    "tags",
+                    false,
+                    Type::List(ListType {
+                        element_id: 11,
+                        element_required: true,
+                        element: Box::new(primitive(PrimitiveType::String)),
+                    }),
+                ),
+                field(
+                    12,
+                    "counts",
+                    false,
+                    Type::Map(MapType {
+                        key_id: 13,
+                        key: Box::new(primitive(PrimitiveType::String)),
+                        value_id: 14,
+                        value_required: false,
+                        value: Box::new(primitive(PrimitiveType::Long)),
+                    }),
+                ),
+            ],
+        };
+        let arrow = iceberg_to_arrow_schema(&schema);
+        assert_eq!(arrow.field(0).data_type(), &DataType::FixedSizeBinary(16));
+        assert_eq!(arrow.field(1).data_type(), &DataType::Decimal128(12, 2));
+        assert_eq!(arrow.field(4).data_type(), &DataType::Date32);
+        assert_eq!(
+            arrow.field(5).data_type(),
+            &DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".to_owned()))
+        );
+        assert!(!arrow.field(0).is_nullable());
+        match arrow.field(7).data_type() {
+            DataType::List(element) => assert_eq!(field_id(element), Some(11)),
+            data_type => panic!("Expected a list, found {}.", data_type),
+        }
+
+        assert_eq!(arrow_to_iceberg_schema(&arrow).unwrap(), schema);
+    }
+
+    #[test]
+    pub fn test_missing_field_ids() {
+        let arrow = Schema::new(vec![
+            Field::new("name", DataType::LargeUtf8, true).with_metadata(Some(BTreeMap::from([(
+                PARQUET_FIELD_ID.to_owned(),
+                "3".to_owned(),
+            )]))),
+            Field::new(
+                "scores",
+                DataType::List(Box::new(Field::new("item", DataType::Int16, false))),
+                true,
+            ),
+            Field::new("at", DataType::Timestamp(TimeUnit::Nanosecond, None), false),
+        ]);
+        let schema = arrow_to_iceberg_schema(&arrow).unwrap();
+        assert_eq!(
+            schema,
+            StructType {
+                fields: vec![
+                    field(3, "name", false, primitive(PrimitiveType::String)),
+                    field(
+                        4,
+                        "scores",
+                        false,
+                        Type::List(ListType {
+                            element_id: 5,
+                            element_required: true,
+                            element: Box::new(primitive(PrimitiveType::Int)),
+                        }),
+                    ),
+                    field(6, "at", true, primitive(PrimitiveType::Timestamp)),
+                ],
+            }
+        );
+
+        let unsupported = Schema::new(vec![Field::new("count", DataType::UInt64, false)]);
+        assert!(arrow_to_iceberg_schema(&unsupported).is_err());
+    }
+
+    #[test]
+    pub fn test_name_mapping() {
+        let schema = StructType {
+            fields: vec![
+                field(1, "id", true, primitive(PrimitiveType::Long)),
+                field(
+                    2,
+                    "tags",
+                    false,
+                    Type::List(ListType {
+                        element_id: 3,
+                        element_required: false,
+                        element: Box::new(primitive(PrimitiveType::String)),
+                    }),
+                ),
+                field(
+                    4,
+                    "prices",
+                    false,
+                    Type::Map(MapType {
+                        key_id: 5,
+                        key: Box::new(primitive(PrimitiveType::String)),
+                        value_id: 6,
+                        value_required: true,
+                        value: Box::new(primitive(PrimitiveType::Double)),
+                    }),
+                ),
+            ],
+        };
+        let mapping: Value = serde_json::from_str(&name_mapping(&schema)).unwrap();
+        assert_eq!(
+            mapping,
+            json!([
+                { "field-id": 1, "names": ["id"] },
+                {
+                    "field-id": 2,
+                    "names": ["tags"],
+                    "fields": [{ "field-id": 3, "names": ["element"] }]
+                },
+                {
+                    "field-id": 4,
+                    "names": ["prices"],
+                    "fields": [
+                        { "field-id": 5, "names": ["key"] },
+                        { "field-id": 6, "names": ["value"] }
+                    ]
+                }
+            ])
+        );
+    }
+
+    #[test]
+    pub fn test_updated_name_mapping() {
+        let previous = json!([
+            { "field-id": 1, "names": ["id"] },
+            { "field-id": 2, "names": ["name"] }
+        ])
+        .to_string();
+        // Field 2 was renamed and field 3 added
+        let schema = StructType {
+            fields: vec![
+                field(1, "id", true, primitive(PrimitiveType::Long)),
+                field(2, "full_name", false, primitive(PrimitiveType::String)),
+                field(3, "email", false, primitive(PrimitiveType::String)),
+            ],
+        };
+        let mapping: Value =
+            serde_json::from_str(&updated_name_mapping(&previous, &schema).unwrap()).unwrap();
+        assert_eq!(
+            mapping,
+            json!([
+                { "field-id": 1, "names": ["id"] },
+                { "field-id": 2, "names": ["full_name", "name"] },
+                { "field-id": 3, "names": ["email"] }
+            ])
+        );
+    }
+}
@@ -0,0 +1,450 @@
+/*!
+ * Typed access to the rows of an iceberg table
+ *
+ * The record batches of a scan are serialized as json rows and deserialized into user defined structs with serde.
+ * Columns are matched to the struct fields by name.
+ *
+ * Inserts go the other way: the structs are serialized as json rows, which are decoded into record batches of the
+ * table schema and written like any other input. The json decoder of arrow converts numbers between the numeric types
+ * and parses strings as numbers, dates and timestamps. Decimal and binary columns are converted separately, because
+ * the decoder can't read them.
+*/
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::{
+        array::{
+            new_null_array, ArrayRef, BinaryArray, Decimal128Array, FixedSizeBinaryArray,
+            LargeBinaryArray,
+        },
+        datatypes::{DataType, Field, Schema, SchemaRef},
+        json::{
+            reader::{Decoder, DecoderOptions},
+            writer::record_batches_to_json_rows,
+        },
+        record_batch::RecordBatch,
+    },
+    common::DataFusionError,
+    datasource::TableProvider,
+    error::Result,
+    physical_plan::{collect, memory::MemoryStream},
+    prelude::SessionContext,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{writer::WrittenFile, DataFusionTable};
+
+/// Number of rows that are decoded into one record batch
+const BATCH_SIZE: usize = 8192;
+
+impl DataFusionTable {
+    /// Scan the table and deserialize every row into a `T`.
+    pub async fn select_as<T: DeserializeOwned>(&self, ctx: &SessionContext) -> Result<Vec<T>> {
+        let plan = self.scan(&ctx.state(), &None, &[], None).await?;
+        let batches = collect(plan, ctx.task_ctx()).await?;
+        record_batches_to_json_rows(&batches)?
+            .into_iter()
+            .map(|row| {
+                serde_json::from_value(Value::Object(row))
+                    .map_err(|err| DataFusionError::Internal(format!("{}", err)))
+            })
+            .collect()
+    }
+    /// Convert the rows into record batches of the table schema and insert them as a new snapshot. Struct fields are
+    /// matched to the columns by name, ignoring the case if no field has the exact name, fields without a column are
+    /// ignored. Returns the written files.
+    pub async fn insert_from_iter<T: Serialize>(
+        &mut self,
+        rows: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<WrittenFile>> {
+        let schema = input_schema(&self.schema());
+        let batches = json_batches(&schema, rows)?;
+        if batches.is_empty() {
+            return Ok(Vec::new());
+        }
+        let stream = MemoryStream::try_new(batches, schema, None)?;
+        let config = self.writer_config()?;
+        self.insert(Box::pin(stream), &config).await
+    }
+}
+
+/// Schema of the table with nullable columns, so that the writer reports null values of required columns with their
+/// row
+fn input_schema(schema: &SchemaRef) -> SchemaRef {
+    Arc::new(Schema::new_with_metadata(
+        schema
+            .fields()
+            .iter()
+            .map(|field| field.clone().with_nullable(true))
+            .collect(),
+        schema.metadata().clone(),
+    ))
+}
+
+/// Decode the serialized rows into record batches of the schema
+fn json_batches<T: Serialize>(
+    schema: &SchemaRef,
+    rows: impl IntoIterator<Item = T>,
+) -> Result<Vec<RecordBatch>> {
+    let decoded: Vec<usize> = (0..schema.fields().len())
+        .filter(|index| !is_converted(schema.field(*index).data_type()))
+        .collect();
+    let decoder = Decoder::new(
+        Arc::new(Schema::new(
+            decoded
+                .iter()
+                .map(|index| schema.field(*index).clone())
+                .collect(),
+        )),
+        DecoderOptions::new().with_batch_size(BATCH_SIZE),
+    );
+    let mut rows = rows.into_iter().map(|row| {
+        let row = serde_json::to_value(row)
+            .map_err(|err| DataFusionError::Execution(format!("{}", err)))?;
+        match row {
+            Value::Object(_) => Ok(rename(row, schema.fields())),
+            row => Err(DataFusionError::Execution(format!(
+                "Rows have to be serialized as structs or maps, found {}.",
+                row
+            ))),
+        }
+    });
+    let mut batches = Vec::new();
+    loop {
+        let chunk = rows
+            .by_ref()
+            .take(BATCH_SIZE)
+            .collect::<Result<Vec<Value>>>()?;
+        if chunk.is_empty() {
+            return Ok(batches);
+        }
+        let decoded_batch = match decoded.is_empty() {
+            true => None,
+            false => decoder.next_batch(&mut chunk.iter().cloned().map(Ok))?,
+        };
+        let columns = (0..schema.fields().len())
+            .map(|index| {
+                match (
+                    decoded.iter().position(|decoded| *decoded == index),
+                    &decoded_batch,
+                ) {
+                    (Some(position), Some(batch)) => Ok(batch.column(position).clone()),
+                    _ => convert_column(&chunk, schema.field(index)),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        batches.push(RecordBatch::try_new(schema.clone(), columns)?);
+    }
+}
+
+/// Rename the keys of the object to the name of the field they match, exactly or ignoring the case. Keys without a
+/// field are dropped. Structs and lists of structs are renamed recursively.
+fn rename(value: Value, fields: &[Field]) -> Value {
+    let mut object = match value {
+        Value::Object(object) => object,
+        value => return value,
+    };
+    let renamed: Map<String, Value> = fields
+        .iter()
+        .filter_map(|field| {
+            let value = object.remove(field.name()).or_else(|| {
+                let key = object
+                    .keys()
+                    .find(|key| key.eq_ignore_ascii_case(field.name()))?
+                    .clone();
+                object.remove(&key)
+            })?;
+            Some((
+                field.name().clone(),
+                rename_nested(value, field.data_type()),
+            ))
+        })
+        .collect();
+    Value::Object(renamed)
+}
+
+fn rename_nested(value: Value, data_type: &DataType) -> Value {
+    match (value, data_type) {
+        (value, DataType::Struct(fields)) => rename(value, fields),
+        (Value::Array(values), DataType::List(field) | DataType::LargeList(field)) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| rename_nested(value, field.data_type()))
+                .collect(),
+        ),
+        (value, _) => value,
+    }
+}
+
+/// Columns that the json decoder can't read
+fn is_converted(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Decimal128(_, _)
+            | DataType::FixedSizeBinary(_)
+            | DataType::Binary
+            | DataType::LargeBinary
+    )
+}
+
+fn convert_column(rows: &[Value], field: &Field) -> Result<ArrayRef> {
+    let invalid = |value: &Value| {
+        DataFusionError::Execution(format!(
+            "Value {} of column {} can't be converted to {}.",
+            value,
+            field.name(),
+            field.data_type()
+        ))
+    };
+    let values = rows
+        .iter()
+        .map(|row| row.get(field.name()).filter(|value| !value.is_null()));
+    match field.data_type() {
+        DataType::Decimal128(precision, scale) => {
+            let array = values
+                .map(|value| {
+                    value
+                        .map(|value| {
+                            decimal(value, *precision, *scale).ok_or_else(|| invalid(value))
+                        })
+                        .transpose()
+                })
+                .collect::<Result<Decimal128Array>>()?;
+            Ok(Arc::new(
+                array.with_precision_and_scale(*precision, *scale)?,
+            ))
+        }
+        DataType::FixedSizeBinary(size) => {
+            let values = values
+                .map(|value| {
+                    value
+                        .map(|value| {
+                            bytes(value, *size == 16)
+                                .filter(|bytes| bytes.len() == *size as usize)
+                                .ok_or_else(|| invalid(value))
+                        })
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // The size can't be inferred from null values
+            if values.iter().all(Option::is_none) {
+                return Ok(new_null_array(field.data_type(), values.len()));
+            }
+            Ok(Arc::new(FixedSizeBinaryArray::try_from_sparse_iter(
+                values.into_iter(),
+            )?))
+        }
+        DataType::Binary | DataType::LargeBinary => {
+            let values = values
+                .map(|value| {
+                    value
+                        .map(|value| bytes(value, false).ok_or_else(|| invalid(value)))
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(match field.data_type() {
+                DataType::Binary => Arc::new(values.into_iter().collect::<BinaryArray>()),
+                _ => Arc::new(values.into_iter().collect::<LargeBinaryArray>()),
+            })
+        }
+        data_type => Err(DataFusionError::Internal(format!(
+            "Columns of type {} are decoded as json.",
+            data_type
+        ))),
+    }
+}
+
+/// Bytes of an array of numbers, like serde serializes byte vectors, or of a string. Strings are read as uuid if
+/// possible for uuid columns.
+fn bytes(value: &Value, uuid: bool) -> Option<Vec<u8>> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+            .collect(),
+        Value::String(value) => match Uuid::parse_str(value) {
+            Ok(parsed) if uuid => Some(parsed.as_bytes().to_vec()),
+            _ => Some(value.as_bytes().to_vec()),
+        },
+        _ => None,
+    }
+}
+
+/// Unscaled value of the decimal with the scale, given as number or string. Digits beyond the scale are rounded half
+/// away from zero. None if the value isn't a decimal or exceeds the precision.
+fn decimal(value: &Value, precision: u8, scale: u8) -> Option<i128> {
+    let text = match value {
+        Value::Number(number) => number.to_string(),
+        Value::String(text) => text.trim().to_owned(),
+        _ => return None,
+    };
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(position) => (&text[..position], text[position + 1..].parse::<i32>().ok()?),
+        None => (text.as_str(), 0),
+    };
+    let (negative, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => (true, mantissa),
+        None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", integer, fraction);
+    if digits.is_empty() || !digits.chars().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    // Number of zeros to append to the digits if positive, number of digits to drop if negative
+    let shift = exponent + scale as i32 - fraction.len() as i32;
+    let unscaled = if shift >= 0 {
+        digits
+            .parse::<i128>()
+            .ok()?
+            .checked_mul(10i128.checked_pow(shift as u32)?)?
+    } else {
+        let dropped = shift.unsigned_abs() as usize;
+        let kept = digits.len().saturating_sub(dropped);
+        let round_up =
+            dropped <= digits.len() && digits[kept..].starts_with(['5', '6', '7', '8', '9']);
+        let value = match kept {
+            0 => 0,
+            kept => digits[..kept].parse::<i128>().ok()?,
+        };
+        value + round_up as i128
+    };
+    if unscaled >= 10i128.checked_pow(precision as u32)? {
+        return None;
+    }
+    Some(if negative { -unscaled } else { unscaled })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::Array;
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+    use serde::Deserialize;
+
+    use crate::{
+        commit::tests::row_count,
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Trip {
+        trip_id: i64,
+        trip_distance: f32,
+    }
+
+    #[tokio::test]
+    pub async fn test_select_as() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        );
+
+        let trips: Vec<Trip> = table
+            .select_as(&SessionContext::new())
+            .await
+            .expect("Failed to deserialize rows.");
+
+        assert_eq!(trips.len(), 4);
+        assert!(trips.iter().all(|trip| trip.trip_id > 0));
+        assert!(trips.iter().all(|trip| trip.trip_distance > 0.0));
+    }
+
+    #[derive(Serialize)]
+    struct NewTrip {
+        #[serde(rename = "Vendor_ID")]
+        vendor_id: i32,
+        trip_id: u32,
+        trip_distance: f64,
+        fare_amount: i64,
+        store_and_fwd_flag: Option<String>,
+        driver: &'static str,
+    }
+
+    #[tokio::test]
+    pub async fn test_insert_from_iter() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        let trips = (0..3).map(|trip| NewTrip {
+            vendor_id: 1 + trip % 2,
+            trip_id: 100 + trip as u32,
+            trip_distance: 1.5,
+            fare_amount: 10,
+            store_and_fwd_flag: None,
+            driver: "unknown",
+        });
+        let files = table.insert_from_iter(trips).await.unwrap();
+        // One file for each vendor id
+        assert_eq!(files.len(), 2);
+        assert_eq!(row_count(&table).await, 7);
+
+        let trips: Vec<Trip> = table.select_as(&SessionContext::new()).await.unwrap();
+        let inserted: Vec<&Trip> = trips
+            .iter()
+            .filter(|trip| (100..103).contains(&trip.trip_id))
+            .collect();
+        assert_eq!(inserted.len(), 3);
+        assert!(inserted.iter().all(|trip| trip.trip_distance == 1.5));
+
+        assert!(table.insert_from_iter(vec![1, 2]).await.is_err());
+        assert!(table
+            .insert_from_iter(Vec::<NewTrip>::new())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    pub fn test_converted_columns() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("amount", DataType::Decimal128(9, 2), true),
+            Field::new("id", DataType::FixedSizeBinary(16), true),
+            Field::new("payload", DataType::Binary, true),
+        ]));
+        let rows = vec![
+            serde_json::json!({"amount": 12.345, "id": "67e55044-10b1-426f-9247-bb680e5fe0c8", "Payload": [1, 2]}),
+            serde_json::json!({"amount": "-0.5e1", "id": null, "payload": "ab"}),
+        ];
+        let batches = json_batches(&schema, rows).unwrap();
+        let amounts = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        assert_eq!(amounts.value(0), 1235);
+        assert_eq!(amounts.value(1), -500);
+        let ids = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<FixedSizeBinaryArray>()
+            .unwrap();
+        assert_eq!(ids.value(0)[0], 0x67);
+        assert!(ids.is_null(1));
+        let payloads = batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<BinaryArray>()
+            .unwrap();
+        assert_eq!(payloads.value(0), &[1, 2]);
+        assert_eq!(payloads.value(1), b"ab");
+
+        assert_eq!(decimal(&serde_json::json!(1234567890), 9, 2), None);
+        assert!(json_batches(&schema, vec![serde_json::json!({"amount": "abc"})]).is_err());
+    }
+}
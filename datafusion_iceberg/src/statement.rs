@@ -0,0 +1,1346 @@
+/*!
+ * Sql statements that change iceberg tables
+ *
+ * Datafusion only plans queries, so the statements that change tables are executed by [`sql`], which passes all other
+ * statements on to the context. Procedures are called like in spark, with the arguments given by position or name:
+ *
+ * ```sql
+ * CALL system.rewrite_data_files(table => 'nyc_taxis', min_input_files => 2)
+ * CALL system.rewrite_data_files(table => 'nyc_taxis', strategy => 'sort', sort_order => 'zorder(vendor_id, trip_id)',
+ *     rewrite_all => true)
+ * CALL system.rewrite_manifests('nyc_taxis')
+ * CALL system.expire_snapshots('nyc_taxis', '2022-09-07 06:20:18', 5)
+ * CALL system.remove_orphan_files(table => 'nyc_taxis', older_than => '2022-09-07 06:20:18', dry_run => true)
+ * ```
+ *
+ * Files are loaded into a table with `COPY INTO`, which reads parquet, csv or json files, given by directory or glob
+ * pattern, and appends their rows in one snapshot. The format is taken from the file extension if it isn't given:
+ *
+ * ```sql
+ * COPY INTO nyc_taxis FROM 's3://bucket/raw/part-*.parquet'
+ * COPY INTO nyc_taxis FROM 's3://bucket/raw/' FORMAT CSV
+ * ```
+ *
+ * The columns of the files are matched with the columns of the table by name and cast to the types of the table.
+ * Optional columns that the files lack are null.
+ *
+ * Table properties are read and changed like in spark:
+ *
+ * ```sql
+ * SHOW TBLPROPERTIES nyc_taxis
+ * ALTER TABLE nyc_taxis SET TBLPROPERTIES ('write.target-file-size-bytes' = '134217728')
+ * ALTER TABLE nyc_taxis UNSET TBLPROPERTIES IF EXISTS ('write.target-file-size-bytes')
+ * ```
+ *
+ * The columns of a table are changed with a schema update. Added columns are optional, types can only be widened and
+ * required columns can be made optional, but not the other way around. Fields of struct columns are named like
+ * `pickup.latitude` and changed like columns, columns and fields are moved with `FIRST` or `AFTER` their sibling:
+ *
+ * ```sql
+ * ALTER TABLE nyc_taxis ADD COLUMN tip_amount DOUBLE
+ * ALTER TABLE nyc_taxis ADD COLUMN pickup.altitude DOUBLE AFTER latitude
+ * ALTER TABLE nyc_taxis DROP COLUMN IF EXISTS store_and_fwd_flag
+ * ALTER TABLE nyc_taxis RENAME COLUMN fare_amount TO fare
+ * ALTER TABLE nyc_taxis ALTER COLUMN passenger_count SET DATA TYPE BIGINT
+ * ALTER TABLE nyc_taxis ALTER COLUMN trip_id DROP NOT NULL
+ * ALTER TABLE nyc_taxis ALTER COLUMN fare FIRST
+ * ```
+ *
+ * Temporary tables are created like other tables, with column definitions or the rows of a query, if the session has
+ * [`TemporaryTables`](crate::temporary::TemporaryTables). They are dropped with the session:
+ *
+ * ```sql
+ * CREATE TEMPORARY TABLE trips AS SELECT * FROM nyc_taxis WHERE vendor_id = 1
+ * CREATE TEMPORARY TABLE fares (trip_id BIGINT NOT NULL, fare DOUBLE)
+ * ```
+ *
+ * The statement changes the table that is registered with the context under the given name. Once the change is
+ * committed, the registered table reads the new metadata.
+*/
+
+use std::{collections::HashMap, sync::Arc};
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, StringArray, UInt64Array},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    },
+    common::DataFusionError,
+    datasource::{source_as_provider, TableProvider},
+    error::Result,
+    logical_expr::LogicalPlan,
+    prelude::{CsvReadOptions, DataFrame, NdJsonReadOptions, ParquetReadOptions, SessionContext},
+    sql::{
+        planner::convert_data_type,
+        sqlparser::{
+            ast::{ColumnDef, ColumnOption, Expr, FunctionArg, FunctionArgExpr, Statement, Value},
+            dialect::GenericDialect,
+            keywords::Keyword,
+            parser::{Parser, ParserError},
+            tokenizer::{Token, Tokenizer},
+        },
+    },
+};
+
+use chrono::NaiveDateTime;
+
+use crate::{
+    dataframe::project,
+    evolution::{has_column, ColumnPosition, SchemaUpdate},
+    expire::ExpireOptions,
+    orphan::OrphanOptions,
+    refresh::RefreshingTable,
+    rewrite::{RewriteManifestsOptions, RewriteOptions, RewriteStrategy},
+    schema::iceberg_to_arrow_schema,
+    temporary::TemporaryTables,
+    DataFusionTable,
+};
+
+/// Execute the statement. Statements that change iceberg tables are executed directly, all other statements are
+/// planned by the context.
+pub async fn sql(ctx: &SessionContext, sql: &str) -> Result<Arc<DataFrame>> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|err| ParserError::TokenizerError(err.to_string()))?;
+    let start = tokens
+        .iter()
+        .position(|token| !matches!(token, Token::Whitespace(_)))
+        .unwrap_or_default();
+    match tokens.get(start) {
+        Some(Token::Word(word)) if word.value.eq_ignore_ascii_case("call") => {
+            let mut parser = Parser::new(tokens[start + 1..].to_vec(), &dialect);
+            let procedure = parser.parse_expr()?;
+            end(&mut parser)?;
+            call(ctx, procedure).await
+        }
+        Some(Token::Word(word)) if word.value.eq_ignore_ascii_case("copy") => {
+            let mut parser = Parser::new(tokens[start + 1..].to_vec(), &dialect);
+            parser.expect_keyword(Keyword::INTO)?;
+            let table_name = parser.parse_object_name()?.to_string();
+            parser.expect_keyword(Keyword::FROM)?;
+            let location = parser.parse_literal_string()?;
+            let format = match parser.parse_keyword(Keyword::FORMAT) {
+                true => FileFormat::from_name(&parser.parse_identifier()?.value)?,
+                false => FileFormat::from_location(&location),
+            };
+            end(&mut parser)?;
+            copy_into(ctx, &table_name, &location, format).await
+        }
+        Some(Token::Word(word)) if word.value.eq_ignore_ascii_case("alter") => {
+            let mut parser = Parser::new(tokens[start + 1..].to_vec(), &dialect);
+            parser.expect_keyword(Keyword::TABLE)?;
+            let table_name = parser.parse_object_name()?.to_string();
+            let set = match parser.peek_token() {
+                Token::Word(word) if word.value.eq_ignore_ascii_case("set") => true,
+                Token::Word(word) if word.value.eq_ignore_ascii_case("unset") => false,
+                _ => return alter_columns(ctx, sql, &table_name, &mut parser).await,
+            };
+            parser.next_token();
+            // Other changes of the table are planned by the context
+            if !parser.parse_keyword(Keyword::TBLPROPERTIES) {
+                return ctx.sql(sql).await;
+            }
+            let mut table = registered_table(ctx, &table_name).await?;
+            if set {
+                parser.expect_token(&Token::LParen)?;
+                let properties = parser.parse_comma_separated(property)?;
+                parser.expect_token(&Token::RParen)?;
+                end(&mut parser)?;
+                table
+                    .set_properties(properties.into_iter().collect())
+                    .await?;
+            } else {
+                let if_exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+                parser.expect_token(&Token::LParen)?;
+                let keys = parser.parse_comma_separated(Parser::parse_literal_string)?;
+                parser.expect_token(&Token::RParen)?;
+                end(&mut parser)?;
+                let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+                table.unset_properties(&keys, if_exists).await?;
+            }
+            replace_table(ctx, &table_name, table).await?;
+            ctx.read_empty()
+        }
+        Some(Token::Word(word)) if word.value.eq_ignore_ascii_case("create") => {
+            let temporary = ctx.state().config.get_extension::<TemporaryTables>();
+            let mut parser = Parser::new(tokens[start..].to_vec(), &dialect);
+            // Statements that the generic dialect can't parse, like external tables, are planned by the context
+            match (parser.parse_statement(), temporary) {
+                (
+                    Ok(Statement::CreateTable {
+                        temporary: true,
+                        name,
+                        columns,
+                        query,
+                        if_not_exists,
+                        or_replace,
+                        ..
+                    }),
+                    Some(temporary),
+                ) => {
+                    end(&mut parser)?;
+                    let name = name.to_string();
+                    if ctx.table_exist(name.as_str())? {
+                        if if_not_exists {
+                            return ctx.read_empty();
+                        } else if !or_replace {
+                            return Err(DataFusionError::Plan(format!(
+                                "Table {} exists already.",
+                                name
+                            )));
+                        }
+                    }
+                    let table = match query {
+                        Some(query) => {
+                            temporary
+                                .create_table_as(&*ctx.sql(&query.to_string()).await?)
+                                .await?
+                        }
+                        None => temporary.create_table(&column_schema(columns)?).await?,
+                    };
+                    ctx.deregister_table(name.as_str())?;
+                    ctx.register_table(name.as_str(), Arc::new(table))?;
+                    ctx.read_empty()
+                }
+                _ => ctx.sql(sql).await,
+            }
+        }
+        Some(Token::Word(word)) if word.value.eq_ignore_ascii_case("show") => {
+            let mut parser = Parser::new(tokens[start + 1..].to_vec(), &dialect);
+            if !parser.parse_keyword(Keyword::TBLPROPERTIES) {
+                return ctx.sql(sql).await;
+            }
+            let table_name = parser.parse_object_name()?.to_string();
+            end(&mut parser)?;
+            let mut properties: Vec<(String, String)> = registered_table(ctx, &table_name)
+                .await?
+                .properties()?
+                .into_iter()
+                .collect();
+            properties.sort();
+            let (keys, values): (Vec<String>, Vec<String>) = properties.into_iter().unzip();
+            let schema = Schema::new(vec![
+                Field::new("key", DataType::Utf8, false),
+                Field::new("value", DataType::Utf8, false),
+            ]);
+            ctx.read_batch(RecordBatch::try_new(
+                Arc::new(schema),
+                vec![
+                    Arc::new(StringArray::from(keys)),
+                    Arc::new(StringArray::from(values)),
+                ],
+            )?)
+        }
+        _ => ctx.sql(sql).await,
+    }
+}
+
+/// Change the columns of the table with a schema update. Fields of struct columns are named like `pickup.latitude`.
+/// Statements that don't change columns are planned by the context.
+async fn alter_columns(
+    ctx: &SessionContext,
+    sql: &str,
+    table_name: &str,
+    parser: &mut Parser<'_>,
+) -> Result<Arc<DataFrame>> {
+    let update = SchemaUpdate::default();
+    let mut if_exists = None;
+    let update = match parser.next_token() {
+        Token::Word(word) if word.keyword == Keyword::ADD => {
+            // The COLUMN keyword is optional like in sqlparser
+            let _ = parser.parse_keyword(Keyword::COLUMN);
+            if is_constraint(&parser.peek_token()) {
+                return ctx.sql(sql).await;
+            }
+            let name = column_name(parser)?;
+            let data_type = convert_data_type(&parser.parse_data_type()?)?;
+            if parser.parse_keywords(&[Keyword::NOT, Keyword::NULL]) {
+                return Err(DataFusionError::Plan(format!(
+                    "Column {} can't be added as required column, the existing rows have no value for it.",
+                    name
+                )));
+            }
+            let update = match name.rsplit_once('.') {
+                Some((parent, field)) => update.with_added_nested_column(parent, field, data_type),
+                None => update.with_added_column(&name, data_type),
+            };
+            match column_position(parser, &name)? {
+                Some(position) => update.with_moved_column(&name, position),
+                None => update,
+            }
+        }
+        Token::Word(word) if word.keyword == Keyword::DROP => {
+            let _ = parser.parse_keyword(Keyword::COLUMN);
+            if is_constraint(&parser.peek_token()) {
+                return ctx.sql(sql).await;
+            }
+            let exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+            let name = column_name(parser)?;
+            if exists {
+                if_exists = Some(name.clone());
+            }
+            update.with_dropped_column(&name)
+        }
+        Token::Word(word)
+            if word.keyword == Keyword::RENAME && parser.parse_keyword(Keyword::COLUMN) =>
+        {
+            let name = column_name(parser)?;
+            parser.expect_keyword(Keyword::TO)?;
+            update.with_renamed_column(&name, &parser.parse_identifier()?.value)
+        }
+        Token::Word(word) if word.keyword == Keyword::ALTER => {
+            let _ = parser.parse_keyword(Keyword::COLUMN);
+            let name = column_name(parser)?;
+            if parser.parse_keywords(&[Keyword::SET, Keyword::DATA, Keyword::TYPE])
+                || parser.parse_keyword(Keyword::TYPE)
+            {
+                update.with_column_type(&name, convert_data_type(&parser.parse_data_type()?)?)
+            } else if parser.parse_keywords(&[Keyword::DROP, Keyword::NOT, Keyword::NULL]) {
+                update.with_optional_column(&name)
+            } else if parser.parse_keywords(&[Keyword::SET, Keyword::NOT, Keyword::NULL]) {
+                return Err(DataFusionError::Plan(format!(
+                    "Column {} can't be made required, the existing rows may have nulls.",
+                    name
+                )));
+            } else if let Some(position) = column_position(parser, &name)? {
+                update.with_moved_column(&name, position)
+            } else {
+                return Err(DataFusionError::Plan(format!(
+                    "Columns of iceberg tables can't be altered with {}.",
+                    parser.peek_token()
+                )));
+            }
+        }
+        _ => return ctx.sql(sql).await,
+    };
+    end(parser)?;
+    let mut table = registered_table(ctx, table_name).await?;
+    if let Some(name) = if_exists {
+        if !has_column(table.table()?.schema(), &name) {
+            return ctx.read_empty();
+        }
+    }
+    table.update_schema(&update).await?;
+    replace_table(ctx, table_name, table).await?;
+    ctx.read_empty()
+}
+
+/// Name of a column, which is the path of dotted names for the fields of structs
+fn column_name(parser: &mut Parser) -> std::result::Result<String, ParserError> {
+    let name = parser.parse_object_name()?;
+    Ok(name
+        .0
+        .iter()
+        .map(|ident| ident.value.as_str())
+        .collect::<Vec<_>>()
+        .join("."))
+}
+
+/// Position like `FIRST` or `AFTER fare_amount`, where the other column is a field of the same struct as the column
+fn column_position(
+    parser: &mut Parser,
+    name: &str,
+) -> std::result::Result<Option<ColumnPosition>, ParserError> {
+    match parser.peek_token() {
+        Token::Word(word) if word.keyword == Keyword::FIRST => {
+            parser.next_token();
+            Ok(Some(ColumnPosition::First))
+        }
+        Token::Word(word) if word.value.eq_ignore_ascii_case("after") => {
+            parser.next_token();
+            let other = parser.parse_identifier()?.value;
+            Ok(Some(ColumnPosition::After(match name.rsplit_once('.') {
+                Some((parent, _)) => parent.to_owned() + "." + &other,
+                None => other,
+            })))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Whether the token starts a constraint or partition instead of a column
+fn is_constraint(token: &Token) -> bool {
+    matches!(token, Token::Word(word) if matches!(
+        word.keyword,
+        Keyword::CONSTRAINT
+            | Keyword::PARTITION
+            | Keyword::PARTITIONS
+            | Keyword::PRIMARY
+            | Keyword::UNIQUE
+            | Keyword::FOREIGN
+            | Keyword::CHECK
+    ))
+}
+
+/// Key and value of a table property like `'write.target-file-size-bytes' = '134217728'`
+fn property(parser: &mut Parser) -> std::result::Result<(String, String), ParserError> {
+    let key = parser.parse_literal_string()?;
+    parser.expect_token(&Token::Eq)?;
+    let value = match parser.parse_value()? {
+        Value::SingleQuotedString(value) | Value::Number(value, _) => value,
+        Value::Boolean(value) => value.to_string(),
+        value => {
+            return Err(ParserError::ParserError(format!(
+                "Expected a string or number as value of the property {}, found {}.",
+                key, value
+            )))
+        }
+    };
+    Ok((key, value))
+}
+
+/// Schema of the column definitions of a new table. Columns are optional unless they are `NOT NULL`.
+fn column_schema(columns: Vec<ColumnDef>) -> Result<Schema> {
+    let fields = columns
+        .iter()
+        .map(|column| {
+            let required = column
+                .options
+                .iter()
+                .any(|option| option.option == ColumnOption::NotNull);
+            Ok(Field::new(
+                &column.name.value,
+                convert_data_type(&column.data_type)?,
+                !required,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Schema::new(fields))
+}
+
+/// Format of the files that are copied into a table
+enum FileFormat {
+    Parquet,
+    Csv,
+    Json,
+}
+
+impl FileFormat {
+    fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "parquet" => Ok(FileFormat::Parquet),
+            "csv" => Ok(FileFormat::Csv),
+            "json" => Ok(FileFormat::Json),
+            _ => Err(DataFusionError::Plan(format!(
+                "Files can be copied from parquet, csv or json, found {}.",
+                name
+            ))),
+        }
+    }
+    /// Format of the file extension of the location, parquet for locations without extension
+    fn from_location(location: &str) -> Self {
+        if location.ends_with(".csv") {
+            FileFormat::Csv
+        } else if location.ends_with(".json") {
+            FileFormat::Json
+        } else {
+            FileFormat::Parquet
+        }
+    }
+}
+
+fn end(parser: &mut Parser) -> Result<()> {
+    let _ = parser.consume_token(&Token::SemiColon);
+    if parser.peek_token() != Token::EOF {
+        return Err(ParserError::ParserError(format!(
+            "Expected the end of the statement, found {}.",
+            parser.peek_token()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Append the rows of the files at the location to the table
+async fn copy_into(
+    ctx: &SessionContext,
+    table_name: &str,
+    location: &str,
+    format: FileFormat,
+) -> Result<Arc<DataFrame>> {
+    let df = match format {
+        FileFormat::Parquet => {
+            ctx.read_parquet(location, ParquetReadOptions::default())
+                .await?
+        }
+        FileFormat::Csv => ctx.read_csv(location, CsvReadOptions::new()).await?,
+        FileFormat::Json => {
+            ctx.clone()
+                .read_json(location, NdJsonReadOptions::default())
+                .await?
+        }
+    };
+    let mut table = registered_table(ctx, table_name).await?;
+    let rows = project(&df, &iceberg_to_arrow_schema(table.table()?.schema()))?;
+    let config = table
+        .writer_config()?
+        .with_session_options(&ctx.state().config);
+    let files = table.insert(rows.execute_stream().await?, &config).await?;
+    replace_table(ctx, table_name, table).await?;
+    output(
+        ctx,
+        &[
+            ("added_data_files_count", files.len()),
+            (
+                "added_records_count",
+                files.iter().map(|file| file.record_count).sum(),
+            ),
+        ],
+    )
+}
+
+async fn call(ctx: &SessionContext, procedure: Expr) -> Result<Arc<DataFrame>> {
+    let (name, args) = match procedure {
+        Expr::Function(function) => (
+            function
+                .name
+                .0
+                .last()
+                .map(|ident| ident.value.to_ascii_lowercase())
+                .unwrap_or_default(),
+            function.args,
+        ),
+        expr => {
+            return Err(DataFusionError::Plan(format!(
+                "Expected a procedure call, found {}.",
+                expr
+            )))
+        }
+    };
+    match name.as_str() {
+        "rewrite_data_files" => {
+            let args = arguments(
+                &name,
+                args,
+                &[
+                    "table",
+                    "target_file_size_bytes",
+                    "min_input_files",
+                    "strategy",
+                    "sort_order",
+                    "rewrite_all",
+                ],
+            )?;
+            let table_name = required(&name, &args, "table")?;
+            let mut options = RewriteOptions::default().with_strategy(strategy(&name, &args)?);
+            if let Some(size) = number(&args, "target_file_size_bytes")? {
+                options = options.with_target_file_size(size);
+            }
+            if let Some(files) = number(&args, "min_input_files")? {
+                options = options.with_min_input_files(files);
+            }
+            if boolean(&args, "rewrite_all")?.unwrap_or(false) {
+                options = options.with_rewrite_all();
+            }
+            let mut table = registered_table(ctx, table_name).await?;
+            let result = table.rewrite_data_files(&ctx.state(), &options).await?;
+            replace_table(ctx, table_name, table).await?;
+            output(
+                ctx,
+                &[
+                    ("rewritten_data_files_count", result.rewritten_data_files),
+                    ("added_data_files_count", result.added_data_files),
+                    ("rewritten_bytes_count", result.rewritten_bytes),
+                ],
+            )
+        }
+        "rewrite_manifests" => {
+            let args = arguments(&name, args, &["table", "target_manifest_size_bytes"])?;
+            let table_name = required(&name, &args, "table")?;
+            let mut options = RewriteManifestsOptions::default();
+            if let Some(size) = number(&args, "target_manifest_size_bytes")? {
+                options = options.with_target_manifest_size(size);
+            }
+            let mut table = registered_table(ctx, table_name).await?;
+            let result = table.rewrite_manifests(&options).await?;
+            replace_table(ctx, table_name, table).await?;
+            output(
+                ctx,
+                &[
+                    ("rewritten_manifests_count", result.rewritten_manifests),
+                    ("added_manifests_count", result.added_manifests),
+                ],
+            )
+        }
+        "expire_snapshots" => {
+            let args = arguments(&name, args, &["table", "older_than", "retain_last"])?;
+            let table_name = required(&name, &args, "table")?;
+            let mut options = ExpireOptions::default();
+            if let Some(older_than) = args.get("older_than") {
+                options = options.with_older_than(timestamp_ms("older_than", older_than)?);
+            }
+            if let Some(retain_last) = number(&args, "retain_last")? {
+                options = options.with_retain_last(retain_last);
+            }
+            let mut table = registered_table(ctx, table_name).await?;
+            let result = table.expire_snapshots(&options).await?;
+            replace_table(ctx, table_name, table).await?;
+            output(
+                ctx,
+                &[
+                    ("expired_snapshots_count", result.expired_snapshots),
+                    (
+                        "deleted_manifest_lists_count",
+                        result.deleted_manifest_lists,
+                    ),
+                    ("deleted_manifest_files_count", result.deleted_manifests),
+                    ("deleted_data_files_count", result.deleted_data_files),
+                ],
+            )
+        }
+        "remove_orphan_files" => {
+            let args = arguments(&name, args, &["table", "older_than", "dry_run"])?;
+            let table_name = required(&name, &args, "table")?;
+            let mut options = OrphanOptions::default();
+            if let Some(older_than) = args.get("older_than") {
+                options = options.with_older_than(timestamp_ms("older_than", older_than)?);
+            }
+            if boolean(&args, "dry_run")?.unwrap_or(false) {
+                options = options.with_dry_run();
+            }
+            let table = registered_table(ctx, table_name).await?;
+            ctx.read_batch(table.remove_orphan_files(&options).await?)
+        }
+        _ => Err(DataFusionError::Plan(format!(
+            "Procedure {} doesn't exist.",
+            name
+        ))),
+    }
+}
+
+/// Values of the arguments by parameter name. Arguments without a name are assigned to the parameters in order.
+fn arguments(
+    procedure: &str,
+    args: Vec<FunctionArg>,
+    parameters: &[&str],
+) -> Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    for (position, arg) in args.into_iter().enumerate() {
+        let (name, arg) = match arg {
+            FunctionArg::Named { name, arg } => (name.value.to_ascii_lowercase(), arg),
+            FunctionArg::Unnamed(arg) => match parameters.get(position) {
+                Some(name) => (name.to_string(), arg),
+                None => {
+                    return Err(DataFusionError::Plan(format!(
+                        "Procedure {} has only {} parameters.",
+                        procedure,
+                        parameters.len()
+                    )))
+                }
+            },
+        };
+        if !parameters.contains(&name.as_str()) {
+            return Err(DataFusionError::Plan(format!(
+                "Procedure {} has no parameter {}.",
+                procedure, name
+            )));
+        }
+        let value = match arg {
+            FunctionArgExpr::Expr(Expr::Value(Value::SingleQuotedString(value)))
+            | FunctionArgExpr::Expr(Expr::Value(Value::Number(value, _))) => value,
+            FunctionArgExpr::Expr(Expr::Value(Value::Boolean(value))) => value.to_string(),
+            arg => {
+                return Err(DataFusionError::Plan(format!(
+                    "Argument {} of procedure {} has to be a string, number or boolean literal, found {}.",
+                    name, procedure, arg
+                )))
+            }
+        };
+        if values.insert(name.clone(), value).is_some() {
+            return Err(DataFusionError::Plan(format!(
+                "Argument {} of procedure {} is given twice.",
+                name, procedure
+            )));
+        }
+    }
+    Ok(values)
+}
+
+fn required<'a>(procedure: &str, args: &'a HashMap<String, String>, name: &str) -> Result<&'a str> {
+    args.get(name).map(String::as_str).ok_or_else(|| {
+        DataFusionError::Plan(format!(
+            "Procedure {} requires the argument {}.",
+            procedure, name
+        ))
+    })
+}
+
+/// Strategy of the rewrite, the sort order lists the columns or wraps them in zorder(...)
+fn strategy(procedure: &str, args: &HashMap<String, String>) -> Result<RewriteStrategy> {
+    let strategy = args
+        .get("strategy")
+        .map(|strategy| strategy.to_ascii_lowercase());
+    match (strategy.as_deref(), args.get("sort_order")) {
+        (None | Some("binpack"), None) => Ok(RewriteStrategy::BinPack),
+        (Some("sort"), Some(sort_order)) => {
+            let sort_order = sort_order.trim();
+            let columns = |columns: &str| -> Vec<String> {
+                columns
+                    .split(',')
+                    .map(|column| column.trim().to_owned())
+                    .collect()
+            };
+            match sort_order
+                .strip_prefix("zorder(")
+                .and_then(|columns| columns.strip_suffix(')'))
+            {
+                Some(zorder) => Ok(RewriteStrategy::ZOrder(columns(zorder))),
+                None => Ok(RewriteStrategy::Sort(columns(sort_order))),
+            }
+        }
+        (Some("sort"), None) => Err(DataFusionError::Plan(format!(
+            "Procedure {} requires the argument sort_order for the sort strategy.",
+            procedure
+        ))),
+        (None | Some("binpack"), Some(_)) => Err(DataFusionError::Plan(
+            "The argument sort_order needs the sort strategy.".to_string(),
+        )),
+        (Some(strategy), _) => Err(DataFusionError::Plan(format!(
+            "Rewrite strategy {} doesn't exist, use binpack or sort.",
+            strategy
+        ))),
+    }
+}
+
+fn boolean(args: &HashMap<String, String>, name: &str) -> Result<Option<bool>> {
+    args.get(name)
+        .map(|value| {
+            value.to_ascii_lowercase().parse().map_err(|_| {
+                DataFusionError::Plan(format!(
+                    "Argument {} has to be true or false, found {}.",
+                    name, value
+                ))
+            })
+        })
+        .transpose()
+}
+
+fn number(args: &HashMap<String, String>, name: &str) -> Result<Option<usize>> {
+    args.get(name)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                DataFusionError::Plan(format!(
+                    "Argument {} has to be a positive integer, found {}.",
+                    name, value
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Milliseconds since the epoch, given as number or as timestamp in UTC like `2022-09-07 06:20:18`
+fn timestamp_ms(name: &str, value: &str) -> Result<i64> {
+    if let Ok(timestamp_ms) = value.parse() {
+        return Ok(timestamp_ms);
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|timestamp| timestamp.timestamp_millis())
+        .ok_or_else(|| {
+            DataFusionError::Plan(format!(
+                "Argument {} has to be a timestamp, found {}.",
+                name, value
+            ))
+        })
+}
+
+fn provider(ctx: &SessionContext, name: &str) -> Result<Arc<dyn TableProvider>> {
+    match ctx.table(name)?.to_unoptimized_plan() {
+        LogicalPlan::TableScan(scan) => source_as_provider(&scan.source),
+        _ => Err(DataFusionError::Plan(format!(
+            "Table {} is not an iceberg table.",
+            name
+        ))),
+    }
+}
+
+/// Copy of the registered iceberg table with its current metadata, which can be changed
+async fn registered_table(ctx: &SessionContext, name: &str) -> Result<DataFusionTable> {
+    let provider = provider(ctx, name)?;
+    if let Some(table) = provider.as_any().downcast_ref::<DataFusionTable>() {
+        table.load_current().await
+    } else if let Some(table) = provider.as_any().downcast_ref::<RefreshingTable>() {
+        table.current().load_current().await
+    } else {
+        Err(DataFusionError::Plan(format!(
+            "Table {} is not an iceberg table.",
+            name
+        )))
+    }
+}
+
+/// Make the registered table read the changed table
+async fn replace_table(ctx: &SessionContext, name: &str, table: DataFusionTable) -> Result<()> {
+    let provider = provider(ctx, name)?;
+    // Refreshing tables stay registered, so that they keep refreshing
+    match provider.as_any().downcast_ref::<RefreshingTable>() {
+        Some(refreshing) => refreshing.refresh().await.map(|_| ()),
+        None => {
+            ctx.deregister_table(name)?;
+            ctx.register_table(name, Arc::new(table)).map(|_| ())
+        }
+    }
+}
+
+/// Single row with the counts of the procedure
+fn output(ctx: &SessionContext, counts: &[(&str, usize)]) -> Result<Arc<DataFrame>> {
+    let schema = Schema::new(
+        counts
+            .iter()
+            .map(|(name, _)| Field::new(name, DataType::UInt64, false))
+            .collect(),
+    );
+    let columns = counts
+        .iter()
+        .map(|(_, count)| Arc::new(UInt64Array::from(vec![*count as u64])) as ArrayRef)
+        .collect();
+    ctx.read_batch(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::arrow::{
+        array::{Float64Array, Int64Array, StringArray, StructArray, UInt64Array},
+        util::pretty::pretty_format_batches,
+    };
+    use iceberg_rs::{model::data_types::Type, table::Table};
+    use object_store::{memory::InMemory, ObjectStore};
+
+    use crate::{
+        commit::tests::insert_trips,
+        dataframe::{WriteIceberg, WriteOptions, WriteTarget},
+        properties::DEFAULT_NAME_MAPPING,
+        schema::name_mapping,
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    async fn count(ctx: &SessionContext) -> i64 {
+        let batches = sql(ctx, "SELECT count(*) FROM nyc_taxis")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .value(0)
+    }
+
+    #[tokio::test]
+    pub async fn test_call_procedures() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        insert_trips(&mut table, [0, 1]).await;
+        let ctx = SessionContext::new();
+        ctx.register_table("nyc_taxis", Arc::new(table)).unwrap();
+        assert_eq!(count(&ctx).await, 6);
+
+        let batches = sql(
+            &ctx,
+            "CALL system.rewrite_data_files(table => 'nyc_taxis', min_input_files => 2);",
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+        let rewritten = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(rewritten, 6);
+        // The registered table reads the replace snapshot
+        assert_eq!(count(&ctx).await, 6);
+        let provider = provider(&ctx, "nyc_taxis").unwrap();
+        let table = provider.as_any().downcast_ref::<DataFusionTable>().unwrap();
+        // The manifest of the new files and the two manifests that record the removed files
+        assert_eq!(table.table().unwrap().manifests().len(), 3);
+
+        // The entries of the three manifests fit into one manifest
+        let batches = sql(&ctx, "CALL system.rewrite_manifests('nyc_taxis')")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let added = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(added, 1);
+        assert_eq!(count(&ctx).await, 6);
+
+        // The snapshots of the original files and the insert are expired, the rewrites are kept
+        let batches = sql(
+            &ctx,
+            "CALL system.expire_snapshots('nyc_taxis', '2100-01-01 00:00:00', 2)",
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+        let expired = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(expired, 2);
+        assert_eq!(count(&ctx).await, 6);
+
+        // The expiration deleted the files of the expired snapshots, only the first metadata file is left over
+        let batches = sql(
+            &ctx,
+            "CALL system.remove_orphan_files('nyc_taxis', '2100-01-01 00:00:00', dry_run => true)",
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+        let orphans = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+        assert!(orphans.value(0).ends_with("/metadata/v0.metadata.json"));
+
+        // The two files of the rewrite are clustered again
+        let batches = sql(
+            &ctx,
+            "CALL system.rewrite_data_files(table => 'nyc_taxis', strategy => 'sort', \
+             sort_order => 'zorder(vendor_id, trip_id)', rewrite_all => true)",
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+        let rewritten = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(rewritten, 2);
+        assert_eq!(count(&ctx).await, 6);
+        assert!(sql(
+            &ctx,
+            "CALL system.rewrite_data_files('nyc_taxis', strategy => 'shuffle')"
+        )
+        .await
+        .is_err());
+
+        assert!(
+            sql(&ctx, "CALL system.rewrite_data_files('nyc_taxis', 1, 2, 3)")
+                .await
+                .is_err()
+        );
+        assert!(sql(&ctx, "CALL system.remove_everything('nyc_taxis')")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_copy_into() {
+        let object_store = taxis_copy(&[]).await;
+        let table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        let ctx = SessionContext::new();
+        ctx.register_table("nyc_taxis", Arc::new(table)).unwrap();
+
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        // The columns are matched by name and the integers are cast to the types of the table
+        std::fs::write(
+            directory.join("trips.csv"),
+            "trip_id,vendor_id,trip_distance,fare_amount,store_and_fwd_flag\n10,1,1.5,12,N\n11,2,2.5,20,Y\n",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("trips.json"),
+            "{\"vendor_id\": 1, \"trip_id\": 12, \"trip_distance\": 3.5, \"fare_amount\": 30.0}\n",
+        )
+        .unwrap();
+        let location = directory.to_str().unwrap();
+
+        let batches = sql(
+            &ctx,
+            &format!("COPY INTO nyc_taxis FROM '{}/*.csv';", location),
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+        let records = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(records, 2);
+        assert_eq!(count(&ctx).await, 6);
+
+        sql(
+            &ctx,
+            &format!("COPY INTO nyc_taxis FROM '{}' FORMAT JSON", location),
+        )
+        .await
+        .unwrap();
+        assert_eq!(count(&ctx).await, 7);
+
+        assert!(sql(
+            &ctx,
+            &format!("COPY INTO nyc_taxis FROM '{}' FORMAT AVRO", location)
+        )
+        .await
+        .is_err());
+        std::fs::write(directory.join("other.csv"), "id\n1\n").unwrap();
+        assert!(sql(
+            &ctx,
+            &format!("COPY INTO nyc_taxis FROM '{}/other.csv'", location)
+        )
+        .await
+        .is_err());
+        assert_eq!(count(&ctx).await, 7);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_table_properties() {
+        let object_store = taxis_copy(&[]).await;
+        let table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        let ctx = SessionContext::new();
+        ctx.register_table("nyc_taxis", Arc::new(table)).unwrap();
+        let properties = |ctx: SessionContext| async move {
+            let batches = sql(&ctx, "SHOW TBLPROPERTIES nyc_taxis")
+                .await
+                .unwrap()
+                .collect()
+                .await
+                .unwrap();
+            let keys = batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            let values = batches[0]
+                .column(1)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            (0..batches[0].num_rows())
+                .map(|row| (keys.value(row).to_owned(), values.value(row).to_owned()))
+                .collect::<HashMap<String, String>>()
+        };
+        let before = properties(ctx.clone()).await;
+
+        sql(
+            &ctx,
+            "ALTER TABLE nyc_taxis SET TBLPROPERTIES ('write.target-file-size-bytes' = 1024, 'comment' = 'taxis')",
+        )
+        .await
+        .unwrap();
+        let after = properties(ctx.clone()).await;
+        assert_eq!(after.len(), before.len() + 2);
+        assert_eq!(after["write.target-file-size-bytes"], "1024");
+        assert_eq!(after["comment"], "taxis");
+        // The registered table writes with the new properties
+        let table = registered_table(&ctx, "nyc_taxis").await.unwrap();
+        assert_eq!(table.writer_config().unwrap().target_file_size(), 1024);
+
+        assert!(sql(
+            &ctx,
+            "ALTER TABLE nyc_taxis SET TBLPROPERTIES ('write.target-file-size-bytes' = 'large')"
+        )
+        .await
+        .is_err());
+        assert!(sql(
+            &ctx,
+            "ALTER TABLE nyc_taxis UNSET TBLPROPERTIES ('comment', 'missing')"
+        )
+        .await
+        .is_err());
+        sql(
+            &ctx,
+            "ALTER TABLE nyc_taxis UNSET TBLPROPERTIES IF EXISTS ('comment', 'missing');",
+        )
+        .await
+        .unwrap();
+        let after = properties(ctx.clone()).await;
+        assert_eq!(after.len(), before.len() + 1);
+        assert!(!after.contains_key("comment"));
+    }
+
+    #[tokio::test]
+    pub async fn test_alter_columns() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table(TAXIS, &object_store)
+                .await
+                .unwrap(),
+        );
+        let mapping = name_mapping(table.table().unwrap().schema());
+        table
+            .set_properties(HashMap::from([(DEFAULT_NAME_MAPPING.to_owned(), mapping)]))
+            .await
+            .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_table("nyc_taxis", Arc::new(table)).unwrap();
+        let query = |sql_query: &'static str| {
+            let ctx = ctx.clone();
+            async move {
+                let batches = sql(&ctx, sql_query).await.unwrap().collect().await.unwrap();
+                pretty_format_batches(&batches).unwrap().to_string()
+            }
+        };
+
+        for statement in [
+            "ALTER TABLE nyc_taxis ADD COLUMN tip_amount DOUBLE",
+            "ALTER TABLE nyc_taxis RENAME COLUMN fare_amount TO fare",
+            "ALTER TABLE nyc_taxis ALTER COLUMN trip_distance SET DATA TYPE DOUBLE",
+            "ALTER TABLE nyc_taxis DROP COLUMN store_and_fwd_flag",
+            "ALTER TABLE nyc_taxis ADD COLUMN store_and_fwd_flag VARCHAR",
+            "ALTER TABLE nyc_taxis DROP COLUMN IF EXISTS missing",
+        ] {
+            sql(&ctx, statement).await.unwrap();
+        }
+        for statement in [
+            "ALTER TABLE nyc_taxis DROP COLUMN vendor_id",
+            "ALTER TABLE nyc_taxis DROP COLUMN missing",
+            "ALTER TABLE nyc_taxis ADD COLUMN passenger_count BIGINT NOT NULL",
+            "ALTER TABLE nyc_taxis ALTER COLUMN trip_id SET NOT NULL",
+            "ALTER TABLE nyc_taxis ALTER COLUMN trip_id SET DATA TYPE INT",
+            "ALTER TABLE nyc_taxis RENAME COLUMN fare TO trip_id",
+        ] {
+            assert!(sql(&ctx, statement).await.is_err(), "{}", statement);
+        }
+
+        let table = registered_table(&ctx, "nyc_taxis").await.unwrap();
+        let schema = table.schema();
+        let names: Vec<&str> = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "vendor_id",
+                "trip_id",
+                "trip_distance",
+                "fare",
+                "tip_amount",
+                "store_and_fwd_flag"
+            ]
+        );
+        assert_eq!(schema.field(2).data_type(), &DataType::Float64);
+        let mapping = &table.properties().unwrap()[DEFAULT_NAME_MAPPING];
+        assert!(mapping.contains(r#""names":["fare","fare_amount"]"#));
+
+        // The data files were written with the previous schema
+        let after =
+            query("SELECT trip_id, fare, trip_distance FROM nyc_taxis ORDER BY trip_id").await;
+        let expected = [
+            "+---------+-------+--------------------+",
+            "| trip_id | fare  | trip_distance      |",
+            "+---------+-------+--------------------+",
+            "| 1000371 | 15.32 | 1.7999999523162842 |",
+            "| 1000372 | 22.15 | 2.5                |",
+            "| 1000373 | 9.01  | 0.8999999761581421 |",
+            "| 1000374 | 42.13 | 8.399999618530273  |",
+            "+---------+-------+--------------------+",
+        ];
+        assert_eq!(after, expected.join("\n"));
+        // The dropped column is not read as the new column of the same name
+        let nulls = query(
+            "SELECT count(*) AS rows FROM nyc_taxis WHERE store_and_fwd_flag IS NULL AND tip_amount IS NULL",
+        )
+        .await;
+        assert!(nulls.contains("| 4    |"), "{}", nulls);
+        let filtered =
+            query("SELECT count(*) AS rows FROM nyc_taxis WHERE trip_distance > 2.0 AND fare > 20")
+                .await;
+        assert!(filtered.contains("| 2    |"), "{}", filtered);
+    }
+
+    fn pickup_batch(ids: Vec<i64>, fields: Vec<(&str, Vec<Option<f64>>)>) -> RecordBatch {
+        let pickup = StructArray::from(
+            fields
+                .into_iter()
+                .map(|(name, values)| {
+                    (
+                        Field::new(name, DataType::Float64, true),
+                        Arc::new(Float64Array::from(values)) as ArrayRef,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+        RecordBatch::try_from_iter(vec![
+            ("id", Arc::new(Int64Array::from(ids)) as ArrayRef),
+            ("pickup", Arc::new(pickup) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[tokio::test]
+    pub async fn test_alter_nested_columns() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let ctx = SessionContext::new();
+        let batch = pickup_batch(
+            vec![1, 2],
+            vec![
+                ("latitude", vec![Some(40.7), Some(40.8)]),
+                ("longitude", vec![Some(-74.0), Some(-73.9)]),
+            ],
+        );
+        let table = ctx
+            .read_batch(batch)
+            .unwrap()
+            .write_iceberg(
+                WriteTarget::FileSystem {
+                    location: "/trips".to_owned(),
+                    object_store,
+                },
+                &WriteOptions::default().with_create_if_not_exists(),
+            )
+            .await
+            .unwrap();
+        ctx.register_table("trips", Arc::new(table)).unwrap();
+
+        for statement in [
+            "ALTER TABLE trips ADD COLUMN pickup.altitude DOUBLE FIRST",
+            "ALTER TABLE trips RENAME COLUMN pickup.latitude TO lat",
+            "ALTER TABLE trips DROP COLUMN pickup.longitude",
+            "ALTER TABLE trips ADD COLUMN pickup.longitude DOUBLE",
+            "ALTER TABLE trips ALTER COLUMN pickup.altitude AFTER longitude",
+            "ALTER TABLE trips DROP COLUMN IF EXISTS pickup.missing",
+        ] {
+            sql(&ctx, statement).await.unwrap();
+        }
+        for statement in [
+            "ALTER TABLE trips ADD COLUMN missing.altitude DOUBLE",
+            "ALTER TABLE trips ADD COLUMN id.altitude DOUBLE",
+            "ALTER TABLE trips ADD COLUMN pickup.lat DOUBLE",
+            "ALTER TABLE trips RENAME COLUMN pickup.lat TO altitude",
+            "ALTER TABLE trips ALTER COLUMN pickup.lat AFTER id",
+            "ALTER TABLE trips ALTER COLUMN pickup.lat SET NOT NULL",
+            "ALTER TABLE trips DROP COLUMN pickup.missing",
+        ] {
+            assert!(sql(&ctx, statement).await.is_err(), "{}", statement);
+        }
+        let table = registered_table(&ctx, "trips").await.unwrap();
+        match &table.table().unwrap().schema().fields[1].field_type {
+            Type::Struct(pickup) => {
+                let names: Vec<&str> = pickup.fields.iter().map(|x| x.name.as_str()).collect();
+                assert_eq!(names, ["lat", "longitude", "altitude"]);
+            }
+            field_type => panic!("Expected a struct, found {}.", field_type),
+        }
+
+        // Rows of the evolved struct are read together with the rows of the first data file
+        let batch = pickup_batch(
+            vec![3],
+            vec![
+                ("lat", vec![Some(40.9)]),
+                ("longitude", vec![Some(-73.8)]),
+                ("altitude", vec![Some(10.0)]),
+            ],
+        );
+        let table = ctx
+            .read_batch(batch)
+            .unwrap()
+            .write_iceberg(
+                WriteTarget::Table(Box::new(table)),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        replace_table(&ctx, "trips", table).await.unwrap();
+        let batches = sql(&ctx, "SELECT id, pickup FROM trips ORDER BY id")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let expected = [
+            "+----+----------------------------------------------------+",
+            "| id | pickup                                             |",
+            "+----+----------------------------------------------------+",
+            r#"| 1  | {"lat": 40.7, "longitude": null, "altitude": null} |"#,
+            r#"| 2  | {"lat": 40.8, "longitude": null, "altitude": null} |"#,
+            r#"| 3  | {"lat": 40.9, "longitude": -73.8, "altitude": 10}  |"#,
+            "+----+----------------------------------------------------+",
+        ];
+        assert_eq!(
+            pretty_format_batches(&batches).unwrap().to_string(),
+            expected.join("\n")
+        );
+        let batches = sql(&ctx, "SELECT count(*) FROM trips WHERE id > 1")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let count = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    pub async fn test_sql_passes_other_statements() {
+        let ctx = SessionContext::new();
+        let batches = sql(&ctx, "SELECT 1 + 1")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let value = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(value, 2);
+
+        assert!(sql(&ctx, "CALL system.remove_everything('nyc_taxis')")
+            .await
+            .is_err());
+        assert!(sql(&ctx, "CALL system.remove_everything('nyc_taxis') now")
+            .await
+            .is_err());
+        assert!(sql(&ctx, "CALL 1 + 1").await.is_err());
+    }
+}
@@ -0,0 +1,84 @@
+/*!
+ * Tables that are loaded from a metadata file without a catalog
+*/
+
+use std::sync::Arc;
+
+use datafusion::error::{DataFusionError, Result};
+use iceberg_rs::table::Table;
+use object_store::ObjectStore;
+
+use crate::{error::Error, DataFusionTable};
+
+impl DataFusionTable {
+    /// Load the table of the metadata file, for example `s3://bucket/table/metadata/v3.metadata.json`, from the object
+    /// store. The table can only be read at the latest metadata file, because older metadata files reference snapshots
+    /// that can't be scanned.
+    pub async fn from_metadata_location(
+        metadata_location: &str,
+        object_store: &Arc<dyn ObjectStore>,
+    ) -> Result<Self> {
+        let (location, file) = metadata_location.rsplit_once("/metadata/").ok_or_else(|| {
+            DataFusionError::Plan(format!(
+                "{} is not in the metadata directory of a table.",
+                metadata_location
+            ))
+        })?;
+        let table = Table::load_file_system_table(location, object_store)
+            .await
+            .map_err(Error::iceberg)?;
+        if !table.metadata_location().ends_with(file) {
+            return Err(DataFusionError::Plan(format!(
+                "{} is not the latest metadata file of the table, which is {}.",
+                metadata_location,
+                table.metadata_location()
+            )));
+        }
+        Ok(DataFusionTable::from(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::prelude::SessionContext;
+    use object_store::local::LocalFileSystem;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_from_metadata_location() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        assert!(DataFusionTable::from_metadata_location(
+            "/home/iceberg/warehouse/nyc/taxis/metadata/v0.metadata.json",
+            &object_store
+        )
+        .await
+        .is_err());
+
+        let table = DataFusionTable::from_metadata_location(
+            "/home/iceberg/warehouse/nyc/taxis/metadata/v1.metadata.json",
+            &object_store,
+        )
+        .await
+        .expect("Failed to load table from metadata file.");
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", Arc::new(table)).unwrap();
+
+        let batches = ctx
+            .sql("SELECT trip_id FROM nyc_taxis")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .expect("Failed to execute query plan.");
+
+        let num_rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+
+        assert_eq!(num_rows, 4)
+    }
+}
@@ -1,49 +1,116 @@
-use anyhow::anyhow;
 use datafusion::physical_plan::{ColumnStatistics, Statistics};
 use iceberg_rs::catalog::relation::Relation;
 
-use super::table::DataFusionTable;
+use super::{
+    column_statistics::merge,
+    metadata::{ManifestFileExt, TableMetadataExt},
+    table::DataFusionTable,
+};
 use anyhow::Result;
 
 impl DataFusionTable {
+    /// Statistics of the current snapshot. They are computed once per snapshot and cached afterwards.
     pub(crate) async fn statistics(&self) -> Result<Statistics> {
-        match &self.0 {
-            Relation::Table(table) => table.manifests().iter().fold(
+        let snapshot_id = match &self.relation {
+            Relation::Table(table) => table.metadata().current_snapshot_id(),
+            Relation::View(_) => None,
+        };
+        if let (Some(snapshot_id), Some((cached_id, statistics))) =
+            (snapshot_id, &*self.statistics_cache.lock().unwrap())
+        {
+            if snapshot_id == *cached_id {
+                return Ok(statistics.clone());
+            }
+        }
+        let statistics = self.compute_statistics().await?;
+        if let Some(snapshot_id) = snapshot_id {
+            *self.statistics_cache.lock().unwrap() = Some((snapshot_id, statistics.clone()));
+        }
+        Ok(statistics)
+    }
+    async fn compute_statistics(&self) -> Result<Statistics> {
+        let statistics = self.manifest_statistics()?;
+        let (table, provider) = match (&self.relation, &self.statistics_provider) {
+            (Relation::Table(table), Some(provider)) => (table, provider),
+            _ => return Ok(statistics),
+        };
+        let snapshot_id = match table.metadata().current_snapshot_id() {
+            Some(snapshot_id) => snapshot_id,
+            None => return Ok(statistics),
+        };
+        let mut external = provider
+            .column_statistics(table.metadata().location(), snapshot_id)
+            .await?;
+        let column_statistics = statistics.column_statistics.map(|columns| {
+            columns
+                .into_iter()
+                .zip(table.schema().fields.iter())
+                .map(|(column, field)| match external.remove(&field.name) {
+                    Some(external) => merge(column, external),
+                    None => column,
+                })
+                .collect()
+        });
+        // External statistics are usually estimates
+        Ok(Statistics {
+            column_statistics,
+            is_exact: false,
+            ..statistics
+        })
+    }
+    fn manifest_statistics(&self) -> Result<Statistics> {
+        match &self.relation {
+            Relation::Table(table) => {
+                // Each data file is listed in exactly one manifest of the snapshot
+                let num_rows = table
+                    .manifests()
+                    .iter()
+                    .map(|manifest| manifest.record_count())
+                    .sum::<Option<i64>>()
+                    .map(|num_rows| num_rows as usize);
                 Ok(Statistics {
-                    num_rows: Some(0),
+                    num_rows,
                     total_byte_size: None,
                     column_statistics: Some(vec![
-                        ColumnStatistics {
-                            null_count: None,
-                            max_value: None,
-                            min_value: None,
-                            distinct_count: None
-                        };
+                        ColumnStatistics::default();
                         table.schema().fields.len()
                     ]),
-                    is_exact: true,
-                }),
-                |acc, x| {
-                    let acc = acc?;
-                    Ok(Statistics {
-                        num_rows: acc.num_rows.zip(x.added_files_count()).map(
-                            |(num_rows, added_files_count)| num_rows + added_files_count as usize,
-                        ),
-                        total_byte_size: None,
-                        column_statistics: Some(vec![
-                            ColumnStatistics {
-                                null_count: None,
-                                max_value: None,
-                                min_value: None,
-                                distinct_count: None
-                            };
-                            table.schema().fields.len()
-                        ]),
-                        is_exact: true,
-                    })
-                },
-            ),
-            Relation::View(_) => Err(anyhow! {"Cannot get statistics for a view."}),
+                    is_exact: num_rows.is_some(),
+                })
+            }
+            // The statistics of a view are only known after its logical plan has been planned
+            Relation::View(_) => Ok(Statistics::default()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_statistics_cache() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        );
+        assert!(table.statistics_cache.lock().unwrap().is_none());
+
+        let first = table.statistics().await.unwrap();
+        let cached = table.statistics_cache.lock().unwrap().clone();
+        assert_eq!(cached.map(|(id, _)| id), Some(638933773299822130));
+
+        let second = table.statistics().await.unwrap();
+        assert_eq!(first, second);
+    }
+}
@@ -4,93 +4,344 @@
 
 use anyhow::Result;
 use chrono::{naive::NaiveDateTime, DateTime, Utc};
-use object_store::ObjectMeta;
-use std::{any::Any, collections::HashMap, ops::DerefMut, sync::Arc};
+use object_store::{ObjectMeta, ObjectStore};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    ops::DerefMut,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use datafusion::{
-    arrow::datatypes::{Schema as ArrowSchema, SchemaRef},
+    arrow::{
+        compute::SortOptions,
+        datatypes::{DataType, Field, Schema as ArrowSchema, SchemaRef},
+    },
     common::DataFusionError,
     datasource::{
-        file_format::{parquet::ParquetFormat, FileFormat},
-        listing::PartitionedFile,
-        object_store::ObjectStoreUrl,
-        TableProvider, ViewTable,
+        listing::PartitionedFile, object_store::ObjectStoreUrl, TableProvider, ViewTable,
     },
     execution::context::SessionState,
-    logical_expr::TableType,
+    logical_expr::{TableProviderFilterPushDown, TableType},
     optimizer::utils::conjunction,
     physical_optimizer::pruning::PruningPredicate,
-    physical_plan::{file_format::FileScanConfig, ExecutionPlan},
+    physical_plan::{
+        expressions::{Column, PhysicalSortExpr},
+        file_format::{FileScanConfig, ParquetExec, ParquetFileReaderFactory},
+        projection::ProjectionExec,
+        union::UnionExec,
+        ColumnStatistics, ExecutionPlan, Partitioning, PhysicalExpr, Statistics,
+    },
     prelude::Expr,
     scalar::ScalarValue,
     sql::{parser::DFParser, planner::SqlToRel},
 };
+use futures::{stream, StreamExt, TryStreamExt};
 use url::Url;
 
-use crate::pruning_statistics::{PruneDataFiles, PruneManifests};
+use crate::{
+    column_statistics::StatisticsProvider,
+    commit::object_path,
+    error::Error,
+    failover::FailoverObjectStore,
+    file_columns::{self, FileColumns, RenamingReaderFactory},
+    masking::{self, MaskingPolicy},
+    metadata::{ManifestEntryExt, TableMetadataExt},
+    metadata_cache::{CachedReaderFactory, ParquetMetadataCache},
+    options,
+    progress::{ProgressCallback, ProgressTracker},
+    pruning_statistics::{PruneDataFiles, PruneManifests},
+    pushdown,
+    query_log::{QueryLog, TableRead},
+    report::{MetricsReporter, ScanReport},
+    sample::Sample,
+    scan::{IcebergScanExec, PlanningMetrics, ScanDetails},
+    schema::{iceberg_to_arrow_schema, without_nested_field_ids},
+    transform,
+};
 
 use iceberg_rs::{
-    arrow::schema::iceberg_to_arrow_schema, catalog::relation::Relation,
-    model::view_metadata::Representation, table::Table, util, view::View,
+    catalog::relation::Relation,
+    model::{
+        manifest::{FileFormat as DataFileFormat, ManifestEntry},
+        partition::Transform,
+        sort::{NullOrder, SortDirection},
+        values::Value,
+        view_metadata::Representation,
+    },
+    table::Table,
+    util,
+    view::View,
 };
 // mod value;
 
+/// Number of failed reads from the table object store before the replica is used
+const FAILOVER_ATTEMPTS: usize = 3;
+
+/// Number of manifests that are fetched at the same time during planning
+const DEFAULT_MANIFEST_CONCURRENCY: usize = 8;
+
+/// Prefix of the virtual columns that contain the partition values
+pub const PARTITION_COLUMN_PREFIX: &str = "_partition_";
+
 /// Iceberg table for datafusion
-pub struct DataFusionTable(pub Relation);
+pub struct DataFusionTable {
+    pub(crate) relation: Relation,
+    sample: Option<Sample>,
+    strict: bool,
+    replica: Option<Arc<dyn ObjectStore>>,
+    progress: Option<ProgressCallback>,
+    query_log: Option<(Arc<QueryLog>, String)>,
+    timezone: Option<String>,
+    partition_columns: bool,
+    pub(crate) masking: HashMap<String, MaskingPolicy>,
+    bucket_partitioning: bool,
+    output_ordering: bool,
+    reporter: Option<Arc<dyn MetricsReporter>>,
+    pub(crate) statistics_provider: Option<Arc<dyn StatisticsProvider>>,
+    metadata_cache: Option<Arc<ParquetMetadataCache>>,
+    manifest_concurrency: usize,
+    exact_filters: bool,
+    // Branch that the table reads and commits to instead of the main branch
+    pub(crate) branch: Option<String>,
+    // Statistics of the snapshot they were computed for
+    pub(crate) statistics_cache: Mutex<Option<(i64, Statistics)>>,
+}
 
 impl core::ops::Deref for DataFusionTable {
     type Target = Relation;
 
     fn deref(self: &'_ DataFusionTable) -> &'_ Self::Target {
-        &self.0
+        &self.relation
     }
 }
 
 impl DerefMut for DataFusionTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.relation
     }
 }
 
 impl From<Relation> for DataFusionTable {
     fn from(value: Relation) -> Self {
-        DataFusionTable(value)
+        DataFusionTable::new(value)
+    }
+}
+
+impl DataFusionTable {
+    /// Table provider for the table or view with the default options
+    pub fn new(relation: Relation) -> Self {
+        DataFusionTable {
+            relation,
+            sample: None,
+            strict: false,
+            replica: None,
+            progress: None,
+            query_log: None,
+            timezone: None,
+            partition_columns: false,
+            masking: HashMap::new(),
+            bucket_partitioning: false,
+            output_ordering: false,
+            reporter: None,
+            statistics_provider: None,
+            metadata_cache: None,
+            manifest_concurrency: DEFAULT_MANIFEST_CONCURRENCY,
+            exact_filters: false,
+            branch: None,
+            statistics_cache: Mutex::new(None),
+        }
+    }
+    /// Table or view of the provider
+    pub fn into_relation(self) -> Relation {
+        self.relation
     }
 }
 
 impl From<Table> for DataFusionTable {
     fn from(value: Table) -> Self {
-        DataFusionTable(Relation::Table(value))
+        DataFusionTable::from(Relation::Table(value))
     }
 }
 
 impl From<View> for DataFusionTable {
     fn from(value: View) -> Self {
-        DataFusionTable(Relation::View(value))
+        DataFusionTable::from(Relation::View(value))
+    }
+}
+
+impl DataFusionTable {
+    /// Only scan a random subset of the row groups. The statistics are scaled accordingly and are no longer exact.
+    pub fn with_sample(mut self, sample: Sample) -> Self {
+        self.sample = Some(sample);
+        self
+    }
+    /// Validate the table metadata against the iceberg specification before every scan
+    pub fn with_strict_validation(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+    /// Read the data from the replica object store when reads from the table object store fail repeatedly.
+    /// The replica has to contain the same keys as the table object store.
+    pub fn with_replica(mut self, replica: Arc<dyn ObjectStore>) -> Self {
+        self.replica = Some(replica);
+        self
+    }
+    /// Report the number of files that a scan is going to read once the scan is planned
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+    /// Return timestamptz columns in the given timezone instead of UTC. Timestamps without timezone are not changed.
+    pub fn with_timezone(mut self, timezone: &str) -> Self {
+        self.timezone = Some(timezone.to_owned());
+        self
+    }
+    /// Expose the partition values of the default partition spec as additional columns named `_partition_<partition name>`
+    pub fn with_partition_columns(mut self) -> Self {
+        self.partition_columns = true;
+        self
+    }
+    /// Mask the values of the column for all sessions without the [`masking::UnmaskedPrivilege`]
+    pub fn with_masking(mut self, column: &str, policy: MaskingPolicy) -> Self {
+        self.masking.insert(column.to_owned(), policy);
+        self
+    }
+    /// Read the files of every bucket of the default partition spec in one partition and report the scan as hash
+    /// partitioned by the bucket column. Without this option the scan never reports a hash partitioning. The buckets
+    /// are computed with the murmur3 hash of iceberg while datafusion repartitions with its own hash function, so the
+    /// partitions only line up with tables that are bucketed the same way. Only use this if the scan is never combined
+    /// with inputs that datafusion partitions by hash, otherwise joins return wrong results.
+    pub fn with_bucket_partitioning(mut self) -> Self {
+        self.bucket_partitioning = true;
+        self
+    }
+    /// Report the default sort order of the table as the output ordering of the scan if all data files were written
+    /// with it. Every data file is sorted on its own, so the scan reads one data file per partition. Only use this for
+    /// tables with few data files or queries that benefit from the ordering. Sort merge joins still repartition both
+    /// inputs by the join key, which loses the ordering, so the ordering doesn't align the files of joined tables.
+    pub fn with_output_ordering(mut self) -> Self {
+        self.output_ordering = true;
+        self
+    }
+    /// Send a report to the reporter after every planned scan
+    pub fn with_metrics_reporter(mut self, reporter: Arc<dyn MetricsReporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+    /// Add the column statistics of the provider to the statistics from the manifests
+    pub fn with_statistics_provider(mut self, provider: Arc<dyn StatisticsProvider>) -> Self {
+        self.statistics_provider = Some(provider);
+        self
+    }
+    /// Keep the metadata of the parquet data files in the cache, so that repeated scans don't read the footers again
+    pub fn with_metadata_cache(mut self, cache: Arc<ParquetMetadataCache>) -> Self {
+        self.metadata_cache = Some(cache);
+        self
+    }
+    /// Number of manifests that are fetched at the same time while a scan is planned
+    pub fn with_manifest_concurrency(mut self, concurrency: usize) -> Self {
+        self.manifest_concurrency = concurrency.max(1);
+        self
+    }
+    /// Evaluate simple filters on every row in the parquet reader, so that datafusion doesn't filter the output of the
+    /// scan again. Filters that the reader can't evaluate exactly are still applied by datafusion.
+    pub fn with_exact_filters(mut self) -> Self {
+        self.exact_filters = true;
+        self
+    }
+    pub(crate) fn with_query_log(mut self, query_log: Arc<QueryLog>, name: String) -> Self {
+        self.query_log = Some((query_log, name));
+        self
+    }
+    /// Table with the same options that reads the relation
+    pub(crate) fn with_relation(&self, relation: Relation) -> Self {
+        DataFusionTable {
+            relation,
+            sample: self.sample,
+            strict: self.strict,
+            replica: self.replica.clone(),
+            progress: self.progress.clone(),
+            query_log: self.query_log.clone(),
+            timezone: self.timezone.clone(),
+            partition_columns: self.partition_columns,
+            masking: self.masking.clone(),
+            bucket_partitioning: self.bucket_partitioning,
+            output_ordering: self.output_ordering,
+            reporter: self.reporter.clone(),
+            statistics_provider: self.statistics_provider.clone(),
+            metadata_cache: self.metadata_cache.clone(),
+            manifest_concurrency: self.manifest_concurrency,
+            exact_filters: self.exact_filters,
+            branch: self.branch.clone(),
+            statistics_cache: Mutex::new(None),
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl TableProvider for DataFusionTable {
     fn as_any(&self) -> &dyn Any {
-        match &self.0 {
-            Relation::Table(table) => table,
-            Relation::View(view) => view,
-        }
+        self
     }
     fn schema(&self) -> SchemaRef {
-        let schema = match &self.0 {
+        let schema = match &self.relation {
             Relation::Table(table) => table.schema(),
             Relation::View(view) => view.schema().unwrap(),
         };
-        Arc::new(iceberg_to_arrow_schema(schema).unwrap())
+        let schema = iceberg_to_arrow_schema(schema);
+        let mut fields: Vec<Field> = schema
+            .fields()
+            .iter()
+            .map(|field| match (field.data_type(), &self.timezone) {
+                // Timestamps are stored relative to UTC, so changing the timezone only changes the type
+                (DataType::Timestamp(unit, Some(_)), Some(timezone)) => Field::new(
+                    field.name(),
+                    DataType::Timestamp(unit.clone(), Some(timezone.clone())),
+                    field.is_nullable(),
+                )
+                .with_metadata(field.metadata().cloned()),
+                _ => field.clone(),
+            })
+            // Masking with nulls makes every column nullable
+            .map(|field| match self.masking.get(field.name()) {
+                Some(MaskingPolicy::Null) => field.with_nullable(true),
+                _ => field,
+            })
+            .collect();
+        if let (true, Relation::Table(table)) = (self.partition_columns, &self.relation) {
+            // Partition values are provided by datafusion as dictionary encoded strings
+            fields.extend(table.metadata().default_spec().iter().map(|field| {
+                Field::new(
+                    &(PARTITION_COLUMN_PREFIX.to_owned() + &field.name),
+                    DataType::Dictionary(Box::new(DataType::UInt16), Box::new(DataType::Utf8)),
+                    true,
+                )
+            }));
+        }
+        Arc::new(ArrowSchema::new(fields))
+    }
+    fn supports_filter_pushdown(
+        &self,
+        filter: &Expr,
+    ) -> Result<TableProviderFilterPushDown, DataFusionError> {
+        if self.is_exact_filter(filter) {
+            Ok(TableProviderFilterPushDown::Exact)
+        } else {
+            Ok(TableProviderFilterPushDown::Inexact)
+        }
     }
     fn table_type(&self) -> TableType {
-        match &self.0 {
+        match &self.relation {
             Relation::Table(_) => TableType::Base,
             Relation::View(_) => TableType::View,
         }
     }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(table = self.relation.metadata_location()))
+    )]
     async fn scan(
         &self,
         session: &SessionState,
@@ -98,7 +349,52 @@ impl TableProvider for DataFusionTable {
         filters: &[Expr],
         limit: Option<usize>,
     ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
-        match &self.0 {
+        self.scan_files(session, projection, filters, limit, None)
+            .await
+    }
+}
+
+impl DataFusionTable {
+    /// Whether the readers evaluate the filter on every row, so that datafusion doesn't have to filter the output again
+    fn is_exact_filter(&self, filter: &Expr) -> bool {
+        let table = match &self.relation {
+            Relation::Table(table) if self.exact_filters => table,
+            _ => return false,
+        };
+        // Source columns of identity partitions are read from the partition values as well
+        let excluded: HashSet<String> = table
+            .metadata()
+            .default_spec()
+            .iter()
+            .flat_map(|field| {
+                let source = match field.transform {
+                    Transform::Identity => table
+                        .schema()
+                        .fields
+                        .iter()
+                        .find(|x| x.id == field.source_id)
+                        .map(|x| x.name.clone()),
+                    _ => None,
+                };
+                std::iter::once(field.name.clone()).chain(source)
+            })
+            .chain(self.masking.keys().cloned())
+            .collect();
+        pushdown::is_exact(filter, &self.schema(), &excluded)
+    }
+    /// Scan of the table. If data files are selected, only these are read and the sample of the table is ignored.
+    pub(crate) async fn scan_files(
+        &self,
+        session: &SessionState,
+        projection: &Option<Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+        selected: Option<&HashSet<String>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        if self.strict {
+            self.validate()?;
+        }
+        match &self.relation {
             Relation::View(view) => {
                 let sql = match view.metadata().representation() {
                     Representation::Sql { sql, .. } => sql,
@@ -111,130 +407,282 @@ impl TableProvider for DataFusionTable {
                     .await
             }
             Relation::Table(table) => {
+                let started = Instant::now();
+                if let Some((query_log, name)) = &self.query_log {
+                    query_log.record(TableRead {
+                        name: name.clone(),
+                        metadata_location: self.relation.metadata_location().to_owned(),
+                        snapshot_id: table.metadata().current_snapshot_id(),
+                    });
+                }
+
                 let schema = self.schema();
 
-                // Create a unique URI for this particular object store
-                let object_store_url = ObjectStoreUrl::parse(
-                    "iceberg://".to_owned()
-                        + &util::strip_prefix(table.metadata().location()).replace('/', "-"),
-                )?;
-                let url: &Url = object_store_url.as_ref();
-                session.runtime_env.register_object_store(
-                    url.scheme(),
-                    url.host_str().unwrap_or_default(),
-                    table.object_store(),
-                );
+                let policies = self.masking_policies(table);
+                let masked = masking::is_masked(&policies, session);
+                let filters = if masked {
+                    masking::unmasked_filters(&policies, filters)?
+                } else {
+                    filters.to_vec()
+                };
+                let filters = filters.as_slice();
+
+                let object_store_url = self.register_object_store(session, table)?;
 
                 // All files have to be grouped according to their partition values. This is done by using a HashMap with the partition values as the key.
                 // This way data files with the same partition value are mapped to the same vector.
                 let mut file_groups: HashMap<Vec<ScalarValue>, Vec<PartitionedFile>> =
                     HashMap::new();
+                // Avro files are read with another reader than the parquet files
+                let mut avro_files: HashSet<String> = HashSet::new();
+                // File samples are weighted by the number of records of the files
+                let mut record_counts: HashMap<String, usize> = HashMap::new();
+                let pruning_predicate = match (!filters.is_empty()
+                    && options::pruning_enabled(session))
+                .then_some(conjunction(filters.iter().cloned()))
+                {
+                    Some(Some(predicate)) => {
+                        // Statistics of required columns are missing for manifests and files without bounds
+                        let nullable = schema
+                            .fields()
+                            .iter()
+                            .map(|field| {
+                                Field::new(field.name(), field.data_type().clone(), true)
+                                    .with_metadata(field.metadata().cloned())
+                            })
+                            .collect();
+                        Some(PruningPredicate::try_new(
+                            predicate,
+                            Arc::new(ArrowSchema::new(nullable)),
+                        )?)
+                    }
+                    _ => None,
+                };
                 // If there is a filter expression the manifests to read are pruned based on the pruning statistics available in the manifest_list file.
-                if let Some(Some(predicate)) =
-                    (!filters.is_empty()).then_some(conjunction(filters.iter().cloned()))
+                let manifests_to_read = match &pruning_predicate {
+                    Some(pruning_predicate) => {
+                        pruning_predicate.prune(&PruneManifests::new(table, &schema))?
+                    }
+                    None => vec![true; table.manifests().len()],
+                };
+                // Bucket partitions are pruned separately because the bucket transform doesn't preserve the order
+                let manifests_to_read: Vec<bool> = manifests_to_read
+                    .into_iter()
+                    .zip(transform::manifests_in_buckets(table, filters))
+                    .map(|(read, in_buckets)| read && in_buckets)
+                    .collect();
+                let default_spec = table.metadata().default_spec();
+                // The ordering is only known if all data files were written with the default sort order
+                let sort_order = if self.output_ordering {
+                    sort_order(table)
+                } else {
+                    None
+                };
+                let mut sorted = sort_order.is_some();
+                let mut planning = PlanningMetrics::default();
+                // Data files that were written before a column was renamed or dropped are read with new column names
+                let mut file_columns = FileColumns::new(table.metadata());
+                let mut renamed = false;
+                let mut widened = false;
+                // The next manifests are fetched while the files of the current one are planned
+                let manifest_count = manifests_to_read.len();
+                let mut manifests =
+                    stream::iter((0..manifest_count).filter(|index| manifests_to_read[*index]))
+                        .map(|index| async move {
+                            let mut mask = vec![false; manifest_count];
+                            mask[index] = true;
+                            table.files(Some(mask)).await.map(|files| (index, files))
+                        })
+                        .buffered(
+                            options::get_usize(&session.config, options::MANIFEST_CONCURRENCY)
+                                .unwrap_or(self.manifest_concurrency),
+                        );
+                while let Some((index, files)) =
+                    manifests.try_next().await.map_err(Error::iceberg)?
                 {
-                    let pruning_predicate = PruningPredicate::try_new(predicate, schema.clone())?;
-                    let manifests_to_prune =
-                        pruning_predicate.prune(&PruneManifests::from(table))?;
-                    let files = table
-                        .files(Some(manifests_to_prune))
-                        .await
-                        .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
+                    let manifest = &table.manifests()[index];
+                    planning.manifests_read += 1;
+                    // The manifests are planned one by one because the partition values of the data files depend on the
+                    // partition spec the manifest was written with.
+                    let spec_id = manifest.partition_spec_id();
+                    let spec = table.metadata().get_spec(spec_id).ok_or_else(|| {
+                        DataFusionError::Internal(format!(
+                            "Partition spec {} doesn't exist.",
+                            spec_id
+                        ))
+                    })?;
+                    let buckets = transform::buckets(table, spec_id, filters);
                     // After the first pruning stage the data_files are pruned again based on the pruning statistics in the manifest files.
-                    let files_to_prune =
-                        pruning_predicate.prune(&PruneDataFiles::new(table, &files))?;
-                    files.into_iter().zip(files_to_prune.into_iter()).for_each(
-                        |(manifest, prune_file)| {
-                            if !prune_file {
-                                let partition_values = manifest
-                                    .partition_values()
-                                    .iter()
-                                    .map(|value| match value {
-                                        Some(v) => ScalarValue::Utf8(Some(
-                                            serde_json::to_string(v).unwrap(),
-                                        )),
-                                        None => ScalarValue::Null,
-                                    })
-                                    .collect::<Vec<ScalarValue>>();
-                                let object_meta = ObjectMeta {
-                                    location: util::strip_prefix(manifest.file_path()).into(),
-                                    size: manifest.file_size_in_bytes() as usize,
-                                    last_modified: {
-                                        let last_updated_ms = table.metadata().last_updated_ms();
-                                        let secs = last_updated_ms / 1000;
-                                        let nsecs = (last_updated_ms % 1000) as u32 * 1000000;
-                                        DateTime::from_utc(
-                                            NaiveDateTime::from_timestamp_opt(secs, nsecs).unwrap(),
-                                            Utc,
-                                        )
-                                    },
-                                };
-                                let file = PartitionedFile {
-                                    object_meta,
-                                    partition_values,
-                                    range: None,
-                                    extensions: None,
-                                };
-                                file_groups
-                                    .entry(file.partition_values.clone())
-                                    .or_default()
-                                    .push(file);
-                            };
-                        },
-                    );
-                } else {
-                    let files = table
-                        .files(None)
-                        .await
-                        .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
-                    files.into_iter().for_each(|manifest| {
-                        let partition_values = manifest
-                            .partition_values()
-                            .iter()
-                            .map(|value| match value {
-                                Some(v) => {
-                                    ScalarValue::Utf8(Some(serde_json::to_string(v).unwrap()))
+                    let files_to_read = match &pruning_predicate {
+                        Some(pruning_predicate) => {
+                            pruning_predicate.prune(&PruneDataFiles::new(table, &schema, &files))?
+                        }
+                        None => vec![true; files.len()],
+                    };
+                    if let Some((entry, _)) = files
+                        .iter()
+                        .zip(files_to_read.iter())
+                        .find(|(entry, read)| **read && !is_readable(entry))
+                    {
+                        return Err(Error::UnsupportedFormat(format!(
+                            "Data file {} has the unsupported format {:?}.",
+                            entry.file_path(),
+                            entry.file_format()
+                        ))
+                        .into());
+                    }
+                    let candidates = files.len();
+                    let matched = planning.files_matched;
+                    files
+                        .into_iter()
+                        .zip(files_to_read)
+                        .filter(|(entry, read)| {
+                            *read
+                                && !entry.is_deleted()
+                                && selected
+                                    .is_none_or(|selected| selected.contains(entry.file_path()))
+                                && transform::file_in_buckets(entry, &buckets)
+                        })
+                        .for_each(|(entry, _)| {
+                            planning.files_matched += 1;
+                            sorted &= sort_order
+                                .as_ref()
+                                .is_some_and(|(id, _)| entry.sort_order_id() == Some(*id));
+                            // Partition fields that are not part of the spec of the manifest have no value
+                            let partition_values = default_spec
+                                .iter()
+                                .map(|field| {
+                                    match spec
+                                        .iter()
+                                        .position(|x| x.name == field.name)
+                                        .and_then(|position| entry.partition_values().get(position))
+                                    {
+                                        Some(Some(v)) => {
+                                            ScalarValue::Utf8(Some(partition_value(v)))
+                                        }
+                                        _ => ScalarValue::Null,
+                                    }
+                                })
+                                .collect::<Vec<ScalarValue>>();
+                            let mut file = partitioned_file(table, &entry, partition_values);
+                            if matches!(entry.file_format(), DataFileFormat::Parquet) {
+                                if let Some(columns) = file_columns.columns(manifest, &entry) {
+                                    renamed |= columns.is_renamed();
+                                    widened |= columns.is_widened();
+                                    file.extensions = Some(columns);
                                 }
-                                None => ScalarValue::Null,
-                            })
-                            .collect::<Vec<ScalarValue>>();
-                        let object_meta = ObjectMeta {
-                            location: util::strip_prefix(manifest.file_path()).into(),
-                            size: manifest.file_size_in_bytes() as usize,
-                            last_modified: {
-                                let last_updated_ms = table.metadata().last_updated_ms();
-                                let secs = last_updated_ms / 1000;
-                                let nsecs = (last_updated_ms % 1000) as u32 * 1000000;
-                                DateTime::from_utc(
-                                    NaiveDateTime::from_timestamp_opt(secs, nsecs).unwrap(),
-                                    Utc,
-                                )
-                            },
-                        };
-                        let file = PartitionedFile {
-                            object_meta,
-                            partition_values,
-                            range: None,
-                            extensions: None,
-                        };
-                        file_groups
-                            .entry(file.partition_values.clone())
-                            .or_default()
-                            .push(file);
-                    });
+                            }
+                            record_counts.insert(
+                                file.object_meta.location.to_string(),
+                                entry.record_count() as usize,
+                            );
+                            if matches!(entry.file_format(), DataFileFormat::Avro) {
+                                avro_files.insert(file.object_meta.location.to_string());
+                            }
+                            file_groups
+                                .entry(file.partition_values.clone())
+                                .or_default()
+                                .push(file);
+                        });
+                    planning.files_pruned += candidates - (planning.files_matched - matched);
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(
+                        manifest = manifest.manifest_path(),
+                        files = candidates,
+                        matched = planning.files_matched - matched,
+                        "Planned manifest"
+                    );
+                }
+
+                let statistics = self.statistics().await.map_err(Error::iceberg)?;
+
+                // Sampling replaces every file with the subset of its byte ranges that is selected
+                let (file_groups, statistics) = match (&self.sample, selected) {
+                    (Some(sample), None) => sample.sample(file_groups, &record_counts, statistics),
+                    _ => (file_groups, statistics),
+                };
+                // The statistics are computed for the whole snapshot, filters that are evaluated by the reader and
+                // scans of some of the files reduce the number of rows
+                let statistics = Statistics {
+                    is_exact: statistics.is_exact && filters.is_empty() && selected.is_none(),
+                    ..statistics
                 };
 
-                let statistics = self
-                    .statistics()
-                    .await
-                    .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
+                ProgressTracker::new(
+                    self.progress.clone(),
+                    Some(file_groups.values().map(Vec::len).sum()),
+                )
+                .report()?;
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    manifests = planning.manifests_read,
+                    files = planning.files_matched,
+                    pruned = planning.files_pruned,
+                    "Planned scan"
+                );
+
+                planning.bytes_scanned = file_groups
+                    .values()
+                    .flatten()
+                    .map(|file| match &file.range {
+                        Some(range) => (range.end - range.start) as usize,
+                        None => file.object_meta.size,
+                    })
+                    .sum();
+
+                // Files of the same bucket are read by the same partition, so that datafusion doesn't have to
+                // repartition by the bucket column
+                let bucketing = if self.bucket_partitioning {
+                    bucket_field(table).and_then(|(position, column, buckets)| {
+                        Some((
+                            bucket_groups(&file_groups, position, buckets)?,
+                            column,
+                            buckets,
+                        ))
+                    })
+                } else {
+                    None
+                };
+                let (file_groups, bucketing) = match bucketing {
+                    Some((groups, column, buckets)) => (groups, Some((column, buckets))),
+                    None => (file_groups.into_values().collect(), None),
+                };
+                // Every data file is sorted on its own, so the ordering is only kept if every partition reads one file
+                let (file_groups, sort_fields) = match (sort_order, sorted && bucketing.is_none()) {
+                    (Some((_, fields)), true) => (group_per_file(file_groups), fields),
+                    _ => (file_groups, vec![]),
+                };
 
                 // Get all partition columns
                 let table_partition_cols: Vec<String> = table
                     .metadata()
                     .default_spec()
                     .iter()
-                    .map(|field| field.name.clone())
+                    .map(|field| {
+                        if self.partition_columns {
+                            PARTITION_COLUMN_PREFIX.to_owned() + &field.name
+                        } else {
+                            field.name.clone()
+                        }
+                    })
+                    .collect();
+                // Columns of identity partitions are read from the partition values, because data files don't have to
+                // contain them
+                let identity_columns: HashMap<String, usize> = table
+                    .metadata()
+                    .default_spec()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, field)| matches!(field.transform, Transform::Identity))
+                    .filter_map(|(position, field)| {
+                        let source = table
+                            .schema()
+                            .fields
+                            .iter()
+                            .find(|x| x.id == field.source_id)?;
+                        Some((source.name.clone(), position))
+                    })
                     .collect();
 
                 // Remove the partition columns from the schema. The values for the partition column are stored in the partition values
@@ -242,73 +690,595 @@ impl TableProvider for DataFusionTable {
                     schema
                         .fields()
                         .iter()
-                        .filter(|f| !table_partition_cols.contains(f.name()))
-                        .cloned()
+                        .filter(|f| {
+                            !table_partition_cols.contains(f.name())
+                                && !identity_columns.contains_key(f.name())
+                        })
+                        // Nested fields of the data files have no field ids
+                        .map(|f| {
+                            Field::new(
+                                f.name(),
+                                without_nested_field_ids(f.data_type()),
+                                f.is_nullable(),
+                            )
+                            .with_metadata(f.metadata().cloned())
+                        })
                         .collect(),
                 ));
 
-                // Get the ids of the partition columns
-                let partition_ids: Vec<usize> = schema
-                    .fields()
+                // Column of the file scan for every projected column of the table. Partition columns come after the
+                // columns of the data files.
+                let scan_fields: Vec<usize> = projection
+                    .clone()
+                    .unwrap_or_else(|| (0..schema.fields().len()).collect());
+                let scan_columns: Vec<usize> = scan_fields
                     .iter()
-                    .enumerate()
-                    .filter_map(|(idx, field)| {
-                        if table_partition_cols.contains(field.name()) {
-                            Some(idx)
-                        } else {
-                            None
+                    .map(|idx| {
+                        let name = schema.field(*idx).name();
+                        match table_partition_cols
+                            .iter()
+                            .position(|x| x == name)
+                            .or_else(|| identity_columns.get(name).copied())
+                        {
+                            Some(position) => Ok(file_schema.fields().len() + position),
+                            None => file_schema.index_of(name),
                         }
                     })
-                    .collect();
-
-                // Change the projection according to the previous schema change
-                let projection = projection.clone().map(|projection| {
-                    projection
-                        .iter()
-                        .map(|idx| {
-                            if partition_ids.contains(idx) {
-                                file_schema.fields.len()
-                                    + partition_ids.iter().position(|x| x == idx).unwrap()
-                            } else {
-                                partition_ids
-                                    .iter()
-                                    .fold(*idx, |acc, x| if idx > x { acc - 1 } else { acc })
-                            }
-                        })
-                        .collect()
+                    .collect::<Result<_, _>>()?;
+                // A partition column that is projected as identity column and as virtual column is only read once
+                let projection = scan_columns.iter().fold(Vec::new(), |mut acc, column| {
+                    if !acc.contains(column) {
+                        acc.push(*column)
+                    }
+                    acc
                 });
 
+                // Statistics of the projected columns. The statistics of masked columns are unknown.
+                let column_statistics: Option<Vec<ColumnStatistics>> =
+                    statistics.column_statistics.as_ref().map(|columns| {
+                        scan_fields
+                            .iter()
+                            .map(|idx| match columns.get(*idx) {
+                                Some(_)
+                                    if masked
+                                        && policies.contains_key(schema.field(*idx).name()) =>
+                                {
+                                    ColumnStatistics::default()
+                                }
+                                Some(column) => column.clone(),
+                                None => ColumnStatistics::default(),
+                            })
+                            .collect()
+                    });
+                // The column statistics are in the order of the table schema
+                let statistics = Statistics {
+                    column_statistics: statistics.column_statistics.map(|columns| {
+                        columns
+                            .into_iter()
+                            .zip(schema.fields())
+                            .filter(|(_, field)| file_schema.index_of(field.name()).is_ok())
+                            .map(|(column, _)| column)
+                            .collect()
+                    }),
+                    ..statistics
+                };
+
                 let file_scan_config = FileScanConfig {
                     object_store_url,
                     file_schema,
-                    file_groups: file_groups.into_values().collect(),
+                    file_groups,
                     statistics,
-                    projection,
+                    projection: Some(projection.clone()),
                     limit,
                     table_partition_cols,
-                    config_options: Default::default(),
+                    // Filter pushdown and page index pruning of the parquet reader are enabled with the session options
+                    config_options: session.config.config_options(),
                 };
-                ParquetFormat::default()
-                    .create_physical_plan(file_scan_config, filters)
-                    .await
+                let object_store = session
+                    .runtime_env
+                    .object_store(&file_scan_config.object_store_url)?;
+                let reader_factory = match (&self.metadata_cache, renamed) {
+                    (Some(cache), false) => Some(Arc::new(CachedReaderFactory::new(
+                        object_store,
+                        cache.clone(),
+                    ))
+                        as Arc<dyn ParquetFileReaderFactory>),
+                    (Some(cache), true) => Some(Arc::new(RenamingReaderFactory::new(Arc::new(
+                        CachedReaderFactory::new(object_store, cache.clone()),
+                    )))
+                        as Arc<dyn ParquetFileReaderFactory>),
+                    (None, true) => Some(Arc::new(RenamingReaderFactory::new(Arc::new(
+                        CachedReaderFactory::without_cache(object_store),
+                    )))
+                        as Arc<dyn ParquetFileReaderFactory>),
+                    (None, false) => None,
+                };
+                let exact_filters: Vec<Expr> = filters
+                    .iter()
+                    .filter(|filter| self.is_exact_filter(filter))
+                    .cloned()
+                    .collect();
+                let plan = create_physical_plan(
+                    file_scan_config,
+                    filters,
+                    &exact_filters,
+                    &avro_files,
+                    reader_factory,
+                    self.exact_filters,
+                )
+                .await?;
+                // Partition values are dictionary encoded strings, they are cast to the type of their column
+                let fields: Vec<Field> = scan_fields
+                    .iter()
+                    .map(|idx| schema.field(*idx).clone())
+                    .collect();
+                let plan = conform(plan, &projection, &scan_columns, &fields)?;
+                // The union of the readers of different formats or file columns has neither the partitioning nor the
+                // ordering
+                let (bucketing, sort_fields) = if avro_files.is_empty() && !widened {
+                    (bucketing, sort_fields)
+                } else {
+                    (None, vec![])
+                };
+                let plan = if masked {
+                    masking::mask(plan, &policies, session)?
+                } else {
+                    plan
+                };
+                // Masked values are no longer partitioned by the bucket
+                let partitioning = bucketing.and_then(|(column, buckets)| {
+                    if masked && policies.contains_key(&column) {
+                        return None;
+                    }
+                    let index = plan.schema().index_of(&column).ok()?;
+                    Some(Partitioning::Hash(
+                        vec![Arc::new(Column::new(&column, index)) as Arc<dyn PhysicalExpr>],
+                        buckets as usize,
+                    ))
+                });
+                // The ordering ends at the first sort field that is masked or not part of the output
+                let ordering: Vec<PhysicalSortExpr> = sort_fields
+                    .into_iter()
+                    .map_while(|(column, options)| {
+                        if masked && policies.contains_key(&column) {
+                            return None;
+                        }
+                        let index = plan.schema().index_of(&column).ok()?;
+                        Some(PhysicalSortExpr {
+                            expr: Arc::new(Column::new(&column, index)),
+                            options,
+                        })
+                    })
+                    .collect();
+                let details = ScanDetails {
+                    snapshot_id: table.metadata().current_snapshot_id(),
+                    predicate: conjunction(filters.iter().cloned()),
+                };
+                if let Some(reporter) = &self.reporter {
+                    reporter.report_scan(&ScanReport {
+                        metadata_location: self.relation.metadata_location().to_owned(),
+                        snapshot_id: details.snapshot_id,
+                        filter: details.predicate.as_ref().map(|x| x.to_string()),
+                        projection: plan
+                            .schema()
+                            .fields()
+                            .iter()
+                            .map(|field| field.name().clone())
+                            .collect(),
+                        manifests_read: planning.manifests_read,
+                        files_matched: planning.files_matched,
+                        files_pruned: planning.files_pruned,
+                        bytes_scanned: planning.bytes_scanned,
+                        planning_duration: started.elapsed(),
+                    });
+                }
+                let exec = IcebergScanExec::new(
+                    plan,
+                    partitioning,
+                    (!ordering.is_empty()).then_some(ordering),
+                    planning,
+                    details,
+                );
+                Ok(Arc::new(match column_statistics {
+                    Some(column_statistics) => exec.with_column_statistics(column_statistics),
+                    None => exec,
+                }))
+            }
+        }
+    }
+    /// Masking policies of the scan. The partition columns of partition fields with a masked source column are masked
+    /// with nulls, because their values reveal the values of the source column.
+    fn masking_policies(&self, table: &Table) -> HashMap<String, MaskingPolicy> {
+        let mut policies = self.masking.clone();
+        if self.partition_columns {
+            let schema = table.schema();
+            policies.extend(
+                table
+                    .metadata()
+                    .default_spec()
+                    .iter()
+                    .filter(|field| {
+                        schema.fields.iter().any(|source| {
+                            source.id == field.source_id && self.masking.contains_key(&source.name)
+                        })
+                    })
+                    .map(|field| {
+                        (
+                            PARTITION_COLUMN_PREFIX.to_owned() + &field.name,
+                            MaskingPolicy::Null,
+                        )
+                    }),
+            );
+        }
+        policies
+    }
+    /// Register the object store of the table with the session. Tables in an object store with a url, for example
+    /// `s3://bucket`, share the store that is registered for the url with all other plans of the session. If no store
+    /// is registered yet, the store of the table is registered for the url. If another store is registered for the
+    /// url, for example one with old credentials, the table uses a store that is private to the table, like tables
+    /// without a url and tables with a replica. Register a `RotatingObjectStore` to rotate the credentials of the
+    /// shared store.
+    fn register_object_store(
+        &self,
+        session: &SessionState,
+        table: &Table,
+    ) -> Result<ObjectStoreUrl, DataFusionError> {
+        let location = Url::parse(table.metadata().location()).ok();
+        match (location, &self.replica) {
+            (Some(location), None) if location.has_host() => {
+                let object_store_url = ObjectStoreUrl::parse(format!(
+                    "{}://{}",
+                    location.scheme(),
+                    location.host_str().unwrap_or_default()
+                ))?;
+                match session.runtime_env.object_store(&object_store_url) {
+                    Ok(registered) if same_store(&registered, &table.object_store()) => {
+                        Ok(object_store_url)
+                    }
+                    Ok(_) => self.register_private_object_store(session, table),
+                    Err(_) => {
+                        session.runtime_env.register_object_store(
+                            location.scheme(),
+                            location.host_str().unwrap_or_default(),
+                            table.object_store(),
+                        );
+                        Ok(object_store_url)
+                    }
+                }
             }
+            _ => self.register_private_object_store(session, table),
         }
     }
+    /// Register the object store of the table under a url that is unique to the table
+    fn register_private_object_store(
+        &self,
+        session: &SessionState,
+        table: &Table,
+    ) -> Result<ObjectStoreUrl, DataFusionError> {
+        let object_store_url = ObjectStoreUrl::parse(
+            "iceberg://".to_owned()
+                + &util::strip_prefix(table.metadata().location()).replace('/', "-"),
+        )?;
+        let url: &Url = object_store_url.as_ref();
+        let object_store = match &self.replica {
+            Some(replica) => Arc::new(FailoverObjectStore::new(
+                table.object_store(),
+                replica.clone(),
+                FAILOVER_ATTEMPTS,
+            )) as Arc<dyn ObjectStore>,
+            None => table.object_store(),
+        };
+        session.runtime_env.register_object_store(
+            url.scheme(),
+            url.host_str().unwrap_or_default(),
+            object_store,
+        );
+        Ok(object_store_url)
+    }
+}
+
+// Whether both handles point to the same object store
+fn same_store(left: &Arc<dyn ObjectStore>, right: &Arc<dyn ObjectStore>) -> bool {
+    Arc::as_ptr(left) as *const () == Arc::as_ptr(right) as *const ()
+}
+
+/// Id of the default sort order and the columns it sorts by. The sort order is cut off at the first field that is not
+/// sorted by the column value itself.
+fn sort_order(table: &Table) -> Option<(i32, Vec<(String, SortOptions)>)> {
+    let sort_order = table.metadata().default_sort_order()?;
+    let fields: Vec<(String, SortOptions)> = sort_order
+        .fields
+        .iter()
+        .map_while(|field| {
+            if !matches!(field.transform, Transform::Identity) {
+                return None;
+            }
+            let source = table
+                .schema()
+                .fields
+                .iter()
+                .find(|x| x.id == field.source_id)?;
+            Some((
+                source.name.clone(),
+                SortOptions {
+                    descending: matches!(field.direction, SortDirection::Descending),
+                    nulls_first: matches!(field.null_order, NullOrder::First),
+                },
+            ))
+        })
+        .collect();
+    (!fields.is_empty()).then_some((sort_order.order_id, fields))
+}
+
+/// One file group per data file. The byte ranges of a sampled file stay in one group.
+fn group_per_file(file_groups: Vec<Vec<PartitionedFile>>) -> Vec<Vec<PartitionedFile>> {
+    file_groups.into_iter().flatten().fold(
+        Vec::new(),
+        |mut acc: Vec<Vec<PartitionedFile>>, file| {
+            match acc.last_mut() {
+                Some(group) if group[0].object_meta.location == file.object_meta.location => {
+                    group.push(file)
+                }
+                _ => acc.push(vec![file]),
+            }
+            acc
+        },
+    )
+}
+
+/// Position, source column and number of buckets of the first bucket field of the default partition spec
+fn bucket_field(table: &Table) -> Option<(usize, String, u32)> {
+    table
+        .metadata()
+        .default_spec()
+        .iter()
+        .enumerate()
+        .find_map(|(position, field)| match field.transform {
+            Transform::Bucket(buckets) => {
+                let source = table
+                    .schema()
+                    .fields
+                    .iter()
+                    .find(|x| x.id == field.source_id)?;
+                Some((position, source.name.clone(), buckets))
+            }
+            _ => None,
+        })
+}
+
+/// One file group per bucket. None if the bucket of a file is unknown, for example because it was written with an
+/// older partition spec.
+fn bucket_groups(
+    file_groups: &HashMap<Vec<ScalarValue>, Vec<PartitionedFile>>,
+    position: usize,
+    buckets: u32,
+) -> Option<Vec<Vec<PartitionedFile>>> {
+    file_groups.iter().try_fold(
+        vec![vec![]; buckets as usize],
+        |mut acc, (values, files)| {
+            let bucket: usize = match values.get(position)? {
+                ScalarValue::Utf8(Some(value)) => serde_json::from_str(value).ok()?,
+                _ => return None,
+            };
+            acc.get_mut(bucket)?.extend(files.iter().cloned());
+            Some(acc)
+        },
+    )
+}
+
+/// Read the data files with the reader of their format and the files with widened columns with a scan of their own. The
+/// exact filters have to be applied to every row.
+async fn create_physical_plan(
+    config: FileScanConfig,
+    filters: &[Expr],
+    exact_filters: &[Expr],
+    avro_files: &HashSet<String>,
+    reader_factory: Option<Arc<dyn ParquetFileReaderFactory>>,
+    pushdown_filters: bool,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let mut config = config;
+    let (file_groups, widened) =
+        file_columns::split_widened(std::mem::take(&mut config.file_groups));
+    config.file_groups = file_groups;
+    let widened = widened
+        .into_iter()
+        .map(|(types, file_groups)| {
+            file_columns::widened_plan(
+                &config,
+                &types,
+                file_groups,
+                exact_filters,
+                reader_factory.clone(),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let plan = create_format_plan(
+        config,
+        filters,
+        exact_filters,
+        avro_files,
+        reader_factory,
+        pushdown_filters,
+    )
+    .await?;
+    if widened.is_empty() {
+        Ok(plan)
+    } else {
+        Ok(Arc::new(UnionExec::new(
+            std::iter::once(plan).chain(widened).collect(),
+        )))
+    }
+}
+
+/// Read the data files with the reader of their format
+async fn create_format_plan(
+    config: FileScanConfig,
+    filters: &[Expr],
+    exact_filters: &[Expr],
+    avro_files: &HashSet<String>,
+    reader_factory: Option<Arc<dyn ParquetFileReaderFactory>>,
+    pushdown_filters: bool,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    #[cfg(feature = "avro")]
+    if !avro_files.is_empty() {
+        return crate::avro::create_physical_plan(
+            config,
+            filters,
+            exact_filters,
+            avro_files,
+            reader_factory,
+            pushdown_filters,
+        )
+        .await;
+    }
+    #[cfg(not(feature = "avro"))]
+    let _ = (exact_filters, avro_files);
+    Ok(parquet_plan(
+        config,
+        filters,
+        reader_factory,
+        pushdown_filters,
+    ))
+}
+
+/// Partition value as string that can be cast to the type of the partition column
+fn partition_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Date(date) => date.to_string(),
+        value => serde_json::to_string(value)
+            .unwrap_or_default()
+            .trim_matches('"')
+            .to_owned(),
+    }
+}
+
+/// Project the output of the file scan to the projected fields of the table. `scan_columns` is the column of the file
+/// scan for every projected field and `projection` the columns that the file scan reads.
+fn conform(
+    plan: Arc<dyn ExecutionPlan>,
+    projection: &[usize],
+    scan_columns: &[usize],
+    fields: &[Field],
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let input = plan.schema();
+    if scan_columns == projection
+        && input
+            .fields()
+            .iter()
+            .zip(fields)
+            .all(|(x, y)| x.name() == y.name() && x.data_type() == y.data_type())
+    {
+        return Ok(plan);
+    }
+    let exprs = scan_columns
+        .iter()
+        .zip(fields)
+        .map(|(column, field)| {
+            let index = projection
+                .iter()
+                .position(|x| x == column)
+                .ok_or_else(|| DataFusionError::Internal("Column is not scanned.".to_owned()))?;
+            let expr: Arc<dyn PhysicalExpr> =
+                Arc::new(Column::new(input.field(index).name(), index));
+            let expr = if input.field(index).data_type() == field.data_type() {
+                expr
+            } else {
+                file_columns::conform_column(expr, &input, field.data_type())?
+            };
+            Ok((expr, field.name().clone()))
+        })
+        .collect::<Result<_, DataFusionError>>()?;
+    Ok(Arc::new(ProjectionExec::try_new(exprs, plan)?))
+}
+
+/// Parquet scan that prunes the row groups with the filters, like the plans of the ParquetFormat. With filter pushdown
+/// the filters are also evaluated on the decoded rows.
+pub(crate) fn parquet_plan(
+    config: FileScanConfig,
+    filters: &[Expr],
+    reader_factory: Option<Arc<dyn ParquetFileReaderFactory>>,
+    pushdown_filters: bool,
+) -> Arc<dyn ExecutionPlan> {
+    let exec = ParquetExec::new(config, conjunction(filters.iter().cloned()), None);
+    let exec = if pushdown_filters {
+        exec.with_pushdown_filters(true)
+    } else {
+        exec
+    };
+    match reader_factory {
+        Some(reader_factory) => Arc::new(exec.with_parquet_file_reader_factory(reader_factory)),
+        None => Arc::new(exec),
+    }
+}
+
+/// Only parquet data files and, with the avro feature, avro data files can be read. Datafusion has no reader for ORC.
+fn is_readable(entry: &ManifestEntry) -> bool {
+    matches!(entry.file_format(), DataFileFormat::Parquet)
+        || (cfg!(feature = "avro") && matches!(entry.file_format(), DataFileFormat::Avro))
+}
+
+fn partitioned_file(
+    table: &Table,
+    entry: &ManifestEntry,
+    partition_values: Vec<ScalarValue>,
+) -> PartitionedFile {
+    let object_meta = ObjectMeta {
+        location: object_path(entry.file_path()),
+        size: entry.file_size_in_bytes() as usize,
+        last_modified: {
+            let last_updated_ms = table.metadata().last_updated_ms();
+            let secs = last_updated_ms / 1000;
+            let nsecs = (last_updated_ms % 1000) as u32 * 1000000;
+            DateTime::from_utc(NaiveDateTime::from_timestamp_opt(secs, nsecs).unwrap(), Utc)
+        },
+    };
+    PartitionedFile {
+        object_meta,
+        partition_values,
+        range: None,
+        extensions: None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use datafusion::{
-        arrow::{array::Float32Array, record_batch::RecordBatch},
-        prelude::SessionContext,
+        arrow::{
+            array::{Float32Array, Int64Array},
+            compute::cast,
+            record_batch::RecordBatch,
+        },
+        config::{OPT_PARQUET_ENABLE_PAGE_INDEX, OPT_PARQUET_PUSHDOWN_FILTERS},
+        physical_plan::collect,
+        prelude::{col, lit, SessionConfig, SessionContext},
     };
     use iceberg_rs::{
-        model::schema::{AllType, PrimitiveType, SchemaStruct, SchemaV2, StructField},
+        model::{
+            data_types::{PrimitiveType, StructField, StructType, Type},
+            schema::SchemaV2,
+        },
         view::view_builder::ViewBuilder,
     };
     use object_store::{local::LocalFileSystem, memory::InMemory, ObjectStore};
 
+    use iceberg_rs::model::{
+        manifest::Status,
+        snapshot::{Operation, Summary},
+        table_metadata::TableMetadata,
+    };
+
+    use crate::{
+        commit::{
+            add_snapshot, explicit_ids, manifest_entry, manifest_file, write_manifest,
+            write_manifest_list, Change,
+        },
+        error::Error,
+        options::IcebergConfigExt,
+        testing::taxis_copy,
+    };
+
     use super::*;
 
     #[tokio::test]
@@ -349,6 +1319,416 @@ mod tests {
         assert!(((1.35 - values.value(0)).abs() - 0.45).abs() < 0.001)
     }
 
+    #[tokio::test]
+    pub async fn test_datafusion_table_filter() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        ));
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        let df = ctx
+            .sql("SELECT trip_id FROM nyc_taxis WHERE trip_distance >= 0.0")
+            .await
+            .unwrap();
+
+        // execute the plan
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        // No file may be pruned because every trip has a positive distance
+        assert_eq!(
+            results.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_datafusion_table_pushdown_filters() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        ));
+
+        let ctx = SessionContext::with_config(
+            SessionConfig::new()
+                .set_bool(OPT_PARQUET_PUSHDOWN_FILTERS, true)
+                .set_bool(OPT_PARQUET_ENABLE_PAGE_INDEX, true),
+        );
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        // The literal has the type of the column, the page index pruning of datafusion 14 panics on cast columns
+        let df = ctx
+            .sql("SELECT trip_id FROM nyc_taxis WHERE trip_distance >= CAST(0.0 AS FLOAT)")
+            .await
+            .unwrap();
+
+        // execute the plan
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        // The filter is evaluated while decoding, every trip still matches
+        assert_eq!(
+            results.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_datafusion_table_exact_filters() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        )
+        .with_exact_filters();
+
+        // vendor_id is a partition column that isn't stored in the data files
+        for (filter, exact) in [
+            (col("trip_distance").gt_eq(lit(0.0_f32)), true),
+            (col("vendor_id").eq(lit(1_i64)), false),
+            (
+                (col("trip_distance") + lit(1.0_f32)).gt(lit(2.0_f32)),
+                false,
+            ),
+        ] {
+            assert_eq!(
+                table.supports_filter_pushdown(&filter).unwrap()
+                    == TableProviderFilterPushDown::Exact,
+                exact
+            );
+        }
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", Arc::new(table)).unwrap();
+
+        let df = ctx
+            .sql("SELECT trip_id FROM nyc_taxis WHERE trip_distance >= 0.0")
+            .await
+            .unwrap();
+
+        // execute the plan
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        assert_eq!(
+            results.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            4
+        );
+
+        // The count can't be answered from the statistics when the filter removes rows
+        let df = ctx
+            .sql("SELECT COUNT(*) FROM nyc_taxis WHERE trip_distance > CAST(1.0 AS FLOAT)")
+            .await
+            .unwrap();
+
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        let count = results[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("Failed to get values from batch.")
+            .value(0);
+        assert_eq!(count, 3);
+    }
+
+    #[tokio::test]
+    pub async fn test_datafusion_table_masking() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        for (unmasked, expected_nulls) in [(false, 4), (true, 0)] {
+            let table = Arc::new(
+                DataFusionTable::from(
+                    Table::load_file_system_table(
+                        "/home/iceberg/warehouse/nyc/taxis",
+                        &object_store,
+                    )
+                    .await
+                    .unwrap(),
+                )
+                .with_masking("trip_id", MaskingPolicy::Null),
+            );
+
+            let config = SessionConfig::new().with_iceberg_options();
+            let ctx = SessionContext::with_config(match unmasked {
+                true => config.with_extension(Arc::new(masking::UnmaskedPrivilege)),
+                false => config,
+            });
+
+            ctx.register_table("nyc_taxis", table).unwrap();
+
+            // The privilege can't be granted from SQL
+            assert!(ctx.sql("SET iceberg.unmasked = true").await.is_err());
+
+            let df = ctx.sql("SELECT trip_id FROM nyc_taxis").await.unwrap();
+
+            // execute the plan
+            let results: Vec<RecordBatch> =
+                df.collect().await.expect("Failed to execute query plan.");
+
+            assert_eq!(
+                results
+                    .iter()
+                    .map(|batch| batch.column(0).null_count())
+                    .sum::<usize>(),
+                expected_nulls
+            );
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_datafusion_table_sample() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let ctx = SessionContext::new();
+
+        for (name, fraction, expected) in [("full_sample", 1.0, 4), ("empty_sample", 0.0, 0)] {
+            let table = Arc::new(
+                DataFusionTable::from(
+                    Table::load_file_system_table(
+                        "/home/iceberg/warehouse/nyc/taxis",
+                        &object_store,
+                    )
+                    .await
+                    .unwrap(),
+                )
+                .with_sample(Sample::new(fraction, 42)),
+            );
+
+            ctx.register_table(name, table).unwrap();
+
+            let df = ctx
+                .sql(&format!("SELECT trip_id FROM {}", name))
+                .await
+                .unwrap();
+
+            // execute the plan
+            let results: Vec<RecordBatch> =
+                df.collect().await.expect("Failed to execute query plan.");
+
+            let num_rows: usize = results.iter().map(|batch| batch.num_rows()).sum();
+
+            assert_eq!(num_rows, expected)
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_datafusion_table_file_sample() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(
+            DataFusionTable::from(
+                Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                    .await
+                    .unwrap(),
+            )
+            .with_sample(Sample::files(0.5, 42)),
+        );
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        let df = ctx.sql("SELECT trip_id FROM nyc_taxis").await.unwrap();
+
+        // execute the plan
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        let num_rows: usize = results.iter().map(|batch| batch.num_rows()).sum();
+
+        // Every file contains one trip, so two of the four files are read
+        assert_eq!(num_rows, 2)
+    }
+
+    #[tokio::test]
+    pub async fn test_datafusion_table_partition_columns() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(
+            DataFusionTable::from(
+                Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                    .await
+                    .unwrap(),
+            )
+            .with_partition_columns(),
+        );
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        let df = ctx
+            .sql("SELECT _partition_vendor_id, COUNT(*) FROM nyc_taxis GROUP BY _partition_vendor_id")
+            .await
+            .unwrap();
+
+        // execute the plan
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        let num_rows: usize = results.iter().map(|batch| batch.num_rows()).sum();
+
+        assert_eq!(num_rows, 2);
+
+        // The source column of the identity partition is read from the partition values as well
+        let df = ctx
+            .sql("SELECT vendor_id, _partition_vendor_id FROM nyc_taxis WHERE vendor_id = 1")
+            .await
+            .unwrap();
+
+        let results: Vec<RecordBatch> = df.collect().await.expect("Failed to execute query plan.");
+
+        let vendor_ids: Vec<i64> = results
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .expect("Failed to get values from batch.")
+                    .iter()
+                    .collect::<Vec<_>>()
+            })
+            .map(|vendor_id| vendor_id.expect("Vendor id is null."))
+            .collect();
+        assert_eq!(vendor_ids, vec![1, 1]);
+    }
+
+    #[tokio::test]
+    pub async fn test_masked_partition_columns() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = Arc::new(
+            DataFusionTable::from(
+                Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                    .await
+                    .unwrap(),
+            )
+            .with_partition_columns()
+            .with_masking("vendor_id", MaskingPolicy::Null),
+        );
+
+        let ctx = SessionContext::new();
+
+        ctx.register_table("nyc_taxis", table).unwrap();
+
+        // The partition column of the masked column is masked as well
+        let results: Vec<RecordBatch> = ctx
+            .sql("SELECT vendor_id, _partition_vendor_id FROM nyc_taxis")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .expect("Failed to execute query plan.");
+        // The dictionary encoded partition column has null values instead of null keys
+        for column in 0..2 {
+            assert_eq!(
+                results
+                    .iter()
+                    .map(|batch| cast(batch.column(column), &DataType::Utf8)
+                        .unwrap()
+                        .null_count())
+                    .sum::<usize>(),
+                4
+            );
+        }
+
+        // Filters on the partition column are evaluated on the masked values instead of pruning the files
+        let results: Vec<RecordBatch> = ctx
+            .sql("SELECT trip_id FROM nyc_taxis WHERE _partition_vendor_id = '1'")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .expect("Failed to execute query plan.");
+        assert_eq!(
+            results.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_rotate_object_store() {
+        let ctx = SessionContext::new();
+        let query = "SELECT trip_id FROM nyc_taxis";
+
+        let old = taxis_copy(&[("\"/home/iceberg", "\"s3://bucket/home/iceberg")]).await;
+        let table = Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &old)
+            .await
+            .unwrap();
+        ctx.register_table("nyc_taxis", Arc::new(DataFusionTable::from(table)))
+            .unwrap();
+        ctx.sql(query).await.unwrap().collect().await.unwrap();
+
+        // The old credentials are revoked once the table is loaded with the new ones
+        let new = taxis_copy(&[("\"/home/iceberg", "\"s3://bucket/home/iceberg")]).await;
+        let table = Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &new)
+            .await
+            .unwrap();
+        ctx.deregister_table("nyc_taxis").unwrap();
+        ctx.register_table("nyc_taxis", Arc::new(DataFusionTable::from(table)))
+            .unwrap();
+        let files: Vec<ObjectMeta> = old.list(None).await.unwrap().try_collect().await.unwrap();
+        for file in files {
+            old.delete(&file.location).await.unwrap();
+        }
+
+        let batches = ctx.sql(query).await.unwrap().collect().await.unwrap();
+        assert_eq!(batches.iter().map(|x| x.num_rows()).sum::<usize>(), 4);
+    }
+
+    #[tokio::test]
+    pub async fn test_bucket_partitioning() {
+        // The vendor ids 1 and 2 are read as the buckets 1 and 2 of 3 buckets
+        let object_store = taxis_copy(&[(
+            "\"transform\" : \"identity\"",
+            "\"transform\" : \"bucket[3]\"",
+        )])
+        .await;
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        );
+        let ctx = SessionContext::new();
+
+        let plan = table.scan(&ctx.state(), &None, &[], None).await.unwrap();
+        assert!(!matches!(
+            plan.output_partitioning(),
+            Partitioning::Hash(_, _)
+        ));
+
+        let table = table.with_bucket_partitioning();
+        let plan = table.scan(&ctx.state(), &None, &[], None).await.unwrap();
+        match plan.output_partitioning() {
+            Partitioning::Hash(exprs, 3) => {
+                let column = exprs[0].as_any().downcast_ref::<Column>().unwrap();
+                assert_eq!(column.name(), "vendor_id");
+            }
+            partitioning => panic!("Unexpected partitioning {:?}", partitioning),
+        }
+        let batches = collect(plan, ctx.task_ctx()).await.unwrap();
+        assert_eq!(batches.iter().map(|x| x.num_rows()).sum::<usize>(), 4);
+    }
+
     #[tokio::test]
     pub async fn test_datafusion_view_scan() {
         let object_store: Arc<dyn ObjectStore> =
@@ -369,21 +1749,20 @@ mod tests {
         let schema = SchemaV2 {
             schema_id: 1,
             identifier_field_ids: Some(vec![1, 2]),
-            name_mapping: None,
-            struct_fields: SchemaStruct {
+            fields: StructType {
                 fields: vec![
                     StructField {
                         id: 1,
                         name: "vendor_id".to_string(),
                         required: false,
-                        field_type: AllType::Primitive(PrimitiveType::Int),
+                        field_type: Type::Primitive(PrimitiveType::Int),
                         doc: None,
                     },
                     StructField {
                         id: 2,
                         name: "min_trip_distance".to_string(),
                         required: false,
-                        field_type: AllType::Primitive(PrimitiveType::Float),
+                        field_type: Type::Primitive(PrimitiveType::Float),
                         doc: None,
                     },
                 ],
@@ -426,4 +1805,88 @@ mod tests {
         // Value can either be 0.9 or 1.8
         assert!(((1.35 - values.value(0)).abs() - 0.45).abs() < 0.001)
     }
+
+    /// Snapshot whose first data file is recorded as an ORC file
+    struct OrcSnapshot;
+
+    #[async_trait::async_trait]
+    impl Change for OrcSnapshot {
+        async fn apply(&mut self, table: &Table) -> Result<TableMetadata, DataFusionError> {
+            let metadata = table.metadata();
+            let manifest = &table.manifests()[0];
+            let format_version = metadata.format_version();
+            let entries = table
+                .files(None)
+                .await
+                .map_err(Error::iceberg)?
+                .into_iter()
+                .enumerate()
+                .map(|(index, entry)| {
+                    let (snapshot_id, sequence_number, mut data_file) =
+                        explicit_ids(manifest, entry);
+                    if index == 0 {
+                        data_file.file_format = DataFileFormat::Orc;
+                    }
+                    manifest_entry(
+                        &format_version,
+                        Status::Existing,
+                        Some(snapshot_id),
+                        sequence_number,
+                        data_file,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let location = metadata.location().trim_end_matches('/');
+            let manifest = write_manifest(
+                table,
+                &format!("{}/metadata/orc-m0.avro", location),
+                manifest.partition_spec_id(),
+                &entries,
+                None,
+            )
+            .await?;
+            let manifest_list = format!("{}/metadata/snap-orc.avro", location);
+            write_manifest_list(
+                &table.object_store(),
+                &manifest_list,
+                &[manifest_file(&format_version, &manifest, 1, 1)],
+                metadata,
+                1,
+                1,
+            )
+            .await?;
+            add_snapshot(
+                metadata,
+                1,
+                1,
+                manifest_list,
+                Summary {
+                    operation: Operation::Replace,
+                    other: HashMap::new(),
+                },
+            )
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_scan_orc_data_file() {
+        let object_store = taxis_copy(&[]).await;
+        let mut table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        );
+        table.commit(&mut OrcSnapshot).await.unwrap();
+        let orc_file = table.table().unwrap().files(None).await.unwrap()[0]
+            .file_path()
+            .to_owned();
+
+        let ctx = SessionContext::new();
+        let err = table
+            .scan(&ctx.state(), &None, &[], None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(&orc_file));
+        assert!(err.to_string().contains("Orc"));
+    }
 }
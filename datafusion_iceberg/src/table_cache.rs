@@ -0,0 +1,172 @@
+/*!
+ * Process wide cache of loaded tables
+ *
+ * Loading a table reads its metadata and manifests. The cache keeps one copy of every loaded table that can be
+ * registered with any number of SessionContexts. Tables are keyed by their metadata location, which never changes its
+ * content, so a cached table can't be stale for the key it was loaded with.
+ *
+ * Every tenant holds references to the tables it used. A tenant can be limited in the number of tables it holds, in
+ * which case the least recently used tables are evicted. A table is dropped from the cache once no tenant holds it
+ * anymore.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use datafusion::error::Result;
+
+use crate::DataFusionTable;
+
+#[derive(Default)]
+struct Tenant {
+    limit: Option<usize>,
+    // Least recently used key first
+    keys: VecDeque<String>,
+}
+
+#[derive(Default)]
+struct Entries {
+    tables: HashMap<String, Arc<DataFusionTable>>,
+    tenants: HashMap<String, Tenant>,
+}
+
+impl Entries {
+    fn touch(&mut self, tenant: &str, key: &str) {
+        let tenant = self.tenants.entry(tenant.to_owned()).or_default();
+        tenant.keys.retain(|x| x != key);
+        tenant.keys.push_back(key.to_owned());
+        let evicted = match tenant.limit {
+            Some(limit) if tenant.keys.len() > limit => {
+                tenant.keys.drain(..tenant.keys.len() - limit).collect()
+            }
+            _ => vec![],
+        };
+        self.release(evicted);
+    }
+    // Drop the tables that are no longer held by any tenant
+    fn release(&mut self, keys: Vec<String>) {
+        for key in keys {
+            if !self
+                .tenants
+                .values()
+                .any(|tenant| tenant.keys.contains(&key))
+            {
+                self.tables.remove(&key);
+            }
+        }
+    }
+}
+
+/// Cache of loaded tables that is shared by multiple SessionContexts
+#[derive(Default)]
+pub struct TableCache {
+    entries: Mutex<Entries>,
+}
+
+impl TableCache {
+    pub fn new() -> Arc<Self> {
+        Arc::new(TableCache::default())
+    }
+    /// Return the cached table for the key or load it. The key should identify the metadata file of the table.
+    pub async fn get_or_load<F, Fut>(
+        &self,
+        tenant: &str,
+        key: &str,
+        load: F,
+    ) -> Result<Arc<DataFusionTable>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<DataFusionTable>>,
+    {
+        let cached = self.entries.lock().unwrap().tables.get(key).cloned();
+        let table = match cached {
+            Some(table) => table,
+            // The lock is not held while loading, if two tenants load the same table concurrently the first one wins
+            None => {
+                let table = Arc::new(load().await?);
+                self.entries
+                    .lock()
+                    .unwrap()
+                    .tables
+                    .entry(key.to_owned())
+                    .or_insert(table)
+                    .clone()
+            }
+        };
+        self.entries.lock().unwrap().touch(tenant, key);
+        Ok(table)
+    }
+    /// Limit the number of tables the tenant holds. The least recently used tables are evicted first.
+    pub fn set_tenant_limit(&self, tenant: &str, limit: Option<usize>) {
+        let mut entries = self.entries.lock().unwrap();
+        let evicted = {
+            let tenant = entries.tenants.entry(tenant.to_owned()).or_default();
+            tenant.limit = limit;
+            match limit {
+                Some(limit) if tenant.keys.len() > limit => {
+                    tenant.keys.drain(..tenant.keys.len() - limit).collect()
+                }
+                _ => vec![],
+            }
+        };
+        entries.release(evicted);
+    }
+    /// Release all tables held by the tenant
+    pub fn evict_tenant(&self, tenant: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(tenant) = entries.tenants.remove(tenant) {
+            entries.release(tenant.keys.into());
+        }
+    }
+    /// Number of tables in the cache
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().tables.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_table_cache() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let object_store = &object_store;
+        let load = || async move {
+            Ok(DataFusionTable::from(
+                Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", object_store)
+                    .await
+                    .unwrap(),
+            ))
+        };
+
+        let cache = TableCache::new();
+
+        let first = cache.get_or_load("first", "taxis", load).await.unwrap();
+        let second = cache.get_or_load("second", "taxis", load).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+
+        cache.set_tenant_limit("first", Some(1));
+        cache.get_or_load("first", "other", load).await.unwrap();
+        // The second tenant still holds the first table
+        assert_eq!(cache.len(), 2);
+
+        cache.evict_tenant("second");
+        assert_eq!(cache.len(), 1);
+        cache.evict_tenant("first");
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+/*!
+ * Temporary iceberg tables of a session
+ *
+ * Temporary tables are file system tables in a scratch directory of the session, which is a new directory below the
+ * scratch location. They are registered with the context like other tables, so intermediate results of a pipeline get
+ * the snapshots, commits and procedures of iceberg tables. The temporary tables are an extension of the session config:
+ *
+ * ```ignore
+ * let temporary = TemporaryTables::new(object_store, "s3://bucket/scratch");
+ * let ctx = SessionContext::with_config(SessionConfig::new().with_extension(Arc::new(temporary)));
+ * sql(&ctx, "CREATE TEMPORARY TABLE trips AS SELECT * FROM nyc_taxis WHERE vendor_id = 1").await?;
+ * ```
+ *
+ * The scratch directory with all files of the temporary tables is deleted when the session is dropped, which is when
+ * the context and all DataFrames of the context are dropped. Inside a multi-threaded runtime the deletion finishes
+ * before the drop returns. A single-threaded runtime can't be blocked, there the deletion is spawned and finishes while
+ * the runtime keeps running.
+*/
+
+use std::sync::Arc;
+
+use datafusion::{arrow::datatypes::Schema, dataframe::DataFrame, error::Result};
+use futures::TryStreamExt;
+use iceberg_rs::util;
+use object_store::{path::Path, ObjectStore};
+use tokio::runtime::{Builder, Handle, RuntimeFlavor};
+use uuid::Uuid;
+
+use crate::{
+    dataframe::{create_file_system_table, WriteIceberg, WriteOptions, WriteTarget},
+    purge::{delete_files, BulkDelete},
+    DataFusionTable,
+};
+
+/// Scratch directory of the temporary tables of a session, which is deleted when the session is dropped
+#[derive(Debug)]
+pub struct TemporaryTables {
+    object_store: Arc<dyn ObjectStore>,
+    location: String,
+}
+
+impl TemporaryTables {
+    /// Temporary tables in a new directory below the scratch location of the object store
+    pub fn new(object_store: Arc<dyn ObjectStore>, scratch_location: &str) -> Self {
+        TemporaryTables {
+            object_store,
+            location: scratch_location.trim_end_matches('/').to_owned()
+                + "/"
+                + &Uuid::new_v4().to_string(),
+        }
+    }
+    /// Scratch directory of the session
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+    /// Create the temporary table with the schema. The table has no snapshot.
+    pub async fn create_table(&self, schema: &Schema) -> Result<DataFusionTable> {
+        create_file_system_table(&self.table_location(), self.object_store.clone(), schema).await
+    }
+    /// Create the temporary table with the schema and the rows of the DataFrame
+    pub async fn create_table_as(&self, df: &DataFrame) -> Result<DataFusionTable> {
+        df.write_iceberg(
+            WriteTarget::FileSystem {
+                location: self.table_location(),
+                object_store: self.object_store.clone(),
+            },
+            &WriteOptions::default().with_create_if_not_exists(),
+        )
+        .await
+    }
+    // Every table gets its own directory, so a replaced table doesn't share the location with its successor
+    fn table_location(&self) -> String {
+        self.location.clone() + "/" + &Uuid::new_v4().to_string()
+    }
+}
+
+impl Drop for TemporaryTables {
+    fn drop(&mut self) {
+        let purge = purge(self.object_store.clone(), self.location.clone());
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(purge))
+            }
+            Ok(handle) => {
+                handle.spawn(purge);
+            }
+            Err(_) => match Builder::new_current_thread().enable_time().build() {
+                Ok(runtime) => runtime.block_on(purge),
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(location = self.location, error = %_err, "Temporary tables were not purged");
+                }
+            },
+        }
+    }
+}
+
+/// Delete all files below the location. Errors can't be returned from a drop, files that are left behind are orphans of
+/// the scratch location.
+async fn purge(object_store: Arc<dyn ObjectStore>, location: String) {
+    let directory = Path::from(util::strip_prefix(&location).as_str());
+    let files = match object_store.list(Some(&directory)).await {
+        Ok(files) => files.try_collect::<Vec<_>>().await,
+        Err(err) => Err(err),
+    };
+    let result = match files {
+        Ok(files) => {
+            let paths: Vec<String> = files.iter().map(|file| file.location.to_string()).collect();
+            delete_files(&object_store, &paths, &BulkDelete::default())
+                .await
+                .map(|_| ())
+        }
+        Err(err) => Err(err.into()),
+    };
+    if let Err(_err) = result {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(location, error = %_err, "Temporary tables were not purged");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::{
+        arrow::array::Int64Array,
+        datasource::TableProvider,
+        prelude::{SessionConfig, SessionContext},
+    };
+    use iceberg_rs::table::Table;
+
+    use crate::{
+        statement::sql,
+        testing::{taxis_copy, TAXIS},
+    };
+
+    use super::*;
+
+    async fn count(ctx: &SessionContext, table: &str) -> i64 {
+        let batches = sql(ctx, &format!("SELECT count(*) FROM {}", table))
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .value(0)
+    }
+
+    async fn list(object_store: &Arc<dyn ObjectStore>, location: &Path) -> Vec<Path> {
+        object_store
+            .list(Some(location))
+            .await
+            .unwrap()
+            .map_ok(|file| file.location)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_temporary_tables() {
+        let object_store = taxis_copy(&[]).await;
+        let temporary = TemporaryTables::new(object_store.clone(), "/scratch");
+        let location = Path::from(util::strip_prefix(temporary.location()).as_str());
+        let ctx =
+            SessionContext::with_config(SessionConfig::new().with_extension(Arc::new(temporary)));
+        let taxis = Table::load_file_system_table(TAXIS, &object_store)
+            .await
+            .unwrap();
+        ctx.register_table("nyc_taxis", Arc::new(DataFusionTable::from(taxis)))
+            .unwrap();
+
+        sql(
+            &ctx,
+            "CREATE TEMPORARY TABLE trips AS SELECT * FROM nyc_taxis WHERE vendor_id = 1",
+        )
+        .await
+        .unwrap();
+        sql(
+            &ctx,
+            "CREATE TEMPORARY TABLE fares (trip_id BIGINT NOT NULL, fare DOUBLE)",
+        )
+        .await
+        .unwrap();
+        assert_eq!(count(&ctx, "trips").await, 2);
+        assert_eq!(count(&ctx, "fares").await, 0);
+        let provider = ctx
+            .catalog("datafusion")
+            .unwrap()
+            .schema("public")
+            .unwrap()
+            .table("fares")
+            .unwrap();
+        let fares = provider.as_any().downcast_ref::<DataFusionTable>().unwrap();
+        assert_eq!(fares.schema().field(0).name(), "trip_id");
+        assert!(!fares.schema().field(0).is_nullable());
+        assert!(fares.schema().field(1).is_nullable());
+        assert!(!list(&object_store, &location).await.is_empty());
+
+        // Existing tables are kept or replaced
+        sql(
+            &ctx,
+            "CREATE TEMPORARY TABLE IF NOT EXISTS trips AS SELECT * FROM nyc_taxis",
+        )
+        .await
+        .unwrap();
+        assert_eq!(count(&ctx, "trips").await, 2);
+        sql(
+            &ctx,
+            "CREATE OR REPLACE TEMPORARY TABLE trips AS SELECT * FROM nyc_taxis",
+        )
+        .await
+        .unwrap();
+        assert_eq!(count(&ctx, "trips").await, 4);
+        assert!(sql(
+            &ctx,
+            "CREATE TEMPORARY TABLE trips AS SELECT * FROM nyc_taxis"
+        )
+        .await
+        .is_err());
+
+        drop(provider);
+        drop(ctx);
+        assert!(list(&object_store, &location).await.is_empty());
+        // The other tables of the session are kept
+        assert!(!list(&object_store, &Path::from(TAXIS)).await.is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+/*!
+ * Test data for the tests of this crate and of the crates that build on it
+ *
+ * The test data contains the nyc taxis table with four trips, two per vendor id. It is read from the tests directory of
+ * this crate, so the helpers only work where the sources of the crate are available. They panic if the test data can't
+ * be read.
+*/
+
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use object_store::{local::LocalFileSystem, memory::InMemory, ObjectMeta, ObjectStore};
+
+/// Location of the nyc taxis table in the object store of the test data
+pub const TAXIS: &str = "/home/iceberg/warehouse/nyc/taxis";
+
+/// Object store with the test data
+pub fn test_data() -> Arc<dyn ObjectStore> {
+    Arc::new(
+        LocalFileSystem::new_with_prefix(concat!(env!("CARGO_MANIFEST_DIR"), "/tests")).unwrap(),
+    )
+}
+
+/// Copy of the taxis table in memory that can be changed. The replacements are applied to the metadata files, for
+/// example to move the table to another location.
+pub async fn taxis_copy(replacements: &[(&str, &str)]) -> Arc<dyn ObjectStore> {
+    let source = test_data();
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let files: Vec<ObjectMeta> = source
+        .list(Some(&TAXIS.trim_start_matches('/').into()))
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    for file in files {
+        let mut bytes = source
+            .get(&file.location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        if !replacements.is_empty() && file.location.as_ref().ends_with(".metadata.json") {
+            let mut metadata = String::from_utf8(bytes.to_vec()).unwrap();
+            for (from, to) in replacements {
+                metadata = metadata.replace(from, to);
+            }
+            bytes = metadata.into();
+        }
+        store.put(&file.location, bytes).await.unwrap();
+    }
+    store
+}
@@ -0,0 +1,316 @@
+/*!
+ * Projection of query predicates through partition transforms
+ *
+ * The partition summaries in the manifest list contain the bounds of the transformed values, for example the days of
+ * a timestamp. For order preserving transforms these bounds are converted to bounds of the source column, so that the
+ * predicates of the query can be evaluated against them.
+ *
+ * Bucket transforms don't preserve the order. Equality predicates on the source column are therefore hashed into the
+ * bucket they belong to, which is compared with the bucket of the manifests and data files.
+*/
+
+use std::any::Any;
+
+use chrono::{Datelike, NaiveDate};
+use datafusion::{
+    arrow::datatypes::DataType,
+    logical_expr::{BinaryExpr, Operator},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+use iceberg_rs::{
+    model::{bytes::bytes_to_any, manifest::ManifestEntry, partition::Transform, values::Value},
+    table::Table,
+};
+
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// Lower bound of the source column given the lower bound of the transformed values
+pub(crate) fn source_lower_bound(
+    transform: &Transform,
+    value: Box<dyn Any>,
+    datatype: &DataType,
+) -> Option<Box<dyn Any>> {
+    match transform {
+        Transform::Identity => Some(value),
+        Transform::Truncate(_) => match datatype {
+            DataType::Int32 | DataType::Int64 | DataType::Utf8 => Some(value),
+            _ => None,
+        },
+        Transform::Year | Transform::Month | Transform::Day | Transform::Hour => {
+            let value = *value.downcast::<i32>().ok()?;
+            let (date, micros) = match transform {
+                Transform::Hour => (None, value as i64 * MICROS_PER_HOUR),
+                Transform::Day => (Some(value), value as i64 * MICROS_PER_DAY),
+                _ => {
+                    let months = if matches!(transform, Transform::Year) {
+                        value * 12
+                    } else {
+                        value
+                    };
+                    let days = first_day_of_month(months)?;
+                    (Some(days), days as i64 * MICROS_PER_DAY)
+                }
+            };
+            temporal(date, micros, datatype)
+        }
+        Transform::Bucket(_) | Transform::Void => None,
+    }
+}
+
+/// Upper bound of the source column given the upper bound of the transformed values
+pub(crate) fn source_upper_bound(
+    transform: &Transform,
+    value: Box<dyn Any>,
+    datatype: &DataType,
+) -> Option<Box<dyn Any>> {
+    match transform {
+        Transform::Identity => Some(value),
+        Transform::Truncate(width) => match datatype {
+            // Without an upper bound if the last value of the truncated range doesn't fit the type
+            DataType::Int32 => Some(Box::new(
+                value
+                    .downcast::<i32>()
+                    .ok()?
+                    .checked_add(*width as i32 - 1)?,
+            )),
+            DataType::Int64 => Some(Box::new(
+                value
+                    .downcast::<i64>()
+                    .ok()?
+                    .checked_add(*width as i64 - 1)?,
+            )),
+            // Any string with the truncated prefix can be larger than the prefix
+            _ => None,
+        },
+        Transform::Year | Transform::Month | Transform::Day | Transform::Hour => {
+            let value = *value.downcast::<i32>().ok()?;
+            // The upper bound is the last value before the next year/month/day/hour starts
+            let (date, micros) = match transform {
+                Transform::Hour => (None, (value as i64 + 1) * MICROS_PER_HOUR - 1),
+                Transform::Day => (Some(value), (value as i64 + 1) * MICROS_PER_DAY - 1),
+                _ => {
+                    let months = if matches!(transform, Transform::Year) {
+                        (value + 1) * 12
+                    } else {
+                        value + 1
+                    };
+                    let days = first_day_of_month(months)? - 1;
+                    (Some(days), (days as i64 + 1) * MICROS_PER_DAY - 1)
+                }
+            };
+            temporal(date, micros, datatype)
+        }
+        Transform::Bucket(_) | Transform::Void => None,
+    }
+}
+
+/// Bucket that the values of the source column have to be in for the filters to match
+pub(crate) fn bucket(transform: &Transform, column: &str, filters: &[Expr]) -> Option<i32> {
+    let buckets = match transform {
+        Transform::Bucket(buckets) => *buckets as i32,
+        _ => return None,
+    };
+    filters.iter().find_map(|filter| match filter {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Eq,
+            right,
+        }) => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(col), Expr::Literal(value))
+            | (Expr::Literal(value), Expr::Column(col))
+                if col.name == column =>
+            {
+                Some((hash(value)? & i32::MAX) % buckets)
+            }
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Buckets that the filters restrict the partition fields of the spec to, together with the position of the partition field
+pub(crate) fn buckets(table: &Table, spec_id: i32, filters: &[Expr]) -> Vec<(usize, i32)> {
+    let spec = match table.metadata().get_spec(spec_id) {
+        Some(spec) => spec,
+        None => return vec![],
+    };
+    spec.iter()
+        .enumerate()
+        .filter_map(|(position, field)| {
+            let source = table
+                .schema()
+                .fields
+                .iter()
+                .find(|x| x.id == field.source_id)?;
+            bucket(&field.transform, &source.name, filters).map(|bucket| (position, bucket))
+        })
+        .collect()
+}
+
+/// Whether the manifests can contain data files in the buckets of the filters, one entry per manifest
+pub(crate) fn manifests_in_buckets(table: &Table, filters: &[Expr]) -> Vec<bool> {
+    let datatype = match (&DataType::Int32).try_into() {
+        Ok(datatype) => datatype,
+        Err(_) => return vec![true; table.manifests().len()],
+    };
+    table
+        .manifests()
+        .iter()
+        .map(|manifest| {
+            let partitions = match manifest.partitions() {
+                Some(partitions) => partitions,
+                None => return true,
+            };
+            buckets(table, manifest.partition_spec_id(), filters)
+                .into_iter()
+                .all(|(position, bucket)| {
+                    let summary = match partitions.get(position) {
+                        Some(summary) => summary,
+                        None => return true,
+                    };
+                    let bound = |bound: Option<&[u8]>| {
+                        bound
+                            .and_then(|bytes| bytes_to_any(bytes, &datatype).ok())
+                            .and_then(|value| value.downcast::<i32>().ok())
+                            .map(|value| *value)
+                    };
+                    bound(summary.lower_bound.as_ref().map(|x| x.as_slice()))
+                        .is_none_or(|lower| lower <= bucket)
+                        && bound(summary.upper_bound.as_ref().map(|x| x.as_slice()))
+                            .is_none_or(|upper| bucket <= upper)
+                })
+        })
+        .collect()
+}
+
+/// Whether the partition values of the data file are in the buckets
+pub(crate) fn file_in_buckets(entry: &ManifestEntry, buckets: &[(usize, i32)]) -> bool {
+    buckets.iter().all(
+        |(position, bucket)| match entry.partition_values().get(*position) {
+            Some(Some(Value::Int(value))) => value == bucket,
+            _ => true,
+        },
+    )
+}
+
+/// Hash of the value as defined by the iceberg specification for the bucket transform
+pub(crate) fn hash(value: &ScalarValue) -> Option<i32> {
+    match value {
+        // Integers are hashed as longs so that promoting a column from int to long doesn't change the buckets
+        ScalarValue::Int32(Some(v)) | ScalarValue::Date32(Some(v)) => {
+            Some(murmur3_32(&(*v as i64).to_le_bytes()))
+        }
+        ScalarValue::Int64(Some(v)) | ScalarValue::TimestampMicrosecond(Some(v), _) => {
+            Some(murmur3_32(&v.to_le_bytes()))
+        }
+        ScalarValue::Utf8(Some(v)) => Some(murmur3_32(v.as_bytes())),
+        ScalarValue::Binary(Some(v)) => Some(murmur3_32(v)),
+        _ => None,
+    }
+}
+
+/// 32 bit murmur3 hash for x86 with seed 0
+pub(crate) fn murmur3_32(bytes: &[u8]) -> i32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut chunks = bytes.chunks_exact(4);
+    let mut h = chunks.by_ref().fold(0u32, |h, chunk| {
+        let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        (h ^ mix(k))
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe6546b64)
+    });
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, byte| (k << 8) | *byte as u32);
+        h ^= mix(k);
+    }
+
+    h ^= bytes.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^= h >> 16;
+    h as i32
+}
+
+/// Days since the epoch of the first day of the month. Months are counted from January 1970.
+fn first_day_of_month(months: i32) -> Option<i32> {
+    let date = NaiveDate::from_ymd_opt(
+        1970 + months.div_euclid(12),
+        months.rem_euclid(12) as u32 + 1,
+        1,
+    )?;
+    Some(date.num_days_from_ce() - NaiveDate::from_ymd_opt(1970, 1, 1)?.num_days_from_ce())
+}
+
+fn temporal(days: Option<i32>, micros: i64, datatype: &DataType) -> Option<Box<dyn Any>> {
+    match datatype {
+        DataType::Date32 => Some(Box::new(days?)),
+        DataType::Timestamp(_, _) => Some(Box::new(micros)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::prelude::{col, lit};
+
+    use super::*;
+
+    #[test]
+    fn test_bucket_hash() {
+        // Values from the appendix of the iceberg specification
+        assert_eq!(hash(&ScalarValue::Int32(Some(34))), Some(2017239379));
+        assert_eq!(hash(&ScalarValue::Int64(Some(34))), Some(2017239379));
+        assert_eq!(
+            hash(&ScalarValue::Utf8(Some("iceberg".to_string()))),
+            Some(1210000089)
+        );
+
+        assert_eq!(
+            bucket(&Transform::Bucket(16), "id", &[col("id").eq(lit(34i64))]),
+            Some(2017239379 % 16)
+        );
+        assert_eq!(
+            bucket(&Transform::Bucket(16), "id", &[col("id").gt(lit(34i64))]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_month_bounds() {
+        // February 1970
+        let lower = source_lower_bound(&Transform::Month, Box::new(1), &DataType::Date32)
+            .and_then(|value| value.downcast::<i32>().ok());
+        let upper = source_upper_bound(&Transform::Month, Box::new(1), &DataType::Date32)
+            .and_then(|value| value.downcast::<i32>().ok());
+        assert_eq!(lower.map(|value| *value), Some(31));
+        assert_eq!(upper.map(|value| *value), Some(58));
+    }
+
+    #[test]
+    fn test_truncate_bounds() {
+        let upper = source_upper_bound(&Transform::Truncate(10), Box::new(20), &DataType::Int32)
+            .and_then(|value| value.downcast::<i32>().ok());
+        assert_eq!(upper.map(|value| *value), Some(29));
+        // The last value of the range doesn't fit into an int
+        let upper = source_upper_bound(
+            &Transform::Truncate(10),
+            Box::new(i32::MAX - 5),
+            &DataType::Int32,
+        );
+        assert!(upper.is_none());
+    }
+}
@@ -0,0 +1,119 @@
+/*!
+ * Validate table metadata against the iceberg specification
+ *
+ * Tables produced by third-party writers can contain metadata that deserializes fine but is inconsistent, for example
+ * partition fields that reference columns that don't exist. Such tables otherwise fail deep inside of the arrow
+ * decoding. Invalid transforms are already rejected when the metadata is deserialized.
+*/
+
+use std::collections::HashSet;
+
+use datafusion::{common::DataFusionError, error::Result};
+use iceberg_rs::{catalog::relation::Relation, table::Table};
+
+use crate::{metadata::TableMetadataExt, DataFusionTable};
+
+impl DataFusionTable {
+    /// Check the metadata of the table and report all violations of the specification at once.
+    pub fn validate(&self) -> Result<()> {
+        let violations = match &self.relation {
+            Relation::Table(table) => table_violations(table),
+            Relation::View(view) => match view.schema() {
+                Some(_) => vec![],
+                None => vec!["The view has no current schema.".to_owned()],
+            },
+        };
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(DataFusionError::Plan(format!(
+                "Table metadata at {} violates the iceberg specification:\n{}",
+                self.relation.metadata_location(),
+                violations.join("\n")
+            )))
+        }
+    }
+}
+
+fn table_violations(table: &Table) -> Vec<String> {
+    let mut violations = Vec::new();
+    let schema = table.schema();
+
+    let mut field_ids = HashSet::new();
+    let mut field_names = HashSet::new();
+    for field in schema.fields.iter() {
+        if !field_ids.insert(field.id) {
+            violations.push(format!(
+                "The schema contains the field id {} twice.",
+                field.id
+            ));
+        }
+        if !field_names.insert(field.name.as_str()) {
+            violations.push(format!(
+                "The schema contains the field name {} twice.",
+                field.name
+            ));
+        }
+    }
+
+    for id in table
+        .metadata()
+        .identifier_field_ids()
+        .into_iter()
+        .flatten()
+    {
+        match schema.fields.iter().find(|field| field.id == *id) {
+            None => violations.push(format!(
+                "The identifier field id {} doesn't reference a field of the schema.",
+                id
+            )),
+            Some(field) if !field.required => violations.push(format!(
+                "The identifier field {} has to be required.",
+                field.name
+            )),
+            Some(_) => (),
+        }
+    }
+
+    let mut partition_names = HashSet::new();
+    for field in table.metadata().default_spec().iter() {
+        if !field_ids.contains(&field.source_id) {
+            violations.push(format!(
+                "The partition field {} references the source id {} which is not part of the schema.",
+                field.name, field.source_id
+            ));
+        }
+        if !partition_names.insert(field.name.as_str()) {
+            violations.push(format!(
+                "The partition spec contains the field name {} twice.",
+                field.name
+            ));
+        }
+    }
+
+    for manifest in table.manifests() {
+        let id = manifest.partition_spec_id();
+        match table.metadata().get_spec(id) {
+            None => violations.push(format!(
+                "The manifest {} references the partition spec {} which doesn't exist.",
+                manifest.manifest_path(),
+                id
+            )),
+            Some(spec) => {
+                if let Some(partitions) = manifest.partitions() {
+                    if partitions.len() != spec.len() {
+                        violations.push(format!(
+                            "The manifest {} contains {} partition summaries but the partition spec {} has {} fields.",
+                            manifest.manifest_path(),
+                            partitions.len(),
+                            id,
+                            spec.len()
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    violations
+}
@@ -0,0 +1,284 @@
+/*!
+ * Write-audit-publish with branches of the table
+ *
+ * Writes are staged on a branch, which is a named reference to a snapshot next to the main branch. The table of a
+ * branch reads the head of the branch and commits its snapshots to the branch, so readers of the main branch don't see
+ * them. After the staged data was audited with queries on the branch, publishing fast-forwards the main branch to the
+ * head of the branch. Dropping the branch rolls the staged writes back, its snapshots are deleted by the next
+ * expiration of snapshots.
+ *
+ * The table of a branch is built from the metadata with the head of the branch as current snapshot. Only tables of a
+ * catalog can be built from metadata, file system tables always read the current snapshot of their latest metadata
+ * file. Branches need format version 2 of the table.
+*/
+
+use std::collections::HashMap;
+
+use datafusion::{common::DataFusionError, error::Result};
+use iceberg_rs::{
+    catalog::relation::Relation,
+    model::{
+        snapshot::Retention,
+        table_metadata::{SnapshotLog, TableMetadata},
+    },
+    table::Table,
+};
+
+use crate::{
+    commit::{branch_reference, clone_metadata, now_ms, Change},
+    error::Error,
+    metadata::TableMetadataExt,
+    DataFusionTable,
+};
+
+/// Name of the branch that the current snapshot of the table belongs to
+pub const MAIN_BRANCH: &str = "main";
+
+impl DataFusionTable {
+    /// Create the branch at the current snapshot of the table. Fails if the branch exists already.
+    pub async fn create_branch(&mut self, name: &str) -> Result<()> {
+        self.commit_branch(name, BranchOperation::Create).await
+    }
+    /// Copy of the table with the same options that reads the head of the branch. Its writes add snapshots to the
+    /// branch and leave the main branch unchanged.
+    pub async fn branch(&self, name: &str) -> Result<DataFusionTable> {
+        check_name(name)?;
+        let relation = branch_table(self.table()?, name).await?;
+        let mut table = self.with_relation(relation);
+        table.branch = Some(name.to_owned());
+        Ok(table)
+    }
+    /// Publish the snapshots of the branch by fast-forwarding the main branch to the head of the branch. Fails if the
+    /// main branch changed since the branch was created, the branch is kept.
+    pub async fn publish_branch(&mut self, name: &str) -> Result<()> {
+        self.commit_branch(name, BranchOperation::Publish).await
+    }
+    /// Drop the branch, which rolls back the writes that were staged on it
+    pub async fn drop_branch(&mut self, name: &str) -> Result<()> {
+        self.commit_branch(name, BranchOperation::Drop).await
+    }
+    /// The relation in the table of the branch, if the table reads a branch
+    pub(crate) async fn on_branch(&self, relation: Relation) -> Result<Relation> {
+        match (&self.branch, relation) {
+            (Some(branch), Relation::Table(table)) => branch_table(&table, branch).await,
+            (Some(_), Relation::View(_)) => Err(DataFusionError::Plan(
+                "Only tables have branches.".to_string(),
+            )),
+            (None, relation) => Ok(relation),
+        }
+    }
+    async fn commit_branch(&mut self, name: &str, operation: BranchOperation) -> Result<()> {
+        check_name(name)?;
+        if self.branch.is_some() {
+            return Err(DataFusionError::Plan(
+                "Branches are changed with the table of the main branch.".to_string(),
+            ));
+        }
+        let mut change = BranchChange {
+            name: name.to_owned(),
+            operation,
+        };
+        self.commit(&mut change).await
+    }
+}
+
+enum BranchOperation {
+    Create,
+    Publish,
+    Drop,
+}
+
+/// Change of the references of the table
+struct BranchChange {
+    name: String,
+    operation: BranchOperation,
+}
+
+#[async_trait::async_trait]
+impl Change for BranchChange {
+    async fn apply(&mut self, table: &Table) -> Result<TableMetadata> {
+        let mut metadata = clone_metadata(table.metadata())?;
+        let metadata_v2 = match &mut metadata {
+            TableMetadata::V1(_) => {
+                return Err(DataFusionError::Plan(
+                    "Branches need format version 2 of the table.".to_string(),
+                ))
+            }
+            TableMetadata::V2(metadata) => metadata,
+        };
+        let current = metadata_v2.current_snapshot_id;
+        let refs = metadata_v2.refs.get_or_insert_with(HashMap::new);
+        if let Some(current) = current {
+            refs.entry(MAIN_BRANCH.to_owned())
+                .or_insert_with(|| branch_reference(current));
+        }
+        match self.operation {
+            BranchOperation::Create => {
+                let current = current.ok_or_else(|| {
+                    DataFusionError::Plan(format!(
+                        "Branch {} needs a snapshot of the table to start from.",
+                        self.name
+                    ))
+                })?;
+                if refs.contains_key(&self.name) {
+                    return Err(DataFusionError::Plan(format!(
+                        "Branch {} exists already.",
+                        self.name
+                    )));
+                }
+                refs.insert(self.name.clone(), branch_reference(current));
+            }
+            BranchOperation::Publish => {
+                let head = branch_head(table.metadata(), &self.name)?;
+                let mut ancestor = Some(head);
+                while ancestor != current {
+                    ancestor = match ancestor {
+                        Some(id) => metadata_v2
+                            .snapshots
+                            .iter()
+                            .flatten()
+                            .find(|snapshot| snapshot.snapshot_id == id)
+                            .and_then(|snapshot| snapshot.parent_snapshot_id),
+                        None => {
+                            return Err(Error::CommitConflict(format!(
+                                "The main branch changed since branch {} was created, it can't be fast-forwarded.",
+                                self.name
+                            ))
+                            .into())
+                        }
+                    };
+                }
+                if current != Some(head) {
+                    metadata_v2.current_snapshot_id = Some(head);
+                    metadata_v2
+                        .refs
+                        .get_or_insert_with(HashMap::new)
+                        .insert(MAIN_BRANCH.to_owned(), branch_reference(head));
+                    metadata_v2
+                        .snapshot_log
+                        .get_or_insert_with(Vec::new)
+                        .push(SnapshotLog {
+                            snapshot_id: head,
+                            timestamp_ms: now_ms(),
+                        });
+                }
+            }
+            BranchOperation::Drop => {
+                branch_head(table.metadata(), &self.name)?;
+                refs.remove(&self.name);
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// Metadata of the change that the table of the branch committed. The main branch keeps its current snapshot, the new
+/// current snapshot becomes the head of the branch.
+pub(crate) fn branch_metadata(
+    base: &TableMetadata,
+    mut metadata: TableMetadata,
+    branch: &str,
+) -> Result<TableMetadata> {
+    let main = match base {
+        TableMetadata::V2(base) => base
+            .refs
+            .iter()
+            .flatten()
+            .find(|(name, _)| name.as_str() == MAIN_BRANCH)
+            .map(|(_, reference)| reference.snapshot_id),
+        TableMetadata::V1(_) => None,
+    };
+    let metadata_v2 = match &mut metadata {
+        TableMetadata::V1(_) => {
+            return Err(DataFusionError::Plan(
+                "Branches need format version 2 of the table.".to_string(),
+            ))
+        }
+        TableMetadata::V2(metadata) => metadata,
+    };
+    let head = metadata_v2.current_snapshot_id;
+    metadata_v2.current_snapshot_id = main;
+    let refs = metadata_v2.refs.get_or_insert_with(HashMap::new);
+    match main {
+        Some(main) => refs.insert(MAIN_BRANCH.to_owned(), branch_reference(main)),
+        None => refs.remove(MAIN_BRANCH),
+    };
+    if let Some(head) = head {
+        refs.insert(branch.to_owned(), branch_reference(head));
+    }
+    // The snapshot log only records the current snapshots of the main branch
+    if head != base.current_snapshot_id() {
+        if let Some(log) = &mut metadata_v2.snapshot_log {
+            if log.last().map(|entry| Some(entry.snapshot_id)) == Some(head) {
+                log.pop();
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+/// Table that reads the head of the branch
+async fn branch_table(table: &Table, branch: &str) -> Result<Relation> {
+    let head = branch_head(table.metadata(), branch)?;
+    let mut metadata = clone_metadata(table.metadata())?;
+    if let TableMetadata::V2(metadata) = &mut metadata {
+        // The main branch is implied if the table has no reference to it
+        if let Some(current) = metadata.current_snapshot_id {
+            metadata
+                .refs
+                .get_or_insert_with(HashMap::new)
+                .entry(MAIN_BRANCH.to_owned())
+                .or_insert_with(|| branch_reference(current));
+        }
+        metadata.current_snapshot_id = Some(head);
+    }
+    with_metadata(table, metadata).await
+}
+
+/// Table of the catalog with the metadata, which is based on the metadata location of the table
+async fn with_metadata(table: &Table, metadata: TableMetadata) -> Result<Relation> {
+    match (table.identifier(), table.catalog()) {
+        (Some(identifier), Some(catalog)) => Ok(Relation::Table(
+            Table::new_metastore_table(
+                identifier.clone(),
+                catalog.clone(),
+                metadata,
+                table.metadata_location(),
+            )
+            .await
+            .map_err(Error::iceberg)?,
+        )),
+        _ => Err(DataFusionError::Plan(
+            "Branches can only be read for tables of a catalog, file system tables read their current snapshot."
+                .to_string(),
+        )),
+    }
+}
+
+/// Snapshot id at the head of the branch
+fn branch_head(metadata: &TableMetadata, branch: &str) -> Result<i64> {
+    let reference = match metadata {
+        TableMetadata::V2(metadata) => metadata.refs.as_ref().and_then(|refs| refs.get(branch)),
+        TableMetadata::V1(_) => None,
+    };
+    match reference {
+        Some(reference) if matches!(reference.retention, Retention::Branch { .. }) => {
+            Ok(reference.snapshot_id)
+        }
+        Some(_) => Err(DataFusionError::Plan(format!(
+            "Reference {} is a tag, not a branch.",
+            branch
+        ))),
+        None => Err(Error::NotFound(format!("Branch {} doesn't exist.", branch)).into()),
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    match name == MAIN_BRANCH {
+        true => Err(DataFusionError::Plan(format!(
+            "The {} branch is the current snapshot of the table, it can't be staged on.",
+            MAIN_BRANCH
+        ))),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,126 @@
+/*!
+ * Watermarks of iceberg tables
+ *
+ * The watermark of a column is the largest value that was written to it. It is derived from the upper bounds that
+ * writers store for every data file in the manifests, so the freshness of a table can be checked without scanning
+ * any data.
+*/
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::{
+        array::StringArray,
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    },
+    common::DataFusionError,
+    datasource::{MemTable, TableProvider},
+    error::Result,
+    physical_optimizer::pruning::PruningStatistics,
+    prelude::Column,
+    scalar::ScalarValue,
+};
+use iceberg_rs::catalog::relation::Relation;
+
+use crate::{
+    error::Error, metadata::ManifestEntryExt, pruning_statistics::PruneDataFiles, DataFusionTable,
+};
+
+impl DataFusionTable {
+    /// Largest value of the column in the current snapshot. None if the table is empty or the data files carry no
+    /// upper bounds for the column.
+    pub async fn watermark(&self, column: &str) -> Result<Option<ScalarValue>> {
+        let table = match &self.relation {
+            Relation::Table(table) => table,
+            Relation::View(_) => {
+                return Err(DataFusionError::Plan(
+                    "Watermarks are only available for tables.".to_string(),
+                ))
+            }
+        };
+        let schema = self.schema();
+        schema.field_with_name(column)?;
+        // Data files that were removed by the current snapshot have no rows in the table
+        let files = table
+            .files(None)
+            .await
+            .map_err(Error::iceberg)?
+            .into_iter()
+            .filter(|entry| !entry.is_deleted())
+            .collect::<Vec<_>>();
+        let max_values = match PruneDataFiles::new(table, &schema, &files)
+            .max_values(&Column::from_name(column))
+        {
+            Some(max_values) => max_values,
+            None => return Ok(None),
+        };
+        (0..max_values.len())
+            .filter(|index| max_values.is_valid(*index))
+            .map(|index| ScalarValue::try_from_array(&max_values, index))
+            .try_fold(None, |acc: Option<ScalarValue>, value| {
+                let value = value?;
+                Ok(match acc {
+                    Some(acc) if acc >= value => Some(acc),
+                    _ => Some(value),
+                })
+            })
+    }
+
+    /// Metadata table with the watermark of every column. Can be registered with a session to query the freshness
+    /// of the table with sql.
+    pub async fn watermarks(&self) -> Result<MemTable> {
+        let schema = self.schema();
+        let mut columns = Vec::with_capacity(schema.fields().len());
+        let mut watermarks = Vec::with_capacity(schema.fields().len());
+        for field in schema.fields() {
+            columns.push(field.name().clone());
+            watermarks.push(
+                self.watermark(field.name())
+                    .await?
+                    .map(|value| value.to_string()),
+            );
+        }
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("column", DataType::Utf8, false),
+            Field::new("watermark", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(columns)),
+                Arc::new(StringArray::from_iter(watermarks)),
+            ],
+        )?;
+        MemTable::try_new(schema, vec![vec![batch]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use iceberg_rs::table::Table;
+    use object_store::{local::LocalFileSystem, ObjectStore};
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_watermark() {
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(LocalFileSystem::new_with_prefix("./tests").unwrap());
+
+        let table = DataFusionTable::from(
+            Table::load_file_system_table("/home/iceberg/warehouse/nyc/taxis", &object_store)
+                .await
+                .unwrap(),
+        );
+
+        let watermark = table
+            .watermark("trip_id")
+            .await
+            .expect("Failed to compute watermark.");
+        assert!(matches!(watermark, Some(ScalarValue::Int64(Some(_)))));
+
+        assert!(table.watermark("missing_column").await.is_err());
+    }
+}
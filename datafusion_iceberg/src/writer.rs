@@ -0,0 +1,1259 @@
+/*!
+ * Write record batches as parquet data files into the location of an iceberg table.
+ *
+ * The writer rolls over to a new file as soon as the current file reaches the target file size. The target file size,
+ * the row group size and the compression codec are read from the table properties and can be overridden. Every file is
+ * uploaded with a multipart upload, to which the row groups are streamed as soon as they are complete, so the writer
+ * only buffers the row group that is being written.
+ *
+ * Small input batches are concatenated before they are written, so that the size of the parquet pages doesn't depend
+ * on the batch size of the producer.
+ *
+ * The parquet writer doesn't write the field ids of the arrow schema, the columns of the files only have the names of
+ * the fields. Readers find the fields of the columns with the name mapping of the table.
+ *
+ * Tables with a partition spec are written with one set of data files per partition. The partition values and the
+ * metrics of the columns are returned with every file, so that the files can be committed to the table.
+ *
+ * Optionally the data files are written into hive-style `column=value` directories of the partition columns, so that
+ * the layout of the bucket can be browsed. Readers still find the files through the table metadata. Unlike the export,
+ * the partition columns are kept in the data files.
+*/
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use datafusion::{
+    arrow::{
+        array::Array,
+        compute::concat_batches,
+        datatypes::{DataType, SchemaRef, TimeUnit},
+        record_batch::RecordBatch,
+    },
+    common::DataFusionError,
+    datasource::file_format::parquet::fetch_parquet_metadata,
+    error::Result,
+    logical_expr::Accumulator,
+    parquet::{
+        arrow::{parquet_to_arrow_schema, ArrowWriter},
+        basic::Compression,
+        file::{
+            metadata::ParquetMetaData,
+            properties::{WriterProperties, WriterPropertiesBuilder},
+            statistics::Statistics,
+        },
+    },
+    physical_plan::{
+        expressions::{MaxAccumulator, MinAccumulator},
+        SendableRecordBatchStream,
+    },
+    prelude::SessionConfig,
+    scalar::ScalarValue,
+};
+use futures::StreamExt;
+use iceberg_rs::model::{data_types::StructType, partition::PartitionField, values::Value};
+use object_store::{path::Path, MultipartId, ObjectMeta, ObjectStore};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{
+    commit::object_path,
+    export::split_batch,
+    options,
+    partition::{self, PartitionColumn},
+    progress::{ProgressCallback, ProgressTracker},
+};
+
+/// Table property for the size at which the writer rolls over to a new file
+pub const WRITE_TARGET_FILE_SIZE_BYTES: &str = "write.target-file-size-bytes";
+/// Table property for the size in bytes at which the writer starts a new parquet row group
+pub const WRITE_PARQUET_ROW_GROUP_SIZE_BYTES: &str = "write.parquet.row-group-size-bytes";
+/// Table property for the parquet compression codec
+pub const WRITE_PARQUET_COMPRESSION_CODEC: &str = "write.parquet.compression-codec";
+
+const DEFAULT_TARGET_FILE_SIZE_BYTES: usize = 536_870_912;
+const DEFAULT_ROW_GROUP_SIZE_BYTES: usize = 134_217_728;
+const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// Options used to write parquet data files
+#[derive(Debug, Clone)]
+pub struct WriterConfig {
+    target_file_size: usize,
+    row_group_size: usize,
+    batch_size: usize,
+    compression: Compression,
+    task_id: usize,
+    progress: Option<ProgressCallback>,
+    partition_columns: Vec<String>,
+    partition_spec: Vec<PartitionColumn>,
+    // Ids and names of the required fields of the table schema
+    required_fields: Option<Vec<(i32, String)>>,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        WriterConfig {
+            target_file_size: DEFAULT_TARGET_FILE_SIZE_BYTES,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE_BYTES,
+            batch_size: DEFAULT_BATCH_SIZE,
+            compression: Compression::ZSTD,
+            task_id: 0,
+            progress: None,
+            partition_columns: Vec::new(),
+            partition_spec: Vec::new(),
+            required_fields: None,
+        }
+    }
+}
+
+impl WriterConfig {
+    /// Read the write options from the table properties. Missing properties use the iceberg defaults.
+    pub fn from_properties(properties: &HashMap<String, String>) -> Result<Self> {
+        let default = WriterConfig::default();
+        let target_file_size = properties
+            .get(WRITE_TARGET_FILE_SIZE_BYTES)
+            .map(|value| parse_size(WRITE_TARGET_FILE_SIZE_BYTES, value))
+            .transpose()?
+            .unwrap_or(default.target_file_size);
+        let row_group_size = properties
+            .get(WRITE_PARQUET_ROW_GROUP_SIZE_BYTES)
+            .map(|value| parse_size(WRITE_PARQUET_ROW_GROUP_SIZE_BYTES, value))
+            .transpose()?
+            .unwrap_or(default.row_group_size);
+        let compression = properties
+            .get(WRITE_PARQUET_COMPRESSION_CODEC)
+            .map(|value| parse_compression(value))
+            .transpose()?
+            .unwrap_or(default.compression);
+        Ok(WriterConfig {
+            target_file_size,
+            row_group_size,
+            batch_size: default.batch_size,
+            compression,
+            task_id: default.task_id,
+            progress: default.progress,
+            partition_columns: default.partition_columns,
+            partition_spec: default.partition_spec,
+            required_fields: default.required_fields,
+        })
+    }
+    /// Apply the write options that are set in the config of the session
+    pub fn with_session_options(mut self, config: &SessionConfig) -> Self {
+        if let Some(target_file_size) = options::get_usize(config, options::WRITE_TARGET_FILE_SIZE)
+        {
+            self.target_file_size = target_file_size;
+        }
+        self
+    }
+    pub(crate) fn target_file_size(&self) -> usize {
+        self.target_file_size
+    }
+    /// Override the size in bytes at which the writer starts a new file
+    pub fn with_target_file_size(mut self, target_file_size: usize) -> Self {
+        self.target_file_size = target_file_size;
+        self
+    }
+    /// Override the size in bytes at which a new row group is started. The size is measured as the size of the input
+    /// batches in memory, the encoded row group is usually smaller.
+    pub fn with_row_group_size(mut self, row_group_size: usize) -> Self {
+        self.row_group_size = row_group_size;
+        self
+    }
+    /// Concatenate input batches until they contain at least this number of rows before writing them
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+    /// Override the compression codec
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+    /// Set the id of the task that writes the files. Concurrent writers should use distinct task ids.
+    pub fn with_task_id(mut self, task_id: usize) -> Self {
+        self.task_id = task_id;
+        self
+    }
+    /// Report the progress after every written file
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+    /// Write the data files into hive-style `column=value` directories of the columns
+    pub fn with_hive_partitioning(mut self, columns: Vec<String>) -> Self {
+        self.partition_columns = columns;
+        self
+    }
+    /// Write one set of data files per partition of the partition spec. The partition values of every file are
+    /// returned with the file.
+    pub fn with_partition_spec(
+        mut self,
+        spec: &[PartitionField],
+        schema: &StructType,
+    ) -> Result<Self> {
+        self.partition_spec = partition::partition_columns(spec, schema)?;
+        Ok(self)
+    }
+    /// Check the required fields of the table schema for null values. Without a table schema the columns that aren't
+    /// nullable in the schema of the input are checked.
+    pub fn with_table_schema(mut self, schema: &StructType) -> Self {
+        self.required_fields = Some(
+            schema
+                .fields
+                .iter()
+                .filter(|field| field.required)
+                .map(|field| (field.id, field.name.clone()))
+                .collect(),
+        );
+        self
+    }
+    fn writer_properties(&self) -> WriterPropertiesBuilder {
+        WriterProperties::builder().set_compression(self.compression)
+    }
+}
+
+fn parse_size(key: &str, value: &str) -> Result<usize> {
+    value.parse().map_err(|_| {
+        DataFusionError::Plan(format!(
+            "Table property {} has invalid value {}.",
+            key, value
+        ))
+    })
+}
+
+fn parse_compression(value: &str) -> Result<Compression> {
+    match value.to_lowercase().as_str() {
+        "uncompressed" | "none" => Ok(Compression::UNCOMPRESSED),
+        "snappy" => Ok(Compression::SNAPPY),
+        "gzip" => Ok(Compression::GZIP),
+        "lz4" => Ok(Compression::LZ4),
+        "zstd" => Ok(Compression::ZSTD),
+        "brotli" => Ok(Compression::BROTLI),
+        _ => Err(DataFusionError::Plan(format!(
+            "Compression codec {} is not supported.",
+            value
+        ))),
+    }
+}
+
+/// Information about a data file produced by the writer
+#[derive(Debug, Clone)]
+pub struct WrittenFile {
+    /// Path of the file in the object store
+    pub path: String,
+    /// Size of the file in bytes
+    pub file_size_in_bytes: usize,
+    /// Number of rows in the file
+    pub record_count: usize,
+    /// Values of the partition fields of the partition spec the file was written with. Empty without a partition spec.
+    pub partition_values: Vec<Option<Value>>,
+    /// Metrics of the top level columns of the file
+    pub columns: Vec<ColumnMetrics>,
+}
+
+/// Metrics of a column of a written data file
+#[derive(Debug, Clone)]
+pub struct ColumnMetrics {
+    /// Name of the column
+    pub name: String,
+    /// Number of values including nulls
+    pub value_count: usize,
+    pub null_count: usize,
+    /// Smallest value of the column. None if the column only contains nulls or its type has no bounds.
+    pub lower_bound: Option<ScalarValue>,
+    /// Largest value of the column
+    pub upper_bound: Option<ScalarValue>,
+}
+
+/// Write the record batches of the stream into parquet files in the data directory of the table location.
+pub async fn write_parquet(
+    location: &str,
+    batches: SendableRecordBatchStream,
+    object_store: &Arc<dyn ObjectStore>,
+    config: &WriterConfig,
+) -> Result<Vec<WrittenFile>> {
+    let directory = location.trim_end_matches('/').to_owned() + "/data";
+    let mut progress = ProgressTracker::new(config.progress.clone(), None);
+    if !config.partition_spec.is_empty() {
+        write_partitioned(&directory, batches, object_store, config, &mut progress).await
+    } else if config.partition_columns.is_empty() {
+        write_parquet_to_directory(&directory, batches, object_store, config, &mut progress).await
+    } else {
+        let partition_columns: Vec<&str> = config
+            .partition_columns
+            .iter()
+            .map(|column| column.as_str())
+            .collect();
+        let projection: Vec<usize> = (0..batches.schema().fields().len()).collect();
+        write_hive_partitioned(
+            &directory,
+            batches,
+            object_store,
+            config,
+            &partition_columns,
+            &projection,
+            &mut progress,
+        )
+        .await
+    }
+}
+
+/// Metrics of an existing parquet file that is added to a table without rewriting it. The metrics are read from the
+/// statistics of the row groups in the footer, so only the footer is downloaded. Nested columns and columns without
+/// statistics in every row group get no metrics.
+pub(crate) async fn file_metrics(
+    object_store: &Arc<dyn ObjectStore>,
+    file: &ObjectMeta,
+) -> Result<WrittenFile> {
+    let metadata = fetch_parquet_metadata(object_store.as_ref(), file, None).await?;
+    let file_metadata = metadata.file_metadata();
+    let schema = parquet_to_arrow_schema(
+        file_metadata.schema_descr(),
+        file_metadata.key_value_metadata(),
+    )?;
+    let record_count = file_metadata.num_rows() as usize;
+    let columns = file_metadata
+        .schema_descr()
+        .columns()
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| column.path().parts().len() == 1)
+        .filter_map(|(index, column)| {
+            let field = schema.field_with_name(column.name()).ok()?;
+            footer_metrics(
+                &metadata,
+                index,
+                field.name(),
+                field.data_type(),
+                record_count,
+            )
+        })
+        .collect();
+    Ok(WrittenFile {
+        path: file.location.to_string(),
+        file_size_in_bytes: file.size,
+        record_count,
+        partition_values: Vec::new(),
+        columns,
+    })
+}
+
+/// Metrics of the leaf column of a top level field from the statistics of all row groups
+fn footer_metrics(
+    metadata: &ParquetMetaData,
+    index: usize,
+    name: &str,
+    datatype: &DataType,
+    value_count: usize,
+) -> Option<ColumnMetrics> {
+    let mut null_count = 0;
+    let mut bounds = has_bounds(datatype).then_some((None, None));
+    for row_group in metadata.row_groups() {
+        let statistics = row_group.column(index).statistics()?;
+        null_count += statistics.null_count() as usize;
+        if let Some((lower, upper)) = &mut bounds {
+            match statistics_bounds(statistics, datatype) {
+                Some((min, max)) => {
+                    merge_bound(lower, min, Ordering::Less);
+                    merge_bound(upper, max, Ordering::Greater);
+                }
+                // Row groups without values don't have bounds
+                None if statistics.null_count() as usize == row_group.num_rows() as usize => (),
+                None => bounds = None,
+            }
+        }
+    }
+    let (lower_bound, upper_bound) = bounds.unwrap_or_default();
+    Some(ColumnMetrics {
+        name: name.to_owned(),
+        value_count,
+        null_count,
+        lower_bound,
+        upper_bound,
+    })
+}
+
+/// Smallest and largest value of the row group statistics, None if the statistics have no valid bounds
+fn statistics_bounds(
+    statistics: &Statistics,
+    datatype: &DataType,
+) -> Option<(ScalarValue, ScalarValue)> {
+    if !statistics.has_min_max_set() {
+        return None;
+    }
+    let bounds = match (statistics, datatype) {
+        (Statistics::Boolean(s), DataType::Boolean) => (
+            ScalarValue::Boolean(Some(*s.min())),
+            ScalarValue::Boolean(Some(*s.max())),
+        ),
+        (Statistics::Int32(s), DataType::Int32) => (
+            ScalarValue::Int32(Some(*s.min())),
+            ScalarValue::Int32(Some(*s.max())),
+        ),
+        (Statistics::Int32(s), DataType::Date32) => (
+            ScalarValue::Date32(Some(*s.min())),
+            ScalarValue::Date32(Some(*s.max())),
+        ),
+        (Statistics::Int64(s), DataType::Int64) => (
+            ScalarValue::Int64(Some(*s.min())),
+            ScalarValue::Int64(Some(*s.max())),
+        ),
+        (Statistics::Int64(s), DataType::Timestamp(TimeUnit::Microsecond, tz)) => (
+            ScalarValue::TimestampMicrosecond(Some(*s.min()), tz.clone()),
+            ScalarValue::TimestampMicrosecond(Some(*s.max()), tz.clone()),
+        ),
+        (Statistics::Float(s), DataType::Float32) => (
+            ScalarValue::Float32(Some(*s.min())),
+            ScalarValue::Float32(Some(*s.max())),
+        ),
+        (Statistics::Double(s), DataType::Float64) => (
+            ScalarValue::Float64(Some(*s.min())),
+            ScalarValue::Float64(Some(*s.max())),
+        ),
+        // Old writers compared strings as signed bytes
+        (Statistics::ByteArray(s), DataType::Utf8) if !statistics.is_min_max_deprecated() => (
+            ScalarValue::Utf8(Some(s.min().as_utf8().ok()?.to_owned())),
+            ScalarValue::Utf8(Some(s.max().as_utf8().ok()?.to_owned())),
+        ),
+        _ => return None,
+    };
+    // Bounds must not be NaN
+    match bounds {
+        (ScalarValue::Float32(Some(min)), ScalarValue::Float32(Some(max)))
+            if min.is_nan() || max.is_nan() =>
+        {
+            None
+        }
+        (ScalarValue::Float64(Some(min)), ScalarValue::Float64(Some(max)))
+            if min.is_nan() || max.is_nan() =>
+        {
+            None
+        }
+        bounds => Some(bounds),
+    }
+}
+
+/// Keep the value if it is smaller, or larger, than the bound
+fn merge_bound(bound: &mut Option<ScalarValue>, value: ScalarValue, ordering: Ordering) {
+    match bound {
+        Some(current) if value.partial_cmp(current) != Some(ordering) => (),
+        _ => *bound = Some(value),
+    }
+}
+
+/// Whether iceberg keeps bounds for columns of the type
+fn has_bounds(datatype: &DataType) -> bool {
+    matches!(
+        datatype,
+        DataType::Boolean
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::Utf8
+            | DataType::Date32
+            | DataType::Timestamp(TimeUnit::Microsecond, _)
+    )
+}
+
+/// Write the record batches of the stream into parquet files directly inside of the given directory.
+/// Required columns are checked for null values before a batch is written.
+pub(crate) async fn write_parquet_to_directory(
+    directory: &str,
+    mut batches: SendableRecordBatchStream,
+    object_store: &Arc<dyn ObjectStore>,
+    config: &WriterConfig,
+    progress: &mut ProgressTracker,
+) -> Result<Vec<WrittenFile>> {
+    let schema = batches.schema();
+    let required = required_columns(&schema, config)?;
+    let mut writer = BatchWriter::new(
+        directory.to_owned(),
+        object_store,
+        config,
+        schema.clone(),
+        Vec::new(),
+    );
+    let mut rows_written = 0;
+
+    while let Some(batch) = batches.next().await {
+        let batch = batch?;
+        validate_required(&batch, &required, rows_written)?;
+        rows_written += batch.num_rows();
+        writer.push(batch, progress).await?;
+    }
+    writer.finish(progress).await
+}
+
+/// Write the record batches of the stream into one directory per combination of values of the partition columns.
+/// The files contain the columns of the projection.
+pub(crate) async fn write_hive_partitioned(
+    directory: &str,
+    mut batches: SendableRecordBatchStream,
+    object_store: &Arc<dyn ObjectStore>,
+    config: &WriterConfig,
+    partition_columns: &[&str],
+    projection: &[usize],
+    progress: &mut ProgressTracker,
+) -> Result<Vec<WrittenFile>> {
+    let schema = batches.schema();
+    let partition_ids = partition_columns
+        .iter()
+        .map(|column| schema.index_of(column))
+        .collect::<std::result::Result<Vec<usize>, _>>()?;
+    let file_schema: SchemaRef = Arc::new(schema.project(projection)?);
+    let required = required_columns(&schema, config)?;
+    let mut writers: HashMap<String, BatchWriter> = HashMap::new();
+    let mut rows_written = 0;
+
+    while let Some(batch) = batches.next().await {
+        let batch = batch?;
+        validate_required(&batch, &required, rows_written)?;
+        rows_written += batch.num_rows();
+        for (partition, batch) in split_batch(
+            &batch,
+            &partition_ids,
+            partition_columns,
+            projection,
+            &file_schema,
+        )? {
+            let writer = writers.entry(partition.clone()).or_insert_with(|| {
+                let directory = if partition.is_empty() {
+                    directory.to_owned()
+                } else {
+                    directory.to_owned() + "/" + &partition
+                };
+                BatchWriter::new(
+                    directory,
+                    object_store,
+                    config,
+                    file_schema.clone(),
+                    Vec::new(),
+                )
+            });
+            writer.push(batch, progress).await?;
+        }
+    }
+    let mut files = Vec::new();
+    for (_, writer) in writers {
+        files.extend(writer.finish(progress).await?);
+    }
+    Ok(files)
+}
+
+/// Write the record batches of the stream into one directory per partition of the partition spec
+async fn write_partitioned(
+    directory: &str,
+    mut batches: SendableRecordBatchStream,
+    object_store: &Arc<dyn ObjectStore>,
+    config: &WriterConfig,
+    progress: &mut ProgressTracker,
+) -> Result<Vec<WrittenFile>> {
+    let schema = batches.schema();
+    let required = required_columns(&schema, config)?;
+    let mut writers: HashMap<Vec<ScalarValue>, BatchWriter> = HashMap::new();
+    let mut rows_written = 0;
+
+    while let Some(batch) = batches.next().await {
+        let batch = batch?;
+        validate_required(&batch, &required, rows_written)?;
+        rows_written += batch.num_rows();
+        for (values, batch) in partition::split(&batch, &config.partition_spec)? {
+            let partition_values = values
+                .iter()
+                .map(partition::to_value)
+                .collect::<Result<Vec<_>>>()?;
+            let path =
+                directory.to_owned() + "/" + &partition::path(&config.partition_spec, &values);
+            let writer = writers.entry(values).or_insert_with(|| {
+                BatchWriter::new(path, object_store, config, schema.clone(), partition_values)
+            });
+            writer.push(batch, progress).await?;
+        }
+    }
+    let mut files = Vec::new();
+    for (_, writer) in writers {
+        files.extend(writer.finish(progress).await?);
+    }
+    Ok(files)
+}
+
+/// Coalesces the batches before writing them
+struct BatchWriter<'a> {
+    writer: RollingWriter<'a>,
+    pending: Vec<RecordBatch>,
+    pending_rows: usize,
+}
+
+impl<'a> BatchWriter<'a> {
+    fn new(
+        directory: String,
+        object_store: &'a Arc<dyn ObjectStore>,
+        config: &'a WriterConfig,
+        schema: SchemaRef,
+        partition_values: Vec<Option<Value>>,
+    ) -> Self {
+        BatchWriter {
+            writer: RollingWriter {
+                directory,
+                object_store,
+                config,
+                schema,
+                partition_values,
+                operation_id: Uuid::new_v4(),
+                current: None,
+                files: Vec::new(),
+            },
+            pending: Vec::new(),
+            pending_rows: 0,
+        }
+    }
+    async fn push(&mut self, batch: RecordBatch, progress: &mut ProgressTracker) -> Result<()> {
+        self.pending_rows += batch.num_rows();
+        self.pending.push(batch);
+        if self.pending_rows >= self.writer.config.batch_size {
+            let batch = coalesce(&self.writer.schema, &mut self.pending)?;
+            self.writer.write(&batch, progress).await?;
+            self.pending_rows = 0;
+        }
+        Ok(())
+    }
+    async fn finish(mut self, progress: &mut ProgressTracker) -> Result<Vec<WrittenFile>> {
+        if !self.pending.is_empty() {
+            let batch = coalesce(&self.writer.schema, &mut self.pending)?;
+            self.writer.write(&batch, progress).await?;
+        }
+        self.writer.finish(progress).await
+    }
+}
+
+/// Concatenate the pending batches into one batch
+fn coalesce(schema: &SchemaRef, pending: &mut Vec<RecordBatch>) -> Result<RecordBatch> {
+    let batch = if pending.len() == 1 {
+        pending.remove(0)
+    } else {
+        concat_batches(schema, pending.as_slice())?
+    };
+    pending.clear();
+    Ok(batch)
+}
+
+struct RollingWriter<'a> {
+    directory: String,
+    object_store: &'a Arc<dyn ObjectStore>,
+    config: &'a WriterConfig,
+    schema: SchemaRef,
+    // Partition values of all files of the writer
+    partition_values: Vec<Option<Value>>,
+    // File names follow the iceberg convention "{task id}-{operation id}-{file count}" so that files of concurrent
+    // writers never collide.
+    operation_id: Uuid,
+    current: Option<OpenFile>,
+    files: Vec<WrittenFile>,
+}
+
+impl<'a> RollingWriter<'a> {
+    async fn write(&mut self, batch: &RecordBatch, progress: &mut ProgressTracker) -> Result<()> {
+        let mut file = match self.current.take() {
+            Some(file) => file,
+            None => {
+                let name = format!(
+                    "{:05}-{}-{:05}.parquet",
+                    self.config.task_id,
+                    self.operation_id,
+                    self.files.len()
+                );
+                OpenFile::try_new(
+                    self.directory.clone() + "/" + &name,
+                    self.schema.clone(),
+                    self.config,
+                    self.object_store,
+                )
+                .await?
+            }
+        };
+        if let Err(err) = file.write(batch, self.config).await {
+            file.abort(self.object_store).await;
+            return Err(err);
+        }
+        // Bytes are only written when a row group is flushed, so the file size lags behind by at most one row group.
+        if file.size() >= self.config.target_file_size {
+            let file = file
+                .finish(self.object_store, self.partition_values.clone())
+                .await?;
+            progress.file_done(file.file_size_in_bytes, file.record_count)?;
+            self.files.push(file);
+        } else {
+            self.current = Some(file);
+        }
+        Ok(())
+    }
+    async fn finish(mut self, progress: &mut ProgressTracker) -> Result<Vec<WrittenFile>> {
+        if let Some(file) = self.current.take() {
+            let file = file
+                .finish(self.object_store, self.partition_values.clone())
+                .await?;
+            progress.file_done(file.file_size_in_bytes, file.record_count)?;
+            self.files.push(file);
+        }
+        Ok(self.files)
+    }
+}
+
+/// Column of the input that must not contain null values
+struct RequiredColumn {
+    index: usize,
+    name: String,
+    field_id: Option<i32>,
+}
+
+/// Columns of the input for the required fields of the table schema, or the columns that aren't nullable if the table
+/// schema is unknown
+fn required_columns(schema: &SchemaRef, config: &WriterConfig) -> Result<Vec<RequiredColumn>> {
+    match &config.required_fields {
+        Some(fields) => fields
+            .iter()
+            .map(|(id, name)| {
+                let index = schema.index_of(name).map_err(|_| {
+                    DataFusionError::Plan(format!(
+                        "Required field {} with id {} is missing in the input.",
+                        name, id
+                    ))
+                })?;
+                Ok(RequiredColumn {
+                    index,
+                    name: name.clone(),
+                    field_id: Some(*id),
+                })
+            })
+            .collect(),
+        None => Ok(schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| !field.is_nullable())
+            .map(|(index, field)| RequiredColumn {
+                index,
+                name: field.name().clone(),
+                field_id: None,
+            })
+            .collect()),
+    }
+}
+
+/// Fail if a required column contains null values. Other engines reject files with nulls in required columns.
+fn validate_required(
+    batch: &RecordBatch,
+    required: &[RequiredColumn],
+    offset: usize,
+) -> Result<()> {
+    for column in required {
+        let array = batch.column(column.index);
+        if array.null_count() == 0 {
+            continue;
+        }
+        let first = (0..array.len())
+            .find(|row| array.is_null(*row))
+            .unwrap_or_default();
+        let name = match column.field_id {
+            Some(id) => format!("{} with field id {}", column.name, id),
+            None => column.name.clone(),
+        };
+        return Err(DataFusionError::Execution(format!(
+            "Column {} is required but contains {} null values in the batch at row {} of the input, the first null is in row {}.",
+            name,
+            array.null_count(),
+            offset,
+            offset + first
+        )));
+    }
+    Ok(())
+}
+
+struct OpenFile {
+    path: Path,
+    writer: ArrowWriter<SharedBuffer>,
+    // Bytes of the flushed row groups that are not uploaded yet
+    buffer: SharedBuffer,
+    upload: Box<dyn AsyncWrite + Unpin + Send>,
+    multipart_id: MultipartId,
+    bytes_uploaded: usize,
+    // Size in memory of the rows of the row group that is being written
+    row_group_size: usize,
+    record_count: usize,
+    metrics: Vec<MetricsAccumulator>,
+}
+
+impl OpenFile {
+    /// Start the upload of a new file with the columns of the schema. The field ids of the schema are not written.
+    async fn try_new(
+        path: String,
+        schema: SchemaRef,
+        config: &WriterConfig,
+        object_store: &Arc<dyn ObjectStore>,
+    ) -> Result<Self> {
+        let path = object_path(&path);
+        let buffer = SharedBuffer::default();
+        let metrics = schema
+            .fields()
+            .iter()
+            .map(|field| MetricsAccumulator::new(field.name(), field.data_type()))
+            .collect();
+        let writer = ArrowWriter::try_new(
+            buffer.clone(),
+            schema,
+            Some(config.writer_properties().build()),
+        )?;
+        let (multipart_id, upload) = object_store.put_multipart(&path).await?;
+        Ok(OpenFile {
+            path,
+            writer,
+            buffer,
+            upload,
+            multipart_id,
+            bytes_uploaded: 0,
+            row_group_size: 0,
+            record_count: 0,
+            metrics,
+        })
+    }
+    /// Write the batch and upload the row group once it reaches the row group size
+    async fn write(&mut self, batch: &RecordBatch, config: &WriterConfig) -> Result<()> {
+        self.writer.write(batch)?;
+        self.record_count += batch.num_rows();
+        for (metrics, column) in self.metrics.iter_mut().zip(batch.columns()) {
+            metrics.update(column)?;
+        }
+        self.row_group_size += batch
+            .columns()
+            .iter()
+            .map(|column| column.get_array_memory_size())
+            .sum::<usize>();
+        if self.row_group_size >= config.row_group_size {
+            self.writer.flush()?;
+            self.row_group_size = 0;
+        }
+        self.upload().await
+    }
+    async fn upload(&mut self) -> Result<()> {
+        let bytes = self.buffer.take();
+        if !bytes.is_empty() {
+            self.upload.write_all(&bytes).await?;
+            self.bytes_uploaded += bytes.len();
+        }
+        Ok(())
+    }
+    /// Size of the file without the row group that is being written
+    fn size(&self) -> usize {
+        self.bytes_uploaded + self.buffer.len()
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(path = self.path.as_ref(), rows = self.record_count))
+    )]
+    async fn finish(
+        self,
+        object_store: &Arc<dyn ObjectStore>,
+        partition_values: Vec<Option<Value>>,
+    ) -> Result<WrittenFile> {
+        let OpenFile {
+            path,
+            writer,
+            buffer,
+            mut upload,
+            multipart_id,
+            bytes_uploaded,
+            record_count,
+            metrics,
+            ..
+        } = self;
+        // Closing the writer writes the last row group and the footer
+        let footer = async {
+            writer.close()?;
+            let bytes = buffer.take();
+            upload.write_all(&bytes).await?;
+            upload.shutdown().await?;
+            Ok::<_, DataFusionError>(bytes.len())
+        }
+        .await;
+        match footer {
+            Ok(footer) => Ok(WrittenFile {
+                path: path.to_string(),
+                file_size_in_bytes: bytes_uploaded + footer,
+                record_count,
+                partition_values,
+                columns: metrics
+                    .into_iter()
+                    .map(MetricsAccumulator::evaluate)
+                    .collect::<Result<_>>()?,
+            }),
+            Err(err) => {
+                let _ = object_store.abort_multipart(&path, &multipart_id).await;
+                Err(err)
+            }
+        }
+    }
+    /// Remove the parts that were uploaded. The error of the upload is reported instead of the error of the abort.
+    async fn abort(self, object_store: &Arc<dyn ObjectStore>) {
+        let _ = object_store
+            .abort_multipart(&self.path, &self.multipart_id)
+            .await;
+    }
+}
+
+/// Collects the metrics of a column while it is written
+struct MetricsAccumulator {
+    name: String,
+    value_count: usize,
+    null_count: usize,
+    // Columns without bounds in iceberg don't have accumulators
+    bounds: Option<(MinAccumulator, MaxAccumulator)>,
+}
+
+impl MetricsAccumulator {
+    fn new(name: &str, datatype: &DataType) -> Self {
+        let bounds = match has_bounds(datatype) {
+            true => MinAccumulator::try_new(datatype)
+                .and_then(|min| Ok((min, MaxAccumulator::try_new(datatype)?)))
+                .ok(),
+            false => None,
+        };
+        MetricsAccumulator {
+            name: name.to_owned(),
+            value_count: 0,
+            null_count: 0,
+            bounds,
+        }
+    }
+    fn update(&mut self, column: &Arc<dyn Array>) -> Result<()> {
+        self.value_count += column.len();
+        self.null_count += column.null_count();
+        if let Some((min, max)) = &mut self.bounds {
+            min.update_batch(std::slice::from_ref(column))?;
+            max.update_batch(std::slice::from_ref(column))?;
+        }
+        Ok(())
+    }
+    fn evaluate(self) -> Result<ColumnMetrics> {
+        // Bounds must not be NaN
+        let bound = |value: ScalarValue| match value {
+            ScalarValue::Float32(Some(v)) if v.is_nan() => None,
+            ScalarValue::Float64(Some(v)) if v.is_nan() => None,
+            value if value.is_null() => None,
+            value => Some(value),
+        };
+        let (lower_bound, upper_bound) = match self.bounds {
+            Some((min, max)) => (bound(min.evaluate()?), bound(max.evaluate()?)),
+            None => (None, None),
+        };
+        Ok(ColumnMetrics {
+            name: self.name,
+            value_count: self.value_count,
+            null_count: self.null_count,
+            lower_bound,
+            upper_bound,
+        })
+    }
+}
+
+/// Buffer that is shared between the parquet writer and the file to keep track of the bytes written so far.
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+    pub(crate) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Write::write(&mut *self.0.lock().unwrap(), buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::{
+        arrow::{
+            array::{Float64Array, Int64Array, StringArray},
+            datatypes::{DataType, Field, Schema},
+            record_batch::RecordBatch,
+        },
+        parquet::file::reader::{FileReader, SerializedFileReader},
+        physical_plan::{memory::MemoryExec, ExecutionPlan},
+        prelude::SessionContext,
+    };
+    use iceberg_rs::model::data_types::{PrimitiveType, StructField, Type};
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_rolling_writer() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batches = (0..10)
+            .map(|i| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![Arc::new(Int64Array::from_iter_values(
+                        i * 1000..(i + 1) * 1000,
+                    ))],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let exec = MemoryExec::try_new(&[batches], schema, None).unwrap();
+        let stream = exec
+            .execute(0, SessionContext::new().task_ctx())
+            .expect("Failed to execute memory plan.");
+
+        let config = WriterConfig::default()
+            .with_target_file_size(1)
+            .with_row_group_size(1000)
+            .with_batch_size(1000)
+            .with_compression(Compression::UNCOMPRESSED)
+            .with_task_id(3);
+
+        let files = write_parquet("test/table", stream, &object_store, &config)
+            .await
+            .expect("Failed to write parquet files.");
+
+        assert_eq!(files.len(), 10);
+        assert_eq!(
+            files.iter().map(|file| file.record_count).sum::<usize>(),
+            10000
+        );
+        for file in files {
+            assert!(file.path.starts_with("test/table/data/00003-"));
+            let meta = object_store
+                .head(&Path::from(file.path.as_str()))
+                .await
+                .unwrap();
+            assert_eq!(meta.size, file.file_size_in_bytes);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_row_group_size() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batches = (0..10)
+            .map(|i| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![Arc::new(Int64Array::from_iter_values(
+                        i * 1000..(i + 1) * 1000,
+                    ))],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let exec = MemoryExec::try_new(&[batches], schema, None).unwrap();
+        let stream = exec
+            .execute(0, SessionContext::new().task_ctx())
+            .expect("Failed to execute memory plan.");
+
+        // Every batch takes a bit more than 8000 bytes in memory, so a row group holds two batches
+        let config = WriterConfig::default()
+            .with_row_group_size(15000)
+            .with_batch_size(1000);
+
+        let files = write_parquet("test/table", stream, &object_store, &config)
+            .await
+            .expect("Failed to write parquet files.");
+
+        assert_eq!(files.len(), 1);
+        let bytes = object_store
+            .get(&Path::from(files[0].path.as_str()))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(bytes.len(), files[0].file_size_in_bytes);
+        let reader = SerializedFileReader::new(bytes).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 5);
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 10000);
+    }
+
+    #[tokio::test]
+    pub async fn test_required_fields() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        // The input is nullable, the table requires the field
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, true)]));
+        let batches = vec![
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int64Array::from_iter_values(0..10))],
+            )
+            .unwrap(),
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int64Array::from(vec![Some(10), None, None]))],
+            )
+            .unwrap(),
+        ];
+        let exec = MemoryExec::try_new(&[batches], schema, None).unwrap();
+        let stream = exec
+            .execute(0, SessionContext::new().task_ctx())
+            .expect("Failed to execute memory plan.");
+
+        let table_schema = StructType {
+            fields: vec![StructField {
+                id: 1,
+                name: "id".to_owned(),
+                required: true,
+                field_type: Type::Primitive(PrimitiveType::Long),
+                doc: None,
+            }],
+        };
+        let config = WriterConfig::default().with_table_schema(&table_schema);
+
+        let err = write_parquet("test/table", stream, &object_store, &config)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Execution error: Column id with field id 1 is required but contains 2 null values in the batch at row 10 \
+             of the input, the first null is in row 11."
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_hive_partitioned_paths() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("vendor_id", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..4)),
+                Arc::new(Int64Array::from(vec![Some(1), Some(2), Some(1), None])),
+            ],
+        )
+        .unwrap();
+        let exec = MemoryExec::try_new(&[vec![batch]], schema, None).unwrap();
+        let stream = exec
+            .execute(0, SessionContext::new().task_ctx())
+            .expect("Failed to execute memory plan.");
+
+        let config = WriterConfig::default().with_hive_partitioning(vec!["vendor_id".to_owned()]);
+
+        let mut files = write_parquet("test/table", stream, &object_store, &config)
+            .await
+            .expect("Failed to write parquet files.");
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(files.len(), 3);
+        assert!(files[0].path.starts_with("test/table/data/vendor_id=1/"));
+        assert_eq!(files[0].record_count, 2);
+        assert!(files[1].path.starts_with("test/table/data/vendor_id=2/"));
+        assert!(files[2]
+            .path
+            .starts_with("test/table/data/vendor_id=__HIVE_DEFAULT_PARTITION__/"));
+    }
+
+    #[tokio::test]
+    pub async fn test_coalesce_batches() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batches = (0..100)
+            .map(|i| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![Arc::new(Int64Array::from_iter_values(i * 10..(i + 1) * 10))],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let exec = MemoryExec::try_new(&[batches], schema, None).unwrap();
+        let stream = exec
+            .execute(0, SessionContext::new().task_ctx())
+            .expect("Failed to execute memory plan.");
+
+        // Every written batch starts a new file, so the files show the size of the coalesced batches
+        let config = WriterConfig::default()
+            .with_target_file_size(1)
+            .with_batch_size(500);
+
+        let files = write_parquet("test/table", stream, &object_store, &config)
+            .await
+            .expect("Failed to write parquet files.");
+
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|file| file.record_count == 500));
+    }
+
+    #[tokio::test]
+    pub async fn test_file_metrics_from_footer() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("score", DataType::Float64, true),
+        ]));
+        let batches = (0..4)
+            .map(|i| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![
+                        Arc::new(Int64Array::from_iter_values(i * 10..(i + 1) * 10)),
+                        Arc::new(StringArray::from_iter(
+                            (0..10).map(|j| (j % 3 != 0).then(|| format!("name {}", i * 10 + j))),
+                        )),
+                        // The scores of the last batch are null
+                        Arc::new(Float64Array::from_iter(
+                            (0..10).map(|j| (i < 3).then_some((i * 10 + j) as f64 / 2.0)),
+                        )),
+                    ],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let exec = MemoryExec::try_new(&[batches], schema, None).unwrap();
+        let stream = exec.execute(0, SessionContext::new().task_ctx()).unwrap();
+        let config = WriterConfig::default()
+            .with_row_group_size(1)
+            .with_batch_size(10);
+        let written = write_parquet("test/table", stream, &object_store, &config)
+            .await
+            .unwrap()
+            .remove(0);
+
+        let meta = object_store
+            .head(&Path::from(written.path.as_str()))
+            .await
+            .unwrap();
+        let file = file_metrics(&object_store, &meta).await.unwrap();
+        assert_eq!(file.record_count, written.record_count);
+        assert_eq!(file.columns.len(), written.columns.len());
+        for (footer, written) in file.columns.iter().zip(&written.columns) {
+            assert_eq!(footer.name, written.name);
+            assert_eq!(footer.value_count, written.value_count);
+            assert_eq!(footer.null_count, written.null_count);
+            assert_eq!(footer.lower_bound, written.lower_bound);
+            assert_eq!(footer.upper_bound, written.upper_bound);
+        }
+    }
+}
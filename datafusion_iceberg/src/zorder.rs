@@ -0,0 +1,259 @@
+/*!
+ * Z-order of the rows of record batches
+ *
+ * The z-value of a row interleaves the bits of its column values, so that rows that are close in all of the columns
+ * get close z-values. Sorting by the z-value clusters the rows in all columns at once, files that are written in that
+ * order cover small ranges of every column and can be pruned by filters on any of them. Every value is mapped to 64
+ * bits that sort like the value: integers and timestamps by their number, floats by their total order and strings and
+ * binary values by their first eight bytes. Nulls sort like the smallest value.
+*/
+
+use std::{any::Any, fmt::Display, sync::Arc};
+
+use datafusion::{
+    arrow::{
+        array::{
+            Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, StringArray,
+            UInt64Array,
+        },
+        compute::cast,
+        datatypes::{DataType, Schema},
+        record_batch::RecordBatch,
+    },
+    common::DataFusionError,
+    error::Result,
+    logical_expr::ColumnarValue,
+    physical_plan::PhysicalExpr,
+};
+
+/// Z-value of the rows as binary value of eight bytes per column
+#[derive(Debug)]
+pub(crate) struct ZOrderExpr {
+    columns: Vec<Arc<dyn PhysicalExpr>>,
+}
+
+impl ZOrderExpr {
+    pub(crate) fn new(columns: Vec<Arc<dyn PhysicalExpr>>) -> Self {
+        ZOrderExpr { columns }
+    }
+}
+
+impl Display for ZOrderExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| column.to_string())
+            .collect();
+        write!(f, "zorder({})", columns.join(", "))
+    }
+}
+
+impl PartialEq<dyn Any> for ZOrderExpr {
+    fn eq(&self, other: &dyn Any) -> bool {
+        let other = match other.downcast_ref::<Arc<dyn PhysicalExpr>>() {
+            Some(other) => other.as_any(),
+            None => other,
+        };
+        other
+            .downcast_ref::<ZOrderExpr>()
+            .map(|other| {
+                self.columns.len() == other.columns.len()
+                    && self
+                        .columns
+                        .iter()
+                        .zip(&other.columns)
+                        .all(|(left, right)| left.eq(right.as_any()))
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl PhysicalExpr for ZOrderExpr {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::Binary)
+    }
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(false)
+    }
+    fn evaluate(&self, batch: &RecordBatch) -> Result<ColumnarValue> {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| ordered_bits(&column.evaluate(batch)?.into_array(batch.num_rows())))
+            .collect::<Result<Vec<_>>>()?;
+        let values = (0..batch.num_rows()).map(|row| {
+            let values: Vec<u64> = columns.iter().map(|column| column[row]).collect();
+            interleave(&values)
+        });
+        Ok(ColumnarValue::Array(Arc::new(
+            BinaryArray::from_iter_values(values),
+        )))
+    }
+    fn children(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        self.columns.clone()
+    }
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn PhysicalExpr>>,
+    ) -> Result<Arc<dyn PhysicalExpr>> {
+        Ok(Arc::new(ZOrderExpr::new(children)))
+    }
+}
+
+/// Whether the values of the type can be mapped to bits that sort like them
+pub(crate) fn is_supported(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Boolean
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::Date32
+            | DataType::Date64
+            | DataType::Time64(_)
+            | DataType::Timestamp(_, _)
+            | DataType::Utf8
+            | DataType::LargeUtf8
+            | DataType::Binary
+            | DataType::LargeBinary
+    )
+}
+
+/// Values of the array as bits that sort like the values, nulls are zero
+fn ordered_bits(array: &ArrayRef) -> Result<Vec<u64>> {
+    let nulls = |array: &dyn Array, bits: &dyn Fn(usize) -> u64| -> Vec<u64> {
+        (0..array.len())
+            .map(|row| match array.is_null(row) {
+                true => 0,
+                false => bits(row),
+            })
+            .collect()
+    };
+    Ok(match array.data_type() {
+        DataType::Boolean => {
+            let array = array.as_any().downcast_ref::<BooleanArray>().unwrap();
+            nulls(array, &|row| array.value(row) as u64)
+        }
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+            let array = cast(array, &DataType::UInt64)?;
+            let array = array.as_any().downcast_ref::<UInt64Array>().unwrap();
+            nulls(array, &|row| array.value(row))
+        }
+        DataType::Float32 | DataType::Float64 => {
+            let array = cast(array, &DataType::Float64)?;
+            let array = array.as_any().downcast_ref::<Float64Array>().unwrap();
+            nulls(array, &|row| {
+                // Negative floats sort in reverse order of their bits
+                let bits = array.value(row).to_bits();
+                match bits >> 63 {
+                    1 => !bits,
+                    _ => bits | 1 << 63,
+                }
+            })
+        }
+        DataType::Utf8 | DataType::LargeUtf8 => {
+            let array = cast(array, &DataType::Utf8)?;
+            let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+            nulls(array, &|row| prefix(array.value(row).as_bytes()))
+        }
+        DataType::Binary | DataType::LargeBinary => {
+            let array = cast(array, &DataType::Binary)?;
+            let array = array.as_any().downcast_ref::<BinaryArray>().unwrap();
+            nulls(array, &|row| prefix(array.value(row)))
+        }
+        data_type if is_supported(data_type) => {
+            let array = cast(array, &DataType::Int64)?;
+            let array = array.as_any().downcast_ref::<Int64Array>().unwrap();
+            nulls(array, &|row| (array.value(row) as u64) ^ 1 << 63)
+        }
+        data_type => {
+            return Err(DataFusionError::Plan(format!(
+                "Columns of type {} can't be z-ordered.",
+                data_type
+            )))
+        }
+    })
+}
+
+// Values that only differ after the first eight bytes get the same bits. Values shorter than eight bytes are padded,
+// which keeps them before the values that continue them.
+fn prefix(value: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    let length = value.len().min(8);
+    bytes[..length].copy_from_slice(&value[..length]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Bits of the values from the most to the least significant bit, the first value comes first at every position
+fn interleave(values: &[u64]) -> Vec<u8> {
+    let mut bytes = vec![0u8; values.len() * 8];
+    let mut position = 0;
+    for bit in (0..64).rev() {
+        for value in values {
+            if value >> bit & 1 == 1 {
+                bytes[position / 8] |= 0x80 >> (position % 8);
+            }
+            position += 1;
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion::{
+        arrow::{
+            compute::sort_to_indices,
+            datatypes::{Field, Schema},
+        },
+        physical_plan::expressions::Column,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_zorder() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("x", DataType::Int64, true),
+            Field::new("y", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(vec![
+                    Some(1),
+                    Some(0),
+                    Some(1),
+                    Some(0),
+                    Some(2),
+                    Some(0),
+                    Some(-1),
+                    None,
+                ])),
+                Arc::new(Int64Array::from(vec![0, 2, 1, 0, 0, 1, 0, 0])),
+            ],
+        )
+        .unwrap();
+        let expr = ZOrderExpr::new(vec![
+            Arc::new(Column::new("x", 0)),
+            Arc::new(Column::new("y", 1)),
+        ]);
+        let zvalues = expr.evaluate(&batch).unwrap().into_array(batch.num_rows());
+        let order = sort_to_indices(&zvalues, None, None).unwrap();
+        // Nulls and negative values come first. The other rows follow the z-curve, which visits (0, 0), (0, 1),
+        // (1, 0) and (1, 1) before it continues with (0, 2) and (2, 0).
+        assert_eq!(order.values(), &[7, 6, 3, 5, 0, 2, 1, 4]);
+    }
+}
@@ -1,23 +1,189 @@
-use std::{any::Any, sync::Arc};
+use std::{any::Any, sync::Arc, time::Duration};
 
 use datafusion::{
     catalog::{catalog::CatalogProvider, schema::SchemaProvider},
-    error::Result,
+    datasource::TableProvider,
+    error::{DataFusionError, Result},
+    execution::context::SessionState,
+    prelude::SessionConfig,
+    scalar::ScalarValue,
 };
-use iceberg_rs::catalog::{namespace::Namespace, Catalog};
+use iceberg_rs::catalog::{identifier::Identifier, namespace::Namespace, Catalog};
 
-use crate::{mirror::Mirror, schema::IcebergSchema};
+use crate::{
+    events::CatalogListener,
+    mirror::Mirror,
+    schema::{resolve, IcebergSchema},
+};
+
+/// Snapshot that a table is pinned to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotRef {
+    /// The snapshot that is current when the table is registered
+    Current,
+    /// The snapshot with the id. It has to be the current snapshot when the table is registered.
+    Snapshot(i64),
+}
 
+/// Session option that makes the catalogs registered with the session resolve identifiers case insensitively. It is
+/// read when the catalog is registered, so it has to be set on the session config with `set_bool` beforehand.
+pub const CASE_INSENSITIVE_IDENTIFIERS: &str = "iceberg.case_insensitive_identifiers";
+
+/// Separator of the levels of nested namespaces in schema names
+const DEFAULT_NAMESPACE_SEPARATOR: &str = ".";
+
+/// Datafusion catalog of the namespaces and tables of an iceberg catalog
+///
+/// Every namespace, including nested ones, is a schema of the catalog. The name of the schema is the levels of the
+/// namespace joined by the namespace separator, by default a dot. Schema names with dots have to be quoted in SQL, so
+/// nested namespaces can be made addressable with a separator that is valid in unquoted identifiers, for example
+/// `__`. With it the table `c` of the namespace `a.b` is queried as `my_catalog.a__b.c`.
+///
+/// Iceberg catalogs are case sensitive, while datafusion lowercases unquoted identifiers. With case insensitive
+/// identifiers a schema or table that doesn't exist with the exact name is resolved to the one whose name only differs
+/// in case, so `SELECT * FROM sales` finds the table `Sales`. Names that match several schemas or tables are not
+/// resolved. Catalogs that are registered through the session helpers take the setting from the
+/// [`CASE_INSENSITIVE_IDENTIFIERS`] option of the session.
+///
+/// The namespaces and tables are loaded when the catalog is created and [`IcebergCatalog::refresh`] reloads them, so
+/// the sync methods of the catalog and its schemas don't wait for the catalog. Catalogs with a ttl synchronize in the
+/// background on the first access after it expired and stale tables are reloaded in the background while their last
+/// metadata is served. Registering and dropping tables is queued, the tables change once the catalog accepted the
+/// change and [`IcebergCatalog::flush`] waits for it.
 pub struct IcebergCatalog {
     catalog: Arc<Mirror>,
+    separator: String,
+    case_insensitive: bool,
 }
 
 impl IcebergCatalog {
     pub async fn new(catalog: Arc<dyn Catalog>) -> Result<Self> {
         Ok(IcebergCatalog {
-            catalog: Arc::new(Mirror::new(catalog).await?),
+            separator: DEFAULT_NAMESPACE_SEPARATOR.to_owned(),
+            case_insensitive: false,
+            catalog: Arc::new(Mirror::new(catalog, None).await?),
+        })
+    }
+    /// Warn when the listings are served from a state that is older than `max_staleness` because the catalog couldn't
+    /// be reached
+    pub async fn new_with_max_staleness(
+        catalog: Arc<dyn Catalog>,
+        max_staleness: Duration,
+    ) -> Result<Self> {
+        Ok(IcebergCatalog {
+            separator: DEFAULT_NAMESPACE_SEPARATOR.to_owned(),
+            case_insensitive: false,
+            catalog: Arc::new(Mirror::new(catalog, Some(max_staleness)).await?),
+        })
+    }
+    /// Synchronize with the catalog on access once the namespaces and tables were loaded longer than `ttl` ago. Tables
+    /// committed by other writers become visible after at most the ttl. The synchronization runs in the background
+    /// and the last state is served meanwhile.
+    pub async fn new_with_ttl(catalog: Arc<dyn Catalog>, ttl: Duration) -> Result<Self> {
+        Ok(IcebergCatalog {
+            separator: DEFAULT_NAMESPACE_SEPARATOR.to_owned(),
+            case_insensitive: false,
+            catalog: Arc::new(Mirror::new(catalog, None).await?.with_ttl(ttl)),
         })
     }
+    /// Import tables that aren't iceberg tables when they are registered, see [`datafusion_iceberg::import`]. They are
+    /// created in the directory of their identifier below the location.
+    pub fn with_warehouse(self, location: &str) -> Self {
+        self.catalog.set_warehouse(location);
+        self
+    }
+    /// Join the levels of nested namespaces with the separator to get the schema names
+    pub fn with_namespace_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_owned();
+        self
+    }
+    /// Resolve schema and table names that only differ in case from the names in the catalog
+    pub fn with_case_insensitive_identifiers(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+    /// Apply the iceberg options of the session config and read the tables that are imported with the session
+    pub fn with_session_state(self, state: &SessionState) -> Self {
+        self.catalog.set_session(state);
+        self.with_session_config(&state.config)
+    }
+    /// Apply the iceberg options of the session config that configure the catalog
+    pub fn with_session_config(self, config: &SessionConfig) -> Self {
+        match config
+            .config_options()
+            .read()
+            .get(CASE_INSENSITIVE_IDENTIFIERS)
+        {
+            Some(ScalarValue::Boolean(Some(true))) => self.with_case_insensitive_identifiers(),
+            _ => self,
+        }
+    }
+    /// Names of the schemas of the namespaces directly below the namespace of the schema
+    pub fn child_schema_names(&self, parent: &str) -> Result<Vec<String>> {
+        let parent = self.namespace(parent)?;
+        Ok(self
+            .catalog
+            .schema_names(Some(&parent))?
+            .iter()
+            .map(|namespace| self.schema_name(namespace))
+            .collect())
+    }
+    /// Load all tables that couldn't be loaded yet or are stale, so that queries read the current metadata
+    pub async fn load_tables(&self) -> Result<()> {
+        self.catalog.load_tables().await
+    }
+    /// Wait until the tables that were registered or dropped through the schemas are changed in the catalog. Returns
+    /// the first error of the changes since the last flush.
+    pub async fn flush(&self) -> Result<()> {
+        self.catalog.flush().await
+    }
+    /// Synchronize the namespaces and tables with the catalog. If the catalog is unreachable the last known state
+    /// is kept.
+    pub async fn refresh(&self) -> Result<()> {
+        self.catalog.refresh().await
+    }
+    /// Reload the metadata of the table on its next access, for example after another writer committed to it
+    pub fn invalidate(&self, identifier: &Identifier) {
+        self.catalog.invalidate(identifier)
+    }
+    /// Serve the table that was just committed through this process, also while the catalog still returns the previous
+    /// metadata
+    pub fn committed(&self, identifier: &Identifier, table: Arc<dyn TableProvider>) {
+        self.catalog.committed(identifier, table)
+    }
+    /// Register the table a second time under the alias, pinned to a snapshot. Queries on the alias keep reading the
+    /// snapshot after the table changes, for example to compare `sales` with `sales_yesterday`.
+    pub async fn register_pinned(
+        &self,
+        alias: Identifier,
+        identifier: Identifier,
+        reference: SnapshotRef,
+    ) -> Result<Arc<dyn TableProvider>> {
+        self.catalog
+            .register_pinned(alias, identifier, reference)
+            .await
+    }
+    /// Call the listener after every change that is made through this catalog, for example to write an audit log
+    pub fn add_listener(&self, listener: Arc<dyn CatalogListener>) {
+        self.catalog.add_listener(listener)
+    }
+    /// Replace the catalog client with one that uses new credentials. Running operations finish with the old client.
+    /// Object store credentials can be rotated with a `RotatingObjectStore`.
+    pub fn update_credentials(&self, catalog: Arc<dyn Catalog>) {
+        self.catalog.update_catalog(catalog)
+    }
+    fn schema_name(&self, namespace: &Namespace) -> String {
+        namespace.levels().join(&self.separator)
+    }
+    fn namespace(&self, name: &str) -> Result<Namespace> {
+        Namespace::try_new(
+            &name
+                .split(self.separator.as_str())
+                .map(|level| level.to_owned())
+                .collect::<Vec<String>>(),
+        )
+        .map_err(|err| DataFusionError::Plan(err.to_string()))
+    }
 }
 
 impl CatalogProvider for IcebergCatalog {
@@ -28,91 +194,56 @@ impl CatalogProvider for IcebergCatalog {
         let namespaces = self.catalog.schema_names(None);
         match namespaces {
             Err(_) => vec![],
-            Ok(namespaces) => namespaces.into_iter().map(|x| x.to_string()).collect(),
+            Ok(namespaces) => namespaces.iter().map(|x| self.schema_name(x)).collect(),
         }
     }
     fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
         let namespaces = self.schema_names();
-        namespaces.iter().find(|x| *x == name).and_then(|y| {
+        resolve(name, &namespaces, self.case_insensitive).and_then(|y| {
             Some(Arc::new(IcebergSchema::new(
-                Namespace::try_new(&y.split(".").map(|z| z.to_owned()).collect::<Vec<String>>())
-                    .ok()?,
+                self.namespace(y).ok()?,
                 Arc::clone(&self.catalog),
+                self.case_insensitive,
             )) as Arc<dyn SchemaProvider>)
         })
     }
-
-    fn register_schema(
-        &self,
-        _name: &str,
-        _schema: Arc<dyn SchemaProvider>,
-    ) -> Result<Option<Arc<dyn SchemaProvider>>> {
-        unimplemented!()
-    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{env, sync::Arc};
+    use std::sync::Arc;
 
-    use iceberg_catalog_rest_client::{apis::configuration::Configuration, catalog::RestCatalog};
-    use iceberg_rs::{
-        catalog::Catalog,
-        object_store::{aws::AmazonS3Builder, ObjectStore},
+    use datafusion_iceberg::testing;
+    use datafusion_iceberg::{
+        dataframe::{WriteIceberg, WriteOptions, WriteTarget},
+        DataFusionTable,
     };
+    use iceberg_rs::catalog::{identifier::Identifier, Catalog};
 
     use datafusion::{
         arrow::{array, record_batch::RecordBatch},
         prelude::*,
     };
 
-    use super::IcebergCatalog;
-
-    fn configuration() -> Configuration {
-        Configuration {
-            base_path: "http://localhost:8080".to_string(),
-            user_agent: None,
-            client: reqwest::Client::new(),
-            basic_auth: None,
-            oauth_access_token: None,
-            bearer_access_token: None,
-            api_key: None,
-        }
-    }
+    use crate::{
+        memory::{MemoryCatalog, TAXIS_METADATA},
+        session::IcebergSessionExt,
+    };
+
+    use super::*;
 
     #[tokio::test]
     pub async fn test_catalog() {
-        let access_key = env::var("AWS_ACCESS_KEY_ID").expect("No access key provided.");
-        let secret_key = env::var("AWS_SECRET_ACCESS_KEY").expect("No secret key provided.");
-
-        let object_store: Arc<dyn ObjectStore> = Arc::new(
-            AmazonS3Builder::new()
-                .with_region("eu-central-1")
-                .with_bucket_name("dashbook-arrow-testing")
-                .with_access_key_id(access_key)
-                .with_secret_access_key(secret_key)
-                .build()
-                .expect("Failed to create aws object store"),
-        );
-
-        let catalog: Arc<dyn Catalog> = Arc::new(RestCatalog::new(
-            "my_catalog".to_owned(),
-            configuration(),
-            object_store,
-        ));
-
-        let datafusion_catalog = Arc::new(
-            IcebergCatalog::new(catalog)
-                .await
-                .expect("Failed to create iceberg catalog"),
-        );
+        let catalog: Arc<dyn Catalog> = Arc::new(MemoryCatalog::with_taxis());
 
         let ctx = SessionContext::new();
 
-        ctx.register_catalog("my_catalog", datafusion_catalog);
+        ctx.register_iceberg_catalog("my_catalog", catalog)
+            .await
+            .expect("Failed to create iceberg catalog");
 
         let df = ctx
-            .sql("SELECT county, SUM(cases) FROM my_catalog.dashbook.covid_nyt GROUP BY county")
+            .sql("SELECT vendor_id, COUNT(*) FROM my_catalog.nyc.taxis GROUP BY vendor_id ORDER BY vendor_id")
             .await
             .expect("Failed to create dataframe.");
 
@@ -124,13 +255,171 @@ mod tests {
             .find(|batch| batch.num_rows() > 0)
             .expect("All record batches are empty");
 
-        let cases = batch
+        let counts = batch
             .column(1)
             .as_any()
             .downcast_ref::<array::Int64Array>()
             .expect("Failed to get values from batch.");
 
-        // Value can either be 0.9 or 1.8
-        assert!(cases.value(0) > 100)
+        assert_eq!(counts.values(), &[2, 2])
+    }
+
+    #[tokio::test]
+    pub async fn test_case_insensitive_session() {
+        let memory = MemoryCatalog::new(testing::test_data());
+        memory.create_namespace("nyc");
+        memory.insert_table("nyc.Taxis", TAXIS_METADATA);
+        let memory: Arc<dyn Catalog> = Arc::new(memory);
+
+        let ctx = SessionContext::new();
+        ctx.register_iceberg_catalog("my_catalog", memory.clone())
+            .await
+            .unwrap();
+        assert!(ctx
+            .sql("SELECT COUNT(*) FROM my_catalog.nyc.taxis")
+            .await
+            .is_err());
+
+        let ctx = SessionContext::with_config(
+            SessionConfig::new().set_bool(CASE_INSENSITIVE_IDENTIFIERS, true),
+        );
+        ctx.register_iceberg_catalog("my_catalog", memory)
+            .await
+            .unwrap();
+        let results = ctx
+            .sql("SELECT COUNT(*) FROM my_catalog.nyc.taxis")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let count = results[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<array::Int64Array>()
+            .unwrap();
+        assert_eq!(count.value(0), 4);
+    }
+
+    #[tokio::test]
+    pub async fn test_nested_namespaces() {
+        let memory = MemoryCatalog::new(testing::test_data());
+        for namespace in ["a", "a.b", "a.b.c", "a.d", "e"] {
+            memory.create_namespace(namespace);
+        }
+        memory.insert_table("a.b.taxis", TAXIS_METADATA);
+        let catalog: Arc<dyn Catalog> = Arc::new(memory);
+
+        let catalog = IcebergCatalog::new(catalog)
+            .await
+            .unwrap()
+            .with_namespace_separator("__");
+
+        let mut schemas = catalog.schema_names();
+        schemas.sort();
+        assert_eq!(schemas, vec!["a", "a__b", "a__b__c", "a__d", "e"]);
+        let mut children = catalog.child_schema_names("a").unwrap();
+        children.sort();
+        assert_eq!(children, vec!["a__b", "a__d"]);
+
+        let ctx = SessionContext::new();
+        let catalog = Arc::new(catalog);
+        ctx.register_catalog("my_catalog", catalog);
+
+        let results = ctx
+            .sql("SELECT COUNT(*) FROM my_catalog.a__b.taxis")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let count = results[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<array::Int64Array>()
+            .unwrap();
+        assert_eq!(count.value(0), 4);
+    }
+
+    #[tokio::test]
+    pub async fn test_write_audit_publish() {
+        let catalog: Arc<dyn Catalog> = Arc::new(MemoryCatalog::with_taxis_copy().await);
+        let ctx = SessionContext::new();
+        ctx.register_iceberg_catalog("my_catalog", catalog.clone())
+            .await
+            .unwrap();
+        let taxis = || async { ctx.sql("SELECT * FROM my_catalog.nyc.taxis").await.unwrap() };
+        let row_count = |table: DataFusionTable| async {
+            ctx.read_table(Arc::new(table))
+                .unwrap()
+                .collect()
+                .await
+                .unwrap()
+                .iter()
+                .map(|batch| batch.num_rows())
+                .sum::<usize>()
+        };
+        let identifier = Identifier::parse("nyc.staged").unwrap();
+        let load = || async {
+            DataFusionTable::from(catalog.clone().load_table(&identifier).await.unwrap())
+        };
+        let append = WriteOptions::default();
+        let mut table = taxis()
+            .await
+            .write_iceberg(
+                WriteTarget::Catalog {
+                    catalog: catalog.clone(),
+                    identifier: identifier.clone(),
+                    base_path: "/home/iceberg/warehouse".to_owned(),
+                },
+                &append.clone().with_create_if_not_exists(),
+            )
+            .await
+            .unwrap();
+
+        // The staged rows are only visible on the branch
+        table.create_branch("audit").await.unwrap();
+        let branch = table.branch("audit").await.unwrap();
+        let branch = taxis()
+            .await
+            .write_iceberg(WriteTarget::Table(Box::new(branch)), &append)
+            .await
+            .unwrap();
+        assert_eq!(row_count(branch).await, 8);
+        assert_eq!(row_count(load().await).await, 4);
+
+        // The metadata of the handle is stale, publishing retries against the current metadata
+        table.publish_branch("audit").await.unwrap();
+        assert_eq!(row_count(load().await).await, 8);
+
+        // Dropping the branch rolls back its writes
+        let mut table = load().await;
+        table.create_branch("rollback").await.unwrap();
+        taxis()
+            .await
+            .write_iceberg(
+                WriteTarget::Table(Box::new(table.branch("rollback").await.unwrap())),
+                &append,
+            )
+            .await
+            .unwrap();
+        table.drop_branch("rollback").await.unwrap();
+        assert!(table.branch("rollback").await.is_err());
+        assert_eq!(row_count(load().await).await, 8);
+
+        // A branch can't be published once the main branch moved on
+        let mut table = load().await;
+        table.create_branch("late").await.unwrap();
+        taxis()
+            .await
+            .write_iceberg(WriteTarget::Table(Box::new(load().await)), &append)
+            .await
+            .unwrap();
+        let err = table.publish_branch("late").await.unwrap_err();
+        assert!(matches!(
+            datafusion_iceberg::error::Error::downcast(&err),
+            Some(datafusion_iceberg::error::Error::CommitConflict(_))
+        ));
+        assert_eq!(row_count(load().await).await, 12);
     }
 }
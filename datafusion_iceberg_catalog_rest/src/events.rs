@@ -0,0 +1,49 @@
+/*!
+ * Notifications about the changes that are made through the catalog
+ *
+ * Listeners are called after the catalog accepted a change, so they never see changes that failed. They are called
+ * synchronously by the operation that made the change and should hand the event off instead of blocking, for example
+ * to write an audit log or to invalidate an external cache. Changes made by other clients of the catalog are not
+ * reported.
+*/
+
+use std::fmt;
+
+use iceberg_rs::catalog::identifier::Identifier;
+
+/// Change of the catalog
+#[derive(Clone)]
+pub enum CatalogEvent {
+    TableRegistered {
+        identifier: Identifier,
+        metadata_location: String,
+    },
+    TableDropped {
+        identifier: Identifier,
+    },
+}
+
+// Identifier doesn't implement Debug, events print the identifier with its display form
+impl fmt::Debug for CatalogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogEvent::TableRegistered {
+                identifier,
+                metadata_location,
+            } => f
+                .debug_struct("TableRegistered")
+                .field("identifier", &identifier.to_string())
+                .field("metadata_location", metadata_location)
+                .finish(),
+            CatalogEvent::TableDropped { identifier } => f
+                .debug_struct("TableDropped")
+                .field("identifier", &identifier.to_string())
+                .finish(),
+        }
+    }
+}
+
+/// Receiver of the changes of a catalog
+pub trait CatalogListener: Send + Sync {
+    fn on_event(&self, event: &CatalogEvent);
+}
@@ -0,0 +1,44 @@
+/*!
+ * Registration of several iceberg catalogs with one session
+ *
+ * Every catalog becomes a datafusion catalog under its own name, so tables of different catalogs can be combined in
+ * one query, for example `SELECT * FROM prod.ns.t JOIN staging.ns.t USING (id)`. Tables that are stored in the same
+ * bucket share the object store that is registered with the session.
+*/
+
+use std::sync::Arc;
+
+use datafusion::{error::Result, prelude::SessionContext};
+use futures::future::try_join_all;
+use iceberg_rs::catalog::Catalog;
+
+use crate::catalog::IcebergCatalog;
+
+/// Create a datafusion catalog for every iceberg catalog and register it with the session under the given name. The
+/// catalogs are loaded concurrently and configured with the iceberg options of the session config.
+pub async fn register_catalogs(
+    ctx: &SessionContext,
+    catalogs: Vec<(String, Arc<dyn Catalog>)>,
+) -> Result<Vec<Arc<IcebergCatalog>>> {
+    let config = ctx.state().config;
+    let loaded = try_join_all(catalogs.into_iter().map(|(name, catalog)| {
+        let config = &config;
+        async move {
+            Ok::<_, datafusion::error::DataFusionError>((
+                name,
+                IcebergCatalog::new(catalog)
+                    .await?
+                    .with_session_config(config),
+            ))
+        }
+    }))
+    .await?;
+    Ok(loaded
+        .into_iter()
+        .map(|(name, catalog)| {
+            let catalog = Arc::new(catalog);
+            ctx.register_catalog(&name, catalog.clone());
+            catalog
+        })
+        .collect())
+}
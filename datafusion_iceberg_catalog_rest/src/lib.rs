@@ -1,3 +1,9 @@
 pub mod catalog;
+pub mod events;
+pub mod federation;
+#[cfg(any(test, feature = "memory"))]
+pub mod memory;
 pub(crate) mod mirror;
 pub mod schema;
+pub mod session;
+pub mod storage;
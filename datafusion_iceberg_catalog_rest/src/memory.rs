@@ -0,0 +1,268 @@
+/*!
+ * In-memory catalog for tests
+ *
+ * The catalog keeps the metadata locations of its tables in memory and reads the metadata files from its object store.
+ * Like other catalogs it only updates a table whose metadata location is still the one the update is based on.
+ * It can be switched to unreachable and made to fail changes, to test how clients serve the last known state and retry
+ * their changes. Other crates can use it for their tests with the `memory` feature.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{anyhow, Result};
+#[cfg(test)]
+use datafusion_iceberg::testing;
+use iceberg_rs::{
+    catalog::{identifier::Identifier, namespace::Namespace, relation::Relation, Catalog},
+    model::table_metadata::TableMetadata,
+    object_store::ObjectStore,
+    table::Table,
+};
+
+/// Metadata file of the nyc taxis table of the test data
+#[cfg(test)]
+pub(crate) const TAXIS_METADATA: &str =
+    "home/iceberg/warehouse/nyc/taxis/metadata/v1.metadata.json";
+
+/// Catalog that keeps its tables in memory
+pub struct MemoryCatalog {
+    object_store: Arc<dyn ObjectStore>,
+    namespaces: Mutex<HashSet<String>>,
+    // Metadata location of every table
+    tables: Mutex<HashMap<String, String>>,
+    // Tables that are left out of the listings
+    unlisted: Mutex<HashSet<String>>,
+    unreachable: AtomicBool,
+    loads: AtomicUsize,
+    // Changes that fail before they are applied
+    failures: AtomicUsize,
+    // Changes that are applied but whose response is lost
+    lost_responses: AtomicUsize,
+}
+
+impl MemoryCatalog {
+    /// Empty catalog whose metadata files are stored in the object store
+    pub fn new(object_store: Arc<dyn ObjectStore>) -> Self {
+        MemoryCatalog {
+            object_store,
+            namespaces: Mutex::new(HashSet::new()),
+            tables: Mutex::new(HashMap::new()),
+            unlisted: Mutex::new(HashSet::new()),
+            unreachable: AtomicBool::new(false),
+            loads: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            lost_responses: AtomicUsize::new(0),
+        }
+    }
+    /// Add the namespace, levels of nested namespaces are separated by dots
+    pub fn create_namespace(&self, namespace: &str) {
+        self.namespaces.lock().unwrap().insert(namespace.to_owned());
+    }
+    /// Add the table directly, like another client of the catalog would
+    pub fn insert_table(&self, identifier: &str, metadata_location: &str) {
+        self.tables
+            .lock()
+            .unwrap()
+            .insert(identifier.to_owned(), metadata_location.to_owned());
+    }
+    /// Remove the table directly, like another client of the catalog would
+    pub fn remove_table(&self, identifier: &str) {
+        self.tables.lock().unwrap().remove(identifier);
+    }
+    /// Leave the table out of the listings, like a catalog whose listings lag behind its changes
+    pub fn unlist_table(&self, identifier: &str) {
+        self.unlisted.lock().unwrap().insert(identifier.to_owned());
+    }
+    /// Fail all requests until the catalog is reachable again
+    pub fn set_unreachable(&self, unreachable: bool) {
+        self.unreachable.store(unreachable, Ordering::SeqCst);
+    }
+    /// Number of tables loaded from the catalog
+    pub fn loads(&self) -> usize {
+        self.loads.load(Ordering::SeqCst)
+    }
+    /// Fail the next changes before they are applied
+    pub fn fail_changes(&self, changes: usize) {
+        self.failures.store(changes, Ordering::SeqCst);
+    }
+    /// Apply the next changes but fail their requests, like a response that got lost
+    pub fn lose_responses(&self, changes: usize) {
+        self.lost_responses.store(changes, Ordering::SeqCst);
+    }
+    fn check(&self) -> Result<()> {
+        match self.unreachable.load(Ordering::SeqCst) {
+            true => Err(anyhow!("The catalog is unreachable.")),
+            false => Ok(()),
+        }
+    }
+    fn change(&self, apply: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.check()?;
+        if take(&self.failures) {
+            return Err(anyhow!("The change failed."));
+        }
+        apply()?;
+        match take(&self.lost_responses) {
+            true => Err(anyhow!("The response was lost.")),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl MemoryCatalog {
+    /// Catalog with the namespace `nyc` and the table `nyc.taxis`
+    pub(crate) fn with_taxis() -> Self {
+        let catalog = MemoryCatalog::new(testing::test_data());
+        catalog.create_namespace("nyc");
+        catalog.insert_table("nyc.taxis", TAXIS_METADATA);
+        catalog
+    }
+    /// Catalog with the namespace `nyc` and the table `nyc.taxis` whose files are copied into memory, so that tests
+    /// can write into the catalog
+    pub(crate) async fn with_taxis_copy() -> Self {
+        let catalog = MemoryCatalog::new(testing::taxis_copy(&[]).await);
+        catalog.create_namespace("nyc");
+        catalog.insert_table("nyc.taxis", TAXIS_METADATA);
+        catalog
+    }
+}
+
+// Decrement the counter, false if it is zero
+fn take(counter: &AtomicUsize) -> bool {
+    counter
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok()
+}
+
+#[async_trait::async_trait]
+impl Catalog for MemoryCatalog {
+    async fn list_tables(&self, namespace: &Namespace) -> Result<Vec<Identifier>> {
+        self.check()?;
+        let unlisted = self.unlisted.lock().unwrap();
+        self.tables
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| !unlisted.contains(*key))
+            .map(|key| Identifier::parse(key))
+            .filter(|identifier| {
+                identifier
+                    .as_ref()
+                    .map(|x| x.namespace().to_string() == namespace.to_string())
+                    .unwrap_or(true)
+            })
+            .collect()
+    }
+    async fn list_namespaces(&self, parent: Option<&str>) -> Result<Vec<Namespace>> {
+        self.check()?;
+        // Top level namespaces without a parent, otherwise the namespaces directly below the parent
+        let (prefix, levels) = match parent {
+            None => (String::new(), 1),
+            Some(parent) => (parent.to_owned() + ".", parent.split('.').count() + 1),
+        };
+        self.namespaces
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|namespace| {
+                namespace.starts_with(&prefix) && namespace.split('.').count() == levels
+            })
+            .map(|namespace| {
+                Namespace::try_new(
+                    &namespace
+                        .split('.')
+                        .map(|level| level.to_owned())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect()
+    }
+    async fn table_exists(&self, identifier: &Identifier) -> Result<bool> {
+        self.check()?;
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .contains_key(&identifier.to_string()))
+    }
+    async fn drop_table(&self, identifier: &Identifier) -> Result<()> {
+        self.change(|| {
+            self.tables
+                .lock()
+                .unwrap()
+                .remove(&identifier.to_string())
+                .map(|_| ())
+                .ok_or_else(|| anyhow!("Table {} doesn't exist.", identifier))
+        })
+    }
+    async fn load_table(self: Arc<Self>, identifier: &Identifier) -> Result<Relation> {
+        self.check()?;
+        let metadata_location = self
+            .tables
+            .lock()
+            .unwrap()
+            .get(&identifier.to_string())
+            .cloned()
+            .ok_or_else(|| anyhow!("Table {} doesn't exist.", identifier))?;
+        let bytes = self
+            .object_store
+            .get(&metadata_location.as_str().into())
+            .await?
+            .bytes()
+            .await?;
+        let metadata: TableMetadata = serde_json::from_slice(&bytes)?;
+        self.loads.fetch_add(1, Ordering::SeqCst);
+        Ok(Relation::Table(
+            Table::new_metastore_table(identifier.clone(), self, metadata, &metadata_location)
+                .await?,
+        ))
+    }
+    async fn invalidate_table(&self, _identifier: &Identifier) -> Result<()> {
+        Ok(())
+    }
+    async fn register_table(
+        self: Arc<Self>,
+        identifier: Identifier,
+        metadata_file_location: &str,
+    ) -> Result<Relation> {
+        self.change(|| {
+            self.insert_table(&identifier.to_string(), metadata_file_location);
+            Ok(())
+        })?;
+        self.load_table(&identifier).await
+    }
+    async fn update_table(
+        self: Arc<Self>,
+        identifier: Identifier,
+        metadata_file_location: &str,
+        previous_metadata_file_location: &str,
+    ) -> Result<Relation> {
+        self.change(|| {
+            let mut tables = self.tables.lock().unwrap();
+            match tables.get(&identifier.to_string()) {
+                Some(current) if current == previous_metadata_file_location => {
+                    tables.insert(identifier.to_string(), metadata_file_location.to_owned());
+                    Ok(())
+                }
+                Some(_) => Err(anyhow!(
+                    "Table {} was changed by another client.",
+                    identifier
+                )),
+                None => Err(anyhow!("Table {} doesn't exist.", identifier)),
+            }
+        })?;
+        self.load_table(&identifier).await
+    }
+    async fn initialize(self: Arc<Self>, _properties: &HashMap<String, String>) -> Result<()> {
+        Ok(())
+    }
+    fn object_store(&self) -> Arc<dyn ObjectStore> {
+        self.object_store.clone()
+    }
+}
@@ -1,81 +1,321 @@
 use anyhow::anyhow;
 use dashmap::DashMap;
-use datafusion::{datasource::TableProvider, error::DataFusionError};
-use datafusion_iceberg::DataFusionTable;
-use futures::{executor::LocalPool, task::LocalSpawnExt};
-use std::{collections::HashSet, sync::Arc};
+use datafusion::{
+    datasource::TableProvider,
+    error::DataFusionError,
+    execution::{context::SessionState, runtime_env::RuntimeEnv},
+    prelude::SessionConfig,
+};
+use datafusion_iceberg::{error::Error, import::import_table, DataFusionTable};
+use futures::StreamExt;
+use log::warn;
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc, oneshot},
+};
 
-use iceberg_rs::catalog::{identifier::Identifier, namespace::Namespace, Catalog};
+use iceberg_rs::{
+    catalog::{identifier::Identifier, namespace::Namespace, relation::Relation, Catalog},
+    model::table_metadata::TableMetadata,
+};
+
+use crate::{
+    catalog::SnapshotRef,
+    events::{CatalogEvent, CatalogListener},
+};
 
 type NamespaceNode = HashSet<String>;
 
 enum Node {
     Namespace(NamespaceNode),
     Relation(Arc<dyn TableProvider>),
+    // Table that is listed in the catalog but not loaded yet
+    Unloaded,
+    // Table whose metadata has to be reloaded. It is served until the reload succeeds.
+    Stale(Arc<dyn TableProvider>),
+}
+
+/// Change of the catalog that sync methods queue instead of waiting for it
+enum QueuedChange {
+    Register(Identifier, Arc<dyn TableProvider>),
+    Deregister(Identifier),
+    // Answered once the changes queued before are applied, with the first error since the last flush
+    Flush(oneshot::Sender<Result<(), DataFusionError>>),
 }
 
 pub struct Mirror {
     storage: DashMap<String, Node>,
-    catalog: Arc<dyn Catalog>,
+    // Tables registered under an alias at a fixed snapshot. They are not changed by refreshes.
+    pinned: DashMap<String, Arc<dyn TableProvider>>,
+    // Operations clone the catalog when they start, so replacing it doesn't affect running operations
+    catalog: RwLock<Arc<dyn Catalog>>,
+    // Tables registered through the mirror that the listings of the catalog don't contain yet
+    registered: Mutex<HashSet<String>>,
+    // Tables committed through this process. Loads that return older metadata are retried and then served this table,
+    // until the catalog returns the committed metadata.
+    committed: DashMap<String, Arc<dyn TableProvider>>,
+    // Time of the last successful synchronization with the catalog
+    synced: Mutex<Instant>,
+    max_staleness: Option<Duration>,
+    // Whether the state was reported as stale since the last synchronization
+    stale_reported: AtomicBool,
+    // Whether a synchronization after the ttl expired runs in the background
+    refreshing: AtomicBool,
+    // Tables that are loaded in the background
+    loading: Mutex<HashSet<String>>,
+    // Queue of the changes of the sync methods, created with its worker on the first change
+    changes: Mutex<Option<mpsc::UnboundedSender<QueuedChange>>>,
+    // Synchronize with the catalog when the last synchronization is older than the ttl
+    ttl: Option<Duration>,
+    listeners: RwLock<Vec<Arc<dyn CatalogListener>>>,
+    // Directory below which tables that aren't iceberg tables yet are imported when they are registered
+    warehouse: RwLock<Option<String>>,
+    // Config and runtime of the session that reads the imported tables. The state itself isn't kept, because its
+    // catalogs would hold on to the mirror.
+    session: RwLock<Option<(SessionConfig, Arc<RuntimeEnv>)>>,
 }
 
 impl Mirror {
-    pub async fn new(catalog: Arc<dyn Catalog>) -> Result<Self, DataFusionError> {
+    pub async fn new(
+        catalog: Arc<dyn Catalog>,
+        max_staleness: Option<Duration>,
+    ) -> Result<Self, DataFusionError> {
         let storage = DashMap::new();
-        let namespaces = catalog
-            .clone()
-            .list_namespaces(None)
-            .await
-            .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
-        for namespace in namespaces {
-            let mut namespace_node = HashSet::new();
-            let tables = catalog
-                .clone()
-                .list_tables(&namespace)
-                .await
-                .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
-            for identifier in tables {
-                let relation = catalog
-                    .clone()
-                    .load_table(&identifier)
-                    .await
-                    .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
-                namespace_node.insert(identifier.to_string());
-                storage.insert(
-                    identifier.to_string(),
-                    Node::Relation(Arc::new(DataFusionTable::from(relation))),
+        for (key, node) in load(&catalog).await? {
+            storage.insert(key, node);
+        }
+        let mirror = Mirror {
+            storage,
+            pinned: DashMap::new(),
+            catalog: RwLock::new(catalog),
+            registered: Mutex::new(HashSet::new()),
+            committed: DashMap::new(),
+            synced: Mutex::new(Instant::now()),
+            max_staleness,
+            stale_reported: AtomicBool::new(false),
+            refreshing: AtomicBool::new(false),
+            loading: Mutex::new(HashSet::new()),
+            changes: Mutex::new(None),
+            ttl: None,
+            listeners: RwLock::new(Vec::new()),
+            warehouse: RwLock::new(None),
+            session: RwLock::new(None),
+        };
+        mirror.preload().await;
+        Ok(mirror)
+    }
+    /// Synchronize with the catalog on access once the state is older than the ttl
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+    /// Call the listener after every change that is made through the mirror
+    pub fn add_listener(&self, listener: Arc<dyn CatalogListener>) {
+        self.listeners.write().unwrap().push(listener);
+    }
+    /// Import tables that aren't iceberg tables when they are registered. The tables are created in the directory of
+    /// their identifier below the location.
+    pub fn set_warehouse(&self, location: &str) {
+        *self.warehouse.write().unwrap() = Some(location.to_owned());
+    }
+    /// Read the tables that are imported with the config and the runtime of the session
+    pub fn set_session(&self, state: &SessionState) {
+        *self.session.write().unwrap() = Some((state.config.clone(), state.runtime_env.clone()));
+    }
+    /// Session for the imports, a default session if none was set
+    fn session_state(&self) -> SessionState {
+        match self.session.read().unwrap().clone() {
+            Some((config, runtime)) => SessionState::with_config_rt(config, runtime),
+            None => {
+                SessionState::with_config_rt(SessionConfig::new(), Arc::new(RuntimeEnv::default()))
+            }
+        }
+    }
+    fn notify(&self, event: CatalogEvent) {
+        // The listeners are called without holding the lock, so that they can add other listeners
+        let listeners = self.listeners.read().unwrap().clone();
+        for listener in listeners {
+            listener.on_event(&event);
+        }
+    }
+    /// Synchronize the mirror with the catalog and reload the tables. Tables that were registered through the mirror
+    /// are kept until the listings of the catalog contain them. If the catalog can't be reached, the error is returned
+    /// and the last state is kept. Tables that fail to reload keep being served.
+    pub async fn refresh(&self) -> Result<(), DataFusionError> {
+        let nodes = load(&self.catalog()).await?;
+        let listed: HashSet<String> = nodes.iter().map(|(key, _)| key.clone()).collect();
+        let registered = {
+            let mut registered = self.registered.lock().unwrap();
+            registered.retain(|key| !listed.contains(key));
+            registered.clone()
+        };
+        // Identifiers of the registered tables and their namespaces
+        let mut kept: HashSet<String> = HashSet::new();
+        for key in &registered {
+            if let Ok(identifier) = Identifier::parse(key) {
+                kept.insert(identifier.namespace().to_string());
+            }
+            kept.insert(key.clone());
+        }
+        for (key, node) in nodes {
+            let node = match node {
+                Node::Namespace(mut tables) => {
+                    tables.extend(
+                        registered
+                            .iter()
+                            .filter(|table| {
+                                Identifier::parse(table)
+                                    .map(|table| table.namespace().to_string() == key)
+                                    .unwrap_or(false)
+                            })
+                            .cloned(),
+                    );
+                    Node::Namespace(tables)
+                }
+                node => match self.storage.get(&key).as_deref() {
+                    Some(Node::Relation(table)) | Some(Node::Stale(table)) => {
+                        Node::Stale(table.clone())
+                    }
+                    _ => node,
+                },
+            };
+            self.storage.insert(key, node);
+        }
+        self.storage
+            .retain(|key, _| listed.contains(key) || kept.contains(key));
+        *self.synced.lock().unwrap() = Instant::now();
+        self.stale_reported.store(false, Ordering::SeqCst);
+        self.preload().await;
+        Ok(())
+    }
+    /// Reload the metadata of the table in the background on its next access. The current metadata is served until the
+    /// reload succeeds.
+    pub fn invalidate(&self, identifier: &Identifier) {
+        if let Some(mut node) = self.storage.get_mut(&identifier.to_string()) {
+            if let Node::Relation(table) = node.value() {
+                *node.value_mut() = Node::Stale(table.clone());
+            }
+        }
+    }
+    /// Serve the table that was just committed through this process. Catalogs and object stores that are eventually
+    /// consistent may still return the previous metadata for a while, so later loads that return older metadata are
+    /// retried and then keep serving the committed table until the catalog returns the committed metadata.
+    pub fn committed(&self, identifier: &Identifier, table: Arc<dyn TableProvider>) {
+        let key = identifier.to_string();
+        if let Some(mut node) = self.storage.get_mut(&key) {
+            if !matches!(node.value(), Node::Namespace(_)) {
+                *node.value_mut() = Node::Relation(table.clone());
+            }
+        }
+        self.committed.insert(key, table);
+    }
+    /// Use the catalog for all operations that start from now on, for example after the credentials were rotated
+    pub fn update_catalog(&self, catalog: Arc<dyn Catalog>) {
+        *self.catalog.write().unwrap() = catalog;
+    }
+    fn catalog(&self) -> Arc<dyn Catalog> {
+        self.catalog.read().unwrap().clone()
+    }
+    /// Synchronize in the background once the state is older than the ttl. The last state is served meanwhile.
+    fn refresh_expired(self: &Arc<Self>) {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        if self.synced.lock().unwrap().elapsed() <= ttl
+            || self.refreshing.swap(true, Ordering::SeqCst)
+        {
+            return;
+        }
+        let mirror = self.clone();
+        let started = spawn(async move {
+            if let Err(err) = mirror.refresh().await {
+                warn!(
+                    "Failed to refresh the catalog, serving the state from {}s ago: {}",
+                    mirror.synced.lock().unwrap().elapsed().as_secs(),
+                    err
                 );
             }
-            storage.insert(namespace.to_string(), Node::Namespace(namespace_node));
+            mirror.refreshing.store(false, Ordering::SeqCst);
+        });
+        if !started {
+            self.refreshing.store(false, Ordering::SeqCst);
         }
-
-        Ok(Mirror { storage, catalog })
+    }
+    /// Warn once per stale period when the state is older than the maximum staleness. Returns whether it warned.
+    fn check_staleness(&self) -> bool {
+        let max_staleness = match self.max_staleness {
+            Some(max_staleness) => max_staleness,
+            None => return false,
+        };
+        let elapsed = self.synced.lock().unwrap().elapsed();
+        if elapsed <= max_staleness || self.stale_reported.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        warn!(
+            "The catalog state is {}s old because the catalog could not be reached.",
+            elapsed.as_secs()
+        );
+        true
     }
     /// Lists all tables in the given namespace.
-    pub fn table_names(&self, namespace: &Namespace) -> Result<Vec<Identifier>, DataFusionError> {
+    pub fn table_names(
+        self: &Arc<Self>,
+        namespace: &Namespace,
+    ) -> Result<Vec<Identifier>, DataFusionError> {
+        self.refresh_expired();
+        self.check_staleness();
         let tables = self
             .storage
             .get(&namespace.to_string())
-            .ok_or_else(|| anyhow!("Namespace not found."))
-            .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
+            .ok_or_else(|| Error::NotFound(format!("Namespace {} not found.", namespace)))?;
         let names = match tables.value() {
-            Node::Relation(_) => Err(anyhow!("Cannot list tables of a table.")),
+            Node::Relation(_) | Node::Unloaded | Node::Stale(_) => {
+                Err(anyhow!("Cannot list tables of a table."))
+            }
             Node::Namespace(names) => Ok(names),
         }
         .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
+        let pinned: Vec<String> = self
+            .pinned
+            .iter()
+            .map(|x| x.key().clone())
+            .filter(|x| {
+                Identifier::parse(x)
+                    .map(|x| x.namespace().to_string() == namespace.to_string())
+                    .unwrap_or(false)
+            })
+            .collect();
         names
             .iter()
+            .chain(pinned.iter())
             .map(|x| {
                 Identifier::parse(x).map_err(|err| DataFusionError::Internal(format!("{}", err)))
             })
             .collect::<Result<_, DataFusionError>>()
     }
-    /// Lists all namespaces in the catalog.
-    pub fn schema_names(&self, _parent: Option<&str>) -> Result<Vec<Namespace>, DataFusionError> {
-        self.storage
+    /// Lists all namespaces in the catalog, including nested ones. With a parent only the namespaces directly below
+    /// the parent are listed.
+    pub fn schema_names(
+        self: &Arc<Self>,
+        parent: Option<&Namespace>,
+    ) -> Result<Vec<Namespace>, DataFusionError> {
+        self.refresh_expired();
+        self.check_staleness();
+        let namespaces = self
+            .storage
             .iter()
             .filter_map(|r| match r.value() {
-                Node::Relation(_) => None,
+                Node::Relation(_) | Node::Unloaded | Node::Stale(_) => None,
                 Node::Namespace(_) => Some(r.key().clone()),
             })
             .map(|x| {
@@ -86,100 +326,851 @@ impl Mirror {
                         .as_slice(),
                 )
             })
-            .collect::<Result<_, anyhow::Error>>()
-            .map_err(|err| DataFusionError::Internal(format!("{}", err)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()
+            .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
+        Ok(match parent {
+            None => namespaces,
+            Some(parent) => namespaces
+                .into_iter()
+                .filter(|namespace| {
+                    namespace.levels().len() == parent.levels().len() + 1
+                        && namespace.levels().starts_with(parent.levels())
+                })
+                .collect(),
+        })
     }
-    pub fn table(&self, identifier: Identifier) -> Option<Arc<dyn TableProvider>> {
-        self.storage
-            .get(&identifier.to_string())
-            .and_then(|x| match x.value() {
-                Node::Relation(relation) => Some(relation.clone()),
-                Node::Namespace(_) => None,
+    /// Get the table. The tables are loaded when the mirror is created and refreshed. Stale tables are served while
+    /// they are reloaded in the background, tables that couldn't be loaded yet are missing until the background load
+    /// succeeds.
+    pub fn table(self: &Arc<Self>, identifier: Identifier) -> Option<Arc<dyn TableProvider>> {
+        if let Some(table) = self.pinned.get(&identifier.to_string()) {
+            return Some(table.clone());
+        }
+        self.refresh_expired();
+        let table = match self.storage.get(&identifier.to_string())?.value() {
+            Node::Relation(relation) => return Some(relation.clone()),
+            Node::Namespace(_) => return None,
+            Node::Unloaded => None,
+            Node::Stale(table) => Some(table.clone()),
+        };
+        self.reload(identifier);
+        table
+    }
+    /// Load the table in the background, unless it is loaded already
+    fn reload(self: &Arc<Self>, identifier: Identifier) {
+        let key = identifier.to_string();
+        if !self.loading.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+        let mirror = self.clone();
+        let loaded = key.clone();
+        let started = spawn(async move {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(table = %identifier, "Load table");
+            if let Err(err) = mirror.load_table(&identifier).await {
+                warn!("Failed to load table {}: {}", identifier, err);
+            }
+            mirror.loading.lock().unwrap().remove(&loaded);
+        });
+        if !started {
+            self.loading.lock().unwrap().remove(&key);
+        }
+    }
+    /// Load the tables that weren't loaded yet or are stale. Tables that fail to load are logged and loaded again on
+    /// their next access.
+    async fn preload(&self) {
+        let unloaded: Vec<String> = self
+            .storage
+            .iter()
+            .filter(|node| matches!(node.value(), Node::Unloaded | Node::Stale(_)))
+            .map(|node| node.key().clone())
+            .collect();
+        futures::stream::iter(unloaded)
+            .for_each_concurrent(PRELOAD_CONCURRENCY, |key| async move {
+                let result = match Identifier::parse(&key) {
+                    Ok(identifier) => self.load_table(&identifier).await.map(|_| ()),
+                    Err(err) => Err(DataFusionError::Internal(format!("{}", err))),
+                };
+                if let Err(err) = result {
+                    warn!("Failed to load table {}: {}", key, err);
+                }
             })
+            .await
+    }
+    /// Load all tables that couldn't be loaded yet or are stale, so that later accesses serve the current metadata.
+    /// Fails with the error of the first table that can't be loaded.
+    pub async fn load_tables(&self) -> Result<(), DataFusionError> {
+        let unloaded: Vec<String> = self
+            .storage
+            .iter()
+            .filter(|node| matches!(node.value(), Node::Unloaded | Node::Stale(_)))
+            .map(|node| node.key().clone())
+            .collect();
+        for key in unloaded {
+            let identifier = Identifier::parse(&key)
+                .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
+            self.load_table(&identifier).await?;
+        }
+        Ok(())
+    }
+    async fn load_table(
+        &self,
+        identifier: &Identifier,
+    ) -> Result<Arc<dyn TableProvider>, DataFusionError> {
+        let key = identifier.to_string();
+        let mut attempt = 1;
+        let table: Arc<dyn TableProvider> = loop {
+            let relation = self
+                .catalog()
+                .load_table(identifier)
+                .await
+                .map_err(Error::catalog)?;
+            let committed = self.committed.get(&key).map(|table| table.clone());
+            let committed = match committed {
+                Some(committed) if is_older(&relation, &committed) => committed,
+                _ => {
+                    self.committed.remove(&key);
+                    break Arc::new(DataFusionTable::from(relation));
+                }
+            };
+            if attempt == CHANGE_ATTEMPTS {
+                warn!(
+                    "The catalog returned older metadata for {} than was committed, serving the committed table.",
+                    identifier
+                );
+                break committed;
+            }
+            tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+            attempt += 1;
+        };
+        // If the table was loaded concurrently, the first table is kept
+        Ok(match self.storage.get_mut(&key) {
+            Some(mut node) => match node.value_mut() {
+                Node::Relation(relation) => relation.clone(),
+                node => {
+                    *node = Node::Relation(table.clone());
+                    table
+                }
+            },
+            // The table was deregistered while it was loaded
+            None => table,
+        })
     }
     pub fn table_exists(&self, identifier: Identifier) -> bool {
         self.storage.contains_key(&identifier.to_string())
+            || self.pinned.contains_key(&identifier.to_string())
     }
-    pub fn register_table(
+    /// Register the table under the alias at the given snapshot. The alias keeps reading the snapshot when the table
+    /// changes.
+    pub async fn register_pinned(
         &self,
+        alias: Identifier,
+        identifier: Identifier,
+        reference: SnapshotRef,
+    ) -> Result<Arc<dyn TableProvider>, DataFusionError> {
+        if self.table_exists(alias.clone()) {
+            return Err(DataFusionError::Plan(format!(
+                "Can't register {} as alias, a table with the name already exists.",
+                alias
+            )));
+        }
+        let relation = self
+            .catalog()
+            .load_table(&identifier)
+            .await
+            .map_err(Error::catalog)?;
+        let current = match &relation {
+            Relation::Table(table) => match table.metadata() {
+                TableMetadata::V1(metadata) => metadata.current_snapshot_id,
+                TableMetadata::V2(metadata) => metadata.current_snapshot_id,
+            },
+            Relation::View(_) => None,
+        };
+        match reference {
+            SnapshotRef::Current => (),
+            // The scan reads the manifests of the current snapshot of the loaded metadata
+            SnapshotRef::Snapshot(snapshot_id) if Some(snapshot_id) == current => (),
+            SnapshotRef::Snapshot(snapshot_id) => {
+                return Err(DataFusionError::Plan(format!(
+                    "Can't pin snapshot {} of {}, only the current snapshot can be read.",
+                    snapshot_id, identifier
+                )))
+            }
+        }
+        let table: Arc<dyn TableProvider> = Arc::new(DataFusionTable::from(relation));
+        self.pinned.insert(alias.to_string(), table.clone());
+        Ok(table)
+    }
+    /// Queue the registration of the table for sync callers and return the table it replaces. The change is applied in
+    /// the background like [`Mirror::register_table`], its error is reported by [`Mirror::flush`].
+    pub fn queue_register(
+        self: &Arc<Self>,
         identifier: Identifier,
         table: Arc<dyn TableProvider>,
     ) -> Result<Option<Arc<dyn TableProvider>>, DataFusionError> {
-        self.storage
-            .insert(identifier.to_string(), Node::Relation(table.clone()));
+        self.check_namespace(&identifier)?;
+        let previous = self.loaded(&identifier);
+        self.queue(QueuedChange::Register(identifier, table))?;
+        Ok(previous)
+    }
+    /// Queue dropping the table for sync callers and return the dropped table, see [`Mirror::queue_register`]
+    pub fn queue_deregister(
+        self: &Arc<Self>,
+        identifier: Identifier,
+    ) -> Result<Option<Arc<dyn TableProvider>>, DataFusionError> {
+        self.check_table(&identifier)?;
+        let previous = self.loaded(&identifier);
+        self.queue(QueuedChange::Deregister(identifier))?;
+        Ok(previous)
+    }
+    /// Wait until the queued changes are applied. Returns the first error of the changes since the last flush.
+    pub async fn flush(&self) -> Result<(), DataFusionError> {
+        let changes = self.changes.lock().unwrap().clone();
+        let changes = match changes {
+            Some(changes) => changes,
+            None => return Ok(()),
+        };
+        let (sender, receiver) = oneshot::channel();
+        if changes.send(QueuedChange::Flush(sender)).is_err() {
+            return Ok(());
+        }
+        receiver.await.unwrap_or(Ok(()))
+    }
+    fn queue(self: &Arc<Self>, change: QueuedChange) -> Result<(), DataFusionError> {
+        let mut changes = self.changes.lock().unwrap();
+        if changes.is_none() {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            // The worker ends when the mirror and with it the sender are dropped
+            if !spawn(apply_changes(Arc::downgrade(self), receiver)) {
+                return Err(DataFusionError::Execution(
+                    "Can't change the catalog outside of a tokio runtime.".to_owned(),
+                ));
+            }
+            *changes = Some(sender);
+        }
+        changes
+            .as_ref()
+            .and_then(|changes| changes.send(change).ok())
+            .ok_or_else(|| DataFusionError::Execution("The catalog queue was closed.".to_owned()))
+    }
+    /// Loaded table of the identifier
+    fn loaded(&self, identifier: &Identifier) -> Option<Arc<dyn TableProvider>> {
+        match self.storage.get(&identifier.to_string()).as_deref() {
+            Some(Node::Relation(relation)) | Some(Node::Stale(relation)) => Some(relation.clone()),
+            _ => None,
+        }
+    }
+    fn check_namespace(&self, identifier: &Identifier) -> Result<(), DataFusionError> {
         match self
             .storage
-            .get_mut(&identifier.namespace().to_string())
-            .ok_or(DataFusionError::Internal(
-                "Namespace doesn't exist".to_string(),
-            ))?
-            .value_mut()
+            .get(&identifier.namespace().to_string())
+            .as_deref()
         {
-            Node::Namespace(namespace) => {
-                namespace.insert(identifier.to_string());
+            Some(Node::Namespace(_)) => Ok(()),
+            _ => Err(DataFusionError::Plan(format!(
+                "Can't register table {}, namespace doesn't exist.",
+                identifier
+            ))),
+        }
+    }
+    fn check_table(&self, identifier: &Identifier) -> Result<(), DataFusionError> {
+        match self.storage.get(&identifier.to_string()).as_deref() {
+            None => Err(Error::NotFound(format!(
+                "Can't deregister table {}, table doesn't exist.",
+                identifier
+            ))
+            .into()),
+            Some(Node::Namespace(_)) => Err(DataFusionError::Plan(format!(
+                "Can't deregister table {}, identifier refers to a namespace.",
+                identifier
+            ))),
+            Some(_) => Ok(()),
+        }
+    }
+    /// Register the table in the catalog. The mirror is only updated once the catalog accepted the table. Tables that
+    /// aren't iceberg tables are imported as new tables below the warehouse.
+    pub async fn register_table(
+        &self,
+        identifier: Identifier,
+        table: Arc<dyn TableProvider>,
+    ) -> Result<Option<Arc<dyn TableProvider>>, DataFusionError> {
+        self.check_namespace(&identifier)?;
+        let catalog = self.catalog();
+        let (table, metadata_location) = match table.as_any().downcast_ref::<DataFusionTable>() {
+            Some(iceberg) => {
+                let metadata_location = iceberg.metadata_location().to_owned();
+                #[cfg(feature = "tracing")]
+                tracing::debug!(table = %identifier, metadata_location, "Register table in catalog");
+                let existed = catalog
+                    .table_exists(&identifier)
+                    .await
+                    .map_err(Error::catalog)?;
+                retry(
+                    &format!("register table {}", identifier),
+                    || {
+                        catalog
+                            .clone()
+                            .register_table(identifier.clone(), &metadata_location)
+                    },
+                    // The table only appears if an attempt whose response was lost registered it
+                    || async {
+                        !existed && catalog.table_exists(&identifier).await.unwrap_or(false)
+                    },
+                )
+                .await?;
+                (table, metadata_location)
+            }
+            None => {
+                let warehouse = self.warehouse.read().unwrap().clone().ok_or_else(|| {
+                    DataFusionError::Plan(format!(
+                        "Table {} is not an iceberg datafusion table and there is no warehouse to import it into.",
+                        identifier
+                    ))
+                })?;
+                #[cfg(feature = "tracing")]
+                tracing::debug!(table = %identifier, warehouse, "Import table into catalog");
+                let imported = import_table(
+                    &self.session_state(),
+                    table,
+                    catalog.clone(),
+                    identifier.clone(),
+                    &warehouse,
+                )
+                .await?;
+                let metadata_location = imported.metadata_location().to_owned();
+                (
+                    Arc::new(imported) as Arc<dyn TableProvider>,
+                    metadata_location,
+                )
             }
-            Node::Relation(_) => {}
         };
-        let pool = LocalPool::new();
-        let spawner = pool.spawner();
-        let cloned_catalog = self.catalog.clone();
-        let metadata_location = table
-            .clone()
-            .as_any()
-            .downcast_ref::<DataFusionTable>()
-            .ok_or(DataFusionError::Internal(
-                "Table is not an iceberg datafusion table.".to_owned(),
-            ))?
-            .0
-            .metadata_location()
-            .to_owned();
-        spawner
-            .spawn_local(async move {
-                cloned_catalog
-                    .register_table(identifier, &metadata_location)
-                    .await
-                    .unwrap();
-            })
-            .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
-        Ok(Some(table))
+        let previous = self
+            .storage
+            .insert(identifier.to_string(), Node::Relation(table.clone()));
+        self.committed.insert(identifier.to_string(), table);
+        self.registered
+            .lock()
+            .unwrap()
+            .insert(identifier.to_string());
+        if let Some(mut node) = self.storage.get_mut(&identifier.namespace().to_string()) {
+            if let Node::Namespace(namespace) = node.value_mut() {
+                namespace.insert(identifier.to_string());
+            }
+        }
+        self.notify(CatalogEvent::TableRegistered {
+            identifier,
+            metadata_location,
+        });
+        Ok(match previous {
+            Some(Node::Relation(relation)) | Some(Node::Stale(relation)) => Some(relation),
+            _ => None,
+        })
     }
-    pub fn deregister_table(
+    /// Drop the table from the catalog. The mirror is only updated once the catalog dropped the table.
+    pub async fn deregister_table(
         &self,
         identifier: Identifier,
     ) -> Result<Option<Arc<dyn TableProvider>>, DataFusionError> {
-        let table = if let (_, Node::Relation(relation)) = self
-            .storage
-            .remove(&identifier.to_string())
-            .ok_or(DataFusionError::Internal(
-                "Can't deregister table, tables doesn't exist.".to_string(),
-            ))? {
-            Ok(relation)
-        } else {
-            Err(DataFusionError::Internal(
-                "Can't deregister table, identifier refers to a namespace.".to_string(),
+        self.check_table(&identifier)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(table = %identifier, "Drop table in catalog");
+        let catalog = self.catalog();
+        retry(
+            &format!("drop table {}", identifier),
+            || catalog.drop_table(&identifier),
+            || async { !catalog.table_exists(&identifier).await.unwrap_or(true) },
+        )
+        .await?;
+        let table = match self.storage.remove(&identifier.to_string()) {
+            Some((_, Node::Relation(relation))) | Some((_, Node::Stale(relation))) => {
+                Some(relation)
+            }
+            _ => None,
+        };
+        self.registered
+            .lock()
+            .unwrap()
+            .remove(&identifier.to_string());
+        self.committed.remove(&identifier.to_string());
+        if let Some(mut node) = self.storage.get_mut(&identifier.namespace().to_string()) {
+            if let Node::Namespace(namespace) = node.value_mut() {
+                namespace.remove(&identifier.to_string());
+            }
+        }
+        self.notify(CatalogEvent::TableDropped { identifier });
+        Ok(table)
+    }
+}
+
+/// Tables that are loaded at the same time when the mirror is created or refreshed
+const PRELOAD_CONCURRENCY: usize = 16;
+/// Attempts of a change of the catalog before its error is returned
+const CHANGE_ATTEMPTS: u32 = 3;
+/// Wait before the first retry of a change, doubled for every further retry
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Run the change of the catalog, retrying failed requests with backoff. A failed attempt counts as successful if
+/// `applied` holds afterwards, so that a change whose response was lost isn't applied twice.
+async fn retry<T, C, CF, A, AF>(
+    description: &str,
+    change: C,
+    applied: A,
+) -> Result<(), DataFusionError>
+where
+    C: Fn() -> CF,
+    CF: Future<Output = anyhow::Result<T>>,
+    A: Fn() -> AF,
+    AF: Future<Output = bool>,
+{
+    let mut attempt = 1;
+    loop {
+        let err = match change().await {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        if applied().await {
+            return Ok(());
+        }
+        if attempt == CHANGE_ATTEMPTS {
+            return Err(Error::catalog(anyhow!(
+                "Failed to {} after {} attempts: {}",
+                description,
+                attempt,
+                err
             ))
-        }?;
-        match self
-            .storage
-            .get_mut(&identifier.namespace().to_string())
-            .ok_or(DataFusionError::Internal(
-                "Namespace doesn't exist".to_string(),
-            ))?
-            .value_mut()
+            .into());
+        }
+        warn!(
+            "Failed to {}, retrying (attempt {} of {}): {}",
+            description, attempt, CHANGE_ATTEMPTS, err
+        );
+        tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+        attempt += 1;
+    }
+}
+
+/// Whether the loaded metadata was written before the metadata of the committed table
+fn is_older(relation: &Relation, committed: &Arc<dyn TableProvider>) -> bool {
+    let committed = match committed.as_any().downcast_ref::<DataFusionTable>() {
+        Some(committed) => &**committed,
+        None => return false,
+    };
+    if relation.metadata_location() == committed.metadata_location() {
+        return false;
+    }
+    match (last_updated_ms(relation), last_updated_ms(committed)) {
+        (Some(loaded), Some(committed)) => loaded < committed,
+        _ => false,
+    }
+}
+
+fn last_updated_ms(relation: &Relation) -> Option<i64> {
+    match relation {
+        Relation::Table(table) => Some(match table.metadata() {
+            TableMetadata::V1(metadata) => metadata.last_updated_ms,
+            TableMetadata::V2(metadata) => metadata.last_updated_ms,
+        }),
+        Relation::View(_) => None,
+    }
+}
+
+/// Run the catalog operation in the background on the tokio runtime of the caller. Outside of a runtime it is
+/// skipped. Returns whether it was started.
+fn spawn<F>(future: F) -> bool
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    match Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn(future);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Apply the queued changes in order until the mirror is dropped
+async fn apply_changes(mirror: Weak<Mirror>, mut changes: mpsc::UnboundedReceiver<QueuedChange>) {
+    let mut failed = None;
+    while let Some(change) = changes.recv().await {
+        let mirror = match mirror.upgrade() {
+            Some(mirror) => mirror,
+            None => return,
+        };
+        let result = match change {
+            QueuedChange::Register(identifier, table) => {
+                mirror.register_table(identifier, table).await.map(|_| ())
+            }
+            QueuedChange::Deregister(identifier) => {
+                mirror.deregister_table(identifier).await.map(|_| ())
+            }
+            QueuedChange::Flush(sender) => {
+                let _ = sender.send(failed.take().map_or(Ok(()), Err));
+                continue;
+            }
+        };
+        if let Err(err) = result {
+            warn!("Failed to change the catalog: {}", err);
+            failed.get_or_insert(err);
+        }
+    }
+}
+
+/// List all namespaces, including nested ones, and tables of the catalog. The mirror loads the tables afterwards.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+async fn load(catalog: &Arc<dyn Catalog>) -> Result<Vec<(String, Node)>, DataFusionError> {
+    let mut nodes = Vec::new();
+    let mut namespaces = catalog
+        .clone()
+        .list_namespaces(None)
+        .await
+        .map_err(Error::catalog)?;
+    let mut visited = HashSet::new();
+    while let Some(namespace) = namespaces.pop() {
+        // Catalogs without nested namespaces may return the parent itself
+        if !visited.insert(namespace.to_string()) {
+            continue;
+        }
+        namespaces.extend(
+            catalog
+                .clone()
+                .list_namespaces(Some(&namespace.to_string()))
+                .await
+                .map_err(Error::catalog)?,
+        );
+        let mut namespace_node = HashSet::new();
+        let tables = catalog
+            .clone()
+            .list_tables(&namespace)
+            .await
+            .map_err(Error::catalog)?;
+        for identifier in tables {
+            namespace_node.insert(identifier.to_string());
+            nodes.push((identifier.to_string(), Node::Unloaded));
+        }
+        nodes.push((namespace.to_string(), Node::Namespace(namespace_node)));
+    }
+    Ok(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use std::time::Duration;
+
+    use datafusion::{
+        datasource::{
+            file_format::parquet::ParquetFormat,
+            listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+            MemTable, TableProvider,
+        },
+        prelude::SessionContext,
+    };
+    use datafusion_iceberg::{properties::DEFAULT_NAME_MAPPING, DataFusionTable};
+    use futures::TryStreamExt;
+    use iceberg_rs::catalog::{identifier::Identifier, namespace::Namespace, Catalog};
+    use tokio::runtime::Runtime;
+
+    use crate::memory::{MemoryCatalog, TAXIS_METADATA};
+
+    const TAXIS_METADATA_V0: &str = "home/iceberg/warehouse/nyc/taxis/metadata/v0.metadata.json";
+
+    use super::{Mirror, Ordering};
+
+    /// Wait until the background loads and synchronizations finished
+    async fn settle(mirror: &Mirror) {
+        while !mirror.loading.lock().unwrap().is_empty() || mirror.refreshing.load(Ordering::SeqCst)
         {
-            Node::Namespace(namespace) => {
-                namespace.remove(&identifier.to_string());
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_preload() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let mirror = Arc::new(
+            Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+                .await
+                .unwrap(),
+        );
+
+        // The tables are loaded with the mirror, accesses don't wait for the catalog
+        assert_eq!(catalog.loads(), 1);
+        assert!(mirror
+            .table(Identifier::parse("nyc.taxis").unwrap())
+            .is_some());
+        assert!(mirror
+            .table(Identifier::parse("nyc.taxis").unwrap())
+            .is_some());
+        assert_eq!(catalog.loads(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_load_in_background() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        catalog.insert_table(
+            "nyc.other",
+            "home/iceberg/warehouse/nyc/other/missing.metadata.json",
+        );
+        let mirror = Arc::new(
+            Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+                .await
+                .unwrap(),
+        );
+        let identifier = Identifier::parse("nyc.other").unwrap();
+
+        // The table that couldn't be loaded is missing until the load on its access succeeds
+        catalog.insert_table("nyc.other", TAXIS_METADATA);
+        assert!(mirror.table(identifier.clone()).is_none());
+        settle(&mirror).await;
+        assert!(mirror.table(identifier).is_some());
+        assert_eq!(catalog.loads(), 2);
+    }
+
+    #[tokio::test]
+    pub async fn test_invalidate() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let mirror = Arc::new(
+            Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+                .await
+                .unwrap(),
+        );
+        let identifier = Identifier::parse("nyc.taxis").unwrap();
+        let loaded = mirror.table(identifier.clone()).unwrap();
+
+        // The loaded table is served while the catalog can't be reached
+        catalog.set_unreachable(true);
+        mirror.invalidate(&identifier);
+        assert!(mirror.table(identifier.clone()).is_some());
+        settle(&mirror).await;
+        assert_eq!(catalog.loads(), 1);
+
+        // The stale table is served while it is reloaded
+        catalog.set_unreachable(false);
+        let table = mirror.table(identifier.clone()).unwrap();
+        assert!(Arc::ptr_eq(&table, &loaded));
+        settle(&mirror).await;
+        let table = mirror.table(identifier).unwrap();
+        assert!(!Arc::ptr_eq(&table, &loaded));
+        assert_eq!(catalog.loads(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_read_own_commit() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let mirror = Arc::new(
+            Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+                .await
+                .unwrap(),
+        );
+        let identifier = Identifier::parse("nyc.taxis").unwrap();
+        let committed = mirror.table(identifier.clone()).unwrap();
+        mirror.committed(&identifier, committed.clone());
+
+        // The catalog still returns the metadata before the commit
+        catalog.insert_table("nyc.taxis", TAXIS_METADATA_V0);
+        mirror.invalidate(&identifier);
+        mirror.load_tables().await.unwrap();
+        let table = mirror.table(identifier.clone()).unwrap();
+        assert!(Arc::ptr_eq(&table, &committed));
+        assert_eq!(catalog.loads(), 4);
+
+        // Once the catalog returns the committed metadata, the tables are loaded again
+        catalog.insert_table("nyc.taxis", TAXIS_METADATA);
+        mirror.invalidate(&identifier);
+        mirror.load_tables().await.unwrap();
+        let table = mirror.table(identifier.clone()).unwrap();
+        assert!(!Arc::ptr_eq(&table, &committed));
+        catalog.insert_table("nyc.taxis", TAXIS_METADATA_V0);
+        mirror.invalidate(&identifier);
+        mirror.load_tables().await.unwrap();
+        mirror.table(identifier).unwrap();
+        assert_eq!(catalog.loads(), 6);
+    }
+
+    #[tokio::test]
+    pub async fn test_refresh_in_background() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let mirror = Arc::new(
+            Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+                .await
+                .unwrap()
+                .with_ttl(Duration::ZERO),
+        );
+        let namespace = Namespace::try_new(&["nyc".to_owned()]).unwrap();
+        catalog.insert_table("nyc.other", TAXIS_METADATA);
+        std::thread::sleep(Duration::from_millis(1));
+
+        // The expired state is served while the synchronization runs
+        assert_eq!(mirror.table_names(&namespace).unwrap().len(), 1);
+        settle(&mirror).await;
+        assert_eq!(mirror.table_names(&namespace).unwrap().len(), 2);
+        assert!(mirror
+            .table(Identifier::parse("nyc.other").unwrap())
+            .is_some());
+        settle(&mirror).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_refresh_merges() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let mirror = Arc::new(
+            Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+                .await
+                .unwrap(),
+        );
+        let taxis = mirror
+            .table(Identifier::parse("nyc.taxis").unwrap())
+            .unwrap();
+
+        let copy = Identifier::parse("nyc.copy").unwrap();
+        mirror.register_table(copy.clone(), taxis).await.unwrap();
+        catalog.unlist_table("nyc.copy");
+        catalog.insert_table("nyc.other", TAXIS_METADATA);
+        catalog.remove_table("nyc.taxis");
+
+        mirror.refresh().await.unwrap();
+        let namespace = Namespace::try_new(&["nyc".to_owned()]).unwrap();
+        let mut names: Vec<String> = mirror
+            .table_names(&namespace)
+            .unwrap()
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        names.sort();
+        // The registered table is kept although the listing doesn't contain it yet
+        assert_eq!(names, vec!["nyc.copy", "nyc.other"]);
+        assert!(mirror.table(copy).is_some());
+
+        // A failed refresh keeps the last state
+        catalog.set_unreachable(true);
+        assert!(mirror.refresh().await.is_err());
+        assert_eq!(mirror.table_names(&namespace).unwrap().len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_import_tables() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis_copy().await);
+        let mirror = Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+            .await
+            .unwrap();
+        let ctx = SessionContext::new();
+        ctx.runtime_env()
+            .register_object_store("memory", "", catalog.object_store());
+        let url =
+            ListingTableUrl::parse("memory:///home/iceberg/warehouse/nyc/taxis/data/vendor_id=1/")
+                .unwrap();
+        let options = ListingOptions::new(Arc::new(ParquetFormat::default()));
+        let schema = options.infer_schema(&ctx.state(), &url).await.unwrap();
+        let listing = Arc::new(
+            ListingTable::try_new(
+                ListingTableConfig::new(url)
+                    .with_listing_options(options)
+                    .with_schema(schema),
+            )
+            .unwrap(),
+        );
+        let rows = Arc::new(
+            MemTable::try_new(
+                listing.schema(),
+                vec![ctx
+                    .read_table(listing.clone())
+                    .unwrap()
+                    .collect()
+                    .await
+                    .unwrap()],
+            )
+            .unwrap(),
+        );
+
+        // Without a warehouse only iceberg tables can be registered
+        let listed = Identifier::parse("nyc.listed").unwrap();
+        assert!(mirror
+            .register_table(listed.clone(), listing.clone())
+            .await
+            .is_err());
+
+        mirror.set_warehouse("/home/iceberg/warehouse");
+        mirror.set_session(&ctx.state());
+        mirror
+            .register_table(listed.clone(), listing)
+            .await
+            .unwrap();
+        let copied = Identifier::parse("nyc.copied").unwrap();
+        mirror.register_table(copied.clone(), rows).await.unwrap();
+
+        let mut counts = Vec::new();
+        for identifier in [listed, copied] {
+            let table =
+                DataFusionTable::from(catalog.clone().load_table(&identifier).await.unwrap());
+            // The columns of the kept files are mapped by name
+            assert!(table
+                .properties()
+                .unwrap()
+                .contains_key(DEFAULT_NAME_MAPPING));
+            let batches = ctx
+                .read_table(Arc::new(table))
+                .unwrap()
+                .collect()
+                .await
+                .unwrap();
+            counts.push(batches.iter().map(|batch| batch.num_rows()).sum::<usize>());
+        }
+        // The listing table keeps its parquet files, the rows of the memory table are written into new files
+        let files = |table: &str| {
+            let prefix = format!("home/iceberg/warehouse/nyc/{}/data", table);
+            let store = catalog.object_store();
+            async move {
+                store
+                    .list(Some(&prefix.as_str().into()))
+                    .await
+                    .unwrap()
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap()
+                    .len()
             }
-            Node::Relation(_) => {}
         };
-        let pool = LocalPool::new();
-        let spawner = pool.spawner();
-        let cloned_catalog = self.catalog.clone();
-        spawner
-            .spawn_local(async move {
-                cloned_catalog.drop_table(&identifier).await.unwrap();
-            })
-            .map_err(|err| DataFusionError::Internal(format!("{}", err)))?;
-        Ok(Some(table))
+        assert_eq!(files("listed").await, 0);
+        assert!(files("copied").await > 0);
+        assert!(counts[0] > 0);
+        assert_eq!(counts[0], counts[1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_staleness_warning() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let mirror = Mirror::new(catalog.clone() as Arc<dyn Catalog>, Some(Duration::ZERO))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+
+        // One warning per stale period
+        assert!(mirror.check_staleness());
+        assert!(!mirror.check_staleness());
+
+        mirror.refresh().await.unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(mirror.check_staleness());
+    }
+
+    #[test]
+    pub fn test_outside_runtime() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let mirror = Arc::new(
+            Runtime::new()
+                .unwrap()
+                .block_on(Mirror::new(catalog.clone() as Arc<dyn Catalog>, None))
+                .unwrap(),
+        );
+        let identifier = Identifier::parse("nyc.taxis").unwrap();
+
+        assert!(mirror.table(identifier.clone()).is_some());
+        // Without a runtime the stale table isn't reloaded and changes can't be queued
+        mirror.invalidate(&identifier);
+        assert!(mirror.table(identifier.clone()).is_some());
+        assert_eq!(catalog.loads(), 1);
+        assert!(mirror.queue_deregister(identifier).is_err());
     }
 }
@@ -6,17 +6,30 @@ use datafusion::{
     error::{DataFusionError, Result},
 };
 use iceberg_rs::catalog::{identifier::Identifier, namespace::Namespace};
+use log::warn;
 
 use crate::mirror::Mirror;
 
 pub struct IcebergSchema {
     schema: Namespace,
     catalog: Arc<Mirror>,
+    case_insensitive: bool,
 }
 
 impl IcebergSchema {
-    pub(crate) fn new(schema: Namespace, catalog: Arc<Mirror>) -> Self {
-        IcebergSchema { schema, catalog }
+    pub(crate) fn new(schema: Namespace, catalog: Arc<Mirror>, case_insensitive: bool) -> Self {
+        IcebergSchema {
+            schema,
+            catalog,
+            case_insensitive,
+        }
+    }
+    /// Name of the table in the catalog that the name refers to
+    fn resolve(&self, name: &str) -> Option<String> {
+        if !self.case_insensitive {
+            return Some(name.to_owned());
+        }
+        resolve(name, &self.table_names(), true).map(|name| name.to_owned())
     }
 }
 
@@ -32,32 +45,157 @@ impl SchemaProvider for IcebergSchema {
         }
     }
     fn table(&self, name: &str) -> Option<Arc<dyn TableProvider>> {
-        self.catalog.table(
-            Identifier::try_new(&[self.schema.levels(), &[name.to_string()]].concat()).unwrap(),
-        )
+        let name = self.resolve(name)?;
+        self.catalog
+            .table(Identifier::try_new(&[self.schema.levels(), &[name]].concat()).unwrap())
     }
     fn table_exist(&self, name: &str) -> bool {
-        self.catalog.table_exists(
-            Identifier::try_new(&[self.schema.levels(), &[name.to_string()]].concat()).unwrap(),
-        )
+        match self.resolve(name) {
+            Some(name) => self.catalog.table_exists(
+                Identifier::try_new(&[self.schema.levels(), &[name]].concat()).unwrap(),
+            ),
+            None => false,
+        }
     }
 
+    /// Queue the registration of the table in the catalog. The table appears once the catalog accepted it, errors are
+    /// returned by [`crate::catalog::IcebergCatalog::flush`].
     fn register_table(
         &self,
         name: String,
         table: Arc<dyn TableProvider>,
     ) -> Result<Option<Arc<dyn TableProvider>>> {
-        let mut full_name = Vec::from(self.schema.levels().clone());
+        let mut full_name = Vec::from(self.schema.levels());
         full_name.push(name.to_owned());
         let identifier = Identifier::try_new(&full_name)
             .map_err(|err| DataFusionError::Internal(err.to_string()))?;
-        self.catalog.register_table(identifier, table)
+        self.catalog.queue_register(identifier, table)
     }
+    /// Queue dropping the table from the catalog like register_table
     fn deregister_table(&self, name: &str) -> Result<Option<Arc<dyn TableProvider>>> {
-        let mut full_name = Vec::from(self.schema.levels().clone());
+        let mut full_name = Vec::from(self.schema.levels());
         full_name.push(name.to_owned());
         let identifier = Identifier::try_new(&full_name)
             .map_err(|err| DataFusionError::Internal(err.to_string()))?;
-        self.catalog.deregister_table(identifier)
+        self.catalog.queue_deregister(identifier)
+    }
+}
+
+/// Find the name among the names of the catalog. Case insensitive names only match if exactly one name differs in case.
+pub(crate) fn resolve<'a>(
+    name: &str,
+    names: &'a [String],
+    case_insensitive: bool,
+) -> Option<&'a str> {
+    if let Some(name) = names.iter().find(|x| *x == name) {
+        return Some(name);
+    }
+    if !case_insensitive {
+        return None;
+    }
+    let mut matches = names.iter().filter(|x| x.eq_ignore_ascii_case(name));
+    match (matches.next(), matches.next()) {
+        (Some(name), None) => Some(name),
+        (Some(_), Some(_)) => {
+            warn!(
+                "The identifier {} is ambiguous, it matches several names that differ in case.",
+                name
+            );
+            None
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use datafusion_iceberg::DataFusionTable;
+    use iceberg_rs::catalog::Catalog;
+
+    use crate::memory::MemoryCatalog;
+
+    use super::*;
+
+    async fn schema(catalog: &Arc<MemoryCatalog>) -> (IcebergSchema, Arc<dyn TableProvider>) {
+        let mirror = Mirror::new(catalog.clone() as Arc<dyn Catalog>, None)
+            .await
+            .unwrap();
+        let schema = IcebergSchema::new(
+            Namespace::try_new(&["nyc".to_owned()]).unwrap(),
+            Arc::new(mirror),
+            false,
+        );
+        let relation = catalog
+            .clone()
+            .load_table(&Identifier::parse("nyc.taxis").unwrap())
+            .await
+            .unwrap();
+        (schema, Arc::new(DataFusionTable::from(relation)))
+    }
+
+    #[tokio::test]
+    pub async fn test_register_retry() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let (schema, table) = schema(&catalog).await;
+
+        catalog.fail_changes(2);
+        schema
+            .register_table("copy".to_owned(), table.clone())
+            .unwrap();
+        schema.catalog.flush().await.unwrap();
+        assert!(schema.table_exist("copy"));
+
+        // The first drop is applied but reported as failed, the retry sees that the table is gone
+        catalog.lose_responses(1);
+        schema.deregister_table("copy").unwrap();
+        schema.catalog.flush().await.unwrap();
+        assert!(!schema.table_exist("copy"));
+
+        catalog.fail_changes(3);
+        schema.register_table("copy".to_owned(), table).unwrap();
+        match schema.catalog.flush().await {
+            Err(err) => assert!(err.to_string().contains("after 3 attempts")),
+            Ok(_) => panic!("The registration should fail."),
+        }
+        assert!(!schema.table_exist("copy"));
+        // The error is only reported once
+        schema.catalog.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_register_queued() {
+        let catalog = Arc::new(MemoryCatalog::with_taxis());
+        let (schema, table) = schema(&catalog).await;
+
+        // The registration is queued without blocking the current-thread runtime
+        assert!(schema
+            .register_table("copy".to_owned(), table.clone())
+            .unwrap()
+            .is_none());
+        assert!(!schema.table_exist("copy"));
+        schema.catalog.flush().await.unwrap();
+        assert!(schema.table_exist("copy"));
+
+        // The registration replaces the loaded table
+        assert!(schema
+            .register_table("copy".to_owned(), table)
+            .unwrap()
+            .is_some());
+        // Changes that can't be applied fail without being queued
+        assert!(schema.deregister_table("missing").is_err());
+        schema.catalog.flush().await.unwrap();
+    }
+
+    #[test]
+    pub fn test_resolve_case_insensitive() {
+        let names = vec!["Sales".to_owned(), "Orders".to_owned(), "orders".to_owned()];
+
+        assert_eq!(resolve("sales", &names, false), None);
+        assert_eq!(resolve("sales", &names, true), Some("Sales"));
+        // The exact name wins over names that differ in case
+        assert_eq!(resolve("orders", &names, true), Some("orders"));
+        // ORDERS matches two tables
+        assert_eq!(resolve("ORDERS", &names, true), None);
     }
 }
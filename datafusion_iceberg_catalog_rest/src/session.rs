@@ -0,0 +1,79 @@
+/*!
+ * Extension of the SessionContext to register iceberg catalogs and tables in one call
+*/
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use datafusion::{dataframe::DataFrame, error::Result, prelude::SessionContext};
+use datafusion_iceberg::{error::Error, DataFusionTable};
+use iceberg_rs::catalog::{identifier::Identifier, Catalog};
+
+use crate::catalog::IcebergCatalog;
+
+#[async_trait]
+pub trait IcebergSessionExt {
+    /// Register all namespaces and tables of the catalog under the name. The catalog is configured with the iceberg
+    /// options of the session config and imports tables with the session.
+    async fn register_iceberg_catalog(
+        &self,
+        name: &str,
+        catalog: Arc<dyn Catalog>,
+    ) -> Result<Arc<IcebergCatalog>>;
+    /// Load the table from the catalog and register it under the name
+    async fn register_iceberg_table(
+        &self,
+        name: &str,
+        catalog: Arc<dyn Catalog>,
+        identifier: &Identifier,
+    ) -> Result<()>;
+    /// Load the table from the catalog and read it into a dataframe
+    async fn read_iceberg(
+        &self,
+        catalog: Arc<dyn Catalog>,
+        identifier: &Identifier,
+    ) -> Result<Arc<DataFrame>>;
+}
+
+#[async_trait]
+impl IcebergSessionExt for SessionContext {
+    async fn register_iceberg_catalog(
+        &self,
+        name: &str,
+        catalog: Arc<dyn Catalog>,
+    ) -> Result<Arc<IcebergCatalog>> {
+        let catalog = Arc::new(
+            IcebergCatalog::new(catalog)
+                .await?
+                .with_session_state(&self.state()),
+        );
+        self.register_catalog(name, catalog.clone());
+        Ok(catalog)
+    }
+    async fn register_iceberg_table(
+        &self,
+        name: &str,
+        catalog: Arc<dyn Catalog>,
+        identifier: &Identifier,
+    ) -> Result<()> {
+        let table = load(catalog, identifier).await?;
+        self.register_table(name, Arc::new(table))?;
+        Ok(())
+    }
+    async fn read_iceberg(
+        &self,
+        catalog: Arc<dyn Catalog>,
+        identifier: &Identifier,
+    ) -> Result<Arc<DataFrame>> {
+        let table = load(catalog, identifier).await?;
+        self.read_table(Arc::new(table))
+    }
+}
+
+async fn load(catalog: Arc<dyn Catalog>, identifier: &Identifier) -> Result<DataFusionTable> {
+    let relation = catalog
+        .load_table(identifier)
+        .await
+        .map_err(Error::catalog)?;
+    Ok(DataFusionTable::from(relation))
+}
@@ -0,0 +1,115 @@
+/*!
+ * Object stores for the locations of a catalog
+ *
+ * The object store is built from the scheme of the warehouse location and the standard iceberg storage properties.
+ * Supported are Amazon S3 (`s3://`, `s3a://`), Google Cloud Storage (`gs://`) and Azure Data Lake Storage
+ * (`abfs://`, `abfss://`).
+*/
+
+use std::{collections::HashMap, sync::Arc};
+
+use datafusion::error::{DataFusionError, Result};
+use object_store::{
+    aws::AmazonS3Builder, azure::MicrosoftAzureBuilder, gcp::GoogleCloudStorageBuilder, ObjectStore,
+};
+use url::Url;
+
+/// Access key id for S3
+pub const S3_ACCESS_KEY_ID: &str = "s3.access-key-id";
+/// Secret access key for S3
+pub const S3_SECRET_ACCESS_KEY: &str = "s3.secret-access-key";
+/// Session token for temporary S3 credentials
+pub const S3_SESSION_TOKEN: &str = "s3.session-token";
+/// Region of the S3 bucket
+pub const S3_REGION: &str = "s3.region";
+/// Endpoint of an S3 compatible object store
+pub const S3_ENDPOINT: &str = "s3.endpoint";
+/// Path of the service account json file for GCS
+pub const GCS_SERVICE_ACCOUNT_FILE: &str = "gcs.service-account-file";
+/// Storage account name for ADLS. Taken from the host of the location if not set.
+pub const ADLS_ACCOUNT_NAME: &str = "adls.account-name";
+/// Storage account key for ADLS
+pub const ADLS_ACCOUNT_KEY: &str = "adls.account-key";
+
+/// Build the object store for the location from the storage properties
+pub fn object_store(
+    location: &str,
+    properties: &HashMap<String, String>,
+) -> Result<Arc<dyn ObjectStore>> {
+    let url = Url::parse(location).map_err(|err| DataFusionError::Plan(err.to_string()))?;
+    let bucket = url.host_str().unwrap_or_default();
+    let property = |key: &str| properties.get(key).cloned();
+    let object_store: Arc<dyn ObjectStore> = match url.scheme() {
+        "s3" | "s3a" => {
+            let mut builder = AmazonS3Builder::new().with_bucket_name(bucket);
+            if let Some(region) = property(S3_REGION) {
+                builder = builder.with_region(region);
+            }
+            if let Some(endpoint) = property(S3_ENDPOINT) {
+                builder = builder.with_endpoint(endpoint);
+            }
+            if let Some(access_key_id) = property(S3_ACCESS_KEY_ID) {
+                builder = builder.with_access_key_id(access_key_id);
+            }
+            if let Some(secret_access_key) = property(S3_SECRET_ACCESS_KEY) {
+                builder = builder.with_secret_access_key(secret_access_key);
+            }
+            if let Some(token) = property(S3_SESSION_TOKEN) {
+                builder = builder.with_token(token);
+            }
+            Arc::new(builder.build()?)
+        }
+        "gs" => {
+            let mut builder = GoogleCloudStorageBuilder::new().with_bucket_name(bucket);
+            if let Some(path) = property(GCS_SERVICE_ACCOUNT_FILE) {
+                builder = builder.with_service_account_path(path);
+            }
+            Arc::new(builder.build()?)
+        }
+        "abfs" | "abfss" => {
+            // The host of ADLS locations is "<container>@<account>.dfs.core.windows.net"
+            let container = url.username();
+            let account = property(ADLS_ACCOUNT_NAME)
+                .unwrap_or_else(|| bucket.split('.').next().unwrap_or_default().to_owned());
+            let mut builder = MicrosoftAzureBuilder::new()
+                .with_container_name(container)
+                .with_account(account);
+            if let Some(key) = property(ADLS_ACCOUNT_KEY) {
+                builder = builder.with_access_key(key);
+            }
+            Arc::new(builder.build()?)
+        }
+        scheme => {
+            return Err(DataFusionError::Plan(format!(
+                "Object stores for the scheme {} are not supported.",
+                scheme
+            )))
+        }
+    };
+    Ok(object_store)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_object_store_from_properties() {
+        let properties = HashMap::from([
+            (S3_REGION.to_owned(), "eu-central-1".to_owned()),
+            (S3_ACCESS_KEY_ID.to_owned(), "key".to_owned()),
+            (S3_SECRET_ACCESS_KEY.to_owned(), "secret".to_owned()),
+        ]);
+        assert!(object_store("s3://bucket/warehouse", &properties).is_ok());
+
+        let properties = HashMap::from([(ADLS_ACCOUNT_KEY.to_owned(), "a2V5".to_owned())]);
+        assert!(object_store(
+            "abfss://container@account.dfs.core.windows.net/warehouse",
+            &properties
+        )
+        .is_ok());
+
+        assert!(object_store("ftp://host/warehouse", &HashMap::new()).is_err());
+    }
+}